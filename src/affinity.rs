@@ -0,0 +1,176 @@
+//! Worker thread placement on cores and NUMA nodes.
+//!
+//! This module pins rayon worker threads to CPU cores and, for NUMA-aware placement,
+//! keeps one copy of the input sequences in each node's local memory so workers do
+//! not have to read sequences across the socket interconnect.
+
+use clap::ValueEnum;
+use core_affinity::CoreId;
+use std::collections::HashMap;
+use std::fs;
+
+/// Strategy for pinning worker threads
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ValueEnum)]
+pub enum PinStrategy {
+    /// Let the operating system schedule worker threads freely
+    #[default]
+    None,
+    /// Pin each worker thread to its own core
+    Cores,
+    /// Spread worker threads evenly over NUMA nodes, pin them to cores, and
+    /// replicate the input sequences into each node's local memory
+    Numa,
+}
+
+/// Assignment of worker threads to cores and NUMA nodes
+#[derive(Debug, Clone)]
+pub struct Placement {
+    strategy: PinStrategy,
+    /// Cores of each NUMA node
+    nodes: Vec<Vec<CoreId>>,
+    /// Cores in the order they are handed out to worker threads, with their node
+    slots: Vec<(usize, CoreId)>,
+}
+
+impl Placement {
+    /// Determines the placement for the given strategy from the machine topology
+    pub fn new(strategy: PinStrategy) -> Self {
+        let nodes = numa_nodes();
+        let slots = match strategy {
+            PinStrategy::None => Vec::new(),
+            PinStrategy::Cores => nodes
+                .iter()
+                .enumerate()
+                .flat_map(|(node, cores)| cores.iter().map(move |&core| (node, core)))
+                .collect(),
+            PinStrategy::Numa => interleave(&nodes),
+        };
+        Self {
+            strategy,
+            nodes,
+            slots,
+        }
+    }
+
+    /// Returns `true` if worker threads should be pinned
+    pub fn is_pinned(&self) -> bool {
+        !self.slots.is_empty()
+    }
+
+    /// Pins the calling thread according to its rayon worker index
+    pub fn pin(&self, thread_index: usize) {
+        if let Some(&(_, core)) = self.slots.get(thread_index % self.slots.len().max(1)) {
+            core_affinity::set_for_current(core);
+        }
+    }
+
+    /// Returns the NUMA node the given worker thread is placed on
+    pub fn node_of(&self, thread_index: usize) -> usize {
+        self.slots
+            .get(thread_index % self.slots.len().max(1))
+            .map_or(0, |&(node, _)| node)
+    }
+
+    /// Copies the input into the local memory of every NUMA node.
+    ///
+    /// Each copy is made by a thread pinned to that node, so the first-touch policy
+    /// of the operating system places its pages in node-local memory. Returns an
+    /// empty vector unless the strategy is [`PinStrategy::Numa`] on a multi-node machine.
+    pub fn replicate(&self, input: &HashMap<String, String>) -> Vec<HashMap<String, String>> {
+        if self.strategy != PinStrategy::Numa || self.nodes.len() < 2 {
+            return Vec::new();
+        }
+
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .nodes
+                .iter()
+                .map(|cores| {
+                    let core = cores.first().copied();
+                    scope.spawn(move || {
+                        if let Some(core) = core {
+                            core_affinity::set_for_current(core);
+                        }
+                        input.clone()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("Replication thread panicked"))
+                .collect()
+        })
+    }
+}
+
+/// Orders cores round-robin across nodes, so consecutive worker threads land on
+/// different nodes and the load is split evenly between sockets.
+fn interleave(nodes: &[Vec<CoreId>]) -> Vec<(usize, CoreId)> {
+    let longest = nodes.iter().map(Vec::len).max().unwrap_or(0);
+    (0..longest)
+        .flat_map(|i| {
+            nodes
+                .iter()
+                .enumerate()
+                .filter_map(move |(node, cores)| cores.get(i).map(|&core| (node, core)))
+        })
+        .collect()
+}
+
+/// Reads the cores of each NUMA node from sysfs.
+///
+/// Falls back to a single node containing all available cores if the topology
+/// cannot be read (e.g. on non-Linux systems).
+fn numa_nodes() -> Vec<Vec<CoreId>> {
+    let available = core_affinity::get_core_ids().unwrap_or_default();
+
+    let mut nodes: Vec<(usize, Vec<CoreId>)> = fs::read_dir("/sys/devices/system/node")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let node: usize = name.strip_prefix("node")?.parse().ok()?;
+            let list = fs::read_to_string(entry.path().join("cpulist")).ok()?;
+            let cores = parse_cpu_list(&list)
+                .into_iter()
+                .filter(|id| available.iter().any(|core| core.id == *id))
+                .map(|id| CoreId { id })
+                .collect::<Vec<_>>();
+            (!cores.is_empty()).then_some((node, cores))
+        })
+        .collect();
+    nodes.sort_by_key(|(node, _)| *node);
+
+    if nodes.is_empty() {
+        vec![available]
+    } else {
+        nodes.into_iter().map(|(_, cores)| cores).collect()
+    }
+}
+
+/// Parses a Linux CPU list such as `0-3,8-11` into individual CPU ids
+fn parse_cpu_list(list: &str) -> Vec<usize> {
+    list.trim()
+        .split(',')
+        .filter(|part| !part.is_empty())
+        .flat_map(|part| match part.split_once('-') {
+            Some((start, end)) => match (start.parse::<usize>(), end.parse::<usize>()) {
+                (Ok(start), Ok(end)) => (start..=end).collect(),
+                _ => Vec::new(),
+            },
+            None => part.parse().into_iter().collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("0-3,8-9,12\n"), vec![0, 1, 2, 3, 8, 9, 12]);
+        assert!(parse_cpu_list("").is_empty());
+    }
+}
@@ -1,103 +1,1094 @@
 //! Sequence alignment functionality.
 //!
 //! This module provides functions for performing pairwise sequence alignments,
-//! including global alignment and pre-filtering based on k-mer matches.
+//! global, semi-global or local (see [`AlignMode`]), and pre-filtering based on k-mer matches.
+//!
+//! Scores are generic over the [`Score`] trait, implemented for integer scores
+//! (built-in matrices) and `f32` scores (e.g. probabilistic substitution matrices).
 
 use bio::alignment::pairwise::*;
 use bio::alignment::sparse::find_kmer_matches;
+use bio::alignment::{Alignment, AlignmentOperation};
+use clap::ValueEnum;
 use indicatif::ParallelProgressIterator;
-use rayon::ThreadPoolBuilder;
 use rayon::prelude::*;
+use rayon::{ThreadPoolBuildError, ThreadPoolBuilder};
+use std::any::Any;
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::cmp::Reverse;
 use std::collections::HashMap;
+use std::fmt;
+use std::ops::Add;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+use tracing::{Span, debug, debug_span, info, instrument, trace_span, warn};
 
+use crate::affinity::{PinStrategy, Placement};
+use crate::blast::{BlastHit, BlastParams};
+use crate::cache::ResultCache;
+use crate::containment::Containment;
+use crate::engine::{self, AlignmentEngine, PairAlignment};
+use crate::filter::FilterChain;
+use crate::hits::TopHits;
+use crate::lanes;
+use crate::memory::MemoryTracker;
+use crate::metrics::Metrics;
+use crate::packed::{PackedInput, PackedSeq};
+use crate::paf::PafAlignment;
+use crate::pairs::{Pair, PairGenerator};
+use crate::profile::{self, Profiler, Stage};
+use crate::sam::SamAlignment;
 use crate::utils::setup_progress_bar;
+use crate::workspace::{self, NO_CLIPS};
 
 /// Function type for scoring matches between amino acids or nucleotides
-pub type MatcherFn = fn(u8, u8) -> i32;
+pub type MatcherFn<S = i32> = fn(u8, u8) -> S;
+
+/// Penalty for opening a gap in an alignment
+pub const GAP_OPEN: i32 = -10;
+
+/// Penalty for extending a gap in an alignment
+pub const GAP_EXTEND: i32 = -1;
+
+/// Largest change of the score a single residue of the built-in scoring schemes
+/// can cause: the highest BLOSUM62 entry
+pub const MAX_RESIDUE_SCORE: i32 = 11;
+
+/// Length up to which no alignment score between two sequences can overflow an
+/// `i32` with the default gap penalties and the built-in scoring schemes
+pub const MAX_SAFE_LEN: usize = GapPenalties {
+    open: GAP_OPEN,
+    extend: GAP_EXTEND,
+}
+.max_safe_len(MAX_RESIDUE_SCORE);
+
+/// Largest cost accepted by `--gap-open` and `--gap-extend`
+const MAX_GAP_COST: u32 = 1000;
+
+/// Scores of opening and extending a gap. A gap of `n` residues scores
+/// `open + n * extend`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GapPenalties {
+    /// Score of opening a gap, added once per gap to the scores of its residues
+    pub open: i32,
+    /// Score of every residue of a gap
+    pub extend: i32,
+}
+
+impl Default for GapPenalties {
+    fn default() -> Self {
+        Self {
+            open: GAP_OPEN,
+            extend: GAP_EXTEND,
+        }
+    }
+}
+
+impl GapPenalties {
+    /// Creates the penalties from the costs of opening and extending a gap, given as
+    /// positive numbers as in `--gap-open 10 --gap-extend 1`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a cost exceeds 1000 or both are zero, which would make
+    /// gaps free.
+    pub fn from_costs(open: u32, extend: u32) -> Result<Self, String> {
+        if open > MAX_GAP_COST || extend > MAX_GAP_COST {
+            return Err(format!(
+                "gap costs must be at most {}, got {} to open and {} to extend",
+                MAX_GAP_COST, open, extend
+            ));
+        }
+        if open == 0 && extend == 0 {
+            return Err("gap costs must not both be zero".to_string());
+        }
+        Ok(Self {
+            open: -(open as i32),
+            extend: -(extend as i32),
+        })
+    }
+
+    /// Returns the length up to which no alignment score between two sequences can
+    /// overflow an `i32` with these penalties, if no matrix entry is further from
+    /// zero than `max_residue_score`
+    pub const fn max_safe_len(&self, max_residue_score: i32) -> usize {
+        // A residue changes the score by a matrix entry or by opening and extending
+        // a gap at most
+        let gap = -(self.open + self.extend);
+        let per_residue = if gap > max_residue_score {
+            gap
+        } else {
+            max_residue_score
+        };
+        ((i32::MAX as i64 + self.open as i64) / (2 * per_residue as i64)) as usize
+    }
+}
+
+/// Which parts of two sequences are aligned
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ValueEnum)]
+pub enum AlignMode {
+    /// Both sequences end to end (Needleman-Wunsch), so terminal gaps are
+    /// penalized
+    #[default]
+    Global,
+    /// The best-scoring pair of subsequences (Smith-Waterman), so divergent
+    /// sequences sharing a conserved domain still score well. Scores are never
+    /// negative.
+    Local,
+    /// The shorter sequence end to end within the longer one, so gaps at the ends
+    /// of the longer sequence are free. Fragments such as domain constructs score
+    /// like the full-length sequences they come from.
+    Semiglobal,
+    /// Both sequences with free gaps at their ends, so sequences truncated at
+    /// different ends score like their overlap.
+    Overlap,
+}
+
+impl AlignMode {
+    /// Returns the name of the mode, as accepted by `--mode`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AlignMode::Global => "global",
+            AlignMode::Local => "local",
+            AlignMode::Semiglobal => "semiglobal",
+            AlignMode::Overlap => "overlap",
+        }
+    }
+
+    /// Returns whether gaps at the ends of sequences of `x_len` and `y_len` residues
+    /// are free, for modes aligning the sequences end to end except for free ends.
+    /// Ties leave the ends of the second sequence free.
+    pub fn free_ends(self, x_len: usize, y_len: usize) -> (bool, bool) {
+        match self {
+            AlignMode::Global | AlignMode::Local => (false, false),
+            AlignMode::Semiglobal => (x_len > y_len, x_len <= y_len),
+            AlignMode::Overlap => (true, true),
+        }
+    }
+}
+
+/// Numeric type of alignment scores
+pub trait Score:
+    Copy
+    + PartialOrd
+    + Add<Output = Self>
+    + fmt::Display
+    + fmt::Debug
+    + Into<f64>
+    + serde::Serialize
+    + Send
+    + Sync
+    + 'static
+{
+    /// Value written in place of the score for pairs that were not aligned
+    const SKIPPED: Self;
+
+    /// Whether scores are whole numbers, stored as integers by typed output formats
+    const INTEGRAL: bool;
+
+    /// Converts an integer gap penalty to this score type
+    fn from_penalty(penalty: i32) -> Self;
+
+    /// Bit pattern under which the score is stored in the result cache
+    fn to_bits(self) -> u32;
+
+    /// Restores a score from its bit pattern in the result cache
+    fn from_bits(bits: u32) -> Self;
+
+    /// Multiplies the score by a factor, rounding integer scores
+    fn scale(self, factor: f64) -> Self;
+
+    /// Computes the alignment score of two sequences in `mode` with the most
+    /// efficient implementation available for this score type
+    fn align(
+        seq1: &str,
+        seq2: &str,
+        matcher: &MatcherFn<Self>,
+        mode: AlignMode,
+        gaps: GapPenalties,
+    ) -> Self;
+
+    /// Returns the engine computing the full alignment with traceback for this
+    /// score type, if there is one, in linear space if `linear_space` is set
+    fn traceback_engine(linear_space: bool) -> Option<&'static dyn AlignmentEngine<Self>>;
+
+    /// Returns `matcher` as a scoring function of the bio aligner, which only
+    /// supports integer scores, if this is an integer score type
+    fn integer_matcher(matcher: MatcherFn<Self>) -> Option<MatcherFn>;
+}
+
+impl Score for i32 {
+    const SKIPPED: Self = -1;
+    const INTEGRAL: bool = true;
+
+    fn from_penalty(penalty: i32) -> Self {
+        penalty
+    }
+
+    fn to_bits(self) -> u32 {
+        self as u32
+    }
+
+    fn from_bits(bits: u32) -> Self {
+        bits as i32
+    }
+
+    fn scale(self, factor: f64) -> Self {
+        (f64::from(self) * factor).round() as i32
+    }
+
+    fn align(
+        seq1: &str,
+        seq2: &str,
+        matcher: &MatcherFn<Self>,
+        mode: AlignMode,
+        gaps: GapPenalties,
+    ) -> Self {
+        align(seq1, seq2, matcher, mode, gaps)
+    }
+
+    fn traceback_engine(linear_space: bool) -> Option<&'static dyn AlignmentEngine<Self>> {
+        if linear_space {
+            Some(&engine::Hirschberg)
+        } else {
+            Some(&engine::Traceback)
+        }
+    }
+
+    fn integer_matcher(matcher: MatcherFn<Self>) -> Option<MatcherFn> {
+        Some(matcher)
+    }
+}
+
+impl Score for f32 {
+    const SKIPPED: Self = -1.0;
+    const INTEGRAL: bool = false;
+
+    fn from_penalty(penalty: i32) -> Self {
+        penalty as f32
+    }
+
+    fn to_bits(self) -> u32 {
+        f32::to_bits(self)
+    }
+
+    fn from_bits(bits: u32) -> Self {
+        f32::from_bits(bits)
+    }
+
+    fn scale(self, factor: f64) -> Self {
+        (f64::from(self) * factor) as f32
+    }
+
+    // The bio aligner only supports integer scores
+    fn align(
+        seq1: &str,
+        seq2: &str,
+        matcher: &MatcherFn<Self>,
+        mode: AlignMode,
+        gaps: GapPenalties,
+    ) -> Self {
+        align_linear(seq1, seq2, matcher, mode, gaps)
+    }
+
+    fn traceback_engine(_linear_space: bool) -> Option<&'static dyn AlignmentEngine<Self>> {
+        None
+    }
+
+    fn integer_matcher(_matcher: MatcherFn<Self>) -> Option<MatcherFn> {
+        None
+    }
+}
+
+/// Outcome of processing a pair
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PairStatus {
+    /// The pair was aligned and has a score
+    #[default]
+    Aligned,
+    /// The pair was rejected by the pre-filter
+    Skipped,
+    /// The alignment was abandoned after exceeding the per-pair timeout
+    Timeout,
+    /// Processing the pair panicked
+    Failed,
+}
+
+impl PairStatus {
+    /// Returns the name of the status, as serialized
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PairStatus::Aligned => "aligned",
+            PairStatus::Skipped => "skipped",
+            PairStatus::Timeout => "timeout",
+            PairStatus::Failed => "failed",
+        }
+    }
+}
 
 /// Represents the result of a pairwise sequence alignment
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct AlignmentResult {
+pub struct AlignmentResult<S = i32> {
     /// Identifier of the query sequence
     pub query_id: String,
     /// Identifier of the subject sequence
     pub subject_id: String,
-    /// Alignment score, None if alignment was skipped
-    pub score: Option<i32>,
+    /// Alignment score, None if alignment was skipped or abandoned
+    pub score: Option<S>,
+    /// Whether the pair was aligned, skipped, abandoned or failed
+    pub status: PairStatus,
+    /// Reason the pair failed, or the filter that rejected a skipped pair and its
+    /// reason
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Fraction of alignment columns with identical residues, only set where the
+    /// full alignment is computed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<f64>,
+    /// Column counts of the alignment, only set where the full alignment is
+    /// computed by the engine
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<Summary>,
+    /// The alignment as a CIGAR string, only set where the full alignment is
+    /// computed with `--traceback`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cigar: Option<String>,
+    /// How much of the shorter sequence lies within the longer one, only set for
+    /// aligned pairs with `--containment`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub containment: Option<Containment>,
+    /// Columns of BLAST's tabular output, only set for aligned pairs with
+    /// `--output-format blast6`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blast: Option<BlastHit>,
+    /// The pair as a SAM record, only set for aligned pairs with
+    /// `--output-format sam`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sam: Option<SamAlignment>,
+    /// The aligned block of the pair as a PAF record, only set for aligned pairs
+    /// with `--output-format paf`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paf: Option<PafAlignment>,
+    /// Score of the shorter sequence aligned with itself, the highest score the
+    /// pair can reach, only set for aligned pairs with `--max-score`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_score: Option<S>,
     /// Length of sequence 1
     pub seq1_len: usize,
     /// Length of sequence 2
     pub seq2_len: usize,
 }
 
-/// Performs pairwise alignments for all unique pairs of sequences in the input,
-/// streaming results through a channel.
-pub fn align_all_streaming(
+#[cfg(test)]
+impl<S> AlignmentResult<S> {
+    /// Returns a result with the given identifiers, score and status and nothing
+    /// else, for tests to set the other fields they check
+    pub fn for_test(
+        query_id: &str,
+        subject_id: &str,
+        score: Option<S>,
+        status: PairStatus,
+    ) -> Self {
+        Self {
+            query_id: query_id.to_string(),
+            subject_id: subject_id.to_string(),
+            score,
+            status,
+            error: None,
+            identity: None,
+            summary: None,
+            cigar: None,
+            containment: None,
+            blast: None,
+            sam: None,
+            paf: None,
+            max_score: None,
+            seq1_len: 0,
+            seq2_len: 0,
+        }
+    }
+}
+
+/// Strategy for distributing pairs over worker threads
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ValueEnum)]
+pub enum Schedule {
+    /// Work stealing over individual pairs (or chunks of at least `chunk_size` pairs)
+    #[default]
+    Dynamic,
+    /// Fixed contiguous chunks, by default one per thread, each processed as a unit
+    Static,
+    /// Most expensive pairs first (by product of lengths), pulled from a shared queue
+    /// so short pairs fill the tail of the run
+    Cost,
+    /// One query at a time, with its targets split into chunks processed in
+    /// parallel, for runs of few queries against many targets
+    PerQuery,
+}
+
+/// Options controlling how the pair loop is executed in parallel
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionOptions {
+    /// Number of worker threads, determined by rayon if `None`
+    pub num_threads: Option<usize>,
+    /// Placement of worker threads on cores and NUMA nodes
+    pub pinning: PinStrategy,
+    /// Strategy for distributing pairs over worker threads
+    pub schedule: Schedule,
+    /// Number of pairs per task, chosen by the schedule if `None`
+    pub chunk_size: Option<usize>,
+    /// Number of best-scoring results of every query sent by the per-query
+    /// schedule, all results if `None`. Results without a score are always sent.
+    pub top_hits: Option<usize>,
+}
+
+impl ExecutionOptions {
+    /// Sets up rayon's global pool with the worker threads and placement of the
+    /// options, keeping rayon's default pool if neither is specified.
+    ///
+    /// The pool has to be set up before the first parallel stage of a run, e.g.
+    /// packing the input or the first pass, which would start the default pool.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the global pool has already been started.
+    pub fn build_global_pool(&self) -> Result<(), ThreadPoolBuildError> {
+        let placement = Placement::new(self.pinning);
+        if self.num_threads.is_none() && !placement.is_pinned() {
+            return Ok(());
+        }
+        let mut builder = ThreadPoolBuilder::new();
+        if let Some(n) = self.num_threads {
+            builder = builder.num_threads(n);
+        }
+        if placement.is_pinned() {
+            builder = builder.start_handler(move |index| placement.pin(index));
+        }
+        builder.build_global()
+    }
+}
+
+/// Aligns pairs with an engine, reusing scores from a result cache where available
+#[derive(Clone, Copy)]
+pub struct Scorer<'a, S: Score = i32> {
+    /// Scoring function for comparing sequence elements
+    pub matcher: &'a MatcherFn<S>,
+    /// Algorithm aligning the pairs
+    pub engine: &'a dyn AlignmentEngine<S>,
+    /// Whether pairs are aligned globally or locally
+    pub mode: AlignMode,
+    /// Penalties of the gaps of the alignments
+    pub gaps: GapPenalties,
+    /// Scores computed in previous runs with the same parameters
+    pub cache: Option<&'a ResultCache>,
+    /// Time after which an alignment is abandoned
+    pub timeout: Option<Duration>,
+    /// Phred qualities of the bases by sequence identifier, to down-weight
+    /// mismatches at low-quality bases
+    pub qualities: Option<&'a HashMap<String, Vec<u8>>>,
+    /// Sequences packed in memory by identifier, in place of their entries in
+    /// the input
+    pub packed: Option<&'a PackedInput>,
+    /// Whether aligned pairs are also checked for one sequence containing the
+    /// other
+    pub containment: bool,
+    /// How aligned pairs are also aligned locally for BLAST's tabular output
+    pub blast: Option<BlastParams>,
+    /// Scoring function of the alignments written as SAM records, if any
+    pub sam: Option<MatcherFn>,
+    /// Scoring function of the alignments written as PAF records, if any
+    pub paf: Option<MatcherFn>,
+    /// Whether the CIGAR strings of engines with a traceback are reported
+    pub cigar: bool,
+    /// Scores of the sequences aligned with themselves, to report the highest
+    /// score every aligned pair can reach
+    pub self_scores: Option<&'a HashMap<String, S>>,
+}
+
+#[cfg(test)]
+impl<'a, S: Score> Scorer<'a, S> {
+    /// Returns a scorer aligning pairs globally with the full engine and
+    /// `matcher`, without a cache, timeout or additional output, for tests to set
+    /// the other fields they need
+    pub fn for_test(matcher: &'a MatcherFn<S>) -> Self {
+        Self {
+            matcher,
+            engine: &crate::engine::Full,
+            mode: AlignMode::Global,
+            gaps: GapPenalties::default(),
+            cache: None,
+            timeout: None,
+            qualities: None,
+            packed: None,
+            containment: false,
+            blast: None,
+            sam: None,
+            paf: None,
+            cigar: false,
+            self_scores: None,
+        }
+    }
+}
+
+impl<S: Score> Scorer<'_, S> {
+    /// Returns the alignment of two sequences in the mode of the scorer, aligning
+    /// them only if the score is not cached.
+    ///
+    /// Returns `None` if the alignment took longer than the timeout.
+    pub fn score(&self, seq1: &str, seq2: &str) -> Option<PairAlignment<S>> {
+        // The cache only holds scores, not what engines with a traceback add to them
+        let Some(cache) = self.cache.filter(|_| !self.engine.capabilities().traceback) else {
+            return self.align(seq1, seq2);
+        };
+        let key = cache.key(seq1, seq2);
+        if let Some(score) = cache.get(&key) {
+            return Some(PairAlignment {
+                score,
+                summary: None,
+                cigar: None,
+            });
+        }
+        let alignment = self.align(seq1, seq2)?;
+        cache.insert(key, alignment.score);
+        Some(alignment)
+    }
+
+    /// Returns the alignment of two sequences like [`Scorer::score`], but
+    /// scores pairs of reads that both have qualities with
+    /// [`align_quality_weighted`] instead, bypassing the engine and the cache.
+    pub fn score_reads(
+        &self,
+        (id1, seq1): (&str, &str),
+        (id2, seq2): (&str, &str),
+    ) -> Option<PairAlignment<S>> {
+        // Sequences repaired after reading no longer line up with their qualities
+        let qualities = |id: &str, seq: &str| {
+            self.qualities?
+                .get(id)
+                .filter(|qualities| qualities.len() == seq.len())
+        };
+        let (Some(qual1), Some(qual2)) = (qualities(id1, seq1), qualities(id2, seq2)) else {
+            return self.score(seq1, seq2);
+        };
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let score = align_quality_weighted(
+            (seq1, qual1),
+            (seq2, qual2),
+            self.matcher,
+            self.mode,
+            self.gaps,
+            deadline,
+        )?;
+        Some(PairAlignment {
+            score,
+            summary: None,
+            cigar: None,
+        })
+    }
+
+    /// Returns the alignment of two packed sequences, decoding them in the kernel
+    /// of the engine where it can.
+    ///
+    /// Returns `None` if the alignment took longer than the timeout.
+    pub fn score_packed(&self, seq1: &PackedSeq, seq2: &PackedSeq) -> Option<PairAlignment<S>> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        self.engine
+            .align_packed(seq1, seq2, self.matcher, self.mode, self.gaps, deadline)
+    }
+
+    /// Returns `true` if pairs of packed sequences can be scored with
+    /// [`Scorer::score_packed`], which bypasses the cache and the qualities
+    pub fn scores_packed(&self) -> bool {
+        self.packed.is_some() && self.cache.is_none() && self.qualities.is_none()
+    }
+
+    /// Returns `true` if runs of pairs sharing a query can be scored side by side
+    /// with [`Scorer::score_lanes`], which bypasses the cache, the qualities and
+    /// the timeout
+    pub fn scores_lanes(&self) -> bool {
+        self.engine.capabilities().lanes
+            && self.cache.is_none()
+            && self.qualities.is_none()
+            && self.timeout.is_none()
+            && self.packed.is_none()
+    }
+
+    /// Returns the alignments of `query` with up to [`lanes::LANES`] short
+    /// subjects, computed side by side, or `None` if the engine has no kernel for
+    /// them
+    pub fn score_lanes(&self, query: &str, subjects: &[&str]) -> Option<Vec<PairAlignment<S>>> {
+        let scores =
+            self.engine
+                .align_lanes(query, subjects, self.matcher, self.mode, self.gaps)?;
+        Some(
+            scores
+                .into_iter()
+                .map(|score| PairAlignment {
+                    score,
+                    summary: None,
+                    cigar: None,
+                })
+                .collect(),
+        )
+    }
+
+    fn align(&self, seq1: &str, seq2: &str) -> Option<PairAlignment<S>> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        self.engine
+            .align(seq1, seq2, self.matcher, self.mode, self.gaps, deadline)
+    }
+}
+
+/// Optional observers that record what happens during a run
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Observers<'a> {
+    /// Records stage timings for `--profile`
+    pub profiler: Option<&'a Profiler>,
+    /// Counts scheduled and completed pairs for monitoring
+    pub metrics: Option<&'a Metrics>,
+    /// Measures the memory of building the pair list and of the alignment
+    pub memory: Option<&'a MemoryTracker>,
+}
+
+/// Performs pairwise alignments for the pairs of sequences in the input produced
+/// by `generator`, streaming results through a channel.
+///
+/// If the receiver of `sender` is dropped, e.g. because results can no longer be
+/// written, the remaining pairs are not processed.
+///
+/// Scores are taken from the result cache of `scorer` where available; all other
+/// pairs are aligned and their scores added to the cache.
+///
+/// Pairs are aligned on rayon's global pool, which callers set up beforehand with
+/// [`ExecutionOptions::build_global_pool`].
+///
+/// The whole run is recorded in an `align_all` tracing span; chunks of pairs processed
+/// by worker threads are recorded as `batch` spans (or `pair` spans for the dynamic
+/// schedule at trace level) nested under it.
+#[instrument(
+    name = "align_all",
+    skip_all,
+    fields(sequences = input.len(), schedule = ?options.schedule, pairs)
+)]
+pub fn align_all_streaming<S: Score>(
     input: &HashMap<String, String>,
-    matcher: &MatcherFn,
-    fraction: Option<f32>,
-    min_matches: usize,
-    sender: Sender<AlignmentResult>,
-    num_threads: Option<usize>,
+    scorer: Scorer<'_, S>,
+    filters: &FilterChain,
+    generator: &dyn PairGenerator,
+    sender: Sender<AlignmentResult<S>>,
+    options: &ExecutionOptions,
+    observers: Observers<'_>,
 ) {
-    // Set up thread pool if num_threads is specified
-    if let Some(n) = num_threads {
-        ThreadPoolBuilder::new()
-            .num_threads(n)
-            .build_global()
-            .expect("Failed to initialize thread pool");
-    }
-
-    // Use references to keys instead of cloning
-    let keys: Vec<&String> = input.keys().collect();
-
-    // Generate all unique pairs using references
-    let pairs: Vec<(&String, &String)> = keys
-        .iter()
-        .enumerate()
-        .flat_map(|(i, query_id)| {
-            keys[..=i]
-                .iter()
-                .map(move |subject_id| (*query_id, *subject_id))
-        })
-        .collect();
+    let Observers {
+        profiler,
+        metrics,
+        memory,
+    } = observers;
+    let placement = Placement::new(options.pinning);
+
+    // Node-local copies of the input for NUMA-aware placement (empty otherwise)
+    let replicas = placement.replicate(input);
+
+    // Per-query runs walk the queries and targets instead of listing the pairs
+    let sides = (options.schedule == Schedule::PerQuery)
+        .then(|| generator.sides(input))
+        .flatten();
+    if options.schedule == Schedule::PerQuery && sides.is_none() {
+        warn!("the per-query schedule needs a query and a target set, using the dynamic one");
+    }
+
+    // Pairs refer to the keys of the input instead of cloning them
+    let generate_pairs = || match sides {
+        Some(_) => Vec::new(),
+        None => generator.pairs(input),
+    };
+    if let Some(memory) = memory {
+        memory.begin("index");
+    }
+    let mut pairs = match profiler {
+        Some(profiler) => profiler.sequential("pairs", generate_pairs),
+        None => generate_pairs(),
+    };
+    if let Some(memory) = memory {
+        memory.begin("align");
+    }
+    let total = match &sides {
+        // A sequence in both sets is not aligned with itself
+        Some((queries, targets)) => queries
+            .iter()
+            .map(|query_id| targets.len() - usize::from(targets.binary_search(query_id).is_ok()))
+            .sum(),
+        None => pairs.len(),
+    };
 
     // Setup progress bar with total comparisons
-    let progress = setup_progress_bar(pairs.len() as u64);
+    let progress = setup_progress_bar(total as u64);
 
-    // Process alignments in parallel and send results through the channel
-    pairs
-        .par_iter()
-        .progress_with(progress)
-        .for_each(|(query_id, subject_id)| {
-            if query_id == subject_id {
-                return;
-            }
+    let run_span = Span::current();
+    run_span.record("pairs", total);
+    if let Some(metrics) = metrics {
+        metrics.add_total_pairs(total as u64);
+    }
+    info!("starting pairwise alignments");
 
-            let query_seq = &input[*query_id];
-            let subject_seq = &input[*subject_id];
-            let score = match fraction {
-                Some(fraction) => {
-                    if worth_aligning(query_seq, subject_seq, fraction, min_matches) {
-                        Some(align(query_seq, subject_seq, matcher))
-                    } else {
-                        None
-                    }
+    let stopped = AtomicBool::new(false);
+    let local_input = || match rayon::current_thread_index() {
+        Some(index) if !replicas.is_empty() => &replicas[placement.node_of(index)],
+        _ => input,
+    };
+    let packed = |id: &str| scorer.packed.and_then(|packed| packed.get(id));
+    let seq_len = |id: &str| packed(id).map_or_else(|| input[id].len(), PackedSeq::len);
+    // Pairs scored side by side come with their alignment
+    let align_pair = |(query_id, subject_id): &Pair, scored: Option<PairAlignment<S>>| {
+        if stopped.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        // Packed sequences are only unpacked for what needs their residues
+        let local_input = local_input();
+        let unpacked = |id: &String| match packed(id) {
+            Some(seq) => Cow::Owned(seq.unpack()),
+            None => Cow::Borrowed(local_input[id].as_str()),
+        };
+        let sequences = OnceCell::new();
+        let residues = || sequences.get_or_init(|| (unpacked(query_id), unpacked(subject_id)));
+        let (query_len, subject_len) = (seq_len(query_id), seq_len(subject_id));
+        // A panic while processing one pair is recorded as a failed pair instead of
+        // unwinding through the worker threads and ending the run
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+            let verdict = if filters.is_empty() {
+                Ok(())
+            } else {
+                profile::measure(profiler, Stage::Prefilter, || {
+                    let (query_seq, subject_seq) = residues();
+                    filters.check(query_seq, subject_seq)
+                })
+            };
+            if let Err((filter, reason)) = verdict {
+                debug!(query_id = %query_id, subject_id = %subject_id, filter, reason, "pair rejected by filter");
+                return (
+                    (None, PairStatus::Skipped),
+                    Some(format!("{}: {}", filter, reason)),
+                );
+            }
+            let score = || match (packed(query_id), packed(subject_id)) {
+                (Some(query_seq), Some(subject_seq)) if scorer.scores_packed() => {
+                    scorer.score_packed(query_seq, subject_seq)
+                }
+                _ => {
+                    let (query_seq, subject_seq) = residues();
+                    scorer.score_reads((query_id, query_seq), (subject_id, subject_seq))
                 }
-                None => Some(align(query_seq, subject_seq, matcher)),
             };
+            match scored.or_else(|| profile::measure(profiler, Stage::Align, score)) {
+                Some(alignment) => ((Some(alignment), PairStatus::Aligned), None),
+                None => ((None, PairStatus::Timeout), None),
+            }
+        }));
+        let ((alignment, status), error) = match outcome {
+            Ok(outcome) => outcome,
+            Err(payload) => ((None, PairStatus::Failed), Some(panic_message(&*payload))),
+        };
+        let containment = alignment.as_ref().filter(|_| scorer.containment).map(|_| {
+            profile::measure(profiler, Stage::Align, || {
+                let (query_seq, subject_seq) = residues();
+                Containment::measure(query_seq, subject_seq)
+            })
+        });
 
-            let result = AlignmentResult {
-                query_id: (*query_id).clone(), // Clone only when creating the result
-                subject_id: (*subject_id).clone(), // Clone only when creating the result
-                score,
-                seq1_len: query_seq.len(),
-                seq2_len: subject_seq.len(),
-            };
+        let blast = scorer.blast.filter(|_| alignment.is_some()).map(|params| {
+            profile::measure(profiler, Stage::Align, || {
+                let (query_seq, subject_seq) = residues();
+                params.hit(query_seq, subject_seq)
+            })
+        });
+
+        let sam = scorer.sam.filter(|_| alignment.is_some()).map(|matcher| {
+            profile::measure(profiler, Stage::Align, || {
+                let (query_seq, subject_seq) = residues();
+                SamAlignment::new(query_seq, subject_seq, &matcher, scorer.gaps)
+            })
+        });
 
-            sender.send(result).expect("Failed to send result");
+        let paf = scorer.paf.filter(|_| alignment.is_some()).map(|matcher| {
+            profile::measure(profiler, Stage::Align, || {
+                let (query_seq, subject_seq) = residues();
+                PafAlignment::new(query_seq, subject_seq, &matcher, scorer.gaps)
+            })
         });
+
+        let max_score = scorer
+            .self_scores
+            .filter(|_| alignment.is_some())
+            .map(|self_scores| {
+                let (query, subject) = (self_scores[*query_id], self_scores[*subject_id]);
+                match query_len.cmp(&subject_len) {
+                    std::cmp::Ordering::Less => query,
+                    std::cmp::Ordering::Greater => subject,
+                    std::cmp::Ordering::Equal if subject < query => subject,
+                    std::cmp::Ordering::Equal => query,
+                }
+            });
+
+        let result = AlignmentResult {
+            query_id: (*query_id).clone(), // Clone only when creating the result
+            subject_id: (*subject_id).clone(), // Clone only when creating the result
+            score: alignment.as_ref().map(|alignment| alignment.score),
+            status,
+            error,
+            identity: alignment
+                .as_ref()
+                .and_then(|alignment| alignment.summary)
+                .map(|summary| summary.identity()),
+            summary: alignment.as_ref().and_then(|alignment| alignment.summary),
+            cigar: alignment
+                .and_then(|alignment| alignment.cigar)
+                .filter(|_| scorer.cigar),
+            containment,
+            blast,
+            sam,
+            paf,
+            max_score,
+            seq1_len: query_len,
+            seq2_len: subject_len,
+        };
+        Some(result)
+    };
+    let send = |result: AlignmentResult<S>| {
+        if sender.send(result).is_err() {
+            if !stopped.swap(true, Ordering::Relaxed) {
+                warn!("results are no longer received, stopping pairwise alignments");
+            }
+            return;
+        }
+        if let Some(metrics) = metrics {
+            metrics.record_sent();
+        }
+    };
+    let process_pair = |pair: &Pair| {
+        if let Some(result) = align_pair(pair, None) {
+            send(result);
+        }
+    };
+
+    // Runs of pairs of short sequences sharing a query are scored side by side,
+    // in the order of the pairs
+    let lanes = filters.is_empty() && scorer.scores_lanes();
+    let process_lanes = |group: &[&Pair]| {
+        let Some(((query_id, _), _)) = group.split_first() else {
+            return;
+        };
+        let local_input = local_input();
+        let subjects: Vec<&str> = group
+            .iter()
+            .map(|(_, subject_id)| local_input[*subject_id].as_str())
+            .collect();
+        let scored = panic::catch_unwind(AssertUnwindSafe(|| {
+            profile::measure(profiler, Stage::Align, || {
+                scorer.score_lanes(&local_input[*query_id], &subjects)
+            })
+        }));
+        match scored {
+            Ok(Some(alignments)) => {
+                for (pair, alignment) in group.iter().zip(alignments) {
+                    if let Some(result) = align_pair(pair, Some(alignment)) {
+                        send(result);
+                    }
+                }
+            }
+            // Pairs are aligned one at a time, recording those that fail
+            _ => group.iter().for_each(|pair| process_pair(pair)),
+        }
+    };
+    let process_chunk = |chunk: &[Pair]| {
+        if !lanes {
+            chunk.iter().for_each(process_pair);
+            return;
+        }
+        let mut group = Vec::with_capacity(lanes::LANES);
+        for pair @ (query_id, subject_id) in chunk {
+            let len = seq_len(query_id).max(seq_len(subject_id));
+            if group
+                .first()
+                .is_some_and(|(first, _): &&Pair| first != query_id)
+            {
+                process_lanes(&group);
+                group.clear();
+            }
+            if lanes::supports(scorer.mode, len) {
+                group.push(pair);
+                if group.len() == lanes::LANES {
+                    process_lanes(&group);
+                    group.clear();
+                }
+            } else {
+                process_lanes(&group);
+                group.clear();
+                process_pair(pair);
+            }
+        }
+        process_lanes(&group);
+    };
+
+    // Process alignments in parallel and send results through the channel
+    let run_pairs = || {
+        if let Some((queries, targets)) = &sides {
+            let chunk_size = options
+                .chunk_size
+                .unwrap_or_else(|| targets.len().div_ceil(4 * rayon::current_num_threads()))
+                .max(1);
+            for query_id in queries {
+                if stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+                let _span = debug_span!(parent: &run_span, "query", query_id = %query_id).entered();
+                // Every chunk leaves only the best hits so far, so the results of a
+                // query never take more memory than a chunk per thread
+                let hits = options
+                    .top_hits
+                    .map(|limit| Mutex::new(TopHits::new(limit, false)));
+                targets.par_chunks(chunk_size).for_each(|chunk| {
+                    let _span =
+                        debug_span!(parent: &run_span, "batch", size = chunk.len()).entered();
+                    let results: Vec<_> = chunk
+                        .iter()
+                        .filter(|subject_id| *subject_id != query_id)
+                        .filter_map(|subject_id| align_pair(&(*query_id, *subject_id), None))
+                        .collect();
+                    progress.inc(results.len() as u64);
+                    let Some(hits) = &hits else {
+                        results.into_iter().for_each(send);
+                        return;
+                    };
+                    let mut hits = hits.lock().unwrap_or_else(|e| e.into_inner());
+                    for result in results {
+                        if result.score.is_none() {
+                            send(result);
+                        } else if let Some(dropped) = hits.keep(result)
+                            && let Some(metrics) = metrics
+                        {
+                            // Counted as if the writer had received it
+                            metrics.record_sent();
+                            metrics.record_result(dropped.score.map(Into::into));
+                        }
+                    }
+                });
+                if let Some(hits) = hits {
+                    let hits = hits.into_inner().unwrap_or_else(|e| e.into_inner());
+                    hits.into_results().into_iter().for_each(send);
+                }
+            }
+            progress.finish();
+        } else {
+            match options.schedule {
+                Schedule::Dynamic | Schedule::PerQuery if lanes => {
+                    // Chunks hold enough pairs of a query to fill the lanes
+                    let chunk_size = options.chunk_size.unwrap_or(1).max(4 * lanes::LANES);
+                    pairs.par_chunks(chunk_size).for_each(|chunk| {
+                        let _span =
+                            debug_span!(parent: &run_span, "batch", size = chunk.len()).entered();
+                        process_chunk(chunk);
+                        progress.inc(chunk.len() as u64);
+                    });
+                    progress.finish();
+                }
+                Schedule::Dynamic | Schedule::PerQuery => pairs
+                    .par_iter()
+                    .with_min_len(options.chunk_size.unwrap_or(1))
+                    .progress_with(progress)
+                    .for_each(|pair| {
+                        let _span = trace_span!(
+                            parent: &run_span,
+                            "pair",
+                            query_id = %pair.0,
+                            subject_id = %pair.1
+                        )
+                        .entered();
+                        process_pair(pair)
+                    }),
+                Schedule::Static => {
+                    let chunk_size = options
+                        .chunk_size
+                        .unwrap_or_else(|| pairs.len().div_ceil(rayon::current_num_threads()))
+                        .max(1);
+                    pairs.par_chunks(chunk_size).for_each(|chunk| {
+                        let _span =
+                            debug_span!(parent: &run_span, "batch", size = chunk.len()).entered();
+                        process_chunk(chunk);
+                        progress.inc(chunk.len() as u64);
+                    });
+                    progress.finish();
+                }
+                Schedule::Cost => {
+                    pairs.sort_by_cached_key(|(query_id, subject_id)| {
+                        Reverse(pair_cost(seq_len(query_id), seq_len(subject_id)))
+                    });
+
+                    // Every worker pulls the next chunk from the sorted list, so the longest
+                    // alignments start first regardless of how rayon would split the range
+                    let chunk_size = options.chunk_size.unwrap_or(1).max(1);
+                    let next = AtomicUsize::new(0);
+                    rayon::broadcast(|_| {
+                        loop {
+                            let start = next.fetch_add(chunk_size, Ordering::Relaxed);
+                            if start >= pairs.len() {
+                                break;
+                            }
+                            let chunk = &pairs[start..(start + chunk_size).min(pairs.len())];
+                            let _span =
+                                debug_span!(parent: &run_span, "batch", start, size = chunk.len())
+                                    .entered();
+                            process_chunk(chunk);
+                            progress.inc(chunk.len() as u64);
+                        }
+                    });
+                    progress.finish();
+                }
+            }
+        }
+    };
+    match profiler {
+        Some(profiler) => profiler.parallel(rayon::current_num_threads(), run_pairs),
+        None => run_pairs(),
+    }
+    info!("finished pairwise alignments");
+}
+
+/// Extracts the message of a panic payload
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "panic with a non-string payload".to_string()
+    }
+}
+
+/// Estimates the relative cost of aligning two sequences.
+///
+/// The cost is the number of cells in the dynamic programming matrix, which
+/// dominates the running time of a global alignment.
+pub fn pair_cost(len1: usize, len2: usize) -> u64 {
+    len1 as u64 * len2 as u64
+}
+
+/// Estimates the memory needed to align two sequences in bytes.
+///
+/// Both implementations keep a few score columns of the length of `seq2`; the
+/// full aligner additionally stores a two-byte traceback cell for every cell of
+/// the dynamic programming matrix.
+pub fn alignment_memory(len1: usize, len2: usize, linear_space: bool) -> u64 {
+    let columns = 6 * std::mem::size_of::<i32>() as u64 * (len2 as u64 + 1);
+    if linear_space {
+        columns
+    } else {
+        columns + 2 * pair_cost(len1 + 1, len2 + 1)
+    }
 }
 
 /// Determines if two sequences are worth aligning based on k-mer sharing.
@@ -128,18 +1119,869 @@ pub fn worth_aligning(seq1: &str, seq2: &str, fraction: f32, min_matches: usize)
     kmers.len() >= min_matches
 }
 
-/// Performs global alignment between two sequences and returns the alignment score.
+/// Aligns every query sequence against every reference sequence in parallel.
+///
+/// Unlike [`align_all_streaming`] this collects all results in memory, for the
+/// small query batches aligned against a reference set that stays loaded.
+///
+/// # Arguments
+///
+/// * `queries` - Map of query IDs to sequences
+/// * `reference` - Map of reference IDs to sequences
+/// * `matcher` - Scoring function for comparing sequence elements
+/// * `filters` - Filters a pair has to pass to be aligned
+///
+/// # Returns
+///
+/// One result per pair with its score and identity, with pairs rejected by a
+/// filter marked as skipped
+pub fn align_against(
+    queries: &HashMap<String, String>,
+    reference: &HashMap<String, String>,
+    matcher: &MatcherFn,
+    filters: &FilterChain,
+) -> Vec<AlignmentResult> {
+    align_against_while(queries, reference, matcher, filters, &|| true)
+}
+
+/// Aligns queries against a reference set like [`align_against`], calling
+/// `proceed` after every pair and leaving out the pairs not yet started once it
+/// has returned `false`.
+pub fn align_against_while(
+    queries: &HashMap<String, String>,
+    reference: &HashMap<String, String>,
+    matcher: &MatcherFn,
+    filters: &FilterChain,
+    proceed: &(dyn Fn() -> bool + Sync),
+) -> Vec<AlignmentResult> {
+    let pairs: Vec<(&String, &String)> = queries
+        .keys()
+        .flat_map(|query_id| {
+            reference
+                .keys()
+                .map(move |subject_id| (query_id, subject_id))
+        })
+        .collect();
+
+    let stopped = AtomicBool::new(false);
+    pairs
+        .par_iter()
+        .filter_map(|(query_id, subject_id)| {
+            if stopped.load(Ordering::Relaxed) {
+                return None;
+            }
+            let query_seq = &queries[*query_id];
+            let subject_seq = &reference[*subject_id];
+            let verdict = filters.check(query_seq, subject_seq);
+            let passes = verdict.is_ok();
+            let aligned = passes.then(|| {
+                align_with_identity(
+                    query_seq,
+                    subject_seq,
+                    matcher,
+                    AlignMode::Global,
+                    GapPenalties::default(),
+                )
+            });
+            let result = AlignmentResult {
+                query_id: (*query_id).clone(),
+                subject_id: (*subject_id).clone(),
+                score: aligned.map(|(score, _)| score),
+                status: if passes {
+                    PairStatus::Aligned
+                } else {
+                    PairStatus::Skipped
+                },
+                error: verdict
+                    .err()
+                    .map(|(filter, reason)| format!("{}: {}", filter, reason)),
+                identity: aligned.map(|(_, identity)| identity),
+                summary: None,
+                containment: None,
+                blast: None,
+                sam: None,
+                paf: None,
+                cigar: None,
+                max_score: None,
+                seq1_len: query_seq.len(),
+                seq2_len: subject_seq.len(),
+            };
+            if !proceed() {
+                stopped.store(true, Ordering::Relaxed);
+            }
+            Some(result)
+        })
+        .collect()
+}
+
+/// Performs alignment between two sequences and returns the alignment score.
 ///
 /// # Arguments
 ///
 /// * `seq1` - First sequence as a string
 /// * `seq2` - Second sequence as a string
 /// * `matcher` - Scoring function for comparing sequence elements
+/// * `mode` - Whether the sequences are aligned globally or locally
+/// * `gaps` - Penalties of opening and extending gaps
 ///
 /// # Returns
 ///
 /// The alignment score as an integer
-pub fn align(seq1: &str, seq2: &str, matcher: &MatcherFn) -> i32 {
-    let mut aligner = Aligner::with_capacity(seq1.len(), seq2.len(), -10, -1, matcher);
-    aligner.global(seq1.as_bytes(), seq2.as_bytes()).score
+pub fn align(
+    seq1: &str,
+    seq2: &str,
+    matcher: &MatcherFn,
+    mode: AlignMode,
+    gaps: GapPenalties,
+) -> i32 {
+    alignment(seq1, seq2, matcher, mode, gaps).score
+}
+
+/// Aligns two sequences in `mode` with traceback
+pub fn alignment(
+    seq1: &str,
+    seq2: &str,
+    matcher: &MatcherFn,
+    mode: AlignMode,
+    gaps: GapPenalties,
+) -> Alignment {
+    let (x, y) = (seq1.as_bytes(), seq2.as_bytes());
+    let aligned = |clips, align: fn(&mut Aligner<MatcherFn>, &[u8], &[u8]) -> Alignment| {
+        workspace::with_aligner(x.len(), y.len(), *matcher, gaps, clips, |aligner| {
+            align(aligner, x, y)
+        })
+    };
+    // Clipped ends of the modes with free ends, as (x prefix, x suffix, y prefix,
+    // y suffix). Clipping both prefixes or both suffixes would let an alignment
+    // start or end anywhere like a local one, so an overlap is the best of the
+    // alignments ending in each pair of a row and a column of the matrix.
+    let clips = match mode {
+        AlignMode::Global => return aligned(NO_CLIPS, Aligner::global),
+        AlignMode::Local => return aligned(NO_CLIPS, Aligner::local),
+        AlignMode::Semiglobal => {
+            let (free_x, free_y) = mode.free_ends(x.len(), y.len());
+            vec![[free_x, free_x, free_y, free_y]]
+        }
+        AlignMode::Overlap => vec![
+            [true, true, false, false],
+            [false, false, true, true],
+            [true, false, false, true],
+            [false, true, true, false],
+        ],
+    };
+    // Free ends are clipped at no cost, the others are never clipped
+    let clip = |free: bool| if free { 0 } else { MIN_SCORE };
+    clips
+        .into_iter()
+        .map(|ends| aligned(ends.map(clip), Aligner::custom))
+        .max_by_key(|alignment| alignment.score)
+        .expect("modes with free ends clip some")
+}
+
+/// Computes the score of a sequence aligned with itself, the sum of the scores of
+/// its residues against themselves.
+///
+/// No alignment of the sequence with another one scores higher, unless residues
+/// score higher against others than against themselves.
+pub fn self_score<S: Score>(seq: &str, matcher: &MatcherFn<S>) -> S {
+    seq.bytes().fold(S::from_penalty(0), |score, residue| {
+        score + matcher(residue, residue)
+    })
+}
+
+/// Column counts of an alignment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Summary {
+    /// Columns with identical residues
+    pub matches: usize,
+    /// Columns with identical residues or residues with a positive score
+    pub positives: usize,
+    /// Columns with a gap in one of the sequences
+    pub gaps: usize,
+    /// All columns of the alignment
+    pub columns: usize,
+}
+
+impl Summary {
+    /// Counts the columns of an alignment of `seq1` with `seq2`, scoring
+    /// substitutions with `matcher` and leaving out the clipped ends of local
+    /// alignments
+    pub fn of(alignment: &Alignment, seq1: &[u8], seq2: &[u8], matcher: &MatcherFn) -> Self {
+        let mut summary = Self {
+            matches: 0,
+            positives: 0,
+            gaps: 0,
+            columns: 0,
+        };
+        let (mut x, mut y) = (alignment.xstart, alignment.ystart);
+        for op in &alignment.operations {
+            match op {
+                AlignmentOperation::Match => {
+                    summary.matches += 1;
+                    summary.positives += 1;
+                }
+                AlignmentOperation::Subst => {
+                    summary.positives += usize::from(matcher(seq1[x], seq2[y]) > 0);
+                }
+                AlignmentOperation::Ins | AlignmentOperation::Del => summary.gaps += 1,
+                // Covered by the start positions, which all alignments have
+                AlignmentOperation::Xclip(_) | AlignmentOperation::Yclip(_) => continue,
+            }
+            summary.columns += 1;
+            x += usize::from(*op != AlignmentOperation::Del);
+            y += usize::from(*op != AlignmentOperation::Ins);
+        }
+        summary
+    }
+
+    /// Returns the fraction of columns with identical residues
+    pub fn identity(&self) -> f64 {
+        self.fraction(self.matches)
+    }
+
+    /// Returns the fraction of columns with identical residues or residues with a
+    /// positive score
+    pub fn similarity(&self) -> f64 {
+        self.fraction(self.positives)
+    }
+
+    fn fraction(&self, count: usize) -> f64 {
+        if self.columns == 0 {
+            0.0
+        } else {
+            count as f64 / self.columns as f64
+        }
+    }
+}
+
+/// Returns the CIGAR string of an alignment of a query (`x`) with a subject (`y`):
+/// `=` and `X` for identical and different residues, `I` and `D` for residues of
+/// only the query or only the subject, and `S` and `N` for unaligned residues at
+/// the ends of the query and of the subject, so that the operations cover both
+/// sequences completely
+pub fn cigar(alignment: &Alignment) -> String {
+    let mut ops: Vec<(char, usize)> = Vec::new();
+    let mut push = |kind: char, len: usize| match ops.last_mut() {
+        _ if len == 0 => {}
+        Some((last, count)) if *last == kind => *count += len,
+        _ => ops.push((kind, len)),
+    };
+    push('S', alignment.xstart);
+    push('N', alignment.ystart);
+    for op in &alignment.operations {
+        match op {
+            AlignmentOperation::Match => push('=', 1),
+            AlignmentOperation::Subst => push('X', 1),
+            AlignmentOperation::Ins => push('I', 1),
+            AlignmentOperation::Del => push('D', 1),
+            // Covered by the start and end positions, which all alignments have
+            AlignmentOperation::Xclip(_) | AlignmentOperation::Yclip(_) => {}
+        }
+    }
+    push('S', alignment.xlen - alignment.xend);
+    push('N', alignment.ylen - alignment.yend);
+    ops.iter()
+        .map(|(kind, len)| format!("{}{}", len, kind))
+        .collect()
+}
+
+/// Computes the alignment score of two sequences in `mode` together with the
+/// fraction of alignment columns with identical residues.
+///
+/// The traceback is computed by [`align`] as well, so the identity comes at no
+/// extra cost.
+pub fn align_with_identity(
+    seq1: &str,
+    seq2: &str,
+    matcher: &MatcherFn,
+    mode: AlignMode,
+    gaps: GapPenalties,
+) -> (i32, f64) {
+    let alignment = alignment(seq1, seq2, matcher, mode, gaps);
+    let summary = Summary::of(&alignment, seq1.as_bytes(), seq2.as_bytes(), matcher);
+    (alignment.score, summary.identity())
+}
+
+/// Computes the alignment score of two sequences in linear space.
+///
+/// This gives the same score as [`align`] with affine gap penalties (Gotoh's
+/// algorithm), but only keeps one row of the dynamic programming matrices instead
+/// of the full traceback, so very long sequences can be scored with little memory.
+/// Unlike [`align`] it supports any [`Score`] type.
+///
+/// # Arguments
+///
+/// * `seq1` - First sequence as a string
+/// * `seq2` - Second sequence as a string
+/// * `matcher` - Scoring function for comparing sequence elements
+/// * `mode` - Whether the sequences are aligned globally or locally
+/// * `gaps` - Penalties of opening and extending gaps
+///
+/// # Returns
+///
+/// The alignment score as an integer
+pub fn align_linear<S: Score>(
+    seq1: &str,
+    seq2: &str,
+    matcher: &MatcherFn<S>,
+    mode: AlignMode,
+    gaps: GapPenalties,
+) -> S {
+    align_linear_until(seq1, seq2, matcher, mode, gaps, None)
+        .expect("alignment without deadline completes")
+}
+
+/// Computes the alignment score like [`align_linear`], giving up once
+/// `deadline` has passed.
+///
+/// The deadline is checked after every row of the dynamic programming matrix.
+///
+/// # Returns
+///
+/// The alignment score, or `None` if the deadline passed before it was computed
+pub fn align_linear_until<S: Score>(
+    seq1: &str,
+    seq2: &str,
+    matcher: &MatcherFn<S>,
+    mode: AlignMode,
+    gaps: GapPenalties,
+    deadline: Option<Instant>,
+) -> Option<S> {
+    let (x, y) = (seq1.as_bytes(), seq2.as_bytes());
+    align_linear_with(
+        (x.len(), y.len()),
+        |i, j| matcher(x[i], y[j]),
+        mode,
+        gaps,
+        deadline,
+    )
+}
+
+/// Computes the alignment score of two packed sequences like
+/// [`align_linear_until`], decoding every residue as the dynamic programming
+/// reaches it rather than unpacking the sequences.
+pub fn align_packed_until<S: Score>(
+    seq1: &PackedSeq,
+    seq2: &PackedSeq,
+    matcher: &MatcherFn<S>,
+    mode: AlignMode,
+    gaps: GapPenalties,
+    deadline: Option<Instant>,
+) -> Option<S> {
+    align_linear_with(
+        (seq1.len(), seq2.len()),
+        |i, j| matcher(seq1.get(i), seq2.get(j)),
+        mode,
+        gaps,
+        deadline,
+    )
+}
+
+/// Computes the alignment score like [`align_linear_until`], weighting
+/// the score of every mismatch by the probability that both bases were called
+/// correctly according to their Phred qualities.
+///
+/// Matches and gaps keep their scores, so a mismatch at a base of quality 10 (a
+/// one in ten chance of a sequencing error) against a base of quality 40 costs
+/// 90% of its usual penalty. The qualities must be as long as their sequences.
+pub fn align_quality_weighted<S: Score>(
+    (seq1, qual1): (&str, &[u8]),
+    (seq2, qual2): (&str, &[u8]),
+    matcher: &MatcherFn<S>,
+    mode: AlignMode,
+    gaps: GapPenalties,
+    deadline: Option<Instant>,
+) -> Option<S> {
+    let confidence = |qualities: &[u8]| -> Vec<f64> {
+        qualities
+            .iter()
+            .map(|&quality| 1.0 - 10f64.powf(-f64::from(quality) / 10.0))
+            .collect()
+    };
+    let (conf1, conf2) = (confidence(qual1), confidence(qual2));
+    let (x, y) = (seq1.as_bytes(), seq2.as_bytes());
+    let score = |i: usize, j: usize| {
+        let score = matcher(x[i], y[j]);
+        if x[i] == y[j] {
+            score
+        } else {
+            score.scale(conf1[i] * conf2[j])
+        }
+    };
+    align_linear_with((x.len(), y.len()), score, mode, gaps, deadline)
+}
+
+/// Computes the global alignment score of two sequences restricted to a diagonal
+/// band, like [`align_linear_until`] otherwise.
+///
+/// The band holds the cells between the diagonals through both corners of the
+/// matrix, widened by `width` diagonals on either side, so sequences of similar
+/// length cost time proportional to their length times the band. The score equals
+/// the unbanded one whenever a best alignment stays within the band and is lower
+/// otherwise.
+///
+/// # Returns
+///
+/// The alignment score, or `None` if the deadline passed before it was computed
+pub fn align_banded<S: Score>(
+    seq1: &str,
+    seq2: &str,
+    matcher: &MatcherFn<S>,
+    gaps: GapPenalties,
+    width: usize,
+    deadline: Option<Instant>,
+) -> Option<S> {
+    let (x, y) = (seq1.as_bytes(), seq2.as_bytes());
+    let max = |a: S, b: S| if b > a { b } else { a };
+    let open = S::from_penalty(gaps.open);
+    let extend = S::from_penalty(gaps.extend);
+    let gap = |len: usize| S::from_penalty(gaps.open + gaps.extend * len as i32);
+    let neg_inf = S::from_penalty(i32::MIN / 2);
+    // Cells (i, j) of the band have i - below <= j <= i + above
+    let below = x.len().saturating_sub(y.len()) + width;
+    let above = y.len().saturating_sub(x.len()) + width;
+
+    // Rows as in `align_linear_with`, with the cells outside the band at -inf
+    let mut h: Vec<S> = (0..=y.len())
+        .map(|j| match j {
+            0 => S::from_penalty(0),
+            j if j <= above => gap(j),
+            _ => neg_inf,
+        })
+        .collect();
+    let mut f = vec![neg_inf; y.len() + 1];
+    for (i, &residue) in x.iter().enumerate() {
+        let (low, high) = ((i + 1).saturating_sub(below), (i + 1 + above).min(y.len()));
+        let first = low.max(1);
+        let mut diagonal = h[first - 1];
+        h[first - 1] = if low == 0 { gap(i + 1) } else { neg_inf };
+        f[first - 1] = neg_inf;
+        let mut e = neg_inf;
+        for j in first..=high {
+            f[j] = max(f[j] + extend, h[j] + open + extend);
+            e = max(e + extend, h[j - 1] + open + extend);
+            let best = max(max(diagonal + matcher(residue, y[j - 1]), e), f[j]);
+            diagonal = h[j];
+            h[j] = best;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return None;
+        }
+    }
+    Some(h[y.len()])
+}
+
+/// Gotoh's algorithm in linear space for sequences x and y of `x_len` and `y_len`
+/// residues, scoring `x[i]` against `y[j]` with `score(i, j)`. Local alignments restart at 0 wherever the score would drop
+/// below it and end at the best cell. Alignments with free ends of a sequence
+/// start anywhere in the first row or column of it and end at the best cell of
+/// the last one.
+fn align_linear_with<S: Score>(
+    (x_len, y_len): (usize, usize),
+    score: impl Fn(usize, usize) -> S,
+    mode: AlignMode,
+    gaps: GapPenalties,
+    deadline: Option<Instant>,
+) -> Option<S> {
+    let local = mode == AlignMode::Local;
+    let (free_x, free_y) = mode.free_ends(x_len, y_len);
+    let max = |a: S, b: S| if b > a { b } else { a };
+    let open = S::from_penalty(gaps.open);
+    let extend = S::from_penalty(gaps.extend);
+    let zero = S::from_penalty(0);
+    // Score of a leading gap of `len` residues of a sequence with or without free ends
+    let gap = |len: usize, free: bool| {
+        if local || free {
+            zero
+        } else {
+            S::from_penalty(gaps.open + gaps.extend * len as i32)
+        }
+    };
+    // Far below any reachable score, but safe to add penalties to
+    let neg_inf = S::from_penalty(i32::MIN / 2);
+
+    // Best score of x[..i] vs y[..j] (h) and of those ending in a gap in y (f),
+    // for the previous row i while it is being overwritten with row i + 1
+    let mut h: Vec<S> = (0..=y_len)
+        .map(|j| if j == 0 { zero } else { gap(j, free_y) })
+        .collect();
+    let mut f = vec![neg_inf; y_len + 1];
+    // Best score of any cell, where local alignments end
+    let mut best_local = zero;
+    // Best score of the last column, where alignments with free ends of x end
+    let mut best_last_column = h[y_len];
+
+    for i in 0..x_len {
+        let mut diagonal = h[0];
+        h[0] = gap(i + 1, free_x);
+        // Best score of the current row ending in a gap in x
+        let mut e = neg_inf;
+        for j in 1..=y_len {
+            f[j] = max(f[j] + extend, h[j] + open + extend);
+            e = max(e + extend, h[j - 1] + open + extend);
+            let mut best = max(max(diagonal + score(i, j - 1), e), f[j]);
+            if local {
+                best = max(best, zero);
+                best_local = max(best_local, best);
+            }
+            diagonal = h[j];
+            h[j] = best;
+        }
+        best_last_column = max(best_last_column, h[y_len]);
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return None;
+        }
+    }
+    if local {
+        return Some(best_local);
+    }
+    let mut end = h[y_len];
+    if free_x {
+        end = max(end, best_last_column);
+    }
+    if free_y {
+        // The best cell of the last row, where alignments with free ends of y end
+        end = h.into_iter().fold(end, max);
+    }
+    Some(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bio::scores::blosum62;
+
+    #[test]
+    fn test_align_linear_matches_full_alignment() {
+        let identity: MatcherFn = |a, b| if a == b { 1 } else { 0 };
+        let pairs = [
+            ("MAVMTPRRERSSLLSRALRF", "MANPYERGPNPTDALLEARSGPF"),
+            ("ACGTACGTTTGA", "ACGAAAAACGTT"),
+            ("A", "WWWWWWW"),
+            ("MKTAYIAKQRQISFVKSHF", "AYIAKQ"),
+            ("", "ACGT"),
+        ];
+        let cheap_gaps = GapPenalties::from_costs(5, 2).unwrap();
+        for (seq1, seq2) in pairs {
+            for matcher in [identity, blosum62] {
+                for mode in [
+                    AlignMode::Global,
+                    AlignMode::Local,
+                    AlignMode::Semiglobal,
+                    AlignMode::Overlap,
+                ] {
+                    for gaps in [GapPenalties::default(), cheap_gaps] {
+                        assert_eq!(
+                            align_linear(seq1, seq2, &matcher, mode, gaps),
+                            align(seq1, seq2, &matcher, mode, gaps),
+                            "{} vs {} ({:?}, {:?})",
+                            seq1,
+                            seq2,
+                            mode,
+                            gaps
+                        );
+                    }
+                }
+            }
+        }
+        // A single gap of one residue costs 7 instead of 11
+        let gaps = [GapPenalties::default(), cheap_gaps]
+            .map(|gaps| align("MAVMTKL", "MAVMKL", &identity, AlignMode::Global, gaps));
+        assert_eq!(gaps, [6 - 11, 6 - 7]);
+        assert!(GapPenalties::from_costs(0, 0).is_err());
+        assert!(GapPenalties::from_costs(1001, 1).is_err());
+        assert_eq!(
+            GapPenalties::default().max_safe_len(MAX_RESIDUE_SCORE),
+            MAX_SAFE_LEN
+        );
+        // Matrix entries of 1000 leave room for sequences 91 times shorter
+        assert_eq!(
+            GapPenalties::default().max_safe_len(1000),
+            (i32::MAX as usize - 10) / 2000
+        );
+        assert_eq!(GapPenalties::default().max_safe_len(i32::MAX), 0);
+
+        // A fragment scores like its matches within the full-length sequence
+        let (full, fragment) = ("MKTAYIAKQRQISFVKSHF", "AYIAKQ");
+        for (seq1, seq2) in [(full, fragment), (fragment, full)] {
+            assert_eq!(
+                align(
+                    seq1,
+                    seq2,
+                    &identity,
+                    AlignMode::Semiglobal,
+                    GapPenalties::default()
+                ),
+                6
+            );
+        }
+        // Sequences truncated at different ends score like their overlap
+        let (head, tail) = ("MKTAYIAKQRQ", "AKQRQISFVKS");
+        for (seq1, seq2) in [(head, tail), (tail, head)] {
+            assert_eq!(
+                align(
+                    seq1,
+                    seq2,
+                    &identity,
+                    AlignMode::Overlap,
+                    GapPenalties::default()
+                ),
+                5
+            );
+        }
+    }
+
+    #[test]
+    fn test_align_banded() {
+        let matcher: MatcherFn = blosum62;
+        let pairs = [
+            ("MKTAYIAKQRQISFVKSHFSRQ", "MKTAYIAKQRQISFVKSHFSRE"),
+            ("MAVMTPRRERSSLLSRALRF", "MANPYERGPNPTDALLEARSGPF"),
+            ("MKTAYIAKQRQISFVKSHF", "AYIAKQ"),
+            ("", "ACGT"),
+        ];
+        for (seq1, seq2) in pairs {
+            let full = align_linear(
+                seq1,
+                seq2,
+                &matcher,
+                AlignMode::Global,
+                GapPenalties::default(),
+            );
+            let wide = seq1.len().max(seq2.len());
+            for (a, b) in [(seq1, seq2), (seq2, seq1)] {
+                assert_eq!(
+                    align_banded(a, b, &matcher, GapPenalties::default(), wide, None),
+                    Some(full)
+                );
+                assert!(
+                    align_banded(a, b, &matcher, GapPenalties::default(), 0, None).unwrap() <= full
+                );
+            }
+        }
+        // Similar sequences of equal length stay on the main diagonal
+        let (seq1, seq2) = pairs[0];
+        assert_eq!(
+            align_banded(seq1, seq2, &matcher, GapPenalties::default(), 0, None),
+            Some(align_linear(
+                seq1,
+                seq2,
+                &matcher,
+                AlignMode::Global,
+                GapPenalties::default()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_self_score() {
+        assert_eq!(self_score("MAVW", &(blosum62 as MatcherFn)), 5 + 4 + 4 + 11);
+        assert_eq!(self_score("", &(blosum62 as MatcherFn)), 0);
+    }
+
+    #[test]
+    fn test_cigar() {
+        let identity: MatcherFn = |a, b| if a == b { 1 } else { -1 };
+        let (seq1, seq2) = ("WWWWMAVMTKK", "CCMAVMTPPPP");
+        let gaps = GapPenalties::default();
+        let local = alignment(seq1, seq2, &identity, AlignMode::Local, gaps);
+        assert_eq!(cigar(&local), "4S2N5=2S4N");
+        // Clips of the modes with free ends are reported once
+        let overlap = alignment(seq1, seq2, &identity, AlignMode::Overlap, gaps);
+        assert_eq!(cigar(&overlap), "2S2X5=2X2N");
+        let global = alignment("MAVMT", "MAVWT", &identity, AlignMode::Global, gaps);
+        assert_eq!(cigar(&global), "3=1X1=");
+    }
+
+    #[test]
+    fn test_quality_weighted_mismatch() {
+        let identity: MatcherFn = |a, b| if a == b { 1 } else { -1 };
+        let (seq1, seq2) = ("ACGTACGT", "ACGAACGT");
+        let high = [40; 8];
+        let mut low = high;
+        low[3] = 2;
+        assert_eq!(
+            align_quality_weighted(
+                (seq1, &high),
+                (seq2, &high),
+                &identity,
+                AlignMode::Global,
+                GapPenalties::default(),
+                None
+            ),
+            Some(6)
+        );
+        // A 63% chance of a sequencing error takes the penalty of the mismatch away
+        assert_eq!(
+            align_quality_weighted(
+                (seq1, &high),
+                (seq2, &low),
+                &identity,
+                AlignMode::Global,
+                GapPenalties::default(),
+                None
+            ),
+            Some(7)
+        );
+    }
+
+    #[test]
+    fn test_align_linear_until_deadline() {
+        let seq = "ACGT".repeat(50);
+        let identity: MatcherFn = |a, b| if a == b { 1 } else { 0 };
+        let passed = Instant::now();
+        assert_eq!(
+            align_linear_until(
+                &seq,
+                &seq,
+                &identity,
+                AlignMode::Global,
+                GapPenalties::default(),
+                Some(passed)
+            ),
+            None
+        );
+        assert_eq!(
+            align_linear_until(
+                &seq,
+                &seq,
+                &identity,
+                AlignMode::Global,
+                GapPenalties::default(),
+                None
+            ),
+            Some(200)
+        );
+    }
+
+    #[test]
+    fn test_panicking_pair_is_recorded_as_failed() {
+        let input = HashMap::from([
+            ("a".to_string(), "ACGT".to_string()),
+            ("b".to_string(), "WWWW".to_string()),
+            ("c".to_string(), "ACGA".to_string()),
+        ]);
+        // Panics whenever a W is compared, i.e. for every pair involving "b"
+        let matcher: MatcherFn = |a, b| {
+            assert!(a != b'W' && b != b'W', "unexpected residue");
+            i32::from(a == b)
+        };
+        let scorer = Scorer::for_test(&matcher);
+        let (tx, rx) = std::sync::mpsc::channel();
+        align_all_streaming(
+            &input,
+            scorer,
+            &FilterChain::default(),
+            &crate::pairs::Triangle,
+            tx,
+            &ExecutionOptions::default(),
+            Observers::default(),
+        );
+
+        let results: Vec<AlignmentResult> = rx.iter().collect();
+        assert_eq!(results.len(), 3);
+        for result in results {
+            if result.query_id == "b" || result.subject_id == "b" {
+                assert_eq!(result.status, PairStatus::Failed);
+                assert_eq!(result.error.as_deref(), Some("unexpected residue"));
+            } else {
+                assert_eq!(result.status, PairStatus::Aligned);
+                assert_eq!(result.score, Some(3));
+            }
+        }
+    }
+
+    #[test]
+    fn test_per_query_schedule_keeps_best_hits() {
+        let input: HashMap<String, String> = [
+            ("q", "MKTAYIAKQR"),
+            ("t1", "MKTAYIAKQR"),
+            ("t2", "MKTAYIAKQE"),
+            ("t3", "WWGPNDCHYE"),
+            ("t4", "MKTAWWAKQR"),
+        ]
+        .into_iter()
+        .map(|(id, seq)| (id.to_string(), seq.to_string()))
+        .collect();
+        let generator = crate::pairs::CrossProduct {
+            queries: ["q".to_string()].into(),
+            targets: ["t1", "t2", "t3", "t4", "q"].map(String::from).into(),
+        };
+        let matcher = crate::ScoringType::Blosum62.matcher();
+        let scorer = Scorer::for_test(&matcher);
+        let run = |top_hits| {
+            let (tx, rx) = std::sync::mpsc::channel();
+            let options = ExecutionOptions {
+                schedule: Schedule::PerQuery,
+                chunk_size: Some(1),
+                top_hits,
+                ..ExecutionOptions::default()
+            };
+            let observers = Observers::default();
+            align_all_streaming(
+                &input,
+                scorer,
+                &FilterChain::default(),
+                &generator,
+                tx,
+                &options,
+                observers,
+            );
+            let mut subjects: Vec<String> = rx.iter().map(|result| result.subject_id).collect();
+            subjects.sort();
+            subjects
+        };
+        assert_eq!(run(None), ["t1", "t2", "t3", "t4"]);
+        assert_eq!(run(Some(2)), ["t1", "t2"]);
+    }
+
+    #[test]
+    fn test_threads_with_packed_sequences() {
+        let mut input: HashMap<String, String> =
+            [("a", "MKTAYIAK"), ("b", "MKTAYIAR"), ("c", "MKTW")]
+                .into_iter()
+                .map(|(id, seq)| (id.to_string(), seq.to_string()))
+                .collect();
+        // Packing is the first parallel stage and starts the global pool
+        let packed = crate::packed::PackedInput::pack(&mut input);
+        let options = ExecutionOptions {
+            num_threads: Some(2),
+            ..ExecutionOptions::default()
+        };
+        assert!(options.build_global_pool().is_err());
+
+        let matcher = crate::ScoringType::Identity.matcher();
+        let scorer = Scorer {
+            packed: Some(&packed),
+            ..Scorer::for_test(&matcher)
+        };
+        let (tx, rx) = std::sync::mpsc::channel();
+        align_all_streaming(
+            &input,
+            scorer,
+            &FilterChain::default(),
+            &crate::pairs::Triangle,
+            tx,
+            &options,
+            Observers::default(),
+        );
+        let mut scores: Vec<(String, String, Option<i32>)> = rx
+            .iter()
+            .map(|result| {
+                let mut ids = [result.query_id, result.subject_id];
+                ids.sort();
+                let [query_id, subject_id] = ids;
+                (query_id, subject_id, result.score)
+            })
+            .collect();
+        scores.sort();
+        assert_eq!(
+            scores,
+            [
+                ("a".to_string(), "b".to_string(), Some(7)),
+                ("a".to_string(), "c".to_string(), Some(-11)),
+                ("b".to_string(), "c".to_string(), Some(-11)),
+            ]
+        );
+    }
 }
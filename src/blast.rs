@@ -0,0 +1,162 @@
+//! BLAST tabular (outfmt 6) statistics of aligned pairs.
+//!
+//! Many pipelines parse the 12 columns of BLAST's tabular output. To act as a
+//! drop-in replacement, `--output-format blast6` aligns every aligned pair once
+//! more locally with traceback and writes the columns BLAST would: identity,
+//! length, mismatches and gap openings of the local alignment, its coordinates,
+//! and an e-value and bit score. E-values and bit scores follow the
+//! Karlin-Altschul statistics of gapped BLOSUM62 alignments with the gap penalties
+//! of this tool, with the summed length of the target sequences as database size.
+//! Other scoring schemes have no such statistics, so they cannot be written as
+//! BLAST output.
+
+use bio::alignment::AlignmentOperation;
+
+use crate::align::{GapPenalties, MatcherFn};
+use crate::workspace::{self, NO_CLIPS};
+
+/// Karlin-Altschul parameters of gapped BLOSUM62 alignments with a gap of length
+/// k scoring -(10 + k), as tabulated by NCBI BLAST
+const BLOSUM62_LAMBDA: f64 = 0.243;
+const BLOSUM62_K: f64 = 0.024;
+
+/// Karlin-Altschul parameters of a scoring scheme
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Statistics {
+    /// Scale of the scores
+    pub lambda: f64,
+    /// Search space correction
+    pub k: f64,
+}
+
+impl Statistics {
+    /// Statistics of gapped BLOSUM62 alignments with the gap penalties of this tool
+    pub const BLOSUM62: Self = Self {
+        lambda: BLOSUM62_LAMBDA,
+        k: BLOSUM62_K,
+    };
+}
+
+/// How the BLAST columns of the aligned pairs are computed
+#[derive(Clone, Copy)]
+pub struct BlastParams {
+    /// Scoring function of the local alignments
+    pub matcher: MatcherFn,
+    /// Penalties of the gaps of the local alignments
+    pub gaps: GapPenalties,
+    /// Statistics of the scoring function
+    pub statistics: Statistics,
+    /// Summed length of the target sequences
+    pub database_len: usize,
+}
+
+/// The BLAST tabular columns of a pair besides its identifiers
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BlastHit {
+    /// Percentage of identical residues in the local alignment
+    pub pident: f64,
+    /// Number of columns of the local alignment
+    pub length: usize,
+    /// Columns with different residues
+    pub mismatch: usize,
+    /// Number of gaps, each counted once regardless of its length
+    pub gapopen: usize,
+    /// One-based first and last query residue of the local alignment
+    pub qstart: usize,
+    pub qend: usize,
+    /// One-based first and last subject residue of the local alignment
+    pub sstart: usize,
+    pub send: usize,
+    /// Expected number of alignments scoring at least as well by chance
+    pub evalue: f64,
+    /// Score normalized by the statistics of the scoring scheme
+    pub bitscore: f64,
+}
+
+impl BlastParams {
+    /// Aligns a pair locally and returns its BLAST columns
+    pub fn hit(&self, query: &str, subject: &str) -> BlastHit {
+        let alignment = workspace::with_aligner(
+            query.len(),
+            subject.len(),
+            self.matcher,
+            self.gaps,
+            NO_CLIPS,
+            |aligner| aligner.local(query.as_bytes(), subject.as_bytes()),
+        );
+        let (mut matches, mut mismatch, mut gapopen, mut length) = (0, 0, 0, 0);
+        let mut previous = None;
+        for op in &alignment.operations {
+            match op {
+                AlignmentOperation::Match => matches += 1,
+                AlignmentOperation::Subst => mismatch += 1,
+                AlignmentOperation::Ins | AlignmentOperation::Del => {
+                    gapopen += usize::from(previous != Some(op));
+                }
+                AlignmentOperation::Xclip(_) | AlignmentOperation::Yclip(_) => continue,
+            }
+            length += 1;
+            previous = Some(op);
+        }
+        let score = f64::from(alignment.score);
+        let Statistics { lambda, k } = self.statistics;
+        let space = query.len() as f64 * self.database_len as f64;
+        let evalue = k * space * (-lambda * score).exp();
+        let bitscore = (lambda * score - k.ln()) / std::f64::consts::LN_2;
+        BlastHit {
+            pident: if length == 0 {
+                0.0
+            } else {
+                100.0 * matches as f64 / length as f64
+            },
+            length,
+            mismatch,
+            gapopen,
+            qstart: alignment.xstart + 1,
+            qend: alignment.xend,
+            sstart: alignment.ystart + 1,
+            send: alignment.yend,
+            evalue,
+            bitscore,
+        }
+    }
+}
+
+/// Formats an e-value like BLAST, in scientific notation below 0.001
+pub fn format_evalue(evalue: f64) -> String {
+    if evalue < 1e-180 {
+        "0.0".to_string()
+    } else if evalue < 1e-3 {
+        format!("{:.2e}", evalue)
+    } else {
+        format!("{:.3}", evalue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ScoringType;
+
+    #[test]
+    fn test_blast_hit() {
+        let params = BlastParams {
+            matcher: ScoringType::Blosum62.matcher(),
+            gaps: GapPenalties::default(),
+            statistics: Statistics::BLOSUM62,
+            database_len: 1000,
+        };
+        // The subject holds the query with one substitution and flanking residues
+        let query = "MKTAYIAKQRQISFVKSHFSRQLEERLGLIEVQ";
+        let subject = "GGGGGMKTAYIAKQRQISFVKSHFSRQLEERLGLIDVQGGGGG";
+        let hit = params.hit(query, subject);
+        assert_eq!((hit.length, hit.mismatch, hit.gapopen), (33, 1, 0));
+        assert_eq!((hit.qstart, hit.qend, hit.sstart, hit.send), (1, 33, 6, 38));
+        assert!((hit.pident - 100.0 * 32.0 / 33.0).abs() < 1e-9);
+        assert!(hit.evalue < 1e-10);
+        assert!(hit.bitscore > 50.0);
+        assert_eq!(format_evalue(2.346e-12), "2.35e-12");
+        assert_eq!(format_evalue(1e-200), "0.0");
+        assert_eq!(format_evalue(0.5), "0.500");
+    }
+}
@@ -0,0 +1,297 @@
+//! Persistent cache of alignment scores.
+//!
+//! Scores are stored on disk keyed by the hashes of the two aligned sequences and
+//! a hash of the alignment parameters, so repeated runs over overlapping data reuse
+//! previously computed scores regardless of the sequence identifiers. Every set of
+//! parameters gets its own cache file inside the cache directory.
+//!
+//! The cache is loaded once at the start of a run, consulted for every pair, and
+//! written back at the end together with the newly computed scores. Entries can be
+//! evicted by age and by count, least recently used first.
+
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::align::Score;
+use crate::error::AlignerError;
+
+/// Identifies the cache file format and version
+const MAGIC: &[u8; 8] = b"ALNCACH1";
+
+/// Size of a serialized entry: two sequence hashes, the score bits and the last use
+const RECORD_LEN: usize = 16 + 16 + 4 + 8;
+
+/// Key of an aligned pair, built from the sequence hashes
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PairKey([u128; 2]);
+
+impl PairKey {
+    /// Creates the order-independent key for a pair of sequences.
+    ///
+    /// Scores of symmetric scoring schemes do not depend on the order of the
+    /// sequences, so both orders map to the same key.
+    pub fn new(seq1: &str, seq2: &str) -> Self {
+        let (a, b) = (sequence_hash(seq1), sequence_hash(seq2));
+        Self([a.min(b), a.max(b)])
+    }
+
+    /// Creates the key for a pair of sequences in this order, for asymmetric
+    /// substitution matrices scoring the two orders differently
+    pub fn ordered(seq1: &str, seq2: &str) -> Self {
+        Self([sequence_hash(seq1), sequence_hash(seq2)])
+    }
+}
+
+/// Limits applied to the cache when it is written back
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Eviction {
+    /// Maximum number of entries kept, least recently used entries are dropped first
+    pub max_entries: Option<usize>,
+    /// Entries not used for longer than this are dropped
+    pub max_age: Option<Duration>,
+}
+
+/// A cached score with the time it was last used
+#[derive(Debug)]
+struct Entry {
+    score: u32,
+    last_used: AtomicU64,
+}
+
+/// On-disk cache of alignment scores for one set of alignment parameters
+#[derive(Debug)]
+pub struct ResultCache {
+    path: PathBuf,
+    eviction: Eviction,
+    ordered: bool,
+    now: u64,
+    entries: HashMap<PairKey, Entry>,
+    added: Mutex<HashMap<PairKey, u32>>,
+    hits: AtomicU64,
+}
+
+impl ResultCache {
+    /// Opens the cache for the given alignment parameters in `dir`.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - Cache directory, created if it does not exist
+    /// * `parameters` - Description of every parameter that influences the score
+    /// * `eviction` - Limits applied when the cache is saved
+    ///
+    /// # Errors
+    ///
+    /// Returns `AlignerError::Io` if the directory cannot be created or the cache
+    /// file cannot be read. A cache file with an unknown format is ignored with a
+    /// warning and replaced on save.
+    pub fn open(dir: &Path, parameters: &str, eviction: Eviction) -> Result<Self, AlignerError> {
+        fs::create_dir_all(dir)?;
+        let digest = Sha256::digest(parameters.as_bytes());
+        let name: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+        let path = dir.join(format!("{}.cache", name));
+
+        let entries = match File::open(&path) {
+            Ok(file) => read_entries(BufReader::new(file)).unwrap_or_else(|e| {
+                warn!(path = %path.display(), "ignoring unreadable cache file: {}", e);
+                HashMap::new()
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        info!(path = %path.display(), entries = entries.len(), "opened result cache");
+
+        Ok(Self {
+            path,
+            eviction,
+            ordered: false,
+            now: unix_secs(SystemTime::now()),
+            entries,
+            added: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+        })
+    }
+
+    /// Keys pairs by the order of their sequences, for scoring schemes that score
+    /// the two orders differently
+    pub fn with_ordered_pairs(mut self, ordered: bool) -> Self {
+        self.ordered = ordered;
+        self
+    }
+
+    /// Returns the key of a pair of sequences, in this order if pairs are ordered
+    pub fn key(&self, seq1: &str, seq2: &str) -> PairKey {
+        if self.ordered {
+            PairKey::ordered(seq1, seq2)
+        } else {
+            PairKey::new(seq1, seq2)
+        }
+    }
+
+    /// Returns the cached score of a pair and marks it as used
+    pub fn get<S: Score>(&self, key: &PairKey) -> Option<S> {
+        let entry = self.entries.get(key)?;
+        entry.last_used.store(self.now, Ordering::Relaxed);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(S::from_bits(entry.score))
+    }
+
+    /// Adds a newly computed score
+    pub fn insert<S: Score>(&self, key: PairKey, score: S) {
+        self.added
+            .lock()
+            .expect("Cache lock poisoned")
+            .insert(key, score.to_bits());
+    }
+
+    /// Number of lookups answered from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Number of scores added during this run
+    pub fn added(&self) -> usize {
+        self.added.lock().expect("Cache lock poisoned").len()
+    }
+
+    /// Writes all entries that survive eviction back to the cache file.
+    ///
+    /// The file is replaced atomically, so an interrupted save leaves the previous
+    /// cache intact. Returns the number of entries written.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AlignerError::Io` if the cache file cannot be written.
+    pub fn save(&self) -> Result<usize, AlignerError> {
+        let added = self.added.lock().expect("Cache lock poisoned");
+        let mut records: Vec<(PairKey, u32, u64)> = self
+            .entries
+            .iter()
+            .map(|(key, entry)| (*key, entry.score, entry.last_used.load(Ordering::Relaxed)))
+            .chain(added.iter().map(|(key, score)| (*key, *score, self.now)))
+            .collect();
+
+        if let Some(max_age) = self.eviction.max_age {
+            let oldest = self.now.saturating_sub(max_age.as_secs());
+            records.retain(|(_, _, last_used)| *last_used >= oldest);
+        }
+        if let Some(max_entries) = self.eviction.max_entries
+            && records.len() > max_entries
+        {
+            records.sort_unstable_by_key(|(_, _, last_used)| std::cmp::Reverse(*last_used));
+            records.truncate(max_entries);
+        }
+
+        let tmp = self.path.with_extension("cache.tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        writer.write_all(MAGIC)?;
+        for (PairKey([a, b]), score, last_used) in &records {
+            writer.write_all(&a.to_le_bytes())?;
+            writer.write_all(&b.to_le_bytes())?;
+            writer.write_all(&score.to_le_bytes())?;
+            writer.write_all(&last_used.to_le_bytes())?;
+        }
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(records.len())
+    }
+}
+
+/// Reads all entries of a cache file
+fn read_entries(mut reader: impl Read) -> std::io::Result<HashMap<PairKey, Entry>> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            "unknown cache format",
+        ));
+    }
+
+    let mut entries = HashMap::new();
+    let mut record = [0u8; RECORD_LEN];
+    loop {
+        match reader.read_exact(&mut record) {
+            Ok(()) => {}
+            // A truncated last record is dropped
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let (a, rest) = record.split_at(16);
+        let (b, rest) = rest.split_at(16);
+        let (score, last_used) = rest.split_at(4);
+        entries.insert(
+            PairKey([
+                u128::from_le_bytes(a.try_into().expect("record layout")),
+                u128::from_le_bytes(b.try_into().expect("record layout")),
+            ]),
+            Entry {
+                score: u32::from_le_bytes(score.try_into().expect("record layout")),
+                last_used: AtomicU64::new(u64::from_le_bytes(
+                    last_used.try_into().expect("record layout"),
+                )),
+            },
+        );
+    }
+    Ok(entries)
+}
+
+/// Stable 128-bit hash of a sequence, independent of the Rust version
+pub fn sequence_hash(seq: &str) -> u128 {
+    let digest = Sha256::digest(seq.as_bytes());
+    u128::from_le_bytes(digest[..16].try_into().expect("digest is 32 bytes"))
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_roundtrip_and_eviction() {
+        let dir = std::env::temp_dir().join(format!("aligner-cache-{}", std::process::id()));
+        let params = "scoring=Identity";
+
+        let cache = ResultCache::open(&dir, params, Eviction::default()).unwrap();
+        cache.insert(PairKey::new("ACGT", "ACGA"), 3);
+        cache.insert(PairKey::new("ACGT", "TTTT"), -2);
+        assert_eq!(cache.save().unwrap(), 2);
+
+        let eviction = Eviction {
+            max_entries: Some(1),
+            max_age: None,
+        };
+        let cache = ResultCache::open(&dir, params, eviction).unwrap();
+        assert_eq!(cache.get(&PairKey::new("ACGA", "ACGT")), Some(3));
+        assert_eq!(cache.get::<i32>(&PairKey::new("ACGT", "GGGG")), None);
+        assert_eq!(cache.hits(), 1);
+        assert_eq!(cache.save().unwrap(), 1);
+
+        // Other parameters use a separate cache file
+        let other = ResultCache::open(&dir, "scoring=Blosum62", Eviction::default()).unwrap();
+        assert_eq!(other.get::<i32>(&PairKey::new("ACGT", "ACGA")), None);
+
+        // Ordered pairs keep the scores of both orders apart
+        let ordered = ResultCache::open(&dir, "scoring=matrix;ordered", Eviction::default())
+            .unwrap()
+            .with_ordered_pairs(true);
+        ordered.insert(ordered.key("ACGT", "ACGA"), 3);
+        ordered.insert(ordered.key("ACGA", "ACGT"), 1);
+        assert_eq!(ordered.save().unwrap(), 2);
+        assert_ne!(ordered.key("ACGT", "ACGA"), ordered.key("ACGA", "ACGT"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,311 @@
+//! Detection of chimeric sequences.
+//!
+//! PCR and gene synthesis can join the start of one template to the end of
+//! another. The `chimera` subcommand aligns every query with its best-scoring
+//! candidate parents, the query end to end and the parents with free end gaps,
+//! and records which query residues every parent explains. It then scans all
+//! breakpoints for the pair of different parents explaining the most residues
+//! with the first parent left of the breakpoint and the second right of it. A
+//! query is flagged as chimeric if two parents explain it clearly better than the
+//! best single parent does.
+
+use bio::alignment::AlignmentOperation;
+use indicatif::ParallelProgressIterator;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::ScoringType;
+use crate::align::{AlignMode, GapPenalties, MatcherFn, align};
+use crate::cluster::parse_identity;
+use crate::error::AlignerError;
+use crate::utils::{parse_input, setup_progress_bar};
+use crate::validate::{check_ascii, check_lengths};
+use crate::workspace::{self, NO_CLIPS};
+
+/// Command-line arguments for the `chimera` subcommand
+#[derive(clap::Args, Debug)]
+pub struct ChimeraArgs {
+    /// Input file with the sequences to check, in any supported input format
+    input: PathBuf,
+
+    /// Input file with the candidate parents; by default every query is checked
+    /// against the other input sequences
+    #[arg(long)]
+    reference: Option<PathBuf>,
+
+    /// Path of the tab-separated table with the best explanation of every query
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Number of best-scoring parents of every query scanned for breakpoints
+    #[arg(long, default_value = "8")]
+    parents: NonZeroUsize,
+
+    /// Gain in identity over the best single parent for a query to be flagged as
+    /// chimeric
+    #[arg(long, default_value = "0.05", value_parser = parse_identity)]
+    min_improvement: f64,
+
+    /// Minimum number of query residues on either side of a breakpoint
+    #[arg(long, default_value = "30")]
+    min_segment: usize,
+
+    /// Scoring type to use for alignment
+    #[arg(short, long, value_enum, default_value_t = ScoringType::Identity)]
+    scoring: ScoringType,
+}
+
+/// The best explanation of a query by two parents joined at a breakpoint
+#[derive(Debug, Clone, PartialEq)]
+pub struct TwoParents {
+    /// Parent explaining the query left of the breakpoint
+    pub left: String,
+    /// Parent explaining the query from the breakpoint on
+    pub right: String,
+    /// One-based position of the first query residue explained by the right
+    /// parent
+    pub breakpoint: usize,
+    /// Fraction of the query residues identical to the residue of their parent
+    pub identity: f64,
+}
+
+/// How well a query is explained by one parent and by two
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+    /// Parent explaining the most query residues on its own
+    pub parent: String,
+    /// Fraction of the query residues identical to their residue of `parent`
+    pub identity: f64,
+    /// Best pair of parents, `None` if there are fewer than two parents or the
+    /// query is too short for two segments
+    pub two_parents: Option<TwoParents>,
+}
+
+impl Explanation {
+    /// Returns `true` if two parents explain the query at least `min_improvement`
+    /// better than the best single parent
+    pub fn is_chimeric(&self, min_improvement: f64) -> bool {
+        self.two_parents
+            .as_ref()
+            .is_some_and(|two| two.identity - self.identity >= min_improvement)
+    }
+}
+
+/// Returns the number of identical residues of the alignment of `query` with
+/// `parent` within every prefix of the query, from the empty prefix to the whole
+/// query
+fn explained_prefixes(query: &str, parent: &str, matcher: &MatcherFn) -> Vec<usize> {
+    let alignment = workspace::with_aligner(
+        query.len(),
+        parent.len(),
+        *matcher,
+        GapPenalties::default(),
+        NO_CLIPS,
+        |aligner| aligner.semiglobal(query.as_bytes(), parent.as_bytes()),
+    );
+    let mut prefixes = Vec::with_capacity(query.len() + 1);
+    prefixes.push(0);
+    let mut explained = 0;
+    for op in &alignment.operations {
+        let residues = match op {
+            AlignmentOperation::Match => {
+                explained += 1;
+                1
+            }
+            AlignmentOperation::Subst | AlignmentOperation::Ins => 1,
+            AlignmentOperation::Xclip(residues) => *residues,
+            AlignmentOperation::Del | AlignmentOperation::Yclip(_) => 0,
+        };
+        prefixes.extend(std::iter::repeat_n(explained, residues));
+    }
+    prefixes.resize(query.len() + 1, explained);
+    prefixes
+}
+
+/// Explains a query by the given parents, with breakpoints at least
+/// `min_segment` residues from either end of the query.
+///
+/// Returns `None` if there are no parents.
+pub fn explain(
+    query: &str,
+    parents: &[(&String, &String)],
+    matcher: &MatcherFn,
+    min_segment: usize,
+) -> Option<Explanation> {
+    let n = query.len();
+    let prefixes: Vec<Vec<usize>> = parents
+        .iter()
+        .map(|(_, parent)| explained_prefixes(query, parent, matcher))
+        .collect();
+    let identity = |explained: usize| {
+        if n == 0 {
+            0.0
+        } else {
+            explained as f64 / n as f64
+        }
+    };
+    let single =
+        (0..parents.len()).max_by(|&a, &b| prefixes[a][n].cmp(&prefixes[b][n]).then(b.cmp(&a)))?;
+
+    // Explained residues, left and right parent and breakpoint of the best pair
+    let mut best: Option<(usize, usize, usize, usize)> = None;
+    let min_segment = min_segment.max(1);
+    for (left, left_prefixes) in prefixes.iter().enumerate() {
+        for (right, right_prefixes) in prefixes.iter().enumerate() {
+            if left == right {
+                continue;
+            }
+            for split in min_segment..=n.saturating_sub(min_segment) {
+                let explained = left_prefixes[split] + right_prefixes[n] - right_prefixes[split];
+                if best.is_none_or(|(most, ..)| explained > most) {
+                    best = Some((explained, left, right, split));
+                }
+            }
+        }
+    }
+    Some(Explanation {
+        parent: parents[single].0.clone(),
+        identity: identity(prefixes[single][n]),
+        two_parents: best.map(|(explained, left, right, split)| TwoParents {
+            left: parents[left].0.clone(),
+            right: parents[right].0.clone(),
+            breakpoint: split + 1,
+            identity: identity(explained),
+        }),
+    })
+}
+
+/// Runs the `chimera` subcommand.
+///
+/// # Errors
+///
+/// Returns an error if an input cannot be read or is invalid, or the output
+/// cannot be written.
+pub fn run(args: ChimeraArgs) -> Result<(), AlignerError> {
+    let mut queries = parse_input(&args.input)?;
+    check_ascii(&mut queries, None)?;
+    check_lengths(&queries, None)?;
+    let reference = match &args.reference {
+        Some(path) => {
+            let mut reference = parse_input(path)?;
+            check_ascii(&mut reference, None)?;
+            check_lengths(&reference, None)?;
+            Some(reference)
+        }
+        None => None,
+    };
+    let parents: &HashMap<String, String> = reference.as_ref().unwrap_or(&queries);
+    info!(
+        queries = queries.len(),
+        parents = parents.len(),
+        "scanning for chimeras"
+    );
+
+    let mut queries: Vec<(&String, &String)> = queries.iter().collect();
+    queries.sort_unstable();
+    let mut candidates: Vec<(&String, &String)> = parents.iter().collect();
+    candidates.sort_unstable();
+    let matcher = args.scoring.matcher();
+    let progress = setup_progress_bar(queries.len() as u64);
+    let explanations: Vec<Option<Explanation>> = queries
+        .par_iter()
+        .progress_with(progress)
+        .map(|(query_id, query_seq)| {
+            let mut scored: Vec<(i32, (&String, &String))> = candidates
+                .iter()
+                // Without a reference, a query is no parent of itself
+                .filter(|(parent_id, _)| args.reference.is_some() || parent_id != query_id)
+                .map(|&parent| {
+                    (
+                        align(
+                            query_seq,
+                            parent.1,
+                            &matcher,
+                            AlignMode::Global,
+                            GapPenalties::default(),
+                        ),
+                        parent,
+                    )
+                })
+                .collect();
+            // Highest score first, ties by identifier
+            scored.sort_by(|(a, x), (b, y)| b.cmp(a).then_with(|| x.cmp(y)));
+            scored.truncate(args.parents.get());
+            let best: Vec<(&String, &String)> =
+                scored.into_iter().map(|(_, parent)| parent).collect();
+            explain(query_seq, &best, &matcher, args.min_segment)
+        })
+        .collect();
+
+    let mut out = BufWriter::new(File::create(&args.output)?);
+    writeln!(
+        out,
+        "query_id\tparent\tidentity\tleft_parent\tright_parent\tbreakpoint\tchimera_identity\tchimeric"
+    )?;
+    let mut chimeric = 0;
+    for ((query_id, _), explanation) in queries.iter().zip(&explanations) {
+        let Some(explanation) = explanation else {
+            continue;
+        };
+        let flagged = explanation.is_chimeric(args.min_improvement);
+        chimeric += usize::from(flagged);
+        let two = explanation.two_parents.as_ref();
+        writeln!(
+            out,
+            "{}\t{}\t{:.3}\t{}\t{}\t{}\t{}\t{}",
+            query_id,
+            explanation.parent,
+            explanation.identity,
+            two.map_or("", |two| two.left.as_str()),
+            two.map_or("", |two| two.right.as_str()),
+            two.map(|two| two.breakpoint.to_string())
+                .unwrap_or_default(),
+            two.map(|two| format!("{:.3}", two.identity))
+                .unwrap_or_default(),
+            flagged
+        )?;
+    }
+    out.flush()?;
+    eprintln!(
+        "Checked {} sequences against {} candidate parents: {} flagged as chimeric",
+        queries.len(),
+        parents.len(),
+        chimeric
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_explain_chimera() {
+        let a = "MKTAYIAKQRQISFVKSHFSRQLEERLGLIEVQAPILSRVGDGTQDNLSGAEKAVQVKVKA".to_string();
+        let b: String = a.chars().rev().collect();
+        let chimera = format!("{}{}", &a[..25], &b[25..]);
+        let (a_id, b_id) = ("a".to_string(), "b".to_string());
+        let parents = [(&a_id, &a), (&b_id, &b)];
+        let matcher = ScoringType::Identity.matcher();
+
+        let explanation = explain(&chimera, &parents, &matcher, 10).unwrap();
+        let two = explanation.two_parents.clone().unwrap();
+        assert_eq!((two.left.as_str(), two.right.as_str()), ("a", "b"));
+        assert_eq!(two.breakpoint, 26);
+        assert_eq!(two.identity, 1.0);
+        assert!(explanation.is_chimeric(0.05));
+
+        let explanation = explain(&a, &parents, &matcher, 10).unwrap();
+        assert_eq!(
+            (explanation.parent.as_str(), explanation.identity),
+            ("a", 1.0)
+        );
+        assert!(!explanation.is_chimeric(0.05));
+        assert!(explain(&a, &[], &matcher, 10).is_none());
+    }
+}
@@ -0,0 +1,454 @@
+//! Clustering of sequences by pairwise identity.
+//!
+//! The `cluster` subcommand aligns all pairs of the input with traceback and
+//! links sequences whose identity reaches `--min-identity`; every connected group
+//! of sequences is a cluster (single linkage). The scores of all pairs within a
+//! cluster are kept, so every cluster can be represented by the member chosen by
+//! the `--representative` policy. The representatives are written as FASTA and
+//! the members of every cluster as a membership table. The quality of the
+//! clusters is summarized with the metrics of [`crate::cohesion`].
+//!
+//! Several thresholds are clustered from the same pair scores. With single
+//! linkage, every cluster at a higher threshold lies within one cluster at each
+//! lower threshold, so `--hierarchy` writes the clusters of all thresholds as
+//! nested labels such as `3.1.2`.
+
+use clap::ValueEnum;
+use indicatif::ParallelProgressIterator;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::ScoringType;
+use crate::align::{AlignMode, GapPenalties, MatcherFn, align_with_identity};
+use crate::cohesion::{self, Distribution};
+use crate::error::AlignerError;
+use crate::fasta;
+use crate::utils::{parse_input, setup_progress_bar};
+use crate::validate::{check_ascii, check_lengths};
+
+/// Command-line arguments for the `cluster` subcommand
+#[derive(clap::Args, Debug)]
+pub struct ClusterArgs {
+    /// Input file with the sequences to cluster, in any supported input format
+    input: PathBuf,
+
+    /// Minimum identity of a pair for its sequences to be in the same cluster.
+    /// Several comma-separated thresholds, e.g. `0.9,0.7,0.5`, are clustered in
+    /// one pass; the representatives, membership table and metrics are those of
+    /// the first.
+    #[arg(
+        long,
+        default_value = "0.9",
+        value_delimiter = ',',
+        value_parser = parse_identity
+    )]
+    min_identity: Vec<f64>,
+
+    /// Member representing every cluster
+    #[arg(long, value_enum, default_value_t = Representative::Longest)]
+    representative: Representative,
+
+    /// Path of the FASTA file of the cluster representatives
+    #[arg(long)]
+    representatives: PathBuf,
+
+    /// Path of the tab-separated table of the members of every cluster
+    #[arg(long)]
+    membership: PathBuf,
+
+    /// Path of the tab-separated table of the nested cluster of every sequence at
+    /// every threshold, from the lowest to the highest threshold
+    #[arg(long)]
+    hierarchy: Option<PathBuf>,
+
+    /// Path of the tab-separated table of the within-cluster identities,
+    /// separation and silhouette of every cluster
+    #[arg(long)]
+    metrics: Option<PathBuf>,
+
+    /// Scoring type to use for alignment
+    #[arg(short, long, value_enum, default_value_t = ScoringType::Identity)]
+    scoring: ScoringType,
+}
+
+/// Parses an identity threshold between 0 and 1
+pub fn parse_identity(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(identity) if (0.0..=1.0).contains(&identity) => Ok(identity),
+        _ => Err(format!(
+            "invalid identity '{}', expected a number between 0 and 1",
+            value
+        )),
+    }
+}
+
+/// Policy choosing the member that represents a cluster
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Representative {
+    /// The longest sequence
+    Longest,
+    /// The member with the highest mean identity to the other members
+    MeanIdentity,
+    /// The member with the highest total score against the other members
+    Medoid,
+}
+
+/// Score and identity of an aligned pair of sequences, by their positions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PairScore {
+    /// Position of the first sequence
+    pub i: usize,
+    /// Position of the second sequence, greater than `i`
+    pub j: usize,
+    /// Alignment score
+    pub score: i32,
+    /// Fraction of alignment columns with identical residues
+    pub identity: f64,
+}
+
+/// Sequences ordered by identifier, with the scores of all their pairs
+pub struct PairScores {
+    /// Identifiers of the sequences
+    pub ids: Vec<String>,
+    /// Sequences in the order of `ids`
+    pub sequences: Vec<String>,
+    /// Scores of every pair of sequences
+    pub pairs: Vec<PairScore>,
+}
+
+impl PairScores {
+    /// Aligns every pair of the input with traceback
+    pub fn compute(input: HashMap<String, String>, matcher: &MatcherFn) -> Self {
+        let mut input: Vec<(String, String)> = input.into_iter().collect();
+        input.sort_unstable();
+        let (ids, sequences): (Vec<String>, Vec<String>) = input.into_iter().unzip();
+
+        let n = sequences.len();
+        let positions: Vec<(usize, usize)> = (0..n)
+            .flat_map(|i| (i + 1..n).map(move |j| (i, j)))
+            .collect();
+        let progress = setup_progress_bar(positions.len() as u64);
+        let pairs = positions
+            .into_par_iter()
+            .progress_with(progress)
+            .map(|(i, j)| {
+                let (score, identity) = align_with_identity(
+                    &sequences[i],
+                    &sequences[j],
+                    matcher,
+                    AlignMode::Global,
+                    GapPenalties::default(),
+                );
+                PairScore {
+                    i,
+                    j,
+                    score,
+                    identity,
+                }
+            })
+            .collect();
+        Self {
+            ids,
+            sequences,
+            pairs,
+        }
+    }
+}
+
+/// A cluster with the scores of the pairs of its members
+#[derive(Debug)]
+pub struct Cluster {
+    /// Positions of the members, in increasing order
+    pub members: Vec<usize>,
+    /// Scores of all pairs of members
+    pub pairs: Vec<PairScore>,
+}
+
+impl Cluster {
+    /// Returns the member representing the cluster under `policy`, preferring
+    /// the first member on ties
+    pub fn representative(&self, policy: Representative, scores: &PairScores) -> usize {
+        let mut totals: HashMap<usize, f64> = HashMap::new();
+        for pair in &self.pairs {
+            let value = match policy {
+                Representative::Longest => continue,
+                Representative::MeanIdentity => pair.identity,
+                Representative::Medoid => f64::from(pair.score),
+            };
+            *totals.entry(pair.i).or_default() += value;
+            *totals.entry(pair.j).or_default() += value;
+        }
+        let rank = |member: usize| match policy {
+            Representative::Longest => scores.sequences[member].len() as f64,
+            // Every member is paired with the same number of others, so the
+            // totals rank the members like the means
+            _ => totals.get(&member).copied().unwrap_or_default(),
+        };
+        self.members
+            .iter()
+            .copied()
+            .reduce(|best, member| {
+                if rank(member) > rank(best) {
+                    member
+                } else {
+                    best
+                }
+            })
+            .expect("clusters have members")
+    }
+
+    /// Returns the identity of every member to the member `other`, in the order
+    /// of the members
+    pub fn identities_to(&self, other: usize) -> Vec<f64> {
+        let mut identities = HashMap::from([(other, 1.0)]);
+        for pair in &self.pairs {
+            if pair.i == other {
+                identities.insert(pair.j, pair.identity);
+            } else if pair.j == other {
+                identities.insert(pair.i, pair.identity);
+            }
+        }
+        self.members
+            .iter()
+            .map(|member| identities.get(member).copied().unwrap_or_default())
+            .collect()
+    }
+}
+
+/// Returns the representative of `position` in a union-find forest, compressing
+/// the path to it
+fn root(parents: &mut [usize], mut position: usize) -> usize {
+    while parents[position] != position {
+        parents[position] = parents[parents[position]];
+        position = parents[position];
+    }
+    position
+}
+
+/// Groups the sequences into single-linkage clusters of pairs reaching
+/// `min_identity`, from the largest to the smallest cluster
+pub fn cluster(scores: &PairScores, min_identity: f64) -> Vec<Cluster> {
+    let n = scores.ids.len();
+    let mut parents: Vec<usize> = (0..n).collect();
+    for pair in scores
+        .pairs
+        .iter()
+        .filter(|pair| pair.identity >= min_identity)
+    {
+        let (a, b) = (root(&mut parents, pair.i), root(&mut parents, pair.j));
+        parents[a.max(b)] = a.min(b);
+    }
+
+    let mut clusters: Vec<Cluster> = Vec::new();
+    let mut index_of_root = HashMap::new();
+    let mut cluster_of = Vec::with_capacity(n);
+    for position in 0..n {
+        let index = *index_of_root
+            .entry(root(&mut parents, position))
+            .or_insert_with(|| {
+                clusters.push(Cluster {
+                    members: Vec::new(),
+                    pairs: Vec::new(),
+                });
+                clusters.len() - 1
+            });
+        clusters[index].members.push(position);
+        cluster_of.push(index);
+    }
+    for pair in &scores.pairs {
+        if cluster_of[pair.i] == cluster_of[pair.j] {
+            clusters[cluster_of[pair.i]].pairs.push(*pair);
+        }
+    }
+    // Stable, so clusters of equal size stay ordered by their first member
+    clusters.sort_by_key(|cluster| std::cmp::Reverse(cluster.members.len()));
+    clusters
+}
+
+/// Returns the nested label of every sequence, given the clusters at increasing
+/// thresholds: the number of its cluster at the lowest threshold, followed by
+/// the number of its cluster within the enclosing cluster at every higher one
+pub fn nest(levels: &[Vec<Cluster>], sequences: usize) -> Vec<Vec<usize>> {
+    let mut labels: Vec<Vec<usize>> = vec![Vec::new(); sequences];
+    for clusters in levels {
+        // Children numbered so far within every parent, by the parent's label
+        let mut children: HashMap<Vec<usize>, usize> = HashMap::new();
+        for cluster in clusters {
+            let parent = labels[cluster.members[0]].clone();
+            let number = children.entry(parent).or_default();
+            *number += 1;
+            for &member in &cluster.members {
+                labels[member].push(*number);
+            }
+        }
+    }
+    labels
+}
+
+/// Writes the nested labels of all sequences at the thresholds, ordered by label
+fn write_hierarchy(
+    path: &Path,
+    scores: &PairScores,
+    thresholds: &[f64],
+    labels: &[Vec<usize>],
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(out, "sequence_id")?;
+    for threshold in thresholds {
+        write!(out, "\tidentity_{}", threshold)?;
+    }
+    writeln!(out)?;
+    let mut order: Vec<usize> = (0..labels.len()).collect();
+    order.sort_by(|&a, &b| labels[a].cmp(&labels[b]));
+    for sequence in order {
+        write!(out, "{}", scores.ids[sequence])?;
+        for depth in 1..=labels[sequence].len() {
+            let label: Vec<String> = labels[sequence][..depth]
+                .iter()
+                .map(usize::to_string)
+                .collect();
+            write!(out, "\t{}", label.join("."))?;
+        }
+        writeln!(out)?;
+    }
+    out.flush()
+}
+
+/// Runs the `cluster` subcommand.
+///
+/// # Errors
+///
+/// Returns the errors of reading the input and writing the representatives and
+/// the membership table.
+pub fn run(args: ClusterArgs) -> Result<(), AlignerError> {
+    let mut input = parse_input(&args.input)?;
+    check_ascii(&mut input, None)?;
+    check_lengths(&input, None)?;
+    info!(sequences = input.len(), "clustering sequences");
+
+    let scores = PairScores::compute(input, &args.scoring.matcher());
+    let min_identity = args.min_identity[0];
+    let mut thresholds = args.min_identity.clone();
+    thresholds.sort_by(f64::total_cmp);
+    thresholds.dedup();
+    let mut levels: Vec<Vec<Cluster>> = thresholds
+        .iter()
+        .map(|&threshold| cluster(&scores, threshold))
+        .collect();
+    for (threshold, clusters) in thresholds.iter().zip(&levels) {
+        eprintln!(
+            "Clustered {} sequences into {} clusters at identity {} (largest has {} members)",
+            scores.ids.len(),
+            clusters.len(),
+            threshold,
+            clusters.first().map_or(0, |cluster| cluster.members.len())
+        );
+    }
+    if let Some(path) = &args.hierarchy {
+        write_hierarchy(path, &scores, &thresholds, &nest(&levels, scores.ids.len()))?;
+    }
+    let first = thresholds
+        .iter()
+        .position(|&threshold| threshold == min_identity)
+        .expect("the first threshold is clustered");
+    let clusters = levels.swap_remove(first);
+    let representatives: Vec<usize> = clusters
+        .iter()
+        .map(|cluster| cluster.representative(args.representative, &scores))
+        .collect();
+
+    let mut out = BufWriter::new(File::create(&args.representatives)?);
+    fasta::write(
+        &mut out,
+        representatives.iter().map(|&representative| {
+            (
+                scores.ids[representative].as_str(),
+                scores.sequences[representative].as_str(),
+            )
+        }),
+    )?;
+    out.flush()?;
+
+    let mut out = BufWriter::new(File::create(&args.membership)?);
+    writeln!(
+        out,
+        "cluster\tsequence_id\trepresentative\tidentity_to_representative"
+    )?;
+    for (index, (cluster, &representative)) in clusters.iter().zip(&representatives).enumerate() {
+        let identities = cluster.identities_to(representative);
+        for (&member, identity) in cluster.members.iter().zip(identities) {
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{:.3}",
+                index + 1,
+                scores.ids[member],
+                scores.ids[representative],
+                identity
+            )?;
+        }
+    }
+    out.flush()?;
+
+    let metrics = cohesion::evaluate(&scores, &clusters);
+    if let Some(path) = &args.metrics {
+        cohesion::write(path, &metrics)?;
+    }
+
+    let within = clusters
+        .iter()
+        .flat_map(|cluster| cluster.pairs.iter().map(|pair| pair.identity))
+        .collect();
+    if let Some(within) = Distribution::of(within) {
+        eprintln!(
+            "Within-cluster identity: min {:.3}, median {:.3}, max {:.3}; mean silhouette {:.3}",
+            within.min,
+            within.median,
+            within.max,
+            cohesion::mean_silhouette(&metrics)
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cluster_representatives() {
+        let input: HashMap<String, String> = [
+            ("a", "MAVMTKLLQ"),
+            ("b", "MAVMTKLLQWW"),
+            ("c", "MAVMTKLAQ"),
+            ("d", "WWWWPPPPGG"),
+        ]
+        .into_iter()
+        .map(|(id, seq)| (id.to_string(), seq.to_string()))
+        .collect();
+        let matcher: MatcherFn = |a, b| if a == b { 1 } else { -1 };
+        let scores = PairScores::compute(input, &matcher);
+        let clusters = cluster(&scores, 0.8);
+
+        let members: Vec<Vec<usize>> = clusters.iter().map(|c| c.members.clone()).collect();
+        assert_eq!(members, [vec![0, 1, 2], vec![3]]);
+        assert_eq!(clusters[0].pairs.len(), 3);
+        let representative = |policy| clusters[0].representative(policy, &scores);
+        assert_eq!(representative(Representative::Longest), 1);
+        // "a" is closest to both other members
+        assert_eq!(representative(Representative::MeanIdentity), 0);
+        assert_eq!(representative(Representative::Medoid), 0);
+        assert_eq!(
+            clusters[1].representative(Representative::Medoid, &scores),
+            3
+        );
+
+        // "b" and "c" only join "a" at the lower threshold
+        let levels = [cluster(&scores, 0.8), cluster(&scores, 0.85)];
+        let labels = nest(&levels, 4);
+        assert_eq!(labels, [vec![1, 1], vec![1, 2], vec![1, 1], vec![2, 1]]);
+    }
+}
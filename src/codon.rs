@@ -0,0 +1,206 @@
+//! Codon alignments of coding sequences guided by their proteins.
+//!
+//! Selection analyses compare coding sequences codon by codon, but nucleotide
+//! alignments are less sensitive than protein alignments and may split codons.
+//! The `codon` subcommand aligns pairs of proteins and projects every alignment
+//! back onto the coding sequences of the proteins, read from a second input with
+//! the same identifiers: every aligned residue becomes its codon and every gap a
+//! gap of three nucleotides, so the nucleotide alignment stays in frame.
+
+use bio::alignment::{Alignment, AlignmentOperation};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::ScoringType;
+use crate::error::AlignerError;
+use crate::fasta;
+use crate::pair::align_pair;
+use crate::pairs::{PairGenerator, PairList, Triangle};
+use crate::translate::{self, GeneticCode};
+use crate::utils::parse_input;
+use crate::validate::{check_ascii, check_lengths};
+
+/// Codon written for every gap in a protein alignment
+const GAP_CODON: &str = "---";
+
+/// Command-line arguments for the `codon` subcommand
+#[derive(clap::Args, Debug)]
+pub struct CodonArgs {
+    /// Input file with the protein sequences, in any supported input format
+    proteins: PathBuf,
+
+    /// Input file with the coding sequence of every protein, under the identifier
+    /// of the protein
+    #[arg(long)]
+    cds: PathBuf,
+
+    /// Path of the aligned FASTA file with the two codon-aligned coding sequences
+    /// of every pair, one pair after the other
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Tab-separated file listing the pairs to align, as for the default mode;
+    /// by default every pair of proteins is aligned
+    #[arg(long, value_name = "FILE")]
+    pairs: Option<PathBuf>,
+
+    /// Scoring type to use for aligning the proteins
+    #[arg(short, long, value_enum, default_value_t = ScoringType::Blosum62)]
+    scoring: ScoringType,
+
+    /// NCBI number of the genetic code whose stop codons are removed from the end
+    /// of the coding sequences
+    #[arg(long, value_name = "ID", default_value = "1", value_parser = translate::parse_gencode)]
+    gencode: &'static GeneticCode,
+}
+
+/// Returns the coding sequence of a protein without a terminal stop codon of a
+/// genetic code.
+///
+/// # Errors
+///
+/// Returns `AlignerError::InvalidInput` if the coding sequence does not have
+/// three nucleotides for every residue of the protein.
+pub fn coding_sequence<'a>(
+    id: &str,
+    protein: &str,
+    cds: &'a str,
+    code: &GeneticCode,
+) -> Result<&'a str, AlignerError> {
+    let len = 3 * protein.len();
+    let cds = match cds.get(len..) {
+        Some(stop) if stop.len() == 3 && code.is_stop(stop.as_bytes()) => &cds[..len],
+        _ => cds,
+    };
+    if cds.len() != len {
+        return Err(AlignerError::InvalidInput(format!(
+            "coding sequence of {} has {} nucleotides, expected {} for its {} residues",
+            id,
+            cds.len(),
+            len,
+            protein.len()
+        )));
+    }
+    Ok(cds)
+}
+
+/// Projects an alignment of two proteins (x and y) onto their coding sequences,
+/// returning the two codon-aligned rows
+pub fn codon_align(alignment: &Alignment, cds1: &str, cds2: &str) -> (String, String) {
+    let (mut row1, mut row2) = (String::new(), String::new());
+    let (mut x, mut y) = (alignment.xstart, alignment.ystart);
+    let codon = |cds: &str, i: usize| cds[3 * i..3 * i + 3].to_string();
+    for op in &alignment.operations {
+        match op {
+            AlignmentOperation::Match | AlignmentOperation::Subst => {
+                row1 += &codon(cds1, x);
+                row2 += &codon(cds2, y);
+                x += 1;
+                y += 1;
+            }
+            AlignmentOperation::Ins => {
+                row1 += &codon(cds1, x);
+                row2 += GAP_CODON;
+                x += 1;
+            }
+            AlignmentOperation::Del => {
+                row1 += GAP_CODON;
+                row2 += &codon(cds2, y);
+                y += 1;
+            }
+            AlignmentOperation::Xclip(n) => x += n,
+            AlignmentOperation::Yclip(n) => y += n,
+        }
+    }
+    (row1, row2)
+}
+
+/// Runs the `codon` subcommand.
+///
+/// # Errors
+///
+/// Returns `AlignerError::InvalidInput` if a protein has no coding sequence or a
+/// coding sequence does not match the length of its protein, and an error if an
+/// input cannot be read or is invalid, or the output cannot be written.
+pub fn run(args: CodonArgs) -> Result<(), AlignerError> {
+    let mut proteins = parse_input(&args.proteins)?;
+    check_ascii(&mut proteins, None)?;
+    check_lengths(&proteins, None)?;
+    let mut cds = parse_input(&args.cds)?;
+    check_ascii(&mut cds, None)?;
+    for sequence in cds.values_mut() {
+        sequence.make_ascii_uppercase();
+    }
+
+    let mut coding: HashMap<&str, &str> = HashMap::with_capacity(proteins.len());
+    for (id, protein) in &proteins {
+        let Some(sequence) = cds.get(id) else {
+            return Err(AlignerError::InvalidInput(format!(
+                "protein {} has no coding sequence in {}",
+                id,
+                args.cds.display()
+            )));
+        };
+        coding.insert(id, coding_sequence(id, protein, sequence, args.gencode)?);
+    }
+
+    let pairs = match &args.pairs {
+        Some(path) => PairList::read(path)?.pairs(&proteins),
+        None => Triangle.pairs(&proteins),
+    };
+    info!(
+        proteins = proteins.len(),
+        pairs = pairs.len(),
+        "aligning codons"
+    );
+    let matcher = args.scoring.matcher();
+    let rows: Vec<(String, String)> = pairs
+        .par_iter()
+        .map(|(id1, id2)| {
+            let alignment = align_pair(&proteins[*id1], &proteins[*id2], &matcher);
+            codon_align(&alignment, coding[id1.as_str()], coding[id2.as_str()])
+        })
+        .collect();
+
+    let mut out = BufWriter::new(File::create(&args.output)?);
+    for ((id1, id2), (row1, row2)) in pairs.iter().zip(&rows) {
+        fasta::write(
+            &mut out,
+            [(id1.as_str(), row1.as_str()), (id2.as_str(), row2.as_str())],
+        )?;
+    }
+    out.flush()?;
+    eprintln!(
+        "Wrote codon alignments of {} pairs of {} proteins to {}",
+        pairs.len(),
+        proteins.len(),
+        args.output.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codon_align() {
+        let (protein1, protein2) = ("MKTAYIAKQRQISFVKSHFSRQ", "MKTAYIAKQRISFVKSHFSRQ");
+        let cds1 = "ATGAAAACCGCGTATATTGCGAAACAGCGCCAGATTAGCTTTGTGAAAAGCCATTTTAGCCGCCAGTAA";
+        let cds2 = "ATGAAAACCGCGTATATTGCGAAACAGCGCATTAGCTTTGTGAAAAGCCATTTTAGCCGCCAG";
+        let cds1 = coding_sequence("a", protein1, cds1, GeneticCode::STANDARD).unwrap();
+        assert_eq!(cds1.len(), 66);
+        let alignment = align_pair(protein1, protein2, &ScoringType::Blosum62.matcher());
+        let (row1, row2) = codon_align(&alignment, cds1, cds2);
+        assert_eq!(row1, cds1);
+        assert_eq!(row2.len(), row1.len());
+        // The deleted glutamine of the second protein is a gap of one codon
+        assert_eq!(row2.matches(GAP_CODON).count(), 1);
+        assert_eq!(row2.replace(GAP_CODON, ""), cds2);
+        assert!(coding_sequence("b", protein2, &cds2[3..], GeneticCode::STANDARD).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+//! Cohesion and separation metrics of clusters.
+//!
+//! With `--metrics`, the `cluster` subcommand judges its clusters by the scores of
+//! all pairs: how similar the members of every cluster are (the distribution of
+//! their identities), how similar they are to the closest sequence outside the
+//! cluster, and the silhouette of the cluster, using one minus the identity as the
+//! distance. Clusters with a low silhouette or a separation close to the threshold
+//! suggest that `--min-identity` joins or splits groups it should not.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::cluster::{Cluster, PairScores};
+
+/// Distribution of the identities of the pairs within a cluster
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Distribution {
+    /// Lowest identity
+    pub min: f64,
+    /// Median identity
+    pub median: f64,
+    /// Mean identity
+    pub mean: f64,
+    /// Highest identity
+    pub max: f64,
+}
+
+impl Distribution {
+    /// Summarizes identities, or returns `None` if there are none
+    pub fn of(mut identities: Vec<f64>) -> Option<Self> {
+        if identities.is_empty() {
+            return None;
+        }
+        identities.sort_unstable_by(f64::total_cmp);
+        let n = identities.len();
+        let median = if n % 2 == 1 {
+            identities[n / 2]
+        } else {
+            (identities[n / 2 - 1] + identities[n / 2]) / 2.0
+        };
+        Some(Self {
+            min: identities[0],
+            median,
+            mean: identities.iter().sum::<f64>() / n as f64,
+            max: identities[n - 1],
+        })
+    }
+}
+
+/// Quality metrics of a cluster
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClusterMetrics {
+    /// Number of members
+    pub members: usize,
+    /// Identities of the pairs of members, `None` for singletons
+    pub cohesion: Option<Distribution>,
+    /// Highest identity of a member to a sequence outside the cluster, `None` if
+    /// there is no other cluster
+    pub separation: Option<f64>,
+    /// Mean silhouette of the members, from -1 (closer to another cluster) to 1
+    /// (much closer to the own cluster); 0 for singletons
+    pub silhouette: f64,
+}
+
+/// Computes the metrics of every cluster from the scores of all pairs
+pub fn evaluate(scores: &PairScores, clusters: &[Cluster]) -> Vec<ClusterMetrics> {
+    let mut cluster_of = vec![0; scores.ids.len()];
+    for (index, cluster) in clusters.iter().enumerate() {
+        for &member in &cluster.members {
+            cluster_of[member] = index;
+        }
+    }
+
+    // Total distance of every sequence to the members of every cluster, and the
+    // highest identity of every cluster to a sequence outside it
+    let mut distances: Vec<HashMap<usize, f64>> = vec![HashMap::new(); scores.ids.len()];
+    let mut separation: Vec<Option<f64>> = vec![None; clusters.len()];
+    for pair in &scores.pairs {
+        let (a, b) = (cluster_of[pair.i], cluster_of[pair.j]);
+        *distances[pair.i].entry(b).or_default() += 1.0 - pair.identity;
+        *distances[pair.j].entry(a).or_default() += 1.0 - pair.identity;
+        if a != b {
+            for cluster in [a, b] {
+                let highest = separation[cluster].get_or_insert(pair.identity);
+                *highest = highest.max(pair.identity);
+            }
+        }
+    }
+
+    clusters
+        .iter()
+        .enumerate()
+        .map(|(index, cluster)| {
+            let size = cluster.members.len();
+            let silhouette = if size < 2 {
+                0.0
+            } else {
+                let total: f64 = cluster
+                    .members
+                    .iter()
+                    .map(|&member| {
+                        let own = distances[member].get(&index).copied().unwrap_or_default()
+                            / (size - 1) as f64;
+                        let nearest = distances[member]
+                            .iter()
+                            .filter(|(other, _)| **other != index)
+                            .map(|(other, total)| total / clusters[*other].members.len() as f64)
+                            .reduce(f64::min);
+                        match nearest {
+                            Some(nearest) if own.max(nearest) > 0.0 => {
+                                (nearest - own) / own.max(nearest)
+                            }
+                            _ => 0.0,
+                        }
+                    })
+                    .sum();
+                total / size as f64
+            };
+            ClusterMetrics {
+                members: size,
+                cohesion: Distribution::of(
+                    cluster.pairs.iter().map(|pair| pair.identity).collect(),
+                ),
+                separation: separation[index],
+                silhouette,
+            }
+        })
+        .collect()
+}
+
+/// Returns the mean silhouette of the sequences of all clusters
+pub fn mean_silhouette(metrics: &[ClusterMetrics]) -> f64 {
+    let sequences: usize = metrics.iter().map(|metrics| metrics.members).sum();
+    if sequences == 0 {
+        return 0.0;
+    }
+    metrics
+        .iter()
+        .map(|metrics| metrics.silhouette * metrics.members as f64)
+        .sum::<f64>()
+        / sequences as f64
+}
+
+/// Writes the metrics as a tab-separated table with a row per cluster, numbered
+/// like in the membership table. Values that do not apply are left empty.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be written.
+pub fn write(path: &Path, metrics: &[ClusterMetrics]) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(
+        out,
+        "cluster\tmembers\tmin_identity\tmedian_identity\tmean_identity\tmax_identity\tseparation\tsilhouette"
+    )?;
+    let format = |value: Option<f64>| {
+        value
+            .map(|value| format!("{:.3}", value))
+            .unwrap_or_default()
+    };
+    for (index, metrics) in metrics.iter().enumerate() {
+        let cohesion = metrics.cohesion;
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.3}",
+            index + 1,
+            metrics.members,
+            format(cohesion.map(|c| c.min)),
+            format(cohesion.map(|c| c.median)),
+            format(cohesion.map(|c| c.mean)),
+            format(cohesion.map(|c| c.max)),
+            format(metrics.separation),
+            metrics.silhouette
+        )?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::{PairScore, cluster};
+
+    #[test]
+    fn test_cluster_metrics() {
+        let pair = |i, j, identity| PairScore {
+            i,
+            j,
+            score: 0,
+            identity,
+        };
+        let scores = PairScores {
+            ids: ["a", "b", "c"].map(String::from).to_vec(),
+            sequences: vec![String::new(); 3],
+            pairs: vec![pair(0, 1, 0.9), pair(0, 2, 0.2), pair(1, 2, 0.4)],
+        };
+        let clusters = cluster(&scores, 0.8);
+        let metrics = evaluate(&scores, &clusters);
+
+        assert_eq!(metrics[0].members, 2);
+        assert_eq!(metrics[0].cohesion.unwrap().median, 0.9);
+        assert_eq!(metrics[0].separation, Some(0.4));
+        // a: own 0.1, nearest 0.8; b: own 0.1, nearest 0.6
+        let expected = ((0.8 - 0.1) / 0.8 + (0.6 - 0.1) / 0.6) / 2.0;
+        assert!((metrics[0].silhouette - expected).abs() < 1e-9);
+        assert_eq!(metrics[1].cohesion, None);
+        assert_eq!(metrics[1].silhouette, 0.0);
+        assert!((mean_silhouette(&metrics) - expected * 2.0 / 3.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,493 @@
+//! Registry of the columns of tabular results.
+//!
+//! Every field that can be written for a result is a [`Column`] with a name, the
+//! BLAST-style aliases it is also accepted under, and a way to take its value from
+//! an [`AlignmentResult`]. Writers work from a list of columns, so `--columns`
+//! selects and orders the fields of the output without touching the writers.
+//! Besides the fixed columns, every column of a `--taxonomy` table is a column of
+//! its own.
+
+use std::fmt;
+
+use crate::align::{AlignmentResult, Score};
+use crate::taxonomy::Taxonomy;
+
+/// A field of the results that can be written as a column
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Column {
+    QueryId,
+    SubjectId,
+    Score,
+    Status,
+    Error,
+    QueryLen,
+    SubjectLen,
+    Identity,
+    Pident,
+    Psimilarity,
+    Gaps,
+    AlignedLen,
+    Cigar,
+    MaxScore,
+    ScoreRatio,
+    Containment,
+    Contained,
+    /// A column of the taxonomy table, by position
+    Taxon(usize),
+}
+
+/// The fixed columns, in the order they are listed in help texts
+const FIXED: [Column; 17] = [
+    Column::QueryId,
+    Column::SubjectId,
+    Column::Score,
+    Column::Status,
+    Column::Error,
+    Column::QueryLen,
+    Column::SubjectLen,
+    Column::Identity,
+    Column::Pident,
+    Column::Psimilarity,
+    Column::Gaps,
+    Column::AlignedLen,
+    Column::Cigar,
+    Column::MaxScore,
+    Column::ScoreRatio,
+    Column::Containment,
+    Column::Contained,
+];
+
+impl Column {
+    /// Returns the name of a fixed column, as written in headers
+    fn fixed_name(self) -> &'static str {
+        match self {
+            Column::QueryId => "query_id",
+            Column::SubjectId => "subject_id",
+            Column::Score => "score",
+            Column::Status => "status",
+            Column::Error => "error",
+            Column::QueryLen => "seq1_len",
+            Column::SubjectLen => "seq2_len",
+            Column::Identity => "identity",
+            Column::Pident => "pident",
+            Column::Psimilarity => "psimilarity",
+            Column::Gaps => "gaps",
+            Column::AlignedLen => "aligned_len",
+            Column::Cigar => "cigar",
+            Column::MaxScore => "max_score",
+            Column::ScoreRatio => "score_ratio",
+            Column::Containment => "containment",
+            Column::Contained => "contained",
+            Column::Taxon(_) => "taxon",
+        }
+    }
+
+    /// Returns the other names a fixed column is accepted under
+    fn aliases(self) -> &'static [&'static str] {
+        match self {
+            Column::QueryId => &["qid", "qseqid"],
+            Column::SubjectId => &["sid", "sseqid"],
+            Column::QueryLen => &["qlen"],
+            Column::SubjectLen => &["slen"],
+            Column::Psimilarity => &["ppos"],
+            Column::AlignedLen => &["length"],
+            _ => &[],
+        }
+    }
+
+    /// Returns `true` if the value is taken from the alignment of the pair, which
+    /// only engines with traceback compute
+    pub fn needs_alignment(self) -> bool {
+        matches!(
+            self,
+            Column::Identity
+                | Column::Pident
+                | Column::Psimilarity
+                | Column::Gaps
+                | Column::AlignedLen
+                | Column::Cigar
+        )
+    }
+
+    /// Returns the name of the column, as written in headers
+    pub fn name(self, taxonomy: Option<&Taxonomy>) -> &str {
+        match (self, taxonomy) {
+            (Column::Taxon(i), Some(taxonomy)) => &taxonomy.columns()[i],
+            _ => self.fixed_name(),
+        }
+    }
+
+    /// Returns the column named `name`, a fixed column or its alias or a column of
+    /// `taxonomy`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error listing the known columns if there is no such column.
+    pub fn parse(name: &str, taxonomy: Option<&Taxonomy>) -> Result<Self, String> {
+        let name = name.trim();
+        if let Some(column) = FIXED
+            .into_iter()
+            .find(|column| column.fixed_name() == name || column.aliases().contains(&name))
+        {
+            return Ok(column);
+        }
+        if let Some(i) = taxonomy
+            .and_then(|taxonomy| taxonomy.columns().iter().position(|column| column == name))
+        {
+            return Ok(Column::Taxon(i));
+        }
+        let known: Vec<&str> = FIXED.iter().map(|column| column.fixed_name()).collect();
+        Err(format!(
+            "unknown column '{}', expected one of: {}{}",
+            name,
+            known.join(", "),
+            if taxonomy.is_some() {
+                " or a taxonomy column"
+            } else {
+                ""
+            }
+        ))
+    }
+
+    /// Returns the columns named in a comma-separated list
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a column is unknown or the list is empty.
+    pub fn parse_list(names: &str, taxonomy: Option<&Taxonomy>) -> Result<Vec<Self>, String> {
+        let columns = names
+            .split(',')
+            .filter(|name| !name.trim().is_empty())
+            .map(|name| Self::parse(name, taxonomy))
+            .collect::<Result<Vec<_>, _>>()?;
+        if columns.is_empty() {
+            return Err("no columns selected".to_string());
+        }
+        Ok(columns)
+    }
+
+    /// Returns the columns written by default: the identifiers, score and lengths,
+    /// followed by the identity, CIGAR, maximum score, containment and taxonomy
+    /// columns where these are computed
+    pub fn defaults(
+        identity: bool,
+        cigar: bool,
+        max_score: bool,
+        containment: bool,
+        taxonomy: Option<&Taxonomy>,
+    ) -> Vec<Self> {
+        let mut columns = vec![
+            Column::QueryId,
+            Column::SubjectId,
+            Column::Score,
+            Column::QueryLen,
+            Column::SubjectLen,
+        ];
+        if identity {
+            columns.push(Column::Identity);
+        }
+        if cigar {
+            columns.push(Column::Cigar);
+        }
+        if max_score {
+            columns.extend([Column::MaxScore, Column::ScoreRatio]);
+        }
+        if containment {
+            columns.extend([Column::Containment, Column::Contained]);
+        }
+        if let Some(taxonomy) = taxonomy {
+            columns.extend((0..taxonomy.columns().len()).map(Column::Taxon));
+        }
+        columns
+    }
+
+    /// Returns the columns written by default to typed formats, which also have a
+    /// `status` column after the score
+    pub fn typed_defaults(
+        identity: bool,
+        cigar: bool,
+        max_score: bool,
+        containment: bool,
+        taxonomy: Option<&Taxonomy>,
+    ) -> Vec<Self> {
+        let mut columns = Self::defaults(identity, cigar, max_score, containment, taxonomy);
+        columns.insert(3, Column::Status);
+        columns
+    }
+
+    /// Returns the columns named in the header of a results file with fields
+    /// separated by `delimiter`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a column is unknown.
+    pub fn parse_header(
+        header: &str,
+        delimiter: u8,
+        taxonomy: Option<&Taxonomy>,
+    ) -> Result<Vec<Self>, String> {
+        header
+            .split(char::from(delimiter))
+            .map(|name| Self::parse(name, taxonomy))
+            .collect()
+    }
+
+    /// Returns the type of the values of the column in typed formats
+    pub fn column_type(self) -> ColumnType {
+        match self {
+            Column::Score | Column::MaxScore => ColumnType::Score,
+            Column::QueryLen | Column::SubjectLen | Column::Gaps | Column::AlignedLen => {
+                ColumnType::Count
+            }
+            Column::Identity
+            | Column::Pident
+            | Column::Psimilarity
+            | Column::ScoreRatio
+            | Column::Containment => ColumnType::Real,
+            _ => ColumnType::Text,
+        }
+    }
+
+    /// Returns `true` if the column may lack a value
+    pub fn nullable(self) -> bool {
+        !matches!(
+            self,
+            Column::QueryId
+                | Column::SubjectId
+                | Column::Status
+                | Column::QueryLen
+                | Column::SubjectLen
+        )
+    }
+
+    /// Returns the value of the column for `result`. `min_containment` is the
+    /// fraction flagging a containment, if containments are measured.
+    pub fn value<'a, S: Score>(
+        self,
+        result: &'a AlignmentResult<S>,
+        min_containment: Option<f64>,
+        taxonomy: Option<&'a Taxonomy>,
+    ) -> Value<'a, S> {
+        match self {
+            Column::QueryId => Value::Text(&result.query_id),
+            Column::SubjectId => Value::Text(&result.subject_id),
+            Column::Score => Value::Score(result.score),
+            Column::Status => Value::Text(result.status.as_str()),
+            Column::Error => result.error.as_deref().map_or(Value::Missing, Value::Text),
+            Column::QueryLen => Value::Count(result.seq1_len),
+            Column::SubjectLen => Value::Count(result.seq2_len),
+            Column::Identity => result.identity.map_or(Value::Missing, Value::Fraction),
+            Column::Pident => result
+                .identity
+                .map_or(Value::Missing, |identity| Value::Percent(identity * 100.0)),
+            Column::Psimilarity => result.summary.map_or(Value::Missing, |summary| {
+                Value::Percent(summary.similarity() * 100.0)
+            }),
+            Column::Gaps => result
+                .summary
+                .map_or(Value::Missing, |summary| Value::Count(summary.gaps)),
+            Column::AlignedLen => result
+                .summary
+                .map_or(Value::Missing, |summary| Value::Count(summary.columns)),
+            Column::Cigar => result.cigar.as_deref().map_or(Value::Missing, Value::Text),
+            Column::MaxScore => result
+                .max_score
+                .map_or(Value::Missing, |max_score| Value::Score(Some(max_score))),
+            Column::ScoreRatio => match (result.score, result.max_score) {
+                (Some(score), Some(max_score)) if max_score.into() > 0.0 => {
+                    Value::Fraction(score.into() / max_score.into())
+                }
+                _ => Value::Missing,
+            },
+            Column::Containment => match (min_containment, result.containment) {
+                (Some(_), Some(containment)) => Value::Fraction(containment.fraction),
+                _ => Value::Missing,
+            },
+            Column::Contained => match (min_containment, result.containment) {
+                (Some(min_fraction), Some(containment))
+                    if containment.is_contained(min_fraction) =>
+                {
+                    Value::Text(if result.seq1_len <= result.seq2_len {
+                        "query"
+                    } else {
+                        "subject"
+                    })
+                }
+                _ => Value::Missing,
+            },
+            Column::Taxon(i) => taxonomy
+                .and_then(|taxonomy| taxonomy.lineage(&result.subject_id))
+                .map_or(Value::Missing, |lineage| Value::Text(&lineage[i])),
+        }
+    }
+}
+
+/// Type of the values of a column in typed formats such as Parquet
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColumnType {
+    Text,
+    /// Unsigned integers
+    Count,
+    /// Alignment scores, integers or floats depending on the scoring scheme
+    Score,
+    /// Floating-point numbers
+    Real,
+}
+
+/// The value of a column for a result
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value<'a, S> {
+    Text(&'a str),
+    Count(usize),
+    /// Score of the pair, `None` if it was not aligned
+    Score(Option<S>),
+    /// A fraction between 0 and 1
+    Fraction(f64),
+    /// A percentage between 0 and 100
+    Percent(f64),
+    /// A value that was not computed for the result
+    Missing,
+}
+
+impl<'a, S: Score> Value<'a, S> {
+    /// Returns the text of a text value
+    pub fn as_text(&self) -> Option<&'a str> {
+        match self {
+            Value::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of a count
+    pub fn as_count(&self) -> Option<usize> {
+        match self {
+            Value::Count(count) => Some(*count),
+            _ => None,
+        }
+    }
+
+    /// Returns the score of an aligned pair
+    pub fn as_score(&self) -> Option<S> {
+        match self {
+            Value::Score(score) => *score,
+            _ => None,
+        }
+    }
+
+    /// Returns the number of a fraction or percentage
+    pub fn as_real(&self) -> Option<f64> {
+        match self {
+            Value::Fraction(value) | Value::Percent(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+/// Serializes values with their type, and scores of pairs that were not aligned
+/// and missing values as `null`
+impl<S: Score> serde::Serialize for Value<'_, S> {
+    fn serialize<T: serde::Serializer>(&self, serializer: T) -> Result<T::Ok, T::Error> {
+        match self {
+            Value::Text(text) => serializer.serialize_str(text),
+            Value::Count(count) => serializer.serialize_u64(*count as u64),
+            Value::Score(Some(score)) => score.serialize(serializer),
+            Value::Fraction(value) | Value::Percent(value) => serializer.serialize_f64(*value),
+            Value::Score(None) | Value::Missing => serializer.serialize_none(),
+        }
+    }
+}
+
+/// Writes values as in the delimited formats: scores of pairs that were not
+/// aligned as -1, fractions and percentages with three decimals unless a precision
+/// is given and missing values as empty fields
+impl<S: Score> fmt::Display for Value<'_, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Text(text) => f.write_str(text),
+            Value::Count(count) => write!(f, "{}", count),
+            Value::Score(score) => match f.precision() {
+                Some(precision) => write!(f, "{:.*}", precision, score.unwrap_or(S::SKIPPED)),
+                None => write!(f, "{}", score.unwrap_or(S::SKIPPED)),
+            },
+            Value::Fraction(value) | Value::Percent(value) => {
+                write!(f, "{:.*}", f.precision().unwrap_or(3), value)
+            }
+            Value::Missing => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::align::{PairStatus, Summary};
+
+    #[test]
+    fn test_columns() {
+        let columns = Column::parse_list("qid,sseqid,pident,score,status", None).unwrap();
+        assert_eq!(
+            columns,
+            [
+                Column::QueryId,
+                Column::SubjectId,
+                Column::Pident,
+                Column::Score,
+                Column::Status
+            ]
+        );
+        assert!(
+            Column::parse("qcov", None)
+                .unwrap_err()
+                .contains("expected one of: query_id")
+        );
+        assert!(Column::parse_list(",", None).is_err());
+        assert_eq!(
+            Column::parse_header("subject_id\tquery_id\tscore", b'\t', None).unwrap(),
+            [Column::SubjectId, Column::QueryId, Column::Score]
+        );
+        assert!(Column::parse_header("query_id,subject_id", b'\t', None).is_err());
+        assert_eq!(
+            Column::parse_header("query_id,subject_id", b',', None).unwrap(),
+            [Column::QueryId, Column::SubjectId]
+        );
+
+        let result = AlignmentResult::<i32> {
+            identity: Some(0.8125),
+            ..AlignmentResult::for_test("a", "b", None, PairStatus::Skipped)
+        };
+        let row: Vec<String> = columns
+            .iter()
+            .map(|column| column.value(&result, None, None).to_string())
+            .collect();
+        assert_eq!(row, ["a", "b", "81.250", "-1", "skipped"]);
+
+        let aligned = AlignmentResult {
+            score: Some(12),
+            status: PairStatus::Aligned,
+            max_score: Some(16),
+            ..result
+        };
+        let row: Vec<String> = Column::parse_list("max_score,score_ratio", None)
+            .unwrap()
+            .iter()
+            .map(|column| column.value(&aligned, None, None).to_string())
+            .collect();
+        assert_eq!(row, ["16", "0.750"]);
+
+        let traced = AlignmentResult {
+            summary: Some(Summary {
+                matches: 13,
+                positives: 14,
+                gaps: 2,
+                columns: 16,
+            }),
+            ..aligned
+        };
+        let row: Vec<String> = Column::parse_list("ppos,gaps,length", None)
+            .unwrap()
+            .iter()
+            .map(|column| column.value(&traced, None, None).to_string())
+            .collect();
+        assert_eq!(row, ["87.500", "2", "16"]);
+    }
+}
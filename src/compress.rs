@@ -0,0 +1,297 @@
+//! Transparent decompression of compressed inputs and compression of outputs.
+//!
+//! Inputs compressed with gzip, zstd, bzip2 or xz are recognized by their magic
+//! bytes, or by their extension if the magic bytes are not recognized. They are
+//! decompressed to a temporary file named like the input without the compression
+//! extension, so that every input format, SQLite databases included, is read and
+//! detected as if the input had not been compressed.
+//!
+//! Outputs are compressed while they are written by a [`Compressor`], so results
+//! never exist uncompressed on disk.
+
+use bzip2::read::MultiBzDecoder;
+use bzip2::write::BzEncoder;
+use clap::ValueEnum;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::info;
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
+
+use crate::error::AlignerError;
+
+/// Compression formats of inputs and outputs
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+    /// gzip, including concatenated members as written by `bgzip`
+    Gzip,
+    /// Zstandard
+    Zstd,
+    /// bzip2
+    Bzip2,
+    /// xz
+    Xz,
+}
+
+impl Compression {
+    const ALL: [Compression; 4] = [
+        Compression::Gzip,
+        Compression::Zstd,
+        Compression::Bzip2,
+        Compression::Xz,
+    ];
+
+    /// Bytes every file in this format starts with
+    fn magic(self) -> &'static [u8] {
+        match self {
+            Compression::Gzip => &[0x1f, 0x8b],
+            Compression::Zstd => &[0x28, 0xb5, 0x2f, 0xfd],
+            Compression::Bzip2 => b"BZh",
+            Compression::Xz => &[0xfd, b'7', b'z', b'X', b'Z', 0x00],
+        }
+    }
+
+    /// Lower-case file extensions of files in this format
+    fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Compression::Gzip => &["gz", "gzip"],
+            Compression::Zstd => &["zst", "zstd"],
+            Compression::Bzip2 => &["bz2"],
+            Compression::Xz => &["xz"],
+        }
+    }
+
+    /// Returns the compression of the file at `path`, or `None` if it is not
+    /// compressed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened or read.
+    pub fn detect(path: &Path) -> io::Result<Option<Self>> {
+        let mut start = Vec::with_capacity(6);
+        File::open(path)?.take(6).read_to_end(&mut start)?;
+        if let Some(compression) = Self::ALL
+            .into_iter()
+            .find(|compression| start.starts_with(compression.magic()))
+        {
+            return Ok(Some(compression));
+        }
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        Ok(Self::ALL.into_iter().find(|compression| {
+            extension
+                .as_deref()
+                .is_some_and(|extension| compression.extensions().contains(&extension))
+        }))
+    }
+
+    /// Returns the compression matching the extension of `path`, or `None` if it
+    /// does not end in a compression extension
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())?
+            .to_ascii_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|compression| compression.extensions().contains(&extension.as_str()))
+    }
+
+    /// Wraps a reader of compressed data in a decoder
+    fn decoder<'a>(self, reader: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+        let reader = BufReader::new(reader);
+        Ok(match self {
+            Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+            Compression::Zstd => Box::new(zstd::Decoder::with_buffer(reader)?),
+            Compression::Bzip2 => Box::new(MultiBzDecoder::new(reader)),
+            Compression::Xz => Box::new(XzDecoder::new_multi_decoder(reader)),
+        })
+    }
+}
+
+/// An encoder writing one compressed stream
+enum Encoder<W: Write> {
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+    Bzip2(BzEncoder<W>),
+    Xz(XzEncoder<W>),
+}
+
+impl<W: Write> Encoder<W> {
+    fn new(compression: Compression, writer: W) -> io::Result<Self> {
+        Ok(match compression {
+            Compression::Gzip => Encoder::Gzip(GzEncoder::new(writer, Default::default())),
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(writer, 0)?),
+            Compression::Bzip2 => Encoder::Bzip2(BzEncoder::new(writer, Default::default())),
+            Compression::Xz => Encoder::Xz(XzEncoder::new(writer, 6)),
+        })
+    }
+
+    fn get_mut(&mut self) -> &mut dyn Write {
+        match self {
+            Encoder::Gzip(encoder) => encoder,
+            Encoder::Zstd(encoder) => encoder,
+            Encoder::Bzip2(encoder) => encoder,
+            Encoder::Xz(encoder) => encoder,
+        }
+    }
+
+    /// Completes the stream and returns the underlying writer
+    fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+            Encoder::Bzip2(encoder) => encoder.finish(),
+            Encoder::Xz(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// Compresses everything written to it before passing it on to a writer.
+///
+/// Flushing completes the compressed stream, and writing after a flush starts
+/// another one. All four formats allow concatenated streams, which decompress to
+/// the concatenated data, so whatever was written before a flush can be
+/// decompressed even if the process stops afterwards. Results sinks only flush
+/// when they are closed, so a results file usually holds a single stream.
+pub struct Compressor<W: Write> {
+    compression: Compression,
+    /// Encoder of the current stream, if anything was written since the last flush
+    encoder: Option<Encoder<W>>,
+    /// The underlying writer between streams, `None` if completing a stream failed
+    writer: Option<W>,
+}
+
+impl<W: Write> Compressor<W> {
+    /// Creates a compressor writing to `writer`
+    pub fn new(compression: Compression, writer: W) -> Self {
+        Self {
+            compression,
+            encoder: None,
+            writer: Some(writer),
+        }
+    }
+}
+
+impl<W: Write> Write for Compressor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.encoder.is_none() {
+            let writer = self
+                .writer
+                .take()
+                .ok_or_else(|| io::Error::other("compressed output failed earlier"))?;
+            self.encoder = Some(Encoder::new(self.compression, writer)?);
+        }
+        match &mut self.encoder {
+            Some(encoder) => encoder.get_mut().write(buf),
+            None => unreachable!("encoder was just created"),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(encoder) = self.encoder.take() {
+            self.writer = Some(encoder.finish()?);
+        }
+        match &mut self.writer {
+            Some(writer) => writer.flush(),
+            None => Err(io::Error::other("compressed output failed earlier")),
+        }
+    }
+}
+
+/// Decompresses the input at `path` to a temporary file named like it without
+/// the compression extension, and returns the path of that file. The caller
+/// removes the file.
+///
+/// # Errors
+///
+/// Returns `AlignerError::InvalidInput` if the input is not valid compressed data
+/// and `AlignerError::Io` if the temporary file cannot be written.
+pub fn decompress(path: &Path, compression: Compression) -> Result<PathBuf, AlignerError> {
+    let name = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension)
+            if compression
+                .extensions()
+                .contains(&extension.to_ascii_lowercase().as_str()) =>
+        {
+            path.file_stem()
+        }
+        _ => path.file_name(),
+    };
+    let name = name.and_then(|name| name.to_str()).unwrap_or("input");
+    let target = std::env::temp_dir().join(format!("aligner-{}-{}", std::process::id(), name));
+
+    info!(path = %path.display(), ?compression, "decompressing input");
+    let written = compression
+        .decoder(File::open(path)?)
+        .and_then(|mut decoder| io::copy(&mut decoder, &mut File::create(&target)?));
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&target);
+        return Err(match e.kind() {
+            io::ErrorKind::InvalidInput
+            | io::ErrorKind::InvalidData
+            | io::ErrorKind::UnexpectedEof => AlignerError::InvalidInput(format!(
+                "{}: invalid {:?} data: {}",
+                path.display(),
+                compression,
+                e
+            )),
+            _ => AlignerError::Io(e),
+        });
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::ParseOptions;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    #[test]
+    fn test_compressed_inputs() {
+        let dir = std::env::temp_dir().join(format!("aligner-compress-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let json = br#"{"a": "MAVMT", "b": "MAVKT"}"#;
+
+        let gzip = dir.join("seqs.json.gz");
+        let mut encoder = GzEncoder::new(File::create(&gzip).unwrap(), Default::default());
+        encoder.write_all(json).unwrap();
+        encoder.finish().unwrap();
+        // Detected from the magic bytes despite the missing extension
+        let zstd = dir.join("seqs.fasta");
+        std::fs::write(&zstd, zstd::encode_all(&b">a\nMAVMT\n"[..], 0).unwrap()).unwrap();
+
+        assert_eq!(Compression::detect(&gzip).unwrap(), Some(Compression::Gzip));
+        assert_eq!(Compression::detect(&zstd).unwrap(), Some(Compression::Zstd));
+        let parsed = crate::source::read(&gzip, &ParseOptions::default()).unwrap();
+        assert_eq!(parsed.sequences["b"], "MAVKT");
+        let parsed = crate::source::read(&zstd, &ParseOptions::default()).unwrap();
+        assert_eq!(parsed.sequences["a"], "MAVMT");
+
+        // A flush completes a stream, and the following writes start another
+        let mut compressor = Compressor::new(Compression::Zstd, Vec::new());
+        compressor.write_all(b">a\nMAV").unwrap();
+        compressor.flush().unwrap();
+        compressor.write_all(b"MT\n").unwrap();
+        compressor.flush().unwrap();
+        let written = compressor.writer.take().unwrap();
+        assert_eq!(zstd::decode_all(&written[..]).unwrap(), b">a\nMAVMT\n");
+        assert_eq!(
+            Compression::from_extension(Path::new("out.tsv.GZ")),
+            Some(Compression::Gzip)
+        );
+
+        let broken = dir.join("broken.json.xz");
+        std::fs::write(&broken, json).unwrap();
+        let error = crate::source::read(&broken, &ParseOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("invalid Xz data"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,455 @@
+//! Configuration from environment variables, `.env` files and presets.
+//!
+//! Options holding credentials or site-wide defaults, such as the Neo4j
+//! connection and the number of threads, can be set through `ALIGNER_*`
+//! environment variables instead of the command line, so passwords do not end up
+//! in the shell history of a shared cluster or in the metadata sidecar of a run.
+//! Before the arguments are parsed, the variables of a `.env` file in the working
+//! directory, or of the file named by `ALIGNER_ENV_FILE`, are added to the
+//! environment.
+//!
+//! Values are resolved in this order, the first one found winning:
+//!
+//! 1. the option on the command line
+//! 2. the variable in the environment of the process
+//! 3. the variable in the `.env` file
+//! 4. the default of the option
+//!
+//! Presets bundle the options suiting a kind of data under a name selected with
+//! `--preset`, e.g. `--preset protein-remote`. Besides the built-in presets, the
+//! configuration file, `~/.config/aligner/config.toml` or the file named by
+//! `ALIGNER_CONFIG`, defines presets as tables of option names and values:
+//!
+//! ```toml
+//! [presets.kinases]
+//! scoring = "blosum62"
+//! mode = "local"
+//! gap-open = 11
+//! filter = ["length:0.5"]
+//! ```
+//!
+//! The options of a preset are placed before those on the command line, so any
+//! option given there overrides the preset. Presets are selected with `--preset`
+//! because `--profile` already profiles the stages of a run, but `[profiles.NAME]`
+//! tables are read as presets as well.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::error::AlignerError;
+
+/// Variable naming the file loaded instead of `.env` in the working directory
+pub const ENV_FILE_VAR: &str = "ALIGNER_ENV_FILE";
+
+/// File loaded from the working directory if `ALIGNER_ENV_FILE` is not set
+const DEFAULT_ENV_FILE: &str = ".env";
+
+/// Variable naming the configuration file read instead of the one in the home
+/// directory
+pub const CONFIG_VAR: &str = "ALIGNER_CONFIG";
+
+/// Presets available without a configuration file
+const BUILTIN_PRESETS: &str = r#"
+# Distant homologs, e.g. a conserved domain within otherwise unrelated proteins
+[presets.protein-remote]
+scoring = "blosum62"
+mode = "local"
+gap-open = 11
+gap-extend = 1
+
+# Members of a protein family, only aligning pairs sharing 3-mers
+[presets.protein-close]
+scoring = "blosum62"
+mode = "global"
+candidate-kmer = 3
+
+# Highly similar nucleotide sequences, aligned with wavefronts
+[presets.dna-close]
+scoring = "identity"
+mode = "global"
+engine = "wfa"
+gap-open = 5
+gap-extend = 2
+candidate-kmer = 11
+"#;
+
+/// Variable naming the Neo4j server runs without another output write to
+const NEO4J_URI_VAR: &str = "ALIGNER_NEO4J_URI";
+
+/// Returns the URI of the Neo4j server set in the environment, if any.
+///
+/// Unlike the other variables, it is not read by the argument parser: a server
+/// configured for all runs must not conflict with the output of a single run,
+/// so it only applies to runs that were given no other output.
+pub fn neo4j_uri() -> Option<String> {
+    std::env::var(NEO4J_URI_VAR)
+        .ok()
+        .filter(|uri| !uri.is_empty())
+}
+
+/// Parses the lines of a `.env` file: `KEY=VALUE` assignments, optionally
+/// preceded by `export` and with the value in single or double quotes. Empty lines
+/// and lines starting with `#` are skipped.
+///
+/// # Errors
+///
+/// Returns the number and reason of the first line that is not an assignment.
+fn parse(contents: &str) -> Result<Vec<(String, String)>, String> {
+    let mut variables = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("line {}: expected KEY=VALUE", number + 1));
+        };
+        let key = key.trim();
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            return Err(format!(
+                "line {}: invalid variable name '{}'",
+                number + 1,
+                key
+            ));
+        }
+        let value = value.trim();
+        let value = unquote(value).unwrap_or(value);
+        variables.push((key.to_string(), value.to_string()));
+    }
+    Ok(variables)
+}
+
+/// Parses the presets of a configuration file into the command-line options of
+/// every preset, by name. Values are TOML strings, numbers, booleans or arrays of
+/// these: `true` gives a flag, `false` leaves it out and the elements of an array
+/// are given to the option one by one.
+///
+/// # Errors
+///
+/// Returns the number and reason of the first line that cannot be parsed.
+fn parse_presets(contents: &str) -> Result<BTreeMap<String, Vec<String>>, String> {
+    let mut presets = BTreeMap::new();
+    let mut current: Option<&mut Vec<String>> = None;
+    for (number, line) in contents.lines().enumerate() {
+        let error = |reason: String| format!("line {}: {}", number + 1, reason);
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(table) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            let table = table.trim();
+            let Some(name) = ["presets.", "profiles."]
+                .iter()
+                .find_map(|prefix| table.strip_prefix(prefix))
+            else {
+                return Err(error(format!("unknown table [{}]", table)));
+            };
+            let name = unquote(name.trim()).unwrap_or(name.trim());
+            current = Some(presets.entry(name.to_string()).or_default());
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(error("expected `option = value`".to_string()));
+        };
+        let Some(arguments) = current.as_deref_mut() else {
+            return Err(error(
+                "options must follow a [presets.NAME] table".to_string(),
+            ));
+        };
+        let option = format!("--{}", key.trim().replace('_', "-"));
+        let value = value.trim();
+        let values: Vec<&str> = match value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            Some(elements) => split_elements(elements),
+            None => vec![value],
+        };
+        for value in values {
+            match value {
+                "true" => arguments.push(option.clone()),
+                "false" => {}
+                _ if value.starts_with(['"', '\'']) => {
+                    let text = unquote(value)
+                        .ok_or_else(|| error(format!("unterminated string {}", value)))?;
+                    arguments.push(format!("{}={}", option, text));
+                }
+                _ if value.parse::<f64>().is_ok() => {
+                    arguments.push(format!("{}={}", option, value));
+                }
+                _ => return Err(error(format!("invalid value {}", value))),
+            }
+        }
+    }
+    Ok(presets)
+}
+
+/// Removes a `#` comment outside of quotes from a line
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            ('#', None) => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Splits the elements of an array at the commas outside of quotes
+fn split_elements(elements: &str) -> Vec<&str> {
+    let (mut parts, mut start, mut quote) = (Vec::new(), 0, None);
+    for (i, c) in elements.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            (',', None) => {
+                parts.push(&elements[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&elements[start..]);
+    parts
+        .into_iter()
+        .map(str::trim)
+        .filter(|element| !element.is_empty())
+        .collect()
+}
+
+/// Returns the text of a string in double or single quotes
+fn unquote(value: &str) -> Option<&str> {
+    ['"', '\''].iter().find_map(|&quote| {
+        value
+            .strip_prefix(quote)
+            .and_then(|value| value.strip_suffix(quote))
+    })
+}
+
+/// Returns the presets of the configuration file, on top of the built-in ones. A
+/// missing configuration file in the home directory is ignored, a missing file
+/// named by `ALIGNER_CONFIG` is an error.
+///
+/// # Errors
+///
+/// Returns `AlignerError::Config` if the file cannot be read or parsed.
+fn presets() -> Result<BTreeMap<String, Vec<String>>, AlignerError> {
+    let mut presets = parse_presets(BUILTIN_PRESETS).map_err(AlignerError::Config)?;
+    let (path, required) = match std::env::var_os(CONFIG_VAR) {
+        Some(path) => (PathBuf::from(path), true),
+        None => match std::env::var_os("HOME") {
+            Some(home) => (
+                PathBuf::from(home).join(".config/aligner/config.toml"),
+                false,
+            ),
+            None => return Ok(presets),
+        },
+    };
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => return Ok(presets),
+        Err(e) => {
+            return Err(AlignerError::Config(format!(
+                "cannot read {}: {}",
+                path.display(),
+                e
+            )));
+        }
+    };
+    let configured = parse_presets(&contents)
+        .map_err(|e| AlignerError::Config(format!("{}: {}", path.display(), e)))?;
+    presets.extend(configured);
+    Ok(presets)
+}
+
+/// Returns the command-line arguments with the options of the preset selected
+/// with `--preset` placed before all others, or the arguments as they are
+/// without a preset. Presets only apply to the alignment without a subcommand,
+/// so arguments starting with one of `subcommands` are returned as they are and
+/// a `--preset` among them is rejected by the subcommand.
+///
+/// # Errors
+///
+/// Returns `AlignerError::Config` if the preset is not defined or the
+/// configuration file cannot be read.
+pub fn expand_preset(
+    arguments: Vec<OsString>,
+    subcommands: &[&str],
+) -> Result<Vec<OsString>, AlignerError> {
+    if arguments
+        .get(1)
+        .and_then(|argument| argument.to_str())
+        .is_some_and(|argument| subcommands.contains(&argument))
+    {
+        return Ok(arguments);
+    }
+    let mut name = None;
+    let mut rest = arguments.iter().skip(1).map(|argument| argument.to_str());
+    while let Some(argument) = rest.next() {
+        match argument {
+            Some("--") => break,
+            Some("--preset") => name = rest.next().flatten(),
+            Some(argument) if argument.starts_with("--preset=") => {
+                name = argument.strip_prefix("--preset=");
+            }
+            _ => {}
+        }
+    }
+    let Some(name) = name else {
+        return Ok(arguments);
+    };
+    let mut presets = presets()?;
+    let Some(options) = presets.remove(name) else {
+        let known: Vec<&str> = presets.keys().map(String::as_str).collect();
+        return Err(AlignerError::Config(format!(
+            "unknown preset '{}', expected one of: {}",
+            name,
+            known.join(", ")
+        )));
+    };
+    let mut expanded = arguments[..1].to_vec();
+    expanded.extend(options.into_iter().map(OsString::from));
+    expanded.extend(arguments[1..].iter().cloned());
+    Ok(expanded)
+}
+
+/// Adds the variables of the `.env` file to the environment, keeping those
+/// already set. A missing `.env` in the working directory is ignored, a missing
+/// file named by `ALIGNER_ENV_FILE` is an error.
+///
+/// Must be called before any other thread is started, as it modifies the
+/// environment of the process.
+///
+/// # Returns
+///
+/// The number of variables added
+///
+/// # Errors
+///
+/// Returns `AlignerError::Config` if the file cannot be read or parsed.
+pub fn load_env_file() -> Result<usize, AlignerError> {
+    let (path, required) = match std::env::var_os(ENV_FILE_VAR) {
+        Some(path) => (PathBuf::from(path), true),
+        None => (PathBuf::from(DEFAULT_ENV_FILE), false),
+    };
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => return Ok(0),
+        Err(e) => {
+            return Err(AlignerError::Config(format!(
+                "cannot read {}: {}",
+                path.display(),
+                e
+            )));
+        }
+    };
+    let variables =
+        parse(&contents).map_err(|e| AlignerError::Config(format!("{}: {}", path.display(), e)))?;
+    let mut added = 0;
+    for (key, value) in variables {
+        if std::env::var_os(&key).is_none() {
+            // SAFETY: called at the start of `main`, before any other thread
+            // could read the environment
+            unsafe { std::env::set_var(&key, value) };
+            added += 1;
+        }
+    }
+    Ok(added)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_env_file() {
+        let contents = "# Neo4j of the lab\n\
+                        ALIGNER_NEO4J_URI=http://localhost:7474\n\
+                        export ALIGNER_NEO4J_PASS=\"s3cret #1\"\n\
+                        \n\
+                        ALIGNER_THREADS = '8'\n\
+                        EMPTY=\n";
+        assert_eq!(
+            parse(contents).unwrap(),
+            [
+                ("ALIGNER_NEO4J_URI", "http://localhost:7474"),
+                ("ALIGNER_NEO4J_PASS", "s3cret #1"),
+                ("ALIGNER_THREADS", "8"),
+                ("EMPTY", ""),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+        assert_eq!(
+            parse("A=1\nnot an assignment\n").unwrap_err(),
+            "line 2: expected KEY=VALUE"
+        );
+        assert!(parse("MY VAR=1").is_err());
+    }
+
+    #[test]
+    fn test_presets() {
+        let presets = parse_presets(BUILTIN_PRESETS).unwrap();
+        assert_eq!(
+            presets["protein-remote"],
+            [
+                "--scoring=blosum62",
+                "--mode=local",
+                "--gap-open=11",
+                "--gap-extend=1"
+            ]
+        );
+        let presets = parse_presets(
+            "[presets.\"kinases\"]  # family of the lab\n\
+             mode = 'local'\n\
+             low_memory = true\n\
+             full-matrix = false\n\
+             filter = [\"length:0.5\", \"sketch:0.1\", \"a,b\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            presets["kinases"],
+            [
+                "--mode=local",
+                "--low-memory",
+                "--filter=length:0.5",
+                "--filter=sketch:0.1",
+                "--filter=a,b"
+            ]
+        );
+        assert_eq!(
+            parse_presets("gap-open = 3").unwrap_err(),
+            "line 1: options must follow a [presets.NAME] table"
+        );
+        assert_eq!(
+            parse_presets("[profiles.dna]\nmode = \"local\"").unwrap()["dna"],
+            ["--mode=local"]
+        );
+        assert!(parse_presets("[settings.dna]").is_err());
+        assert!(parse_presets("[presets.dna]\nmode = local").is_err());
+
+        let arguments = |list: &[&str]| list.iter().map(OsString::from).collect::<Vec<_>>();
+        let expanded = expand_preset(
+            arguments(&["aligner", "in.fasta", "--preset", "dna-close"]),
+            &["cluster"],
+        )
+        .unwrap();
+        assert_eq!(expanded[1], "--scoring=identity");
+        assert_eq!(
+            expanded[expanded.len() - 3..],
+            arguments(&["in.fasta", "--preset", "dna-close"])
+        );
+        assert!(expand_preset(arguments(&["aligner", "--preset=nope"]), &[]).is_err());
+        let subcommand = arguments(&["aligner", "cluster", "--preset", "dna-close", "in.json"]);
+        assert_eq!(
+            expand_preset(subcommand.clone(), &["cluster"]).unwrap(),
+            subcommand
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Consensus sequences and column conservation of existing alignments.
+//!
+//! The `consensus` subcommand writes the consensus sequence of every alignment of
+//! a Stockholm or aligned FASTA file as FASTA, and the `conservation` subcommand
+//! writes how conserved every column of the alignments is, e.g. to find the
+//! invariant residues of a family before choosing sites to inspect.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::error::AlignerError;
+use crate::fasta;
+use crate::msa::{Msa, read_alignments};
+
+/// Command-line arguments for the `consensus` subcommand
+#[derive(clap::Args, Debug)]
+pub struct ConsensusArgs {
+    /// Alignment file, read as Stockholm if it ends in `.sto` or `.stk` and as
+    /// aligned FASTA otherwise
+    alignment: PathBuf,
+
+    /// Path of the FASTA file with the consensus of every alignment
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Fraction of the residues of a column the most frequent one must make up;
+    /// other columns get `X`, or `N` in nucleotide alignments
+    #[arg(long, default_value_t = 0.5)]
+    min_frequency: f64,
+
+    /// Fraction of gaps above which a column is left out of the consensus
+    #[arg(long, default_value_t = 0.5)]
+    max_gaps: f64,
+}
+
+/// Command-line arguments for the `conservation` subcommand
+#[derive(clap::Args, Debug)]
+pub struct ConservationArgs {
+    /// Alignment file, read as Stockholm if it ends in `.sto` or `.stk` and as
+    /// aligned FASTA otherwise
+    alignment: PathBuf,
+
+    /// Path of the tab-separated table with a row per column of every alignment
+    #[arg(short, long)]
+    output: PathBuf,
+}
+
+/// Runs the `consensus` subcommand.
+///
+/// # Errors
+///
+/// Returns `AlignerError::InvalidInput` if a fraction is not between 0 and 1, and
+/// an error if the alignment cannot be read or the output cannot be written.
+pub fn run_consensus(args: ConsensusArgs) -> Result<(), AlignerError> {
+    for (name, fraction) in [
+        ("--min-frequency", args.min_frequency),
+        ("--max-gaps", args.max_gaps),
+    ] {
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err(AlignerError::InvalidInput(format!(
+                "{} must be between 0 and 1",
+                name
+            )));
+        }
+    }
+    let alignments = read_alignments(&args.alignment)?;
+    let consensus: Vec<(String, String)> = alignments
+        .iter()
+        .map(|msa| {
+            (
+                name(msa).to_string(),
+                msa.consensus(args.min_frequency, args.max_gaps),
+            )
+        })
+        .collect();
+
+    let mut out = BufWriter::new(File::create(&args.output)?);
+    fasta::write(
+        &mut out,
+        consensus
+            .iter()
+            .map(|(name, sequence)| (name.as_str(), sequence.as_str())),
+    )?;
+    out.flush()?;
+
+    for ((name, sequence), msa) in consensus.iter().zip(&alignments) {
+        eprintln!(
+            "{}: consensus of {} residues from {} sequences and {} columns",
+            name,
+            sequence.len(),
+            msa.ids.len(),
+            msa.columns()
+        );
+    }
+    eprintln!("Consensus written to {}", args.output.display());
+    Ok(())
+}
+
+/// Runs the `conservation` subcommand.
+///
+/// # Errors
+///
+/// Returns an error if the alignment cannot be read or the output cannot be
+/// written.
+pub fn run_conservation(args: ConservationArgs) -> Result<(), AlignerError> {
+    let alignments = read_alignments(&args.alignment)?;
+
+    let mut out = BufWriter::new(File::create(&args.output)?);
+    writeln!(
+        out,
+        "alignment\tcolumn\tresidue\tfrequency\tgaps\tentropy\tconservation"
+    )?;
+    for msa in &alignments {
+        let kind = msa.kind();
+        let stats = msa.column_stats();
+        info!(
+            alignment = name(msa),
+            columns = stats.len(),
+            "computing column conservation"
+        );
+        for (column, stats) in stats.iter().enumerate() {
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{:.3}\t{:.3}\t{:.3}\t{:.3}",
+                name(msa),
+                column + 1,
+                stats.residue.map_or('-', char::from),
+                stats.frequency,
+                stats.gaps,
+                stats.entropy,
+                stats.conservation(kind)
+            )?;
+        }
+        let invariant = stats
+            .iter()
+            .filter(|stats| stats.gaps == 0.0 && stats.frequency == 1.0)
+            .count();
+        eprintln!(
+            "{}: {} of {} columns invariant across {} {} sequences",
+            name(msa),
+            invariant,
+            stats.len(),
+            msa.ids.len(),
+            kind.as_str()
+        );
+    }
+    out.flush()?;
+    eprintln!("Conservation written to {}", args.output.display());
+    Ok(())
+}
+
+/// Returns the name of an alignment read by [`read_alignments`]
+fn name(msa: &Msa) -> &str {
+    msa.name.as_deref().unwrap_or_default()
+}
@@ -0,0 +1,89 @@
+//! Detection of sequences contained in others.
+//!
+//! A fragment aligned globally with the full-length sequence it was cut from
+//! scores like a divergent pair, because the unaligned ends of the longer sequence
+//! are penalized as gaps. With `--containment`, every aligned pair is aligned once
+//! more semi-globally, the shorter sequence end to end and the longer one with free
+//! end gaps, and the results report which fraction of the shorter sequence was
+//! found in the longer one. A pair is flagged as a containment when that fraction
+//! is high while the alignment covers only part of the longer sequence.
+
+use bio::alignment::AlignmentOperation;
+
+use crate::align::{GapPenalties, MatcherFn};
+use crate::workspace::{self, NO_CLIPS};
+
+/// How much of the shorter sequence of a pair lies within the longer one
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Containment {
+    /// Fraction of the residues of the shorter sequence aligned to identical
+    /// residues of the longer one
+    pub fraction: f64,
+    /// Fraction of the residues of the longer sequence aligned to a residue of the
+    /// shorter one
+    pub longer_coverage: f64,
+}
+
+impl Containment {
+    /// Aligns the shorter of two sequences end to end within the longer one
+    pub fn measure(seq1: &str, seq2: &str) -> Self {
+        let (shorter, longer) = if seq1.len() <= seq2.len() {
+            (seq1, seq2)
+        } else {
+            (seq2, seq1)
+        };
+        if shorter.is_empty() {
+            return Self {
+                fraction: 0.0,
+                longer_coverage: 0.0,
+            };
+        }
+        let matcher: MatcherFn = |a, b| if a == b { 1 } else { -1 };
+        let alignment = workspace::with_aligner(
+            shorter.len(),
+            longer.len(),
+            matcher,
+            GapPenalties::default(),
+            NO_CLIPS,
+            |aligner| aligner.semiglobal(shorter.as_bytes(), longer.as_bytes()),
+        );
+        let count = |wanted: fn(&AlignmentOperation) -> bool| {
+            alignment.operations.iter().filter(|op| wanted(op)).count()
+        };
+        let matches = count(|op| *op == AlignmentOperation::Match);
+        let aligned =
+            count(|op| matches!(op, AlignmentOperation::Match | AlignmentOperation::Subst));
+        Self {
+            fraction: matches as f64 / shorter.len() as f64,
+            longer_coverage: aligned as f64 / longer.len() as f64,
+        }
+    }
+
+    /// Returns `true` if at least `min_fraction` of the shorter sequence lies
+    /// within the longer one, and the alignment covers less of the longer sequence
+    /// than that
+    pub fn is_contained(&self, min_fraction: f64) -> bool {
+        self.fraction >= min_fraction && self.longer_coverage < min_fraction
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_containment() {
+        let full = "MKTAYIAKQRQISFVKSHFSRQLEERLGLIEVQAPILSRVGDGTQDNLSGAEKAVQVKVKALPDAQ";
+        let fragment = &full[20..50];
+        let containment = Containment::measure(full, fragment);
+        assert_eq!(containment.fraction, 1.0);
+        assert!((containment.longer_coverage - 30.0 / full.len() as f64).abs() < 1e-9);
+        assert!(containment.is_contained(0.95));
+
+        // Full-length near-duplicates are not containments
+        let variant = full.replace('W', "F").replacen('K', "R", 1);
+        let containment = Containment::measure(&variant, full);
+        assert!(containment.fraction > 0.95);
+        assert!(!containment.is_contained(0.95));
+    }
+}
@@ -0,0 +1,758 @@
+//! Long-running alignment daemon.
+//!
+//! The `daemon` subcommand keeps named reference sets (datasets) loaded and
+//! accepts alignment jobs over HTTP on a Unix domain socket, so small query
+//! batches do not pay for starting the process and loading the reference every
+//! time. Datasets can be loaded, replaced and dropped while the daemon runs; jobs
+//! keep the reference set they were submitted against. Jobs are queued and run by
+//! a fixed number of workers sharing one thread pool (see [`crate::jobs`]). The
+//! `client` subcommand submits query files to it.
+//!
+//! Endpoints:
+//!
+//! * `GET /status` - number of datasets and jobs as JSON
+//! * `GET /datasets` - names and sizes of the loaded datasets
+//! * `POST /datasets` - loads a dataset from a JSON object with a `name` and
+//!   either the `sequences` or the `path` of a file readable by the daemon,
+//!   replacing a dataset of the same name
+//! * `DELETE /datasets/{name}` - drops a dataset
+//! * `POST /jobs?dataset={name}` - queues a job aligning the sequences of a JSON
+//!   object mapping identifiers to sequences against a dataset (by default
+//!   `default`), returning its status
+//! * `GET /jobs` - status of all jobs
+//! * `GET /jobs/{id}` - status and progress of a job
+//! * `GET /jobs/{id}/results` - results of a completed job, best scores first,
+//!   as a JSON page or a tab-separated table (see [`ResultQuery`])
+//! * `DELETE /jobs/{id}` - cancels a job, or deletes a finished job and its results
+//! * `GET /metrics` - running and queued jobs and the progress of every job in the
+//!   Prometheus text format
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{debug, info, info_span, warn};
+
+use crate::ScoringType;
+use crate::align::{AlignmentResult, MatcherFn, align_against_while};
+use crate::error::AlignerError;
+use crate::filter::FilterChain;
+use crate::jobs::{Job, JobInput, JobQueue, JobState, JobStatus, QueueFull};
+use crate::metrics;
+use crate::utils::parse_input;
+use crate::validate::{check_ascii, check_lengths};
+
+/// Socket path used by the daemon and the client when none is given
+pub const DEFAULT_SOCKET: &str = "aligner.sock";
+
+/// Dataset that jobs are aligned against unless they name another one
+pub const DEFAULT_DATASET: &str = "default";
+
+/// Number of results per page unless a request sets a limit
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Time between two progress requests of the client
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Command-line arguments for the `daemon` subcommand
+#[derive(clap::Args, Debug)]
+pub struct DaemonArgs {
+    /// Path to a reference file loaded as the dataset `default` on startup.
+    /// Further datasets can be loaded while the daemon runs.
+    #[arg(short, long)]
+    reference: Option<PathBuf>,
+
+    /// Path of the Unix domain socket to listen on
+    #[arg(long, default_value = DEFAULT_SOCKET)]
+    socket: PathBuf,
+
+    /// Fraction for pre-filtering using k-mer matches (between 0 and 1)
+    #[arg(short, long)]
+    fraction: Option<f32>,
+
+    /// Minimum number of k-mer matches required for alignment
+    #[arg(short, long, default_value = "0")]
+    min_matches: usize,
+
+    /// Scoring type to use for alignment
+    #[arg(short, long, value_enum, default_value_t = ScoringType::Identity)]
+    scoring: ScoringType,
+
+    /// Number of threads shared by all running jobs
+    #[arg(short, long, env = "ALIGNER_THREADS")]
+    threads: Option<usize>,
+
+    /// Number of jobs that run at the same time
+    #[arg(long, default_value = "1")]
+    max_jobs: usize,
+
+    /// Number of jobs that may wait for a worker before submissions are rejected
+    #[arg(long, default_value = "16")]
+    max_queued: usize,
+
+    /// Number of finished jobs whose results are kept; older ones are deleted
+    #[arg(long, default_value = "100")]
+    keep_jobs: usize,
+}
+
+/// Command-line arguments for the `client` subcommand
+#[derive(clap::Args, Debug)]
+pub struct ClientArgs {
+    /// Path to the file with the query sequences, in any supported input format
+    queries: PathBuf,
+
+    /// Path of the socket the daemon listens on
+    #[arg(long, default_value = DEFAULT_SOCKET)]
+    socket: PathBuf,
+
+    /// Path to the output file (tab-separated). Defaults to standard output.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Name of the dataset to align the queries against
+    #[arg(short, long, default_value = DEFAULT_DATASET)]
+    dataset: String,
+}
+
+/// Answer to `GET /status`
+#[derive(Debug, Serialize)]
+struct Status {
+    datasets: usize,
+    jobs: usize,
+}
+
+/// Name and size of a loaded dataset
+#[derive(Debug, Serialize)]
+struct DatasetInfo {
+    name: String,
+    sequences: usize,
+}
+
+/// Body of `POST /datasets`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NewDataset {
+    name: String,
+    sequences: Option<HashMap<String, String>>,
+    path: Option<PathBuf>,
+}
+
+/// Query parameters of `POST /jobs`
+#[derive(Debug, Deserialize)]
+struct JobParams {
+    dataset: Option<String>,
+}
+
+/// Format of the results returned by `GET /jobs/{id}/results`
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ResultFormat {
+    /// A page of results with the number of matching results
+    #[default]
+    Json,
+    /// A tab-separated table with a header
+    Tsv,
+}
+
+/// Query parameters of `GET /jobs/{id}/results`, selecting which of the stored
+/// results are returned, e.g. `?min_identity=0.4&offset=100&limit=50`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ResultQuery {
+    /// Minimum alignment score
+    min_score: Option<i32>,
+    /// Minimum fraction of identical alignment columns
+    min_identity: Option<f64>,
+    /// Only results of this query sequence
+    query: Option<String>,
+    /// Only results of this reference sequence
+    subject: Option<String>,
+    /// Number of matching results to skip
+    #[serde(default)]
+    offset: usize,
+    /// Maximum number of results returned. Defaults to [`DEFAULT_PAGE_SIZE`] for
+    /// JSON and to all results for tables.
+    limit: Option<usize>,
+    #[serde(default)]
+    format: ResultFormat,
+}
+
+impl ResultQuery {
+    fn matches(&self, result: &AlignmentResult) -> bool {
+        self.min_score
+            .is_none_or(|min| result.score.is_some_and(|score| score >= min))
+            && self
+                .min_identity
+                .is_none_or(|min| result.identity.is_some_and(|identity| identity >= min))
+            && self.query.as_ref().is_none_or(|id| *id == result.query_id)
+            && self
+                .subject
+                .as_ref()
+                .is_none_or(|id| *id == result.subject_id)
+    }
+}
+
+/// Answer to `GET /jobs/{id}/results` in JSON format
+#[derive(Debug, Serialize)]
+struct ResultPage<'a> {
+    /// Number of results matching the filters
+    total: usize,
+    offset: usize,
+    results: Vec<&'a AlignmentResult>,
+}
+
+/// Reference sets by name
+type Datasets = BTreeMap<String, Arc<HashMap<String, String>>>;
+
+/// Datasets, settings and jobs shared by all requests
+struct Daemon {
+    datasets: RwLock<Datasets>,
+    matcher: MatcherFn,
+    filters: FilterChain,
+    queue: JobQueue,
+}
+
+/// Error answered to a request, with its HTTP status code
+struct RequestError(u16, String);
+
+impl From<AlignerError> for RequestError {
+    fn from(e: AlignerError) -> Self {
+        RequestError(400, e.to_string())
+    }
+}
+
+impl Daemon {
+    /// Handles a request, returning the response body
+    fn handle(&self, request: &mut tiny_http::Request) -> Result<String, RequestError> {
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match (request.method(), segments.as_slice()) {
+            (tiny_http::Method::Get, ["status"]) => to_json(&Status {
+                datasets: read(&self.datasets).len(),
+                jobs: self.queue.list().len(),
+            }),
+            (tiny_http::Method::Get, ["datasets"]) => {
+                let datasets: Vec<DatasetInfo> = read(&self.datasets)
+                    .iter()
+                    .map(|(name, sequences)| DatasetInfo {
+                        name: name.clone(),
+                        sequences: sequences.len(),
+                    })
+                    .collect();
+                to_json(&datasets)
+            }
+            (tiny_http::Method::Post, ["datasets"]) => {
+                let dataset: NewDataset = read_json(request)?;
+                let sequences = match (dataset.sequences, dataset.path) {
+                    (Some(sequences), None) => sequences,
+                    (None, Some(path)) => parse_input(&path)?,
+                    _ => {
+                        return Err(RequestError(
+                            400,
+                            "a dataset needs either sequences or a path".to_string(),
+                        ));
+                    }
+                };
+                let info = self.load(dataset.name, sequences)?;
+                to_json(&info)
+            }
+            (tiny_http::Method::Delete, ["datasets", name]) => {
+                let sequences = write(&self.datasets)
+                    .remove(*name)
+                    .ok_or_else(|| RequestError(404, format!("no dataset named '{}'", name)))?;
+                info!(dataset = name, "dropped dataset");
+                to_json(&DatasetInfo {
+                    name: name.to_string(),
+                    sequences: sequences.len(),
+                })
+            }
+            (tiny_http::Method::Post, ["jobs"]) => {
+                let params: JobParams = serde_urlencoded::from_str(query)
+                    .map_err(|e| RequestError(400, format!("invalid query: {}", e)))?;
+                let dataset = params.dataset.as_deref().unwrap_or(DEFAULT_DATASET);
+                let reference = read(&self.datasets)
+                    .get(dataset)
+                    .cloned()
+                    .ok_or_else(|| RequestError(404, format!("no dataset named '{}'", dataset)))?;
+                let mut queries: HashMap<String, String> = read_json(request)?;
+                check_ascii(&mut queries, None)?;
+                check_lengths(&queries, None)?;
+
+                let job = self
+                    .queue
+                    .submit(dataset, JobInput { queries, reference })
+                    .map_err(|QueueFull| {
+                        RequestError(503, "too many queued jobs, try again later".to_string())
+                    })?;
+                info!(job = job.id, dataset, pairs = job.total, "queued job");
+                to_json(&job.status())
+            }
+            (tiny_http::Method::Get, ["jobs"]) => to_json(&self.queue.list()),
+            (tiny_http::Method::Get, ["jobs", id]) => to_json(&self.job(id)?.status()),
+            (tiny_http::Method::Get, ["jobs", id, "results"]) => {
+                let params: ResultQuery = serde_urlencoded::from_str(query)
+                    .map_err(|e| RequestError(400, format!("invalid query: {}", e)))?;
+                let job = self.job(id)?;
+                let results = job.results().ok_or_else(|| {
+                    RequestError(409, format!("job {} has not completed", job.id))
+                })?;
+                let matching = results.iter().filter(|result| params.matches(result));
+                match params.format {
+                    ResultFormat::Json => {
+                        let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+                        let page: Vec<&AlignmentResult> =
+                            matching.clone().skip(params.offset).take(limit).collect();
+                        to_json(&ResultPage {
+                            total: matching.count(),
+                            offset: params.offset,
+                            results: page,
+                        })
+                    }
+                    ResultFormat::Tsv => Ok(results_table(
+                        matching
+                            .skip(params.offset)
+                            .take(params.limit.unwrap_or(usize::MAX)),
+                    )),
+                }
+            }
+            (tiny_http::Method::Get, ["metrics"]) => {
+                Ok(metrics::jobs_to_prometheus(&self.queue.list()))
+            }
+            (tiny_http::Method::Delete, ["jobs", id]) => {
+                let id = parse_id(id)?;
+                let status = self.queue.cancel(id).ok_or_else(|| not_found(id))?;
+                info!(job = id, "cancelled or deleted job");
+                to_json(&status)
+            }
+            (_, ["status"] | ["metrics"] | ["datasets", ..] | ["jobs", ..]) => {
+                Err(RequestError(405, "Method Not Allowed".to_string()))
+            }
+            _ => Err(RequestError(404, "Not Found".to_string())),
+        }
+    }
+
+    /// Validates a reference set and stores it under `name`, replacing a dataset
+    /// of the same name
+    fn load(
+        &self,
+        name: String,
+        mut sequences: HashMap<String, String>,
+    ) -> Result<DatasetInfo, RequestError> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        {
+            return Err(RequestError(
+                400,
+                format!(
+                    "invalid dataset name '{}': use letters, digits, '_', '-' and '.'",
+                    name
+                ),
+            ));
+        }
+        check_ascii(&mut sequences, None)?;
+        check_lengths(&sequences, None)?;
+        let info = DatasetInfo {
+            name: name.clone(),
+            sequences: sequences.len(),
+        };
+        let replaced = write(&self.datasets).insert(name, Arc::new(sequences));
+        info!(
+            dataset = info.name,
+            sequences = info.sequences,
+            replaced = replaced.is_some(),
+            "loaded dataset"
+        );
+        Ok(info)
+    }
+
+    /// Looks up a job by the identifier in a request path
+    fn job(&self, id: &str) -> Result<Arc<Job>, RequestError> {
+        let id = parse_id(id)?;
+        self.queue.get(id).ok_or_else(|| not_found(id))
+    }
+
+    /// Runs queued jobs on the shared thread pool until the queue is closed
+    fn work(&self, pool: &ThreadPool) {
+        while let Some((job, input)) = self.queue.next() {
+            let _span = info_span!("job", job = job.id).entered();
+            let results = pool.install(|| {
+                align_against_while(
+                    &input.queries,
+                    &input.reference,
+                    &self.matcher,
+                    &self.filters,
+                    &|| job.advance(),
+                )
+            });
+            job.finish(results);
+            match job.status().state {
+                JobState::Cancelled => info!("job cancelled"),
+                _ => info!("job completed"),
+            }
+            self.queue.prune();
+        }
+    }
+}
+
+fn parse_id(id: &str) -> Result<u64, RequestError> {
+    id.parse()
+        .map_err(|_| RequestError(400, format!("invalid job identifier '{}'", id)))
+}
+
+fn not_found(id: u64) -> RequestError {
+    RequestError(404, format!("no job with identifier {}", id))
+}
+
+fn to_json(value: &impl Serialize) -> Result<String, RequestError> {
+    Ok(serde_json::to_string(value).map_err(AlignerError::from)?)
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(
+    request: &mut tiny_http::Request,
+) -> Result<T, RequestError> {
+    Ok(serde_json::from_reader(request.as_reader()).map_err(AlignerError::from)?)
+}
+
+/// Locks the datasets for reading, ignoring poisoning since a dataset is only
+/// ever inserted or removed as a whole
+fn read(datasets: &RwLock<Datasets>) -> std::sync::RwLockReadGuard<'_, Datasets> {
+    datasets.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Locks the datasets for writing, see [`read`]
+fn write(datasets: &RwLock<Datasets>) -> std::sync::RwLockWriteGuard<'_, Datasets> {
+    datasets.write().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Formats results as a tab-separated table with a header
+fn results_table<'a>(results: impl Iterator<Item = &'a AlignmentResult>) -> String {
+    let mut table = String::from("query_id\tsubject_id\tscore\tidentity\tseq1_len\tseq2_len\n");
+    for result in results {
+        table.push_str(&format!(
+            "{}\t{}\t{}\t{:.3}\t{}\t{}\n",
+            result.query_id,
+            result.subject_id,
+            result.score.unwrap_or(-1),
+            result.identity.unwrap_or(0.0),
+            result.seq1_len,
+            result.seq2_len
+        ));
+    }
+    table
+}
+
+/// Removes a socket file left behind by a daemon that is no longer running.
+///
+/// # Errors
+///
+/// Returns `AlignerError::Config` if another daemon is listening on the socket.
+fn remove_stale_socket(socket: &Path) -> Result<(), AlignerError> {
+    if !socket.exists() {
+        return Ok(());
+    }
+    if UnixStream::connect(socket).is_ok() {
+        return Err(AlignerError::Config(format!(
+            "a daemon is already listening on {}",
+            socket.display()
+        )));
+    }
+    warn!(socket = %socket.display(), "removing stale socket");
+    fs::remove_file(socket)?;
+    Ok(())
+}
+
+/// Runs the `daemon` subcommand until the process is terminated.
+///
+/// # Errors
+///
+/// Returns an error if the reference file cannot be read or fails validation, or
+/// if the socket cannot be bound. Failed requests are answered with an error
+/// status and do not stop the daemon.
+pub fn run(args: DaemonArgs) -> Result<(), AlignerError> {
+    if let Some(fraction) = args.fraction
+        && !(0.0..=1.0).contains(&fraction)
+    {
+        return Err(AlignerError::Config(
+            "fraction must be between 0 and 1".to_string(),
+        ));
+    }
+    if args.max_jobs == 0 {
+        return Err(AlignerError::Config(
+            "max-jobs must be at least 1".to_string(),
+        ));
+    }
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(args.threads.unwrap_or(0))
+        .build()
+        .expect("Failed to initialize thread pool");
+
+    let daemon = Daemon {
+        datasets: RwLock::new(Datasets::new()),
+        matcher: args.scoring.matcher(),
+        filters: FilterChain::from_options(args.fraction, args.min_matches, &[]),
+        queue: JobQueue::new(args.max_queued, args.keep_jobs),
+    };
+
+    if let Some(path) = &args.reference {
+        let mut reference = parse_input(path)?;
+        check_ascii(&mut reference, None)?;
+        check_lengths(&reference, None)?;
+        write(&daemon.datasets).insert(DEFAULT_DATASET.to_string(), Arc::new(reference));
+    }
+
+    remove_stale_socket(&args.socket)?;
+    let server = tiny_http::Server::http_unix(&args.socket).map_err(|e| {
+        AlignerError::Config(format!("cannot listen on {}: {}", args.socket.display(), e))
+    })?;
+    match read(&daemon.datasets).get(DEFAULT_DATASET) {
+        Some(reference) => eprintln!(
+            "Serving {} reference sequences on {}",
+            reference.len(),
+            args.socket.display()
+        ),
+        None => eprintln!(
+            "Serving on {}; load datasets with POST /datasets",
+            args.socket.display()
+        ),
+    }
+
+    std::thread::scope(|scope| {
+        for _ in 0..args.max_jobs {
+            scope.spawn(|| daemon.work(&pool));
+        }
+        serve(&server, &daemon);
+        daemon.queue.close();
+    });
+    Ok(())
+}
+
+/// Answers requests until the server is shut down
+fn serve(server: &tiny_http::Server, daemon: &Daemon) {
+    for mut request in server.incoming_requests() {
+        let response = match daemon.handle(&mut request) {
+            Ok(body) => tiny_http::Response::from_string(body),
+            Err(RequestError(code, message)) => {
+                tiny_http::Response::from_string(message + "\n").with_status_code(code)
+            }
+        };
+        if let Err(e) = request.respond(response) {
+            debug!("failed to answer request: {}", e);
+        }
+    }
+}
+
+/// Sends an HTTP request to the daemon listening on `socket` and returns the
+/// response body.
+///
+/// # Errors
+///
+/// Returns `AlignerError::Io` if the daemon cannot be reached, and
+/// `AlignerError::Config` with the daemon's message if it answers with an error
+/// status.
+pub fn request(
+    socket: &Path,
+    method: &str,
+    path: &str,
+    body: &[u8],
+) -> Result<Vec<u8>, AlignerError> {
+    let mut stream = UnixStream::connect(socket).map_err(|e| {
+        AlignerError::Config(format!(
+            "cannot reach a daemon on {}: {}",
+            socket.display(),
+            e
+        ))
+    })?;
+    // HTTP/1.0 makes the daemon close the connection after a response that is
+    // neither chunked nor kept alive
+    write!(
+        stream,
+        "{} {} HTTP/1.0\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+        method,
+        path,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+
+    let malformed = || AlignerError::Config("malformed response from the daemon".to_string());
+    let end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(malformed)?;
+    let status: u16 = std::str::from_utf8(&response[..end])
+        .ok()
+        .and_then(|head| head.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(malformed)?;
+    let body = response.split_off(end + 4);
+    if status != 200 {
+        return Err(AlignerError::Config(format!(
+            "daemon answered {}: {}",
+            status,
+            String::from_utf8_lossy(&body).trim_end()
+        )));
+    }
+    Ok(body)
+}
+
+/// Runs the `client` subcommand, submitting the query sequences to a daemon as a
+/// job, waiting for it while reporting its progress, and writing the results.
+///
+/// # Errors
+///
+/// Returns an error if the queries cannot be read, the daemon cannot be reached
+/// or rejects the queries, the job is cancelled, or the results cannot be
+/// written.
+pub fn run_client(args: ClientArgs) -> Result<(), AlignerError> {
+    let queries = parse_input(&args.queries)?;
+    let body = serde_json::to_vec(&queries)?;
+    let submit = format!(
+        "/jobs?{}",
+        serde_urlencoded::to_string([("dataset", &args.dataset)])
+            .map_err(|e| AlignerError::Config(e.to_string()))?
+    );
+    let mut status: JobStatus =
+        serde_json::from_slice(&request(&args.socket, "POST", &submit, &body)?)?;
+    let job_path = format!("/jobs/{}", status.id);
+    loop {
+        eprint!(
+            "\rJob {}: {} ({}/{} pairs)",
+            status.id,
+            serde_json::to_value(status.state)?
+                .as_str()
+                .unwrap_or_default(),
+            status.done,
+            status.total
+        );
+        match status.state {
+            JobState::Completed => break,
+            JobState::Cancelled => {
+                eprintln!();
+                return Err(AlignerError::Config(format!(
+                    "job {} was cancelled",
+                    status.id
+                )));
+            }
+            JobState::Queued | JobState::Running => {}
+        }
+        std::thread::sleep(POLL_INTERVAL);
+        status = serde_json::from_slice(&request(&args.socket, "GET", &job_path, b"")?)?;
+    }
+    eprintln!();
+
+    let results = request(
+        &args.socket,
+        "GET",
+        &format!("{}/results?format=tsv", job_path),
+        b"",
+    )?;
+    match &args.output {
+        Some(path) => fs::write(path, results)?,
+        None => std::io::stdout().write_all(&results)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_daemon_requests() {
+        let socket =
+            std::env::temp_dir().join(format!("aligner-daemon-{}.sock", std::process::id()));
+        let _ = fs::remove_file(&socket);
+        let server = tiny_http::Server::http_unix(&socket).unwrap();
+        let pool = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let daemon = Daemon {
+            datasets: RwLock::new(Datasets::new()),
+            matcher: ScoringType::Identity.matcher(),
+            filters: FilterChain::default(),
+            queue: JobQueue::new(4, 4),
+        };
+        std::thread::scope(|scope| {
+            scope.spawn(|| serve(&server, &daemon));
+
+            let error = request(&socket, "POST", "/jobs", br#"{"q": "MAVMT"}"#).unwrap_err();
+            assert!(error.to_string().contains("no dataset named 'default'"));
+            let dataset = br#"{"name": "pdb", "sequences": {"r": "MAVMT", "s": "MAWWT"}}"#;
+            request(&socket, "POST", "/datasets", dataset).unwrap();
+            let status = request(&socket, "GET", "/status", b"").unwrap();
+            assert_eq!(status, br#"{"datasets":1,"jobs":0}"#);
+
+            let job: JobStatus = serde_json::from_slice(
+                &request(&socket, "POST", "/jobs?dataset=pdb", br#"{"q": "MAVMT"}"#).unwrap(),
+            )
+            .unwrap();
+            assert_eq!((job.id, job.state, job.total), (1, JobState::Queued, 2));
+            let metrics = request(&socket, "GET", "/metrics", b"").unwrap();
+            let metrics = String::from_utf8(metrics).unwrap();
+            assert!(metrics.contains("\naligner_jobs_in_flight 0\n"));
+            assert!(metrics.contains("\naligner_jobs_queued 1\n"));
+            assert!(metrics.contains("aligner_job_progress{job=\"1\",dataset=\"pdb\"} 0.000000\n"));
+            // Dropping the dataset does not affect the queued job
+            request(&socket, "DELETE", "/datasets/pdb", b"").unwrap();
+            assert_eq!(request(&socket, "GET", "/datasets", b"").unwrap(), b"[]");
+            let error = request(&socket, "GET", "/jobs/1/results", b"").unwrap_err();
+            assert!(error.to_string().contains("daemon answered 409"));
+
+            // Start a worker only now, so the job was still queued above
+            scope.spawn(|| daemon.work(&pool));
+            let mut status = job;
+            while status.state != JobState::Completed {
+                std::thread::sleep(Duration::from_millis(10));
+                status = serde_json::from_slice(&request(&socket, "GET", "/jobs/1", b"").unwrap())
+                    .unwrap();
+            }
+            assert_eq!(status.done, 2);
+            let metrics = request(&socket, "GET", "/metrics", b"").unwrap();
+            assert!(
+                String::from_utf8(metrics)
+                    .unwrap()
+                    .contains("aligner_job_pairs_done{job=\"1\",dataset=\"pdb\"} 2\n")
+            );
+            let results = request(&socket, "GET", "/jobs/1/results?format=tsv", b"").unwrap();
+            assert_eq!(
+                String::from_utf8(results).unwrap(),
+                "query_id\tsubject_id\tscore\tidentity\tseq1_len\tseq2_len\n\
+                 q\tr\t5\t1.000\t5\t5\nq\ts\t3\t0.600\t5\t5\n"
+            );
+            let page: serde_json::Value = serde_json::from_slice(
+                &request(
+                    &socket,
+                    "GET",
+                    "/jobs/1/results?min_identity=0.5&offset=1",
+                    b"",
+                )
+                .unwrap(),
+            )
+            .unwrap();
+            assert_eq!(
+                (
+                    page["total"].as_u64(),
+                    page["results"][0]["subject_id"].as_str()
+                ),
+                (Some(2), Some("s"))
+            );
+            let error = request(&socket, "GET", "/jobs/1/results?limit=x", b"").unwrap_err();
+            assert!(error.to_string().contains("daemon answered 400"));
+
+            request(&socket, "DELETE", "/jobs/1", b"").unwrap();
+            let error = request(&socket, "GET", "/jobs/1", b"").unwrap_err();
+            assert!(error.to_string().contains("daemon answered 404"));
+
+            daemon.queue.close();
+            server.unblock();
+        });
+        fs::remove_file(&socket).unwrap();
+    }
+}
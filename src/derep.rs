@@ -0,0 +1,251 @@
+//! Dereplication of sequence sets.
+//!
+//! The `derep` subcommand removes every sequence that is an exact copy of another,
+//! found through the hashes of the sequences, and with `--min-identity` also every
+//! sequence whose identity to a kept sequence reaches the threshold. Sequences are
+//! kept greedily from the longest to the shortest, so every near-duplicate is
+//! assigned to a longer (or equally long) kept sequence. The identity of a pair
+//! cannot exceed the ratio of the shorter to the longer length, so pairs whose
+//! lengths differ too much are never aligned.
+
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::ScoringType;
+use crate::align::{AlignMode, GapPenalties, MatcherFn, align_with_identity};
+use crate::cache::sequence_hash;
+use crate::cluster::parse_identity;
+use crate::error::AlignerError;
+use crate::fasta;
+use crate::filter::{LengthRatio, PairFilter, Verdict};
+use crate::utils::{parse_input, setup_progress_bar};
+use crate::validate::{check_ascii, check_lengths};
+
+/// Command-line arguments for the `derep` subcommand
+#[derive(clap::Args, Debug)]
+pub struct DerepArgs {
+    /// Input file with the sequences to dereplicate, in any supported input format
+    input: PathBuf,
+
+    /// Path of the FASTA file of the kept sequences
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Path of the tab-separated table mapping every removed sequence to the kept
+    /// sequence it duplicates
+    #[arg(long)]
+    mapping: PathBuf,
+
+    /// Also remove sequences whose identity to a kept sequence reaches this
+    /// threshold, instead of only exact duplicates
+    #[arg(long, value_parser = parse_identity)]
+    min_identity: Option<f64>,
+
+    /// Scoring type to use for aligning near-duplicates
+    #[arg(short, long, value_enum, default_value_t = ScoringType::Identity)]
+    scoring: ScoringType,
+}
+
+/// A removed sequence and the kept sequence it duplicates
+#[derive(Debug, Clone, PartialEq)]
+pub struct Removed {
+    /// Identifier of the removed sequence
+    pub id: String,
+    /// Identifier of the kept sequence
+    pub kept: String,
+    /// Identity of the two sequences, 1 for exact duplicates
+    pub identity: f64,
+    /// Whether the sequences are identical
+    pub exact: bool,
+}
+
+/// Sequences kept and removed by dereplication
+#[derive(Debug, Default)]
+pub struct Dereplicated {
+    /// Identifiers of the kept sequences, in identifier order
+    pub kept: Vec<String>,
+    /// Removed sequences, in identifier order
+    pub removed: Vec<Removed>,
+}
+
+/// Removes exact duplicates and, with `min_identity`, near-duplicates from the
+/// input. Of identical sequences the one with the smallest identifier is kept.
+pub fn dereplicate(
+    input: &HashMap<String, String>,
+    min_identity: Option<f64>,
+    matcher: &MatcherFn,
+) -> Dereplicated {
+    let mut ids: Vec<&String> = input.keys().collect();
+    ids.sort();
+
+    // First sequence of every distinct hash, and the copies of it
+    let mut first: HashMap<u128, &String> = HashMap::new();
+    let mut copies: Vec<(&String, &String)> = Vec::new();
+    let mut unique: Vec<&String> = Vec::new();
+    for id in ids {
+        match first.entry(sequence_hash(&input[id])) {
+            Entry::Occupied(original) => copies.push((id, *original.get())),
+            Entry::Vacant(entry) => {
+                entry.insert(id);
+                unique.push(id);
+            }
+        }
+    }
+
+    // Kept sequence and identity of every near-duplicate
+    let mut near: HashMap<&String, (&String, f64)> = HashMap::new();
+    let mut kept: Vec<&String> = unique.clone();
+    if let Some(min_identity) = min_identity {
+        unique.sort_by(|a, b| input[*b].len().cmp(&input[*a].len()).then_with(|| a.cmp(b)));
+        let bound = LengthRatio {
+            min_ratio: min_identity,
+        };
+        let progress = setup_progress_bar(unique.len() as u64);
+        let mut representatives: Vec<&String> = Vec::new();
+        for id in unique {
+            let seq = &input[id];
+            let duplicate = representatives
+                .par_iter()
+                .filter(|kept| bound.check(&input[**kept], seq) == Verdict::Accept)
+                .map(|kept| {
+                    (
+                        *kept,
+                        align_with_identity(
+                            &input[*kept],
+                            seq,
+                            matcher,
+                            AlignMode::Global,
+                            GapPenalties::default(),
+                        )
+                        .1,
+                    )
+                })
+                .find_first(|(_, identity)| *identity >= min_identity);
+            match duplicate {
+                Some(duplicate) => {
+                    near.insert(id, duplicate);
+                }
+                None => representatives.push(id),
+            }
+            progress.inc(1);
+        }
+        progress.finish_and_clear();
+        kept.retain(|id| !near.contains_key(id));
+    }
+
+    let mut removed: Vec<Removed> = near
+        .iter()
+        .map(|(id, (kept, identity))| Removed {
+            id: id.to_string(),
+            kept: kept.to_string(),
+            identity: *identity,
+            exact: false,
+        })
+        .collect();
+    // Copies of a near-duplicate are assigned to the sequence it duplicates
+    removed.extend(copies.into_iter().map(|(id, original)| {
+        let (kept, identity) = near.get(original).copied().unwrap_or((original, 1.0));
+        Removed {
+            id: id.to_string(),
+            kept: kept.to_string(),
+            identity,
+            exact: kept == original,
+        }
+    }));
+    removed.sort_unstable_by(|a, b| a.id.cmp(&b.id));
+    Dereplicated {
+        kept: kept.into_iter().cloned().collect(),
+        removed,
+    }
+}
+
+/// Runs the `derep` subcommand
+///
+/// # Errors
+///
+/// Returns an error if the input cannot be read or is invalid, or an output
+/// cannot be written.
+pub fn run(args: DerepArgs) -> Result<(), AlignerError> {
+    let mut input = parse_input(&args.input)?;
+    check_ascii(&mut input, None)?;
+    check_lengths(&input, None)?;
+    info!(sequences = input.len(), "dereplicating sequences");
+
+    let dereplicated = dereplicate(&input, args.min_identity, &args.scoring.matcher());
+
+    let mut out = BufWriter::new(File::create(&args.output)?);
+    fasta::write(
+        &mut out,
+        dereplicated
+            .kept
+            .iter()
+            .map(|id| (id.as_str(), input[id].as_str())),
+    )?;
+    out.flush()?;
+
+    let mut out = BufWriter::new(File::create(&args.mapping)?);
+    writeln!(out, "removed_id\tkept_id\tidentity\tkind")?;
+    for removed in &dereplicated.removed {
+        writeln!(
+            out,
+            "{}\t{}\t{:.3}\t{}",
+            removed.id,
+            removed.kept,
+            removed.identity,
+            if removed.exact { "exact" } else { "near" }
+        )?;
+    }
+    out.flush()?;
+
+    let exact = dereplicated.removed.iter().filter(|r| r.exact).count();
+    eprintln!(
+        "Kept {} of {} sequences, removed {} exact and {} near-duplicates",
+        dereplicated.kept.len(),
+        input.len(),
+        exact,
+        dereplicated.removed.len() - exact
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dereplicate() {
+        let input: HashMap<String, String> = [
+            ("a", "MKTAYIAKQRQISFVKSHFSRQ"),
+            ("b", "MKTAYIAKQRQISFVKSHFSRQ"),
+            ("c", "MKTAYIAKQRQISFVKSHFSRQLEE"),
+            ("d", "WWWWWWWWWWWWWWWWWWWWWW"),
+        ]
+        .into_iter()
+        .map(|(id, seq)| (id.to_string(), seq.to_string()))
+        .collect();
+        let matcher = ScoringType::Identity.matcher();
+
+        let exact = dereplicate(&input, None, &matcher);
+        assert_eq!(exact.kept, ["a", "c", "d"]);
+        assert_eq!(exact.removed.len(), 1);
+        assert_eq!(
+            (exact.removed[0].kept.as_str(), exact.removed[0].exact),
+            ("a", true)
+        );
+
+        let near = dereplicate(&input, Some(0.85), &matcher);
+        assert_eq!(near.kept, ["c", "d"]);
+        let kept: Vec<(&str, &str, bool)> = near
+            .removed
+            .iter()
+            .map(|r| (r.id.as_str(), r.kept.as_str(), r.exact))
+            .collect();
+        assert_eq!(kept, [("a", "c", false), ("b", "c", false)]);
+        assert!((near.removed[1].identity - 22.0 / 25.0).abs() < 1e-9);
+    }
+}
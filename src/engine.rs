@@ -0,0 +1,717 @@
+//! Alignment algorithms behind a common interface.
+//!
+//! The pair loop aligns pairs through an [`AlignmentEngine`] chosen with
+//! `--engine`, so adding an algorithm does not touch the orchestration code.
+//! Engines declare what they support besides computing the score in their
+//! [`Capabilities`]: the output gains the columns an engine can fill, and options
+//! an engine cannot honor, such as a pair timeout, are rejected before the run.
+
+use clap::ValueEnum;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use crate::align::{
+    AlignMode, GapPenalties, MatcherFn, Score, Summary, align_banded, align_linear_until,
+    align_packed_until, alignment, cigar,
+};
+use crate::error::AlignerError;
+use crate::hirschberg;
+use crate::lanes;
+use crate::myers;
+use crate::packed::PackedSeq;
+use crate::wfa;
+
+/// What an engine supports besides computing the score
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities {
+    /// Computes the alignment itself, so the identity of a pair can be reported
+    pub traceback: bool,
+    /// Can abandon an alignment once a deadline has passed
+    pub interruptible: bool,
+    /// Needs memory proportional to the sequence lengths rather than their product
+    pub linear_space: bool,
+    /// Scores a query with several short subjects at once, see [`lanes`]
+    pub lanes: bool,
+    /// Decodes packed sequences in its kernel instead of unpacking them first
+    pub packed: bool,
+}
+
+/// Result of aligning a pair with an engine
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairAlignment<S> {
+    /// Alignment score
+    pub score: S,
+    /// Column counts of the alignment, from which its identity and similarity
+    /// follow, computed by engines with a traceback
+    pub summary: Option<Summary>,
+    /// The alignment as a CIGAR string, computed by engines with a traceback
+    pub cigar: Option<String>,
+}
+
+/// An algorithm for aligning two sequences globally or locally
+pub trait AlignmentEngine<S: Score>: Send + Sync {
+    /// Returns what the engine supports besides computing the score
+    fn capabilities(&self) -> Capabilities;
+
+    /// Aligns two sequences in `mode` with `gaps`, giving up once `deadline` has
+    /// passed if the engine is interruptible.
+    ///
+    /// Returns `None` if the alignment was abandoned.
+    fn align(
+        &self,
+        seq1: &str,
+        seq2: &str,
+        matcher: &MatcherFn<S>,
+        mode: AlignMode,
+        gaps: GapPenalties,
+        deadline: Option<Instant>,
+    ) -> Option<PairAlignment<S>>;
+
+    /// Scores the alignments in `mode` of `query` with up to [`lanes::LANES`]
+    /// subjects at once, or returns `None` if the engine aligns pairs one at a time
+    fn align_lanes(
+        &self,
+        _query: &str,
+        _subjects: &[&str],
+        _matcher: &MatcherFn<S>,
+        _mode: AlignMode,
+        _gaps: GapPenalties,
+    ) -> Option<Vec<S>> {
+        None
+    }
+
+    /// Aligns two packed sequences like [`AlignmentEngine::align`], unpacking them
+    /// first unless the engine decodes them itself
+    fn align_packed(
+        &self,
+        seq1: &PackedSeq,
+        seq2: &PackedSeq,
+        matcher: &MatcherFn<S>,
+        mode: AlignMode,
+        gaps: GapPenalties,
+        deadline: Option<Instant>,
+    ) -> Option<PairAlignment<S>> {
+        self.align(
+            &seq1.unpack(),
+            &seq2.unpack(),
+            matcher,
+            mode,
+            gaps,
+            deadline,
+        )
+    }
+}
+
+/// Engines selectable with `--engine`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum EngineKind {
+    /// Full dynamic programming matrices, scores only (the default)
+    Full,
+    /// Linear-space scoring, which can be interrupted by a pair timeout
+    Linear,
+    /// Full alignment with traceback, adding an `identity` column (integer
+    /// scores only). Computed in linear space with Hirschberg's algorithm where
+    /// the run needs it.
+    Traceback,
+    /// Edit distance with Myers' bit-parallel algorithm, reported as a negative
+    /// score. Ignores the scoring scheme and the gap penalties.
+    Myers,
+    /// Wavefront alignment, fastest for similar sequences. Needs equal scores for
+    /// all matches and all mismatches; only global alignments use wavefronts.
+    Wfa,
+}
+
+/// Scores pairs with the full dynamic programming matrices
+#[derive(Debug, Clone, Copy)]
+pub struct Full;
+
+impl<S: Score> AlignmentEngine<S> for Full {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            lanes: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn align(
+        &self,
+        seq1: &str,
+        seq2: &str,
+        matcher: &MatcherFn<S>,
+        mode: AlignMode,
+        gaps: GapPenalties,
+        _deadline: Option<Instant>,
+    ) -> Option<PairAlignment<S>> {
+        Some(PairAlignment {
+            score: S::align(seq1, seq2, matcher, mode, gaps),
+            summary: None,
+            cigar: None,
+        })
+    }
+
+    fn align_lanes(
+        &self,
+        query: &str,
+        subjects: &[&str],
+        matcher: &MatcherFn<S>,
+        mode: AlignMode,
+        gaps: GapPenalties,
+    ) -> Option<Vec<S>> {
+        Some(lanes::align(query, subjects, matcher, mode, gaps))
+    }
+}
+
+/// Scores pairs keeping a single row of the dynamic programming matrices
+#[derive(Debug, Clone, Copy)]
+pub struct Linear;
+
+impl<S: Score> AlignmentEngine<S> for Linear {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            interruptible: true,
+            linear_space: true,
+            lanes: true,
+            packed: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn align(
+        &self,
+        seq1: &str,
+        seq2: &str,
+        matcher: &MatcherFn<S>,
+        mode: AlignMode,
+        gaps: GapPenalties,
+        deadline: Option<Instant>,
+    ) -> Option<PairAlignment<S>> {
+        align_linear_until(seq1, seq2, matcher, mode, gaps, deadline).map(|score| PairAlignment {
+            score,
+            summary: None,
+            cigar: None,
+        })
+    }
+
+    fn align_lanes(
+        &self,
+        query: &str,
+        subjects: &[&str],
+        matcher: &MatcherFn<S>,
+        mode: AlignMode,
+        gaps: GapPenalties,
+    ) -> Option<Vec<S>> {
+        Some(lanes::align(query, subjects, matcher, mode, gaps))
+    }
+
+    fn align_packed(
+        &self,
+        seq1: &PackedSeq,
+        seq2: &PackedSeq,
+        matcher: &MatcherFn<S>,
+        mode: AlignMode,
+        gaps: GapPenalties,
+        deadline: Option<Instant>,
+    ) -> Option<PairAlignment<S>> {
+        align_packed_until(seq1, seq2, matcher, mode, gaps, deadline).map(|score| PairAlignment {
+            score,
+            summary: None,
+            cigar: None,
+        })
+    }
+}
+
+/// Scores global alignments within a diagonal band of the dynamic programming
+/// matrices, keeping a single row of them. Other modes are aligned without a band.
+#[derive(Debug, Clone, Copy)]
+pub struct Banded {
+    /// Number of diagonals the band extends beyond the length difference of a pair
+    pub width: usize,
+}
+
+impl<S: Score> AlignmentEngine<S> for Banded {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            interruptible: true,
+            linear_space: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn align(
+        &self,
+        seq1: &str,
+        seq2: &str,
+        matcher: &MatcherFn<S>,
+        mode: AlignMode,
+        gaps: GapPenalties,
+        deadline: Option<Instant>,
+    ) -> Option<PairAlignment<S>> {
+        let score = match mode {
+            AlignMode::Global => align_banded(seq1, seq2, matcher, gaps, self.width, deadline),
+            _ => align_linear_until(seq1, seq2, matcher, mode, gaps, deadline),
+        };
+        score.map(|score| PairAlignment {
+            score,
+            summary: None,
+            cigar: None,
+        })
+    }
+}
+
+/// Computes the full alignment of pairs, reporting their identity
+#[derive(Debug, Clone, Copy)]
+pub struct Traceback;
+
+impl AlignmentEngine<i32> for Traceback {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            traceback: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn align(
+        &self,
+        seq1: &str,
+        seq2: &str,
+        matcher: &MatcherFn,
+        mode: AlignMode,
+        gaps: GapPenalties,
+        _deadline: Option<Instant>,
+    ) -> Option<PairAlignment<i32>> {
+        let alignment = alignment(seq1, seq2, matcher, mode, gaps);
+        Some(PairAlignment {
+            score: alignment.score,
+            summary: Some(Summary::of(
+                &alignment,
+                seq1.as_bytes(),
+                seq2.as_bytes(),
+                matcher,
+            )),
+            cigar: Some(cigar(&alignment)),
+        })
+    }
+}
+
+/// Computes the alignment of pairs in linear space with Hirschberg's algorithm,
+/// reporting their identity
+#[derive(Debug, Clone, Copy)]
+pub struct Hirschberg;
+
+impl AlignmentEngine<i32> for Hirschberg {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            traceback: true,
+            linear_space: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn align(
+        &self,
+        seq1: &str,
+        seq2: &str,
+        matcher: &MatcherFn,
+        mode: AlignMode,
+        gaps: GapPenalties,
+        _deadline: Option<Instant>,
+    ) -> Option<PairAlignment<i32>> {
+        let alignment = hirschberg::alignment(seq1, seq2, matcher, mode, gaps);
+        Some(PairAlignment {
+            score: alignment.score,
+            summary: Some(Summary::of(
+                &alignment,
+                seq1.as_bytes(),
+                seq2.as_bytes(),
+                matcher,
+            )),
+            cigar: Some(cigar(&alignment)),
+        })
+    }
+}
+
+/// Computes the edit distance of pairs with Myers' bit-parallel algorithm, scoring
+/// them with the negated distance
+#[derive(Debug, Clone, Copy)]
+pub struct Myers;
+
+impl<S: Score> AlignmentEngine<S> for Myers {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            interruptible: true,
+            linear_space: true,
+            packed: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn align(
+        &self,
+        seq1: &str,
+        seq2: &str,
+        _matcher: &MatcherFn<S>,
+        mode: AlignMode,
+        _gaps: GapPenalties,
+        deadline: Option<Instant>,
+    ) -> Option<PairAlignment<S>> {
+        let distance = myers::edit_distance(seq1.as_bytes(), seq2.as_bytes(), mode, deadline)?;
+        Some(PairAlignment {
+            score: S::from_penalty(-(distance as i32)),
+            summary: None,
+            cigar: None,
+        })
+    }
+
+    fn align_packed(
+        &self,
+        seq1: &PackedSeq,
+        seq2: &PackedSeq,
+        _matcher: &MatcherFn<S>,
+        mode: AlignMode,
+        _gaps: GapPenalties,
+        deadline: Option<Instant>,
+    ) -> Option<PairAlignment<S>> {
+        let distance = myers::edit_distance_packed(seq1, seq2, mode, deadline)?;
+        Some(PairAlignment {
+            score: S::from_penalty(-(distance as i32)),
+            summary: None,
+            cigar: None,
+        })
+    }
+}
+
+/// Scores global alignments with the wavefront algorithm, in time growing with
+/// the penalty of the alignment. Other modes, and scoring schemes the penalties
+/// cannot be derived from, are aligned in linear space.
+///
+/// The scores of a match and a mismatch are read from the matcher, so it must
+/// score all matches and all mismatches alike.
+#[derive(Debug, Clone, Copy)]
+pub struct Wfa;
+
+impl<S: Score> AlignmentEngine<S> for Wfa {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            interruptible: true,
+            linear_space: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn align(
+        &self,
+        seq1: &str,
+        seq2: &str,
+        matcher: &MatcherFn<S>,
+        mode: AlignMode,
+        gaps: GapPenalties,
+        deadline: Option<Instant>,
+    ) -> Option<PairAlignment<S>> {
+        let integral = |score: S| {
+            let score: f64 = score.into();
+            (score.fract() == 0.0).then_some(score as i32)
+        };
+        let scores = integral(matcher(b'A', b'A')).zip(integral(matcher(b'A', b'C')));
+        let penalties = scores.and_then(|(matches, mismatches)| {
+            wfa::Penalties::from_scores(matches, mismatches, gaps.open, gaps.extend)
+                .map(|penalties| (matches, penalties))
+        });
+        let score = match (mode, penalties) {
+            (AlignMode::Global, Some((matches, penalties))) => {
+                let penalty = wfa::align(seq1.as_bytes(), seq2.as_bytes(), penalties, deadline)?;
+                S::from_penalty(wfa::score(matches, seq1.len(), seq2.len(), penalty))
+            }
+            _ => align_linear_until(seq1, seq2, matcher, mode, gaps, deadline)?,
+        };
+        Some(PairAlignment {
+            score,
+            summary: None,
+            cigar: None,
+        })
+    }
+}
+
+/// Aligns pairs in two phases: every pair is scored by a score-only engine, and
+/// only pairs scoring at least the threshold are aligned again with traceback.
+///
+/// Computing the traceback costs more time and memory than the score alone, so
+/// runs that only need the identity of similar pairs save most of it.
+pub struct Planner<S: Score> {
+    score: &'static dyn AlignmentEngine<S>,
+    traceback: &'static dyn AlignmentEngine<S>,
+    min_score: f64,
+    traced: AtomicU64,
+}
+
+impl<S: Score> Planner<S> {
+    /// Returns the number of pairs aligned with traceback so far
+    pub fn traced(&self) -> u64 {
+        self.traced.load(Ordering::Relaxed)
+    }
+}
+
+impl<S: Score> AlignmentEngine<S> for Planner<S> {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            traceback: true,
+            // Pairs above the threshold are aligned by both engines
+            interruptible: self.score.capabilities().interruptible
+                && self.traceback.capabilities().interruptible,
+            linear_space: self.traceback.capabilities().linear_space,
+            lanes: false,
+            packed: false,
+        }
+    }
+
+    fn align(
+        &self,
+        seq1: &str,
+        seq2: &str,
+        matcher: &MatcherFn<S>,
+        mode: AlignMode,
+        gaps: GapPenalties,
+        deadline: Option<Instant>,
+    ) -> Option<PairAlignment<S>> {
+        let alignment = self
+            .score
+            .align(seq1, seq2, matcher, mode, gaps, deadline)?;
+        if alignment.score.into() < self.min_score {
+            return Some(alignment);
+        }
+        self.traced.fetch_add(1, Ordering::Relaxed);
+        self.traceback
+            .align(seq1, seq2, matcher, mode, gaps, deadline)
+    }
+}
+
+/// Creates the planner of a run that scores pairs with `score` and traces back
+/// pairs scoring at least `min_score`, interrupting both phases of a pair if the
+/// run has a pair `timeout`.
+///
+/// # Errors
+///
+/// Returns `AlignerError::Config` if `score` already computes the traceback, if
+/// there is no traceback engine for the score type, or if the run has a pair
+/// timeout that one of the engines cannot honour.
+pub fn plan<S: Score>(
+    score: &'static dyn AlignmentEngine<S>,
+    min_score: f64,
+    timeout: bool,
+) -> Result<Planner<S>, AlignerError> {
+    if score.capabilities().traceback {
+        return Err(AlignerError::Config(
+            "--traceback-min-score needs a score-only engine".to_string(),
+        ));
+    }
+    // Pairs above the threshold are aligned in linear space if the others are
+    let traceback = S::traceback_engine(score.capabilities().linear_space).ok_or_else(|| {
+        AlignerError::Config("the traceback engine only supports integer scores".to_string())
+    })?;
+    let planner = Planner {
+        score,
+        traceback,
+        min_score,
+        traced: AtomicU64::new(0),
+    };
+    if timeout && !planner.capabilities().interruptible {
+        return Err(AlignerError::Config(
+            "--pair-timeout cannot interrupt the traceback of pairs above --traceback-min-score"
+                .to_string(),
+        ));
+    }
+    Ok(planner)
+}
+
+/// Chooses the engine of a run.
+///
+/// Without an explicit choice the full matrices are used, unless the run must be
+/// aligned in linear space or interrupted by a pair timeout. Alignments with
+/// traceback in linear space are computed with Hirschberg's algorithm.
+///
+/// # Errors
+///
+/// Returns `AlignerError::Config` if the chosen engine does not support the score
+/// type, a pair timeout or linear space where the run needs it.
+pub fn select<S: Score>(
+    kind: Option<EngineKind>,
+    linear_space: bool,
+    timeout: bool,
+) -> Result<&'static dyn AlignmentEngine<S>, AlignerError> {
+    let engine: &'static dyn AlignmentEngine<S> = match kind {
+        None if linear_space || timeout => &Linear,
+        None | Some(EngineKind::Full) => &Full,
+        Some(EngineKind::Linear) => &Linear,
+        Some(EngineKind::Traceback) => S::traceback_engine(linear_space).ok_or_else(|| {
+            AlignerError::Config("the traceback engine only supports integer scores".to_string())
+        })?,
+        Some(EngineKind::Myers) => &Myers,
+        Some(EngineKind::Wfa) => &Wfa,
+    };
+    let capabilities = engine.capabilities();
+    if timeout && !capabilities.interruptible {
+        return Err(AlignerError::Config(
+            "--pair-timeout needs an interruptible engine such as --engine linear".to_string(),
+        ));
+    }
+    if linear_space && !capabilities.linear_space {
+        return Err(AlignerError::Config(
+            "the alignments do not fit into memory with this engine; use --engine linear"
+                .to_string(),
+        ));
+    }
+    Ok(engine)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ScoringType;
+
+    #[test]
+    fn test_select_engine() {
+        let matcher = ScoringType::Identity.matcher();
+        let engine = select::<i32>(Some(EngineKind::Traceback), false, false).unwrap();
+        assert!(engine.capabilities().traceback);
+        let alignment = engine
+            .align(
+                "MAVMT",
+                "MAVKT",
+                &matcher,
+                AlignMode::Global,
+                GapPenalties::default(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            (
+                alignment.score,
+                alignment.summary.map(|summary| summary.identity())
+            ),
+            (4, Some(0.8))
+        );
+        assert_eq!(alignment.cigar.as_deref(), Some("3=1X1="));
+
+        assert!(
+            select::<i32>(None, false, true)
+                .unwrap()
+                .capabilities()
+                .interruptible
+        );
+        assert!(select::<f32>(Some(EngineKind::Traceback), false, false).is_err());
+        let hirschberg = select::<i32>(Some(EngineKind::Traceback), true, false).unwrap();
+        assert!(hirschberg.capabilities().linear_space);
+        assert_eq!(
+            hirschberg.align(
+                "MAVMT",
+                "MAVKT",
+                &matcher,
+                AlignMode::Global,
+                GapPenalties::default(),
+                None
+            ),
+            Some(alignment.clone())
+        );
+        assert!(select::<i32>(Some(EngineKind::Full), false, true).is_err());
+        let myers = select::<f32>(Some(EngineKind::Myers), true, true).unwrap();
+        let ignored: MatcherFn<f32> = |_, _| 0.0;
+        let distance = myers
+            .align(
+                "MAVMT",
+                "MAVKTT",
+                &ignored,
+                AlignMode::Global,
+                GapPenalties::default(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(distance.score, -2.0);
+        let wfa = select::<i32>(Some(EngineKind::Wfa), false, true).unwrap();
+        for mode in [AlignMode::Global, AlignMode::Local] {
+            assert_eq!(
+                wfa.align(
+                    "WWWWWWMAVMT",
+                    "MAVMTKKKKKK",
+                    &matcher,
+                    mode,
+                    GapPenalties::default(),
+                    None
+                ),
+                Linear.align(
+                    "WWWWWWMAVMT",
+                    "MAVMTKKKKKK",
+                    &matcher,
+                    mode,
+                    GapPenalties::default(),
+                    None
+                )
+            );
+        }
+
+        let planner = plan::<i32>(&Full, 4.0, false).unwrap();
+        assert_eq!(
+            planner.align(
+                "MAVMT",
+                "MAVKT",
+                &matcher,
+                AlignMode::Global,
+                GapPenalties::default(),
+                None
+            ),
+            Some(alignment)
+        );
+        let low = planner
+            .align(
+                "MAVMT",
+                "MWWWT",
+                &matcher,
+                AlignMode::Global,
+                GapPenalties::default(),
+                None,
+            )
+            .unwrap();
+        assert_eq!((low.score, low.summary, planner.traced()), (2, None, 1));
+        assert!(plan::<i32>(engine, 4.0, false).is_err());
+        // The traceback of pairs above the threshold cannot be interrupted
+        assert!(!planner.capabilities().interruptible);
+        assert!(plan::<i32>(&Linear, 4.0, true).is_err());
+
+        // Local alignments are not penalized for the unrelated ends
+        let (seq1, seq2) = ("WWWWWWMAVMT", "MAVMTKKKKKK");
+        let global = engine
+            .align(
+                seq1,
+                seq2,
+                &matcher,
+                AlignMode::Global,
+                GapPenalties::default(),
+                None,
+            )
+            .unwrap();
+        let local = engine
+            .align(
+                seq1,
+                seq2,
+                &matcher,
+                AlignMode::Local,
+                GapPenalties::default(),
+                None,
+            )
+            .unwrap();
+        assert_eq!((global.score, local.score), (0, 5));
+        assert_eq!(
+            Linear.align(
+                seq1,
+                seq2,
+                &matcher,
+                AlignMode::Local,
+                GapPenalties::default(),
+                None
+            ),
+            Some(PairAlignment {
+                score: 5,
+                summary: None,
+                cigar: None
+            })
+        );
+    }
+}
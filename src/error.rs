@@ -23,4 +23,39 @@ pub(crate) enum AlignerError {
     /// the input JSON file cannot be properly parsed into the expected format.
     #[error("Parse error: {0}")]
     Parse(#[from] serde_json::Error),
+
+    /// Parse error that occurs during YAML deserialization.
+    ///
+    /// This variant wraps a serde_yaml error and is returned when a YAML
+    /// input file cannot be parsed into the expected format.
+    #[error("YAML parse error: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+
+    /// Database error that occurs while reading an SQLite input.
+    ///
+    /// This variant wraps a rusqlite error and is returned when a database
+    /// cannot be opened or lacks the expected table or columns.
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+
+    /// Download error that occurs when an input is given as a URL.
+    ///
+    /// This variant is returned when the server cannot be reached or answers
+    /// with an error status.
+    #[error("Download error: {0}")]
+    Download(String),
+
+    /// Configuration error caused by invalid or inconsistent options.
+    ///
+    /// This variant is returned when command-line values are outside their
+    /// accepted range or cannot be combined with each other.
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+
+    /// Input error caused by malformed or inconsistent input data.
+    ///
+    /// This variant is returned when an auxiliary input file such as an ID
+    /// mapping cannot be interpreted or does not match the sequences.
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
 }
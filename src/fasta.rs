@@ -0,0 +1,123 @@
+//! Parsing of FASTA files.
+//!
+//! Every record starts with a `>` header line whose first word is the identifier
+//! of the sequence; the rest of the header is a description and is ignored. The
+//! sequence may be wrapped over any number of lines. Lines starting with `;` are
+//! comments.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use crate::error::AlignerError;
+use crate::input::{ParseOptions, Parsed};
+
+/// Reads the sequences of a FASTA file.
+///
+/// # Errors
+///
+/// Returns `AlignerError::Io` if the file cannot be read, and
+/// `AlignerError::InvalidInput` if a sequence precedes the first header or,
+/// unless the parse mode is lenient, a header has no identifier.
+pub fn read(path: &Path, options: &ParseOptions) -> Result<Parsed, AlignerError> {
+    parse(BufReader::new(File::open(path)?), options)
+        .map_err(|e| AlignerError::InvalidInput(format!("{}: {}", path.display(), e)))
+}
+
+/// Parses FASTA records from a reader
+fn parse(reader: impl BufRead, options: &ParseOptions) -> Result<Parsed, String> {
+    let mut parsed = Parsed::default();
+    let mut records: usize = 0;
+    let mut current: Option<(String, String)> = None;
+
+    let mut finish = |parsed: &mut Parsed, (id, sequence): (String, String)| {
+        records += 1;
+        let sequence = if id.is_empty() {
+            Err("header has no identifier".to_string())
+        } else {
+            Ok(sequence)
+        };
+        parsed.add(options.mode, records, id, sequence)
+    };
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.starts_with(';') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('>') {
+            if let Some(record) = current.take() {
+                finish(&mut parsed, record)?;
+            }
+            let id = header.split_whitespace().next().unwrap_or_default();
+            current = Some((id.to_string(), String::new()));
+        } else if let Some((_, sequence)) = &mut current {
+            sequence.extend(line.chars().filter(|c| !c.is_ascii_whitespace()));
+        } else if !line.trim().is_empty() {
+            return Err(format!(
+                "line {}: sequence before the first header",
+                index + 1
+            ));
+        }
+    }
+    if let Some(record) = current {
+        finish(&mut parsed, record)?;
+    }
+    Ok(parsed)
+}
+
+/// Length of the sequence lines of written FASTA records
+const LINE_WIDTH: usize = 60;
+
+/// Writes FASTA records, wrapping the sequences after [`LINE_WIDTH`] residues.
+///
+/// # Errors
+///
+/// Returns an error if the writer fails.
+pub fn write<'a>(
+    out: &mut impl Write,
+    records: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> io::Result<()> {
+    for (id, sequence) in records {
+        writeln!(out, ">{}", id)?;
+        for line in sequence.as_bytes().chunks(LINE_WIDTH) {
+            out.write_all(line)?;
+            out.write_all(b"\n")?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::ParseMode;
+
+    #[test]
+    fn test_parse_fasta() {
+        let fasta = "\
+; exported sequences
+>sp|P1|A first protein
+MAVMT
+KLLQ
+
+>B
+  WWW
+>
+MA
+";
+        let lenient = ParseOptions {
+            mode: ParseMode::Lenient,
+            ..ParseOptions::default()
+        };
+        let parsed = parse(fasta.as_bytes(), &lenient).unwrap();
+        assert_eq!(parsed.sequences["sp|P1|A"], "MAVMTKLLQ");
+        assert_eq!(parsed.sequences["B"], "WWW");
+        assert_eq!(parsed.invalid[0].record, 3);
+
+        let error = parse(fasta.as_bytes(), &ParseOptions::default()).unwrap_err();
+        assert!(error.contains("header has no identifier"));
+        let error = parse("MAV\n>a\n".as_bytes(), &lenient).unwrap_err();
+        assert_eq!(error, "line 1: sequence before the first header");
+    }
+}
@@ -0,0 +1,136 @@
+//! Parsing of FASTQ files.
+//!
+//! Every record consists of an `@` header line whose first word is the identifier
+//! of the read, the sequence, a `+` separator line and a quality line with one
+//! character per base. Qualities are decoded from Phred+33 and kept next to the
+//! sequences, so that `--quality-weighted` can down-weight mismatches at bases
+//! that were called with low confidence.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::error::AlignerError;
+use crate::input::{ParseOptions, Parsed};
+
+/// Offset of the quality characters in FASTQ files of current sequencers
+const PHRED_OFFSET: u8 = b'!';
+
+/// Reads the sequences and base qualities of a FASTQ file.
+///
+/// # Errors
+///
+/// Returns `AlignerError::Io` if the file cannot be read, and
+/// `AlignerError::InvalidInput` if a record is truncated or lacks its `@` header
+/// or `+` separator or, unless the parse mode is lenient, its header has no
+/// identifier or its qualities do not match its sequence.
+pub fn read(path: &Path, options: &ParseOptions) -> Result<Parsed, AlignerError> {
+    parse(BufReader::new(File::open(path)?), options)
+        .map_err(|e| AlignerError::InvalidInput(format!("{}: {}", path.display(), e)))
+}
+
+/// Parses FASTQ records from a reader
+fn parse(reader: impl BufRead, options: &ParseOptions) -> Result<Parsed, String> {
+    let mut parsed = Parsed::default();
+    let mut lines = reader.lines().enumerate();
+    let mut records = 0;
+
+    while let Some((index, header)) = lines.next() {
+        let header = header.map_err(|e| e.to_string())?;
+        if header.trim().is_empty() {
+            continue;
+        }
+        let Some(header) = header.strip_prefix('@') else {
+            return Err(format!("line {}: expected an '@' header", index + 1));
+        };
+        let mut next = |expected: &str| match lines.next() {
+            Some((index, line)) => line.map(|line| (index, line)).map_err(|e| e.to_string()),
+            None => Err(format!(
+                "record {} is truncated before its {}",
+                records + 1,
+                expected
+            )),
+        };
+        let (_, sequence) = next("sequence")?;
+        let (index, separator) = next("'+' separator")?;
+        if !separator.starts_with('+') {
+            return Err(format!("line {}: expected a '+' separator", index + 1));
+        }
+        let (_, quality) = next("qualities")?;
+        records += 1;
+
+        let id = header
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let (sequence, quality) = (sequence.trim_end(), quality.trim_end());
+        let checked = if id.is_empty() {
+            Err("header has no identifier".to_string())
+        } else if quality.len() != sequence.len() {
+            Err(format!(
+                "{} qualities for {} bases",
+                quality.len(),
+                sequence.len()
+            ))
+        } else if let Some(c) = quality.chars().find(|c| !('!'..='~').contains(c)) {
+            Err(format!("invalid quality character '{}'", c))
+        } else {
+            Ok(quality
+                .bytes()
+                .map(|q| q - PHRED_OFFSET)
+                .collect::<Vec<u8>>())
+        };
+        let (sequence, quality) = match checked {
+            Ok(quality) => (Ok(sequence.to_string()), Some(quality)),
+            Err(reason) => (Err(reason), None),
+        };
+        parsed.add(options.mode, records, id.clone(), sequence)?;
+        if let Some(quality) = quality
+            && parsed.sequences.contains_key(&id)
+        {
+            parsed.qualities.insert(id, quality);
+        }
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::ParseMode;
+
+    #[test]
+    fn test_parse_fastq() {
+        let fastq = "\
+@read1 run=7
+ACGT
++
+II#5
+
+@read2
+ACG
++read2
+II
+@read3
+TTA
++
+!!!
+";
+        let lenient = ParseOptions {
+            mode: ParseMode::Lenient,
+            ..ParseOptions::default()
+        };
+        let parsed = parse(fastq.as_bytes(), &lenient).unwrap();
+        assert_eq!(parsed.sequences["read1"], "ACGT");
+        assert_eq!(parsed.qualities["read1"], [40, 40, 2, 20]);
+        assert_eq!(parsed.qualities["read3"], [0, 0, 0]);
+        assert_eq!(parsed.invalid[0].reason, "2 qualities for 3 bases");
+        assert!(!parsed.sequences.contains_key("read2"));
+
+        let error = parse("@a\nACGT\nII\n".as_bytes(), &lenient).unwrap_err();
+        assert_eq!(error, "line 3: expected a '+' separator");
+        let error = parse("@a\nACGT\n+\n".as_bytes(), &lenient).unwrap_err();
+        assert_eq!(error, "record 1 is truncated before its qualities");
+    }
+}
@@ -0,0 +1,429 @@
+//! Filters deciding which pairs are worth aligning.
+//!
+//! Pairs pass an ordered [`FilterChain`] of [`PairFilter`]s before they are
+//! aligned. The first filter rejecting a pair ends the chain and gives the reason,
+//! which is kept with the skipped pair; every filter counts the pairs it checked
+//! and rejected. Filters are given on the command line as `NAME:ARGS`, e.g.
+//! `--filter length:0.5 --filter sketch:0.1`, and run after the k-mer pre-filter of
+//! `--fraction`.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::align::worth_aligning;
+
+/// Default k-mer length of the `sketch` filter
+pub const DEFAULT_SKETCH_K: usize = 4;
+
+/// Default number of hashes kept per sequence by the `sketch` filter
+pub const DEFAULT_SKETCH_SIZE: usize = 128;
+
+/// Decision of a filter about a pair
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// The pair is passed on to the next filter or aligned
+    Accept,
+    /// The pair is not aligned, for the given reason
+    Reject(String),
+}
+
+/// A test a pair has to pass to be aligned
+pub trait PairFilter: fmt::Debug + Send + Sync {
+    /// Name of the filter, as used in `--filter`
+    fn name(&self) -> &'static str;
+
+    /// Decides whether the pair of sequences is worth aligning
+    fn check(&self, seq1: &str, seq2: &str) -> Verdict;
+}
+
+/// Rejects pairs whose shorter sequence is less than `min_ratio` times as long as
+/// the longer one
+#[derive(Debug, Clone, Copy)]
+pub struct LengthRatio {
+    /// Smallest accepted ratio of the shorter to the longer length
+    pub min_ratio: f64,
+}
+
+impl PairFilter for LengthRatio {
+    fn name(&self) -> &'static str {
+        "length"
+    }
+
+    fn check(&self, seq1: &str, seq2: &str) -> Verdict {
+        let (shorter, longer) = (seq1.len().min(seq2.len()), seq1.len().max(seq2.len()));
+        let ratio = if longer == 0 {
+            1.0
+        } else {
+            shorter as f64 / longer as f64
+        };
+        if ratio >= self.min_ratio {
+            Verdict::Accept
+        } else {
+            Verdict::Reject(format!(
+                "length ratio {:.3} below {}",
+                ratio, self.min_ratio
+            ))
+        }
+    }
+}
+
+/// Rejects pairs sharing fewer than `min_matches` k-mers, with the k-mer size a
+/// fraction of the shorter sequence length
+#[derive(Debug, Clone, Copy)]
+pub struct Kmer {
+    /// Fraction of the shorter sequence length used as k-mer size
+    pub fraction: f32,
+    /// Minimum number of k-mer matches required for alignment
+    pub min_matches: usize,
+}
+
+impl PairFilter for Kmer {
+    fn name(&self) -> &'static str {
+        "kmer"
+    }
+
+    fn check(&self, seq1: &str, seq2: &str) -> Verdict {
+        if worth_aligning(seq1, seq2, self.fraction, self.min_matches) {
+            Verdict::Accept
+        } else {
+            Verdict::Reject(format!("fewer than {} shared k-mers", self.min_matches))
+        }
+    }
+}
+
+/// Rejects pairs whose k-mer sets have an estimated Jaccard similarity below
+/// `min_similarity`, estimated from MinHash sketches of `size` hashes
+#[derive(Debug, Clone, Copy)]
+pub struct Sketch {
+    /// Smallest accepted estimated Jaccard similarity
+    pub min_similarity: f64,
+    /// Length of the hashed k-mers
+    pub k: usize,
+    /// Number of smallest k-mer hashes kept per sequence
+    pub size: usize,
+}
+
+impl Sketch {
+    /// Returns the sorted smallest distinct k-mer hashes of `seq`
+    pub fn sketch(&self, seq: &str) -> Vec<u64> {
+        let mut hashes: Vec<u64> = seq
+            .as_bytes()
+            .windows(self.k)
+            .map(|kmer| {
+                let mut hasher = DefaultHasher::new();
+                kmer.hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect();
+        hashes.sort_unstable();
+        hashes.dedup();
+        hashes.truncate(self.size);
+        hashes
+    }
+
+    /// Estimates the Jaccard similarity of the k-mer sets of two sequences from
+    /// the smallest hashes of their union
+    fn similarity(&self, seq1: &str, seq2: &str) -> f64 {
+        self.compare(&self.sketch(seq1), &self.sketch(seq2))
+    }
+
+    /// Estimates the Jaccard similarity of the k-mer sets of two sequences from
+    /// their sketches, so every sequence is sketched once when comparing many pairs
+    pub fn compare(&self, sketch1: &[u64], sketch2: &[u64]) -> f64 {
+        let mut union: Vec<u64> = sketch1.iter().chain(sketch2).copied().collect();
+        union.sort_unstable();
+        union.dedup();
+        union.truncate(self.size);
+        if union.is_empty() {
+            return 0.0;
+        }
+        let shared = union
+            .iter()
+            .filter(|hash| {
+                sketch1.binary_search(hash).is_ok() && sketch2.binary_search(hash).is_ok()
+            })
+            .count();
+        shared as f64 / union.len() as f64
+    }
+}
+
+impl PairFilter for Sketch {
+    fn name(&self) -> &'static str {
+        "sketch"
+    }
+
+    fn check(&self, seq1: &str, seq2: &str) -> Verdict {
+        let similarity = self.similarity(seq1, seq2);
+        if similarity >= self.min_similarity {
+            Verdict::Accept
+        } else {
+            Verdict::Reject(format!(
+                "estimated k-mer similarity {:.3} below {}",
+                similarity, self.min_similarity
+            ))
+        }
+    }
+}
+
+/// Rejects pairs whose residue composition vectors have a cosine similarity
+/// below `min_similarity`
+#[derive(Debug, Clone, Copy)]
+pub struct Composition {
+    /// Smallest accepted cosine similarity
+    pub min_similarity: f64,
+}
+
+impl Composition {
+    /// Returns the counts of the letters A to Z in `seq`, ignoring case
+    fn embed(seq: &str) -> [f64; 26] {
+        let mut counts = [0.0; 26];
+        for residue in seq.bytes().filter(u8::is_ascii_alphabetic) {
+            counts[usize::from(residue.to_ascii_uppercase() - b'A')] += 1.0;
+        }
+        counts
+    }
+}
+
+impl PairFilter for Composition {
+    fn name(&self) -> &'static str {
+        "embedding"
+    }
+
+    fn check(&self, seq1: &str, seq2: &str) -> Verdict {
+        let (a, b) = (Self::embed(seq1), Self::embed(seq2));
+        let dot: f64 = a.iter().zip(&b).map(|(x, y)| x * y).sum();
+        let norm = |v: &[f64; 26]| v.iter().map(|x| x * x).sum::<f64>().sqrt();
+        let norms = norm(&a) * norm(&b);
+        let similarity = if norms > 0.0 { dot / norms } else { 0.0 };
+        if similarity >= self.min_similarity {
+            Verdict::Accept
+        } else {
+            Verdict::Reject(format!(
+                "composition similarity {:.3} below {}",
+                similarity, self.min_similarity
+            ))
+        }
+    }
+}
+
+/// Runs an executable with the two sequences as arguments, accepting the pair if
+/// it exits successfully. The first line of its output is the reason for
+/// rejected pairs.
+#[derive(Debug, Clone)]
+pub struct Script {
+    /// Path of the executable
+    pub command: PathBuf,
+}
+
+impl PairFilter for Script {
+    fn name(&self) -> &'static str {
+        "script"
+    }
+
+    fn check(&self, seq1: &str, seq2: &str) -> Verdict {
+        match Command::new(&self.command).arg(seq1).arg(seq2).output() {
+            Ok(output) if output.status.success() => Verdict::Accept,
+            Ok(output) => {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                let reason = stdout.lines().next().unwrap_or_default().trim();
+                Verdict::Reject(if reason.is_empty() {
+                    format!("{} exited with {}", self.command.display(), output.status)
+                } else {
+                    reason.to_string()
+                })
+            }
+            Err(e) => Verdict::Reject(format!("failed to run {}: {}", self.command.display(), e)),
+        }
+    }
+}
+
+/// Parses a filter given as `NAME:ARGS`:
+///
+/// - `length:MIN_RATIO`
+/// - `kmer:FRACTION[:MIN_MATCHES]`
+/// - `sketch:MIN_SIMILARITY[:K[:SIZE]]`
+/// - `embedding:MIN_SIMILARITY`
+/// - `script:PATH`
+pub fn parse_filter(spec: &str) -> Result<Arc<dyn PairFilter>, String> {
+    let (name, args) = spec.split_once(':').unwrap_or((spec, ""));
+    let args: Vec<&str> = args.split(':').filter(|arg| !arg.is_empty()).collect();
+    let number = |index: usize, what: &str| -> Result<f64, String> {
+        let value: f64 = args
+            .get(index)
+            .ok_or_else(|| format!("filter '{}' needs {}", spec, what))?
+            .parse()
+            .map_err(|_| format!("invalid {} in filter '{}'", what, spec))?;
+        if (0.0..=1.0).contains(&value) {
+            Ok(value)
+        } else {
+            Err(format!(
+                "{} in filter '{}' must be between 0 and 1",
+                what, spec
+            ))
+        }
+    };
+    let count = |index: usize, what: &str, default: usize| -> Result<usize, String> {
+        args.get(index).map_or(Ok(default), |arg| {
+            arg.parse()
+                .map_err(|_| format!("invalid {} in filter '{}'", what, spec))
+        })
+    };
+
+    Ok(match name {
+        "length" => Arc::new(LengthRatio {
+            min_ratio: number(0, "a minimum length ratio")?,
+        }),
+        "kmer" => Arc::new(Kmer {
+            fraction: number(0, "a k-mer fraction")? as f32,
+            min_matches: count(1, "number of k-mer matches", 0)?,
+        }),
+        "sketch" => {
+            let sketch = Sketch {
+                min_similarity: number(0, "a minimum similarity")?,
+                k: count(1, "k-mer length", DEFAULT_SKETCH_K)?,
+                size: count(2, "sketch size", DEFAULT_SKETCH_SIZE)?,
+            };
+            if sketch.k == 0 || sketch.size == 0 {
+                return Err(format!(
+                    "k-mer length and sketch size in filter '{}' must be positive",
+                    spec
+                ));
+            }
+            Arc::new(sketch)
+        }
+        "embedding" => Arc::new(Composition {
+            min_similarity: number(0, "a minimum similarity")?,
+        }),
+        "script" => {
+            let command = PathBuf::from(args.join(":"));
+            if !command.is_file() {
+                return Err(format!("filter script '{}' not found", command.display()));
+            }
+            Arc::new(Script { command })
+        }
+        _ => {
+            return Err(format!(
+                "unknown filter '{}', expected one of: length, kmer, sketch, embedding, script",
+                name
+            ));
+        }
+    })
+}
+
+/// A filter of a chain with the number of pairs it checked and rejected
+struct Stage {
+    filter: Arc<dyn PairFilter>,
+    checked: AtomicU64,
+    rejected: AtomicU64,
+}
+
+/// Number of pairs a filter of a chain checked and rejected
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct FilterStats {
+    /// Name of the filter
+    pub name: &'static str,
+    /// Pairs reaching the filter
+    pub checked: u64,
+    /// Pairs rejected by the filter
+    pub rejected: u64,
+}
+
+/// Filters a pair has to pass in order, stopping at the first rejection
+#[derive(Default)]
+pub struct FilterChain {
+    stages: Vec<Stage>,
+}
+
+impl FilterChain {
+    /// Creates a chain of the filters in the given order
+    pub fn new(filters: impl IntoIterator<Item = Arc<dyn PairFilter>>) -> Self {
+        Self {
+            stages: filters
+                .into_iter()
+                .map(|filter| Stage {
+                    filter,
+                    checked: AtomicU64::new(0),
+                    rejected: AtomicU64::new(0),
+                })
+                .collect(),
+        }
+    }
+
+    /// Creates the chain of a run: the k-mer pre-filter of `--fraction` and
+    /// `--min-matches` if given, followed by `filters`
+    pub fn from_options(
+        fraction: Option<f32>,
+        min_matches: usize,
+        filters: &[Arc<dyn PairFilter>],
+    ) -> Self {
+        let kmer = fraction.map(|fraction| {
+            Arc::new(Kmer {
+                fraction,
+                min_matches,
+            }) as Arc<dyn PairFilter>
+        });
+        Self::new(kmer.into_iter().chain(filters.iter().cloned()))
+    }
+
+    /// Returns `true` if the chain accepts every pair without checking it
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Passes a pair through the filters, returning the name of the rejecting
+    /// filter and its reason if one rejects it
+    pub fn check(&self, seq1: &str, seq2: &str) -> Result<(), (&'static str, String)> {
+        for stage in &self.stages {
+            stage.checked.fetch_add(1, Ordering::Relaxed);
+            if let Verdict::Reject(reason) = stage.filter.check(seq1, seq2) {
+                stage.rejected.fetch_add(1, Ordering::Relaxed);
+                return Err((stage.filter.name(), reason));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the number of pairs each filter checked and rejected so far
+    pub fn stats(&self) -> Vec<FilterStats> {
+        self.stages
+            .iter()
+            .map(|stage| FilterStats {
+                name: stage.filter.name(),
+                checked: stage.checked.load(Ordering::Relaxed),
+                rejected: stage.rejected.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_chain() {
+        let filters = [
+            parse_filter("length:0.5").unwrap(),
+            parse_filter("sketch:0.2:3").unwrap(),
+        ];
+        let chain = FilterChain::from_options(Some(0.5), 1, &filters);
+
+        assert_eq!(chain.check("MAVMTKLLQ", "MAVMTKLLQ"), Ok(()));
+        let (filter, reason) = chain.check("MAVMTKLLQ", "WWWW").unwrap_err();
+        assert_eq!(filter, "kmer");
+        assert_eq!(reason, "fewer than 1 shared k-mers");
+        assert_eq!(chain.check("MAVM", "MAVMWWWWW").unwrap_err().0, "length");
+        assert_eq!(chain.check("MAVMTK", "MAVWWW").unwrap_err().0, "sketch");
+
+        let rejected: Vec<u64> = chain.stats().iter().map(|stats| stats.rejected).collect();
+        assert_eq!(rejected, [1, 1, 1]);
+        assert_eq!(chain.stats()[2].checked, 2);
+        assert!(parse_filter("length:2").is_err());
+        assert!(parse_filter("bloom:0.5").is_err());
+    }
+}
@@ -0,0 +1,442 @@
+//! Parsing of GenBank and EMBL flat files.
+//!
+//! Either the full sequence of every record is read, identified by its accession
+//! (with version), or the sequences of all features of one type, e.g. the protein
+//! sequences of the `CDS` features. Features are identified by their protein ID,
+//! locus tag or gene name, in that order. CDS features use their `/translation`
+//! qualifier; features without one are translated from the record sequence with
+//! the standard genetic code.
+//!
+//! Both formats share the layout of the feature table, with keys starting in the
+//! sixth and qualifiers in the twenty-second column; EMBL lines carry an additional
+//! two-letter line type.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::error::AlignerError;
+use crate::input::{ParseOptions, Parsed};
+use crate::translate::{GeneticCode, reverse_complement};
+
+/// A feature of the feature table
+#[derive(Debug, Default)]
+struct Feature {
+    kind: String,
+    location: String,
+    qualifiers: Vec<(String, String)>,
+    /// Whether the value of the last qualifier is a quoted string still open
+    open_quote: bool,
+}
+
+impl Feature {
+    /// Returns the value of the first qualifier with the given name
+    fn qualifier(&self, name: &str) -> Option<&str> {
+        self.qualifiers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Adds a line of the feature table that continues this feature
+    fn continue_with(&mut self, text: &str) {
+        if let Some(qualifier) = text.strip_prefix('/') {
+            let (name, value) = qualifier.split_once('=').unwrap_or((qualifier, ""));
+            self.open_quote = value.starts_with('"') && !(value.len() > 1 && value.ends_with('"'));
+            self.qualifiers
+                .push((name.to_string(), value.trim_matches('"').to_string()));
+        } else if let Some((name, value)) = self.qualifiers.last_mut()
+            && self.open_quote
+        {
+            self.open_quote = !text.ends_with('"');
+            // Translations are wrapped without separators, free text at spaces
+            if name != "translation" {
+                value.push(' ');
+            }
+            value.push_str(text.trim_end_matches('"'));
+        } else {
+            self.location.push_str(text);
+        }
+    }
+}
+
+/// A record of a flat file
+#[derive(Debug, Default)]
+struct Record {
+    name: String,
+    accession: Option<String>,
+    features: Vec<Feature>,
+    sequence: Vec<u8>,
+}
+
+impl Record {
+    /// Identifier of the record: accession with version if known, or its name
+    fn id(&self) -> &str {
+        self.accession.as_deref().unwrap_or(&self.name)
+    }
+}
+
+/// Reads a GenBank or EMBL flat file.
+///
+/// With `options.feature` set, the sequences of all features of that type are
+/// read instead of the full record sequences.
+///
+/// # Errors
+///
+/// Returns `AlignerError::Io` if the file cannot be read, and
+/// `AlignerError::InvalidInput` if a record is truncated or, unless the parse
+/// mode is lenient, a feature has a location that cannot be extracted.
+pub fn read(path: &Path, options: &ParseOptions) -> Result<Parsed, AlignerError> {
+    parse(BufReader::new(File::open(path)?), options)
+        .map_err(|e| AlignerError::InvalidInput(format!("{}: {}", path.display(), e)))
+}
+
+/// Parses flat file records from a reader
+fn parse(reader: impl BufRead, options: &ParseOptions) -> Result<Parsed, String> {
+    let mut parsed = Parsed::default();
+    let mut number = 0;
+    let mut record: Option<Record> = None;
+    let mut section = Section::Header;
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        let at_line = |message: &str| format!("line {}: {}", index + 1, message);
+        if line.trim().is_empty() {
+            continue;
+        }
+        if line.starts_with("//") {
+            let finished = record
+                .take()
+                .ok_or_else(|| at_line("'//' outside a record"))?;
+            add_sequences(&mut parsed, &mut number, finished, options)?;
+            section = Section::Header;
+            continue;
+        }
+
+        let (tag, content) = split_line(&line);
+        if let Some(name) = match tag {
+            "LOCUS" | "ID" => content.split([' ', ';']).find(|s| !s.is_empty()),
+            _ => None,
+        } {
+            if record.is_some() {
+                return Err(at_line("record is not terminated by '//'"));
+            }
+            let accession = embl_version(content).map(|version| format!("{}.{}", name, version));
+            record = Some(Record {
+                name: name.to_string(),
+                accession,
+                ..Record::default()
+            });
+            section = Section::Header;
+            continue;
+        }
+        let current = record
+            .as_mut()
+            .ok_or_else(|| at_line("line outside a record"))?;
+
+        match (tag, section) {
+            ("VERSION", _) => current.accession = content.split_whitespace().next().map(Into::into),
+            ("ACCESSION" | "AC", _) if current.accession.is_none() => {
+                current.accession = content
+                    .split([' ', ';'])
+                    .find(|s| !s.is_empty())
+                    .map(Into::into);
+            }
+            ("FEATURES" | "FH", _) => section = Section::Features,
+            ("FT", _) | ("", Section::Features) => {
+                // Keys start in column 6 and qualifiers in column 22 of the table
+                let text = content;
+                if text.starts_with(' ') || text.starts_with('/') {
+                    let feature = current
+                        .features
+                        .last_mut()
+                        .ok_or_else(|| at_line("qualifier before the first feature"))?;
+                    feature.continue_with(text.trim());
+                } else {
+                    let (kind, location) = text.split_once(' ').unwrap_or((text, ""));
+                    current.features.push(Feature {
+                        kind: kind.to_string(),
+                        location: location.trim().to_string(),
+                        ..Feature::default()
+                    });
+                }
+            }
+            ("ORIGIN" | "SQ", _) => section = Section::Sequence,
+            ("", Section::Sequence) => current
+                .sequence
+                .extend(content.bytes().filter(u8::is_ascii_alphabetic)),
+            _ => section = Section::Header,
+        }
+    }
+    if record.is_some() {
+        return Err("last record is not terminated by '//'".to_string());
+    }
+    Ok(parsed)
+}
+
+/// Part of a record a line belongs to
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Section {
+    Header,
+    Features,
+    Sequence,
+}
+
+/// Splits a line into its keyword or EMBL line type and the content.
+///
+/// Continuation lines have an empty keyword. For feature table lines the content
+/// starts at the column of the feature keys, so qualifiers keep their indentation.
+fn split_line(line: &str) -> (&str, &str) {
+    if let Some(content) = line.strip_prefix("FT") {
+        return ("FT", content.get(3..).unwrap_or_default());
+    }
+    if line.starts_with(' ') {
+        return ("", line.get(5..).unwrap_or_default());
+    }
+    match line.find(' ') {
+        // EMBL line types are two letters followed by three spaces
+        Some(2) => (&line[..2], line[2..].trim_start()),
+        Some(end) => (&line[..end], line[end..].trim_start()),
+        None => (line, ""),
+    }
+}
+
+/// Extracts the sequence version from an EMBL `ID` line such as
+/// `X56734; SV 1; linear; ...`
+fn embl_version(content: &str) -> Option<&str> {
+    content
+        .split(';')
+        .find_map(|field| field.trim().strip_prefix("SV "))
+        .map(str::trim)
+}
+
+/// Adds the full sequence or the selected features of a record
+fn add_sequences(
+    parsed: &mut Parsed,
+    number: &mut usize,
+    record: Record,
+    options: &ParseOptions,
+) -> Result<(), String> {
+    let sequence = String::from_utf8_lossy(&record.sequence).to_ascii_uppercase();
+    let Some(kind) = &options.feature else {
+        *number += 1;
+        let id = record.id().to_string();
+        let sequence = if sequence.is_empty() {
+            Err("record has no sequence".to_string())
+        } else {
+            Ok(sequence)
+        };
+        return parsed.add(options.mode, *number, id, sequence);
+    };
+
+    let features = record
+        .features
+        .iter()
+        .filter(|feature| feature.kind.eq_ignore_ascii_case(kind));
+    for (index, feature) in features.enumerate() {
+        *number += 1;
+        let id = ["protein_id", "locus_tag", "gene"]
+            .iter()
+            .find_map(|name| feature.qualifier(name))
+            .map_or_else(
+                || format!("{}_{}_{}", record.id(), feature.kind, index + 1),
+                str::to_string,
+            );
+        let extracted = if feature.kind == "CDS" {
+            translate_feature(feature, &sequence, options.genetic_code)
+        } else {
+            extract(&feature.location, &sequence)
+        };
+        let extracted = extracted.map_err(|e| format!("{} in record {}", e, record.id()));
+        parsed.add(options.mode, *number, id, extracted)?;
+    }
+    Ok(())
+}
+
+/// Returns the protein sequence of a CDS feature, translated with its
+/// `/transl_table` or else with `default_code`
+fn translate_feature(
+    feature: &Feature,
+    sequence: &str,
+    default_code: &GeneticCode,
+) -> Result<String, String> {
+    if let Some(translation) = feature.qualifier("translation") {
+        return Ok(translation.split_whitespace().collect());
+    }
+    let code = match feature.qualifier("transl_table") {
+        Some(table) => {
+            let table: u32 = table
+                .parse()
+                .map_err(|_| "invalid /transl_table".to_string())?;
+            GeneticCode::get(table)
+                .ok_or_else(|| format!("translation table {} is not supported", table))?
+        }
+        None => default_code,
+    };
+    let codon_start: usize = feature
+        .qualifier("codon_start")
+        .map_or(Ok(1), str::parse)
+        .map_err(|_| "invalid /codon_start".to_string())?;
+    let coding = extract(&feature.location, sequence)?;
+    if codon_start <= 1 {
+        return Ok(code.translate_cds(&coding));
+    }
+    // Features starting within a codon have no start codon
+    let mut protein = code.translate(coding.get(codon_start - 1..).unwrap_or_default());
+    if protein.ends_with('*') {
+        protein.pop();
+    }
+    Ok(protein)
+}
+
+/// A contiguous part of a feature location, with one-based inclusive positions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Span {
+    start: usize,
+    end: usize,
+    complement: bool,
+}
+
+/// Extracts the nucleotide sequence of a feature location
+fn extract(location: &str, sequence: &str) -> Result<String, String> {
+    let location: String = location.split_whitespace().collect();
+    let mut extracted = String::new();
+    for span in parse_location(&location)? {
+        let part = sequence
+            .get(span.start - 1..span.end)
+            .ok_or_else(|| format!("location {} is outside the sequence", location))?;
+        if span.complement {
+            extracted += &reverse_complement(part);
+        } else {
+            extracted.push_str(part);
+        }
+    }
+    Ok(extracted)
+}
+
+/// Parses a location such as `complement(join(<1..206,300..>512))`
+fn parse_location(location: &str) -> Result<Vec<Span>, String> {
+    if let Some(inner) = location
+        .strip_prefix("complement(")
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        let mut spans = parse_location(inner)?;
+        spans.reverse();
+        for span in &mut spans {
+            span.complement = !span.complement;
+        }
+        return Ok(spans);
+    }
+    if let Some(inner) = ["join(", "order("]
+        .iter()
+        .find_map(|prefix| location.strip_prefix(prefix))
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        let mut spans = Vec::new();
+        for part in split_top_level(inner) {
+            spans.extend(parse_location(part)?);
+        }
+        return Ok(spans);
+    }
+    if location.contains(':') {
+        return Err(format!("location {} refers to another record", location));
+    }
+
+    let position = |value: &str| {
+        value
+            .trim_start_matches('<')
+            .trim_start_matches('>')
+            .parse::<usize>()
+            .ok()
+            .filter(|position| *position > 0)
+            .ok_or_else(|| format!("invalid location {}", location))
+    };
+    let (start, end) = match location.split_once("..") {
+        Some((start, end)) => (position(start)?, position(end)?),
+        None => {
+            let single = position(location)?;
+            (single, single)
+        }
+    };
+    if start > end {
+        return Err(format!("invalid location {}", location));
+    }
+    Ok(vec![Span {
+        start,
+        end,
+        complement: false,
+    }])
+}
+
+/// Splits at commas that are not nested in parentheses
+fn split_top_level(list: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (index, c) in list.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&list[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&list[start..]);
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GENBANK: &str = "\
+LOCUS       TEST1        24 bp    DNA     linear   BCT 01-JAN-2024
+ACCESSION   AB000001
+VERSION     AB000001.2
+FEATURES             Location/Qualifiers
+     CDS             1..12
+                     /locus_tag=\"tst_0001\"
+                     /product=\"hypothetical
+                     protein\"
+                     /translation=\"MAV
+                     K\"
+     CDS             complement(13..24)
+                     /locus_tag=\"tst_0002\"
+ORIGIN
+        1 atggccgtga aataaacttt ccat
+//
+";
+
+    const EMBL: &str = "\
+ID   X56734; SV 1; linear; mRNA; STD; PLN; 12 BP.
+AC   X56734;
+FT   CDS             1..12
+FT                   /protein_id=\"CAA39967.1\"
+SQ   Sequence 12 BP;
+     atggccgtga aa                                                        12
+//
+";
+
+    #[test]
+    fn test_parse_flat_files() {
+        let options = ParseOptions::default();
+        let parsed = parse(GENBANK.as_bytes(), &options).unwrap();
+        assert_eq!(parsed.sequences["AB000001.2"], "ATGGCCGTGAAATAAACTTTCCAT");
+
+        let cds = ParseOptions {
+            feature: Some("CDS".to_string()),
+            ..ParseOptions::default()
+        };
+        let parsed = parse(GENBANK.as_bytes(), &cds).unwrap();
+        assert_eq!(parsed.sequences["tst_0001"], "MAVK");
+        // ATGGAAAGTTTA on the reverse strand
+        assert_eq!(parsed.sequences["tst_0002"], "MESL");
+
+        let parsed = parse(EMBL.as_bytes(), &options).unwrap();
+        assert_eq!(parsed.sequences["X56734.1"], "ATGGCCGTGAAA");
+        let parsed = parse(EMBL.as_bytes(), &cds).unwrap();
+        assert_eq!(parsed.sequences["CAA39967.1"], "MAVK");
+    }
+}
@@ -0,0 +1,408 @@
+//! Alignments with traceback in linear space.
+//!
+//! The traceback matrix of an alignment grows with the product of the sequence
+//! lengths, so the alignments of sequences of tens of kilobases no longer fit into
+//! memory. Hirschberg's divide and conquer computes them with memory proportional
+//! to the sequence lengths instead, in the variant of Myers and Miller for affine
+//! gap penalties: the best score of every cell of the middle row is computed from
+//! the top and from the bottom in linear space, the cell where the best alignment
+//! crosses the row splits the problem into two halves, and the halves are aligned
+//! the same way. This takes about twice the time of the full matrices.
+//!
+//! Alignments that do not cover both sequences, in the local, semi-global and
+//! overlap modes, are first delimited by scoring forward to the cell where the best
+//! alignment ends and backward from there to the cell where it starts, so only the
+//! part between them is aligned end to end.
+
+use bio::alignment::{Alignment, AlignmentMode, AlignmentOperation};
+
+use crate::align::{AlignMode, GapPenalties, MatcherFn};
+
+/// Far below any reachable score, but safe to add penalties to
+const NEG_INF: i32 = i32::MIN / 2;
+
+/// Aligns two sequences in `mode` with traceback in linear space.
+///
+/// The score is the same as that of the full matrices. Of several best alignments,
+/// a different one may be chosen.
+pub fn alignment(
+    seq1: &str,
+    seq2: &str,
+    matcher: &MatcherFn,
+    mode: AlignMode,
+    gaps: GapPenalties,
+) -> Alignment {
+    let (x, y) = (seq1.as_bytes(), seq2.as_bytes());
+    let (free_x, free_y) = mode.free_ends(x.len(), y.len());
+    let local = mode == AlignMode::Local;
+    let ends = (free_x, free_y, local);
+    let (xend, yend) = if mode == AlignMode::Global {
+        (x.len(), y.len())
+    } else {
+        best_cell(x, y, matcher, gaps, ends, true)
+    };
+    // The best alignment ending there, scored backward, starts where it is best
+    let (xstart, ystart) = if mode == AlignMode::Global {
+        (0, 0)
+    } else {
+        let (rx, ry): (Vec<u8>, Vec<u8>) = (
+            x[..xend].iter().rev().copied().collect(),
+            y[..yend].iter().rev().copied().collect(),
+        );
+        let (xlen, ylen) = best_cell(&rx, &ry, matcher, gaps, ends, false);
+        (xend - xlen, yend - ylen)
+    };
+
+    let mut aligner = Hirschberg {
+        matcher,
+        open: gaps.open,
+        extend: gaps.extend,
+        operations: Vec::new(),
+        cc: Vec::new(),
+        dd: Vec::new(),
+        rr: Vec::new(),
+        ss: Vec::new(),
+    };
+    aligner.align(&x[xstart..xend], &y[ystart..yend], gaps.open, gaps.open);
+    let operations = aligner.operations;
+    Alignment {
+        score: score_of(
+            &operations,
+            &x[xstart..xend],
+            &y[ystart..yend],
+            matcher,
+            gaps,
+        ),
+        ystart,
+        xstart,
+        yend,
+        xend,
+        ylen: y.len(),
+        xlen: x.len(),
+        operations,
+        mode: match mode {
+            AlignMode::Global => AlignmentMode::Global,
+            AlignMode::Local => AlignmentMode::Local,
+            AlignMode::Semiglobal | AlignMode::Overlap => AlignmentMode::Custom,
+        },
+    }
+}
+
+/// Returns the score of the global alignment of `x` and `y` given by `operations`
+fn score_of(
+    operations: &[AlignmentOperation],
+    x: &[u8],
+    y: &[u8],
+    matcher: &MatcherFn,
+    gaps: GapPenalties,
+) -> i32 {
+    let (mut i, mut j, mut score) = (0, 0, 0);
+    let mut previous = None;
+    for op in operations {
+        match op {
+            AlignmentOperation::Match | AlignmentOperation::Subst => {
+                score += matcher(x[i], y[j]);
+                i += 1;
+                j += 1;
+            }
+            AlignmentOperation::Ins | AlignmentOperation::Del => {
+                if previous != Some(op) {
+                    score += gaps.open;
+                }
+                score += gaps.extend;
+                if *op == AlignmentOperation::Ins {
+                    i += 1;
+                } else {
+                    j += 1;
+                }
+            }
+            AlignmentOperation::Xclip(_) | AlignmentOperation::Yclip(_) => {}
+        }
+        previous = Some(op);
+    }
+    score
+}
+
+/// Scores `x` against `y` with Gotoh's algorithm in linear space and returns the
+/// best-scoring cell of those an alignment may end in: any cell for local
+/// alignments, the last column or row if the ends of x or y are free, the last
+/// cell otherwise. If `free_start` is set, alignments start in the same way anywhere
+/// in the first column or row, or in any cell, instead of in the first cell.
+fn best_cell(
+    x: &[u8],
+    y: &[u8],
+    matcher: &MatcherFn,
+    gaps: GapPenalties,
+    (free_x, free_y, local): (bool, bool, bool),
+    free_start: bool,
+) -> (usize, usize) {
+    let gap = |len: usize, free: bool| {
+        if len == 0 || (free_start && (free || local)) {
+            0
+        } else {
+            gaps.open + gaps.extend * len as i32
+        }
+    };
+    let mut h: Vec<i32> = (0..=y.len()).map(|j| gap(j, free_y)).collect();
+    let mut f = vec![NEG_INF; y.len() + 1];
+    let mut best = (NEG_INF, 0, 0);
+    let mut consider = |score: i32, i: usize, j: usize| {
+        let allowed = local
+            || (free_x && j == y.len())
+            || (free_y && i == x.len())
+            || (i == x.len() && j == y.len());
+        if allowed && score > best.0 {
+            best = (score, i, j);
+        }
+    };
+    for (j, &score) in h.iter().enumerate() {
+        consider(score, 0, j);
+    }
+    for (i, &residue) in x.iter().enumerate() {
+        let mut diagonal = h[0];
+        h[0] = gap(i + 1, free_x);
+        consider(h[0], i + 1, 0);
+        let mut e = NEG_INF;
+        for j in 1..=y.len() {
+            f[j] = f[j].max(h[j] + gaps.open) + gaps.extend;
+            e = e.max(h[j - 1] + gaps.open) + gaps.extend;
+            let mut cell = (diagonal + matcher(residue, y[j - 1])).max(e).max(f[j]);
+            if local && free_start {
+                cell = cell.max(0);
+            }
+            diagonal = h[j];
+            h[j] = cell;
+            consider(cell, i + 1, j);
+        }
+    }
+    (best.1, best.2)
+}
+
+/// Divide and conquer alignment of Myers and Miller, with the rows of the forward
+/// (`cc`, `dd`) and backward (`rr`, `ss`) scores reused between the subproblems
+struct Hirschberg<'a> {
+    matcher: &'a MatcherFn,
+    open: i32,
+    extend: i32,
+    operations: Vec<AlignmentOperation>,
+    /// Best scores of the cells of the middle row from the top
+    cc: Vec<i32>,
+    /// Best scores of the cells of the middle row from the top ending in a gap in y
+    dd: Vec<i32>,
+    /// Best scores of the cells of the middle row from the bottom
+    rr: Vec<i32>,
+    /// Best scores of the cells of the middle row from the bottom ending in a gap in y
+    ss: Vec<i32>,
+}
+
+impl Hirschberg<'_> {
+    /// Score of a gap of `len` residues
+    fn gap(&self, len: usize) -> i32 {
+        if len == 0 {
+            0
+        } else {
+            self.open + self.extend * len as i32
+        }
+    }
+
+    fn push(&mut self, op: AlignmentOperation, count: usize) {
+        self.operations.extend(std::iter::repeat_n(op, count));
+    }
+
+    /// Appends the operations of the best global alignment of `x` and `y`, where
+    /// a gap in y at the start costs `start` instead of the opening score and one
+    /// at the end `end`, as they continue a gap of the enclosing problem if zero
+    fn align(&mut self, x: &[u8], y: &[u8], start: i32, end: i32) {
+        let (m, n) = (x.len(), y.len());
+        if n == 0 {
+            self.push(AlignmentOperation::Ins, m);
+            return;
+        }
+        if m == 0 {
+            self.push(AlignmentOperation::Del, n);
+            return;
+        }
+        if m == 1 {
+            // Either the residue of x is left unaligned, continuing a gap of the
+            // enclosing problem where that is cheaper, or aligned with a residue of y
+            let mut best = start.max(end) + self.extend + self.gap(n);
+            let mut column = None;
+            for j in 1..=n {
+                let score = self.gap(j - 1) + (self.matcher)(x[0], y[j - 1]) + self.gap(n - j);
+                if score > best {
+                    best = score;
+                    column = Some(j);
+                }
+            }
+            match column {
+                Some(j) => {
+                    self.push(AlignmentOperation::Del, j - 1);
+                    let op = if x[0] == y[j - 1] {
+                        AlignmentOperation::Match
+                    } else {
+                        AlignmentOperation::Subst
+                    };
+                    self.push(op, 1);
+                    self.push(AlignmentOperation::Del, n - j);
+                }
+                None if start >= end => {
+                    self.push(AlignmentOperation::Ins, 1);
+                    self.push(AlignmentOperation::Del, n);
+                }
+                None => {
+                    self.push(AlignmentOperation::Del, n);
+                    self.push(AlignmentOperation::Ins, 1);
+                }
+            }
+            return;
+        }
+
+        let middle = m / 2;
+        self.forward(&x[..middle], y, start);
+        self.backward(&x[middle..], y, end);
+        // The best alignment crosses the middle row in a cell, or with a gap in y
+        // spanning it, which is opened only once
+        let (mut best, mut column, mut spanning) = (i32::MIN, 0, false);
+        for j in 0..=n {
+            let score = self.cc[j] + self.rr[j];
+            if score > best {
+                (best, column, spanning) = (score, j, false);
+            }
+            let score = self.dd[j] + self.ss[j] - self.open;
+            if score > best {
+                (best, column, spanning) = (score, j, true);
+            }
+        }
+        if spanning {
+            self.align(&x[..middle - 1], &y[..column], start, 0);
+            self.push(AlignmentOperation::Ins, 2);
+            self.align(&x[middle + 1..], &y[column..], 0, end);
+        } else {
+            self.align(&x[..middle], &y[..column], start, self.open);
+            self.align(&x[middle..], &y[column..], self.open, end);
+        }
+    }
+
+    /// Computes the last row of the scores of `x` against `y` from the top into
+    /// `cc` and `dd`
+    fn forward(&mut self, x: &[u8], y: &[u8], start: i32) {
+        let n = y.len();
+        let (open, extend) = (self.open, self.extend);
+        self.cc.clear();
+        self.dd.clear();
+        self.cc.push(0);
+        self.dd.push(0);
+        for j in 1..=n {
+            let score = self.gap(j);
+            self.cc.push(score);
+            self.dd.push(score + open);
+        }
+        let mut t = start;
+        for &residue in x {
+            let mut diagonal = self.cc[0];
+            t += extend;
+            let mut c = t;
+            self.cc[0] = c;
+            let mut e = t + open;
+            for j in 1..=n {
+                e = e.max(c + open) + extend;
+                let d = self.dd[j].max(self.cc[j] + open) + extend;
+                c = d.max(e).max(diagonal + (self.matcher)(residue, y[j - 1]));
+                diagonal = self.cc[j];
+                self.cc[j] = c;
+                self.dd[j] = d;
+            }
+        }
+        self.dd[0] = self.cc[0];
+    }
+
+    /// Computes the first row of the scores of `x` against `y` from the bottom into
+    /// `rr` and `ss`
+    fn backward(&mut self, x: &[u8], y: &[u8], end: i32) {
+        let n = y.len();
+        let (open, extend) = (self.open, self.extend);
+        self.rr.clear();
+        self.ss.clear();
+        self.rr.resize(n + 1, 0);
+        self.ss.resize(n + 1, 0);
+        for j in (0..n).rev() {
+            let score = self.gap(n - j);
+            self.rr[j] = score;
+            self.ss[j] = score + open;
+        }
+        let mut t = end;
+        for &residue in x.iter().rev() {
+            let mut diagonal = self.rr[n];
+            t += extend;
+            let mut c = t;
+            self.rr[n] = c;
+            let mut e = t + open;
+            for j in (0..n).rev() {
+                e = e.max(c + open) + extend;
+                let d = self.ss[j].max(self.rr[j] + open) + extend;
+                c = d.max(e).max(diagonal + (self.matcher)(residue, y[j]));
+                diagonal = self.rr[j];
+                self.rr[j] = c;
+                self.ss[j] = d;
+            }
+        }
+        self.ss[n] = self.rr[n];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::align::{Summary, align};
+    use bio::scores::blosum62;
+
+    #[test]
+    fn test_hirschberg_matches_full_alignment() {
+        let identity: MatcherFn = |a, b| if a == b { 1 } else { -1 };
+        let pairs = [
+            ("MAVMTPRRERSSLLSRALRF", "MANPYERGPNPTDALLEARSGPF"),
+            ("ACGTACGTTTGA", "ACGAAAAACGTT"),
+            ("A", "WWWWWWW"),
+            ("MKTAYIAKQRQISFVKSHF", "AYIAKQ"),
+            ("WWWWWWMAVMT", "MAVMTKKKKKK"),
+            ("", "ACGT"),
+        ];
+        let cheap_gaps = GapPenalties::from_costs(5, 2).unwrap();
+        for (seq1, seq2) in pairs {
+            for matcher in [identity, blosum62] {
+                for mode in [
+                    AlignMode::Global,
+                    AlignMode::Local,
+                    AlignMode::Semiglobal,
+                    AlignMode::Overlap,
+                ] {
+                    for gaps in [GapPenalties::default(), cheap_gaps] {
+                        let alignment = alignment(seq1, seq2, &matcher, mode, gaps);
+                        let expected = align(seq1, seq2, &matcher, mode, gaps);
+                        assert_eq!(alignment.score, expected, "{seq1} {seq2} {mode:?}");
+                        let (x, y) = (
+                            &seq1.as_bytes()[alignment.xstart..alignment.xend],
+                            &seq2.as_bytes()[alignment.ystart..alignment.yend],
+                        );
+                        assert_eq!(
+                            score_of(&alignment.operations, x, y, &matcher, gaps),
+                            expected,
+                            "{seq1} {seq2} {mode:?}"
+                        );
+                    }
+                }
+            }
+        }
+        let alignment = alignment(
+            "MAVMTKL",
+            "MAVMKL",
+            &identity,
+            AlignMode::Global,
+            GapPenalties::default(),
+        );
+        assert_eq!(
+            Summary::of(&alignment, b"MAVMTKL", b"MAVMKL", &identity).identity(),
+            6.0 / 7.0
+        );
+    }
+}
@@ -0,0 +1,144 @@
+//! Aggregation of the best hits of every query.
+//!
+//! With `--top-hits N`, or `--best-hit-only` for a single hit, a run writes only
+//! the N highest-scoring subjects of every sequence once all pairs are aligned,
+//! instead of a row per pair. Every query keeps its hits in a min-heap bounded to
+//! N entries, so memory grows with the number of sequences rather than pairs.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::align::{AlignmentResult, Score};
+
+/// A result ranked by score, preferring the smaller subject identifier on ties
+struct Hit<S>(AlignmentResult<S>);
+
+impl<S: Score> Hit<S> {
+    fn score(&self) -> f64 {
+        self.0.score.map_or(f64::NEG_INFINITY, Into::into)
+    }
+}
+
+impl<S: Score> Ord for Hit<S> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score()
+            .total_cmp(&other.score())
+            .then_with(|| other.0.subject_id.cmp(&self.0.subject_id))
+    }
+}
+
+impl<S: Score> PartialOrd for Hit<S> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<S: Score> PartialEq for Hit<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<S: Score> Eq for Hit<S> {}
+
+/// The best-scoring subjects of every query seen so far
+pub struct TopHits<S> {
+    limit: usize,
+    symmetric: bool,
+    heaps: HashMap<String, BinaryHeap<Reverse<Hit<S>>>>,
+}
+
+impl<S: Score> TopHits<S> {
+    /// Creates an empty aggregation keeping `limit` hits per query.
+    ///
+    /// If `symmetric` is set, every pair is aligned in one order only and also
+    /// counts as a hit of its subject.
+    pub fn new(limit: usize, symmetric: bool) -> Self {
+        Self {
+            limit,
+            symmetric,
+            heaps: HashMap::new(),
+        }
+    }
+
+    /// Considers a result as a hit of its query, and of its subject for symmetric
+    /// runs. Pairs without a score are ignored.
+    pub fn offer(&mut self, result: AlignmentResult<S>) {
+        if result.score.is_none() {
+            return;
+        }
+        if self.symmetric {
+            let mirrored = AlignmentResult {
+                query_id: result.subject_id.clone(),
+                subject_id: result.query_id.clone(),
+                seq1_len: result.seq2_len,
+                seq2_len: result.seq1_len,
+                ..result.clone()
+            };
+            self.push(mirrored);
+        }
+        self.push(result);
+    }
+
+    /// Keeps a result as a hit of its query only, returning the result it pushes
+    /// out of the hits of the query, if any
+    pub fn keep(&mut self, result: AlignmentResult<S>) -> Option<AlignmentResult<S>> {
+        self.push(result)
+    }
+
+    fn push(&mut self, result: AlignmentResult<S>) -> Option<AlignmentResult<S>> {
+        let heap = self.heaps.entry(result.query_id.clone()).or_default();
+        heap.push(Reverse(Hit(result)));
+        if heap.len() > self.limit {
+            heap.pop().map(|Reverse(Hit(result))| result)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the hits ordered by query identifier and then from best to worst
+    pub fn into_results(self) -> Vec<AlignmentResult<S>> {
+        let mut queries: Vec<_> = self.heaps.into_iter().collect();
+        queries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        queries
+            .into_iter()
+            .flat_map(|(_, heap)| {
+                // Sorting the reversed hits ascending puts the best hit first
+                heap.into_sorted_vec()
+                    .into_iter()
+                    .map(|Reverse(Hit(result))| result)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::align::PairStatus;
+
+    #[test]
+    fn test_top_hits() {
+        let result = |query_id: &str, subject_id: &str, score: Option<i32>| AlignmentResult {
+            seq1_len: 1,
+            seq2_len: 2,
+            ..AlignmentResult::for_test(query_id, subject_id, score, PairStatus::Aligned)
+        };
+        let mut hits = TopHits::new(1, true);
+        hits.offer(result("b", "a", Some(5)));
+        hits.offer(result("c", "a", Some(7)));
+        hits.offer(result("c", "b", Some(5)));
+        hits.offer(result("d", "a", None));
+
+        let best: Vec<(String, String, Option<i32>, usize)> = hits
+            .into_results()
+            .into_iter()
+            .map(|r| (r.query_id, r.subject_id, r.score, r.seq1_len))
+            .collect();
+        let expected = [("a", "c", 7, 2), ("b", "a", 5, 1), ("c", "a", 7, 1)];
+        assert_eq!(
+            best,
+            expected.map(|(q, s, score, len)| (q.to_string(), s.to_string(), Some(score), len))
+        );
+    }
+}
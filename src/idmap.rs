@@ -0,0 +1,347 @@
+//! Renaming of sequence identifiers via a mapping file.
+//!
+//! A mapping file is a tab-separated file with the original identifier in the first
+//! column and the new name in the second, e.g. to replace accessions by gene names.
+//! Names can be applied right after parsing, so all later stages see the new names,
+//! or only when results are written.
+//!
+//! Independently of a mapping file, identifiers containing tabs, line breaks or
+//! other control characters are sanitized before results are written, since they
+//! would corrupt the rows of the tab-separated output. The renamed identifiers are
+//! recorded in a mapping file from which the original identifiers can be restored.
+
+use clap::ValueEnum;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::AlignerError;
+
+/// Number of unmapped identifiers listed in error messages
+const REPORTED_IDS: usize = 5;
+
+/// Point at which identifiers are renamed
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ValueEnum)]
+pub enum MapStage {
+    /// Rename sequences right after reading the input
+    #[default]
+    Input,
+    /// Keep the original identifiers while aligning and rename them in the output
+    Output,
+}
+
+/// Handling of sequences whose identifier is missing from the mapping file
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ValueEnum)]
+pub enum Unmapped {
+    /// Abort with an error listing the unmapped identifiers
+    #[default]
+    Error,
+    /// Keep the original identifier
+    Keep,
+    /// Leave the sequence out of the run
+    Drop,
+}
+
+/// Mapping from original sequence identifiers to new names
+#[derive(Debug, Clone)]
+pub struct IdMap {
+    names: HashMap<String, String>,
+}
+
+impl IdMap {
+    /// Reads a mapping file.
+    ///
+    /// Empty lines and lines starting with `#` are ignored. Additional columns
+    /// after the second are ignored as well.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AlignerError::Io` if the file cannot be read, and
+    /// `AlignerError::InvalidInput` if a line has fewer than two columns or an
+    /// identifier is mapped more than once.
+    pub fn read(path: &Path) -> Result<Self, AlignerError> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut names = HashMap::new();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut columns = line.split('\t');
+            let (Some(from), Some(to)) = (columns.next(), columns.next()) else {
+                return Err(AlignerError::InvalidInput(format!(
+                    "line {} of {} does not have two tab-separated columns",
+                    number + 1,
+                    path.display()
+                )));
+            };
+            if names.insert(from.to_string(), to.to_string()).is_some() {
+                return Err(AlignerError::InvalidInput(format!(
+                    "identifier '{}' is mapped more than once in {}",
+                    from,
+                    path.display()
+                )));
+            }
+        }
+        Ok(Self { names })
+    }
+
+    /// Returns the new name of an identifier, or the identifier itself if unmapped
+    pub fn name<'a>(&'a self, id: &'a str) -> &'a str {
+        self.names.get(id).map_or(id, String::as_str)
+    }
+
+    /// Applies the unmapped-identifier policy to the input sequences.
+    ///
+    /// With [`Unmapped::Drop`] the unmapped sequences are removed from `input`;
+    /// with [`Unmapped::Keep`] it is left unchanged. Returns the number of
+    /// unmapped identifiers.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AlignerError::InvalidInput` listing the first unmapped identifiers
+    /// if the policy is [`Unmapped::Error`] and any identifier is unmapped.
+    pub fn check(
+        &self,
+        input: &mut HashMap<String, String>,
+        unmapped: Unmapped,
+    ) -> Result<usize, AlignerError> {
+        let mut missing: Vec<&String> = input
+            .keys()
+            .filter(|id| !self.names.contains_key(*id))
+            .collect();
+        let count = missing.len();
+        match unmapped {
+            Unmapped::Error if count > 0 => {
+                missing.sort();
+                let listed: Vec<&str> = missing
+                    .iter()
+                    .take(REPORTED_IDS)
+                    .map(|id| id.as_str())
+                    .collect();
+                Err(AlignerError::InvalidInput(format!(
+                    "{} sequence identifiers are not in the ID map: {}{}",
+                    count,
+                    listed.join(", "),
+                    if count > REPORTED_IDS { ", ..." } else { "" }
+                )))
+            }
+            Unmapped::Drop => {
+                input.retain(|id, _| self.names.contains_key(id));
+                Ok(count)
+            }
+            _ => Ok(count),
+        }
+    }
+
+    /// Renames the keys of the input sequences.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AlignerError::InvalidInput` if two sequences end up with the same
+    /// name, which would silently merge them.
+    pub fn rename(
+        &self,
+        input: HashMap<String, String>,
+    ) -> Result<HashMap<String, String>, AlignerError> {
+        let mut renamed = HashMap::with_capacity(input.len());
+        for (id, seq) in input {
+            let name = self.name(&id).to_string();
+            if renamed.contains_key(&name) {
+                return Err(AlignerError::InvalidInput(format!(
+                    "more than one sequence is named '{}' after applying the ID map",
+                    name
+                )));
+            }
+            renamed.insert(name, seq);
+        }
+        Ok(renamed)
+    }
+
+    /// Translates identifiers found in the output back to the input identifiers
+    /// they were renamed from
+    pub fn originals(
+        &self,
+        input: &HashMap<String, String>,
+        names: &HashSet<String>,
+    ) -> HashSet<String> {
+        input
+            .keys()
+            .filter(|id| names.contains(self.name(id)))
+            .cloned()
+            .collect()
+    }
+
+    /// Translates pairs of identifiers found in the output back to the input
+    /// identifiers they were renamed from, leaving out pairs of unknown names
+    pub fn original_pairs(
+        &self,
+        input: &HashMap<String, String>,
+        pairs: Vec<(String, String)>,
+    ) -> Vec<(String, String)> {
+        let originals: HashMap<&str, &String> =
+            input.keys().map(|id| (self.name(id), id)).collect();
+        pairs
+            .into_iter()
+            .filter_map(|(query_id, subject_id)| {
+                let query_id = originals.get(query_id.as_str())?;
+                let subject_id = originals.get(subject_id.as_str())?;
+                Some(((*query_id).clone(), (*subject_id).clone()))
+            })
+            .collect()
+    }
+}
+
+/// An identifier that was changed to make it safe for tab-separated output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sanitized {
+    /// Identifier used in the output
+    pub name: String,
+    /// Identifier as it appeared in the input
+    pub original: String,
+}
+
+/// Replaces tabs, line breaks and other control characters in the identifiers of
+/// the input sequences by underscores.
+///
+/// Sanitized identifiers that collide with another identifier get a numeric
+/// suffix. Identifiers are processed in sorted order, so the same input always
+/// yields the same names. Returns the renamed identifiers, sorted by name.
+pub fn sanitize_ids(input: &mut HashMap<String, String>) -> Vec<Sanitized> {
+    let mut unsafe_ids: Vec<String> = input
+        .keys()
+        .filter(|id| id.chars().any(char::is_control))
+        .cloned()
+        .collect();
+    if unsafe_ids.is_empty() {
+        return Vec::new();
+    }
+    unsafe_ids.sort();
+
+    let mut taken: HashSet<String> = input
+        .keys()
+        .filter(|id| !id.chars().any(char::is_control))
+        .cloned()
+        .collect();
+    let mut renamed = Vec::with_capacity(unsafe_ids.len());
+    for original in unsafe_ids {
+        let base: String = original
+            .chars()
+            .map(|c| if c.is_control() { '_' } else { c })
+            .collect();
+        let mut name = base.clone();
+        let mut suffix = 1;
+        while taken.contains(&name) {
+            suffix += 1;
+            name = format!("{}_{}", base, suffix);
+        }
+        taken.insert(name.clone());
+
+        let seq = input
+            .remove(&original)
+            .expect("identifier taken from input");
+        input.insert(name.clone(), seq);
+        renamed.push(Sanitized { name, original });
+    }
+    renamed.sort_by(|a, b| a.name.cmp(&b.name));
+    renamed
+}
+
+/// Returns the path of the sanitized-identifier mapping written next to `output`
+pub fn sanitized_map_path(output: &Path) -> PathBuf {
+    let mut name = output.as_os_str().to_owned();
+    name.push(".ids.tsv");
+    PathBuf::from(name)
+}
+
+/// Writes the sanitized identifiers to a tab-separated mapping file.
+///
+/// Each row holds the sanitized name and the original identifier, in which
+/// backslashes and control characters are escaped (`\\`, `\t`, `\n`, `\r`,
+/// `\u{..}`) so the original can be restored exactly. With `append` the rows are
+/// added to an existing file, otherwise the file is replaced.
+///
+/// # Errors
+///
+/// Returns `AlignerError::Io` if the file cannot be written.
+pub fn write_sanitized(
+    path: &Path,
+    renamed: &[Sanitized],
+    append: bool,
+) -> Result<(), AlignerError> {
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)?;
+    let is_empty = file.metadata()?.len() == 0;
+    let mut writer = BufWriter::new(file);
+    if is_empty {
+        writeln!(writer, "sanitized_id\toriginal_id")?;
+    }
+    for entry in renamed {
+        writeln!(writer, "{}\t{}", entry.name, escape(&entry.original))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Escapes backslashes and control characters of an identifier
+fn escape(id: &str) -> String {
+    let mut escaped = String::with_capacity(id.len());
+    for c in id.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_and_rename() {
+        let map = IdMap {
+            names: HashMap::from([
+                ("P1".to_string(), "geneA".to_string()),
+                ("P2".to_string(), "geneB".to_string()),
+            ]),
+        };
+        let mut input = HashMap::from([
+            ("P1".to_string(), "ACGT".to_string()),
+            ("P2".to_string(), "AGGT".to_string()),
+            ("P3".to_string(), "TTTT".to_string()),
+        ]);
+
+        assert!(map.check(&mut input.clone(), Unmapped::Error).is_err());
+        assert_eq!(map.check(&mut input, Unmapped::Drop).unwrap(), 1);
+        let renamed = map.rename(input).unwrap();
+        assert_eq!(renamed["geneA"], "ACGT");
+        assert_eq!(renamed.len(), 2);
+        assert_eq!(map.name("P3"), "P3");
+    }
+
+    #[test]
+    fn test_sanitize_ids() {
+        let mut input = HashMap::from([
+            ("a\tb".to_string(), "ACGT".to_string()),
+            ("a_b".to_string(), "AGGT".to_string()),
+            ("c\\d\n".to_string(), "TTTT".to_string()),
+        ]);
+        let renamed = sanitize_ids(&mut input);
+        assert_eq!(renamed.len(), 2);
+        assert_eq!(renamed[0].name, "a_b_2");
+        assert_eq!(input["a_b_2"], "ACGT");
+        assert_eq!(input["a_b"], "AGGT");
+        assert_eq!(escape(&renamed[1].original), "c\\\\d\\n");
+    }
+}
@@ -0,0 +1,284 @@
+//! Persistent k-mer index of a target database.
+//!
+//! The `index` subcommand reads a set of target sequences once and writes them
+//! together with the positions of the targets containing each k-mer to a binary
+//! file. `search` loads this file instead of re-reading and re-indexing the
+//! database for every batch of queries.
+//!
+//! The file starts with [`MAGIC`], followed by little-endian `u32` values: the
+//! k-mer length, the number of targets, each target as the lengths and bytes of
+//! its identifier and sequence, the number of k-mers, and each k-mer as its bytes,
+//! the number of targets containing it and their positions.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::error::AlignerError;
+use crate::utils::parse_input;
+use crate::validate::{check_ascii, check_lengths};
+
+/// Bytes every index file starts with, including the version of the layout
+pub const MAGIC: &[u8; 8] = b"ALNIDX01";
+
+/// Command-line arguments for the `index` subcommand
+#[derive(clap::Args, Debug)]
+pub struct IndexArgs {
+    /// Input file with the target sequences, in any supported input format
+    input: PathBuf,
+
+    /// Path of the index file to write
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Length of the indexed k-mers
+    #[arg(short, long, default_value = "5")]
+    k: usize,
+}
+
+/// Target sequences with the positions of the targets containing each k-mer
+#[derive(Debug, PartialEq, Eq)]
+pub struct KmerIndex {
+    k: usize,
+    ids: Vec<String>,
+    sequences: Vec<String>,
+    postings: HashMap<Vec<u8>, Vec<u32>>,
+}
+
+impl KmerIndex {
+    /// Indexes the k-mers of the targets, ordering the targets by identifier
+    pub fn build(targets: HashMap<String, String>, k: usize) -> Self {
+        let mut targets: Vec<(String, String)> = targets.into_iter().collect();
+        targets.sort_unstable();
+        let (ids, sequences): (Vec<String>, Vec<String>) = targets.into_iter().unzip();
+
+        let mut postings: HashMap<Vec<u8>, Vec<u32>> = HashMap::new();
+        for (position, sequence) in sequences.iter().enumerate() {
+            let kmers: HashSet<&[u8]> = sequence.as_bytes().windows(k).collect();
+            for kmer in kmers {
+                postings
+                    .entry(kmer.to_vec())
+                    .or_default()
+                    .push(position as u32);
+            }
+        }
+        Self {
+            k,
+            ids,
+            sequences,
+            postings,
+        }
+    }
+
+    /// Returns the number of targets
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Returns the identifier of the target at `position`
+    pub fn id(&self, position: usize) -> &str {
+        &self.ids[position]
+    }
+
+    /// Returns the position of the target with identifier `id`
+    pub fn position(&self, id: &str) -> Option<usize> {
+        self.ids
+            .binary_search_by(|other| other.as_str().cmp(id))
+            .ok()
+    }
+
+    /// Returns the sequence of the target at `position`
+    pub fn sequence(&self, position: usize) -> &str {
+        &self.sequences[position]
+    }
+
+    /// Returns the targets sharing at least `min_shared` distinct k-mers with
+    /// `query` as positions and numbers of shared k-mers, with the most shared
+    /// k-mers first and at most `limit` targets
+    pub fn candidates(&self, query: &str, min_shared: usize, limit: usize) -> Vec<(usize, usize)> {
+        let kmers: HashSet<&[u8]> = query.as_bytes().windows(self.k).collect();
+        let mut shared: HashMap<u32, usize> = HashMap::new();
+        for kmer in kmers {
+            for &position in self.postings.get(kmer).into_iter().flatten() {
+                *shared.entry(position).or_default() += 1;
+            }
+        }
+        let mut candidates: Vec<(usize, usize)> = shared
+            .into_iter()
+            .filter(|(_, count)| *count >= min_shared.max(1))
+            .map(|(position, count)| (position as usize, count))
+            .collect();
+        candidates.sort_unstable_by_key(|&(position, count)| (std::cmp::Reverse(count), position));
+        candidates.truncate(limit);
+        candidates
+    }
+
+    /// Writes the index to a file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        write_u32(&mut out, self.k)?;
+        write_u32(&mut out, self.ids.len())?;
+        for (id, sequence) in self.ids.iter().zip(&self.sequences) {
+            write_bytes(&mut out, id.as_bytes())?;
+            write_bytes(&mut out, sequence.as_bytes())?;
+        }
+        // Sorted, so the same targets always give the same file
+        let mut kmers: Vec<(&Vec<u8>, &Vec<u32>)> = self.postings.iter().collect();
+        kmers.sort_unstable();
+        write_u32(&mut out, kmers.len())?;
+        for (kmer, positions) in kmers {
+            out.write_all(kmer)?;
+            write_u32(&mut out, positions.len())?;
+            for &position in positions {
+                out.write_all(&position.to_le_bytes())?;
+            }
+        }
+        out.flush()
+    }
+
+    /// Reads an index written by [`KmerIndex::write`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `AlignerError::Io` if the file cannot be read, and
+    /// `AlignerError::InvalidInput` if it is not an index file or is truncated.
+    pub fn read(path: &Path) -> Result<Self, AlignerError> {
+        let invalid =
+            |reason: &str| AlignerError::InvalidInput(format!("{}: {}", path.display(), reason));
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|_| invalid("not an index file"))?;
+        if &magic != MAGIC {
+            return Err(invalid(if magic.starts_with(&MAGIC[..6]) {
+                "index file of an unsupported version"
+            } else {
+                "not an index file"
+            }));
+        }
+
+        let read = |reader: &mut BufReader<File>| -> Result<Self, io::Error> {
+            let k = read_u32(reader)?;
+            let count = read_u32(reader)?;
+            let (mut ids, mut sequences) = (Vec::with_capacity(count), Vec::with_capacity(count));
+            for _ in 0..count {
+                ids.push(read_string(reader)?);
+                sequences.push(read_string(reader)?);
+            }
+            let kmers = read_u32(reader)?;
+            let mut postings = HashMap::with_capacity(kmers);
+            for _ in 0..kmers {
+                let mut kmer = vec![0; k];
+                reader.read_exact(&mut kmer)?;
+                let positions = (0..read_u32(reader)?)
+                    .map(|_| read_u32(reader).map(|position| position as u32))
+                    .collect::<Result<Vec<u32>, _>>()?;
+                postings.insert(kmer, positions);
+            }
+            Ok(Self {
+                k,
+                ids,
+                sequences,
+                postings,
+            })
+        };
+        read(&mut reader).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => invalid("index file is truncated"),
+            io::ErrorKind::InvalidData => invalid(&e.to_string()),
+            _ => AlignerError::Io(e),
+        })
+    }
+}
+
+fn write_u32(out: &mut impl Write, value: usize) -> io::Result<()> {
+    let value = u32::try_from(value)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "index is too large"))?;
+    out.write_all(&value.to_le_bytes())
+}
+
+fn write_bytes(out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    write_u32(out, bytes.len())?;
+    out.write_all(bytes)
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<usize> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes) as usize)
+}
+
+fn read_string(reader: &mut impl Read) -> io::Result<String> {
+    let mut bytes = vec![0; read_u32(reader)?];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Runs the `index` subcommand.
+///
+/// # Errors
+///
+/// Returns `AlignerError::Config` if the k-mer length is zero, and the errors of
+/// reading the targets and writing the index.
+pub fn run(args: IndexArgs) -> Result<(), AlignerError> {
+    if args.k == 0 {
+        return Err(AlignerError::Config(
+            "k-mer length must be at least 1".to_string(),
+        ));
+    }
+    let mut targets = parse_input(&args.input)?;
+    check_ascii(&mut targets, None)?;
+    check_lengths(&targets, None)?;
+
+    let index = KmerIndex::build(targets, args.k);
+    index.write(&args.output)?;
+    info!(
+        targets = index.len(),
+        kmers = index.postings.len(),
+        "wrote index"
+    );
+    eprintln!(
+        "Indexed {} targets ({} distinct {}-mers) in {}",
+        index.len(),
+        index.postings.len(),
+        args.k,
+        args.output.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_roundtrip() {
+        let targets: HashMap<String, String> = [("b", "MAVMTKL"), ("a", "WWWWW"), ("c", "MAVKT")]
+            .into_iter()
+            .map(|(id, seq)| (id.to_string(), seq.to_string()))
+            .collect();
+        let index = KmerIndex::build(targets, 3);
+        let path = std::env::temp_dir().join(format!("aligner-index-{}.idx", std::process::id()));
+        index.write(&path).unwrap();
+        let loaded = KmerIndex::read(&path).unwrap();
+        assert_eq!(loaded, index);
+
+        // "b" shares MAV, AVM and VMT, "c" only MAV
+        let candidates = loaded.candidates("MAVMT", 1, 10);
+        assert_eq!(candidates, [(1, 3), (2, 1)]);
+        assert_eq!(loaded.id(1), "b");
+        assert_eq!(loaded.candidates("MAVMT", 2, 10).len(), 1);
+
+        std::fs::write(&path, &MAGIC[..]).unwrap();
+        let error = KmerIndex::read(&path).unwrap_err();
+        assert!(error.to_string().contains("truncated"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,598 @@
+//! Record-level parsing of sequence files in JSON or YAML.
+//!
+//! An input file maps sequence identifiers to sequences, or lists records with an
+//! identifier and a sequence field; the layout is detected from the document.
+//! Instead of rejecting the whole file with the first deserialization error, every
+//! entry is read as a separate record, so a malformed record can be reported with
+//! its position or skipped. The other scalar fields of records, such as the
+//! organism, are kept as metadata of their sequence.
+//!
+//! Syntax errors that make the rest of the file unreadable are always fatal.
+
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde_json::Value;
+use serde_json::error::Category;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::error::AlignerError;
+use crate::schema;
+use crate::translate::GeneticCode;
+
+/// Handling of malformed records
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ParseMode {
+    /// Fail on records whose sequence is not a string; a repeated identifier
+    /// replaces the earlier record
+    #[default]
+    Standard,
+    /// Additionally fail on empty sequences and repeated identifiers
+    Strict,
+    /// Skip malformed records, empty sequences and repeated identifiers and report them
+    Lenient,
+}
+
+/// A record that was skipped in lenient mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidRecord {
+    /// One-based position of the record in the file
+    pub record: usize,
+    /// Identifier of the record, empty if it has none
+    pub id: String,
+    /// Reason the record was skipped
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.id.is_empty() {
+            write!(f, "record {}: {}", self.record, self.reason)
+        } else {
+            write!(f, "record {} ('{}'): {}", self.record, self.id, self.reason)
+        }
+    }
+}
+
+/// Sequences read from a file together with the records that were skipped
+#[derive(Debug, Default)]
+pub struct Parsed {
+    /// Sequences by identifier
+    pub sequences: HashMap<String, String>,
+    /// Records skipped in lenient mode, in file order
+    pub invalid: Vec<InvalidRecord>,
+    /// Phred qualities of the bases of every sequence, for formats that have them
+    pub qualities: HashMap<String, Vec<u8>>,
+    /// Further scalar fields of the record of every sequence, for formats with
+    /// records of named fields
+    pub metadata: HashMap<String, Metadata>,
+}
+
+/// Names and values of the fields of a record besides its identifier and
+/// sequence, in file order
+pub type Metadata = Vec<(String, String)>;
+
+impl Parsed {
+    /// Adds a record to the parsed sequences.
+    ///
+    /// Returns the message of the error that ends parsing if the record is
+    /// malformed and `mode` is not lenient.
+    pub fn add(
+        &mut self,
+        mode: ParseMode,
+        record: usize,
+        id: String,
+        sequence: Result<String, String>,
+    ) -> Result<(), String> {
+        let reason = match sequence {
+            Ok(seq) if mode == ParseMode::Standard => {
+                self.sequences.insert(id, seq);
+                return Ok(());
+            }
+            Ok(seq) if seq.is_empty() => "sequence is empty".to_string(),
+            Ok(_) if self.sequences.contains_key(&id) => {
+                "identifier occurs more than once".to_string()
+            }
+            Ok(seq) => {
+                self.sequences.insert(id, seq);
+                return Ok(());
+            }
+            Err(reason) => reason,
+        };
+        let invalid = InvalidRecord { record, id, reason };
+        if mode != ParseMode::Lenient {
+            return Err(invalid.to_string());
+        }
+        self.invalid.push(invalid);
+        Ok(())
+    }
+}
+
+/// Settings for reading sequence files
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// Handling of malformed records
+    pub mode: ParseMode,
+    /// Field holding the sequence in records that are objects
+    pub sequence_key: String,
+    /// Field holding the identifier in a list of records
+    pub id_key: String,
+    /// Feature type to read from GenBank and EMBL files instead of the full
+    /// record sequences, e.g. `CDS`
+    pub feature: Option<String>,
+    /// Name of the input format, detected from the file extension if `None`
+    pub format: Option<String>,
+    /// Field delimiter of tabular input, chosen by the format if `None`
+    pub delimiter: Option<u8>,
+    /// Genetic code translating GenBank and EMBL CDS features without a
+    /// `/transl_table` qualifier
+    pub genetic_code: &'static GeneticCode,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            mode: ParseMode::default(),
+            sequence_key: "sequence".to_string(),
+            id_key: "id".to_string(),
+            feature: None,
+            format: None,
+            delimiter: None,
+            genetic_code: GeneticCode::STANDARD,
+        }
+    }
+}
+
+/// Reads a JSON file of sequences.
+///
+/// The file is either an object mapping identifiers to sequences, where each
+/// sequence is a string or an object with the sequence in its `sequence_key`
+/// field, or an array of objects with an `id_key` and a `sequence_key` field.
+/// Records are read one at a time; their other fields with a string, number or
+/// boolean value are kept as metadata, and nested fields are skipped.
+///
+/// # Errors
+///
+/// Returns `AlignerError::Io` if the file cannot be read, and
+/// `AlignerError::Parse` if it is not valid JSON or, unless the mode is
+/// [`ParseMode::Lenient`], contains a malformed record. Parse errors include the
+/// line and column of the offending record. If the document does not match the
+/// input schema, `AlignerError::InvalidInput` lists all schema violations instead.
+pub fn read_json(path: &Path, options: &ParseOptions) -> Result<Parsed, AlignerError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let parsed = Records { options }
+        .deserialize(&mut deserializer)
+        .and_then(|parsed| deserializer.end().map(|()| parsed));
+    match parsed {
+        Ok(parsed) => Ok(parsed),
+        // Well-formed JSON of the wrong shape: explain it with the schema, which
+        // needs the whole document in memory and is therefore only done on failure
+        Err(e) if e.classify() == Category::Data => {
+            let document: Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+            schema::validate(&document, options)?;
+            Err(e.into())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Reads a YAML file of sequences, accepting the same shapes as [`read_json`].
+///
+/// # Errors
+///
+/// Returns `AlignerError::Io` if the file cannot be read, and
+/// `AlignerError::Yaml` if it is not valid YAML or, unless the mode is
+/// [`ParseMode::Lenient`], contains a malformed record. If the document does not
+/// match the input schema, `AlignerError::InvalidInput` lists all schema
+/// violations instead.
+pub fn read_yaml(path: &Path, options: &ParseOptions) -> Result<Parsed, AlignerError> {
+    let content = fs::read_to_string(path)?;
+    let deserializer = serde_yaml::Deserializer::from_str(&content);
+    match (Records { options }).deserialize(deserializer) {
+        Ok(parsed) => Ok(parsed),
+        Err(e) => {
+            let document: Value = serde_yaml::from_str(&content)?;
+            schema::validate(&document, options)?;
+            Err(e.into())
+        }
+    }
+}
+
+/// Deserializes a map or list of records, checking each one according to the
+/// parse mode
+struct Records<'a> {
+    options: &'a ParseOptions,
+}
+
+impl Records<'_> {
+    /// Takes the sequence and metadata from the value of a map entry
+    fn sequence(&self, entry: Entry) -> (Result<String, String>, Metadata) {
+        match entry {
+            Entry::Sequence(seq) => (Ok(seq), Metadata::new()),
+            Entry::Object {
+                sequence, metadata, ..
+            } => (string_field(sequence, &self.options.sequence_key), metadata),
+            Entry::Other(kind) => (
+                Err(format!(
+                    "sequence must be a string or an object, found {}",
+                    kind
+                )),
+                Metadata::new(),
+            ),
+        }
+    }
+
+    /// Takes the identifier, sequence and metadata from an element of a record
+    /// list
+    fn record(&self, entry: Entry) -> (String, Result<String, String>, Metadata) {
+        let kind = match entry {
+            Entry::Object {
+                id,
+                sequence,
+                metadata,
+            } => {
+                return match string_field(id, &self.options.id_key) {
+                    Ok(id) => (
+                        id,
+                        string_field(sequence, &self.options.sequence_key),
+                        metadata,
+                    ),
+                    Err(reason) => (String::new(), Err(reason), Metadata::new()),
+                };
+            }
+            Entry::Sequence(_) => "a string",
+            Entry::Other(kind) => kind,
+        };
+        (
+            String::new(),
+            Err(format!("record must be an object, found {}", kind)),
+            Metadata::new(),
+        )
+    }
+
+    /// Adds a record with its metadata to the parsed sequences, see [`Parsed::add`]
+    fn add(
+        &self,
+        parsed: &mut Parsed,
+        record: usize,
+        id: String,
+        sequence: Result<String, String>,
+        metadata: Metadata,
+    ) -> Result<(), String> {
+        let skipped = parsed.invalid.len();
+        parsed.add(self.options.mode, record, id.clone(), sequence)?;
+        if parsed.invalid.len() == skipped {
+            // A repeated identifier replaces the metadata of the earlier record
+            if metadata.is_empty() {
+                parsed.metadata.remove(&id);
+            } else {
+                parsed.metadata.insert(id, metadata);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for Records<'_> {
+    type Value = Parsed;
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<Parsed, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Records<'_> {
+    type Value = Parsed;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a map of sequence identifiers to sequences or a list of records")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Parsed, A::Error> {
+        let mut parsed = Parsed::default();
+        let mut record = 0;
+        while let Some(id) = map.next_key::<String>()? {
+            record += 1;
+            let entry = map.next_value_seed(EntrySeed {
+                options: self.options,
+            })?;
+            let (sequence, metadata) = self.sequence(entry);
+            // serde_json appends the line and column to custom errors
+            self.add(&mut parsed, record, id, sequence, metadata)
+                .map_err(de::Error::custom)?;
+        }
+        Ok(parsed)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Parsed, A::Error> {
+        let mut parsed = Parsed::default();
+        let mut record = 0;
+        while let Some(entry) = seq.next_element_seed(EntrySeed {
+            options: self.options,
+        })? {
+            record += 1;
+            let (id, sequence, metadata) = self.record(entry);
+            self.add(&mut parsed, record, id, sequence, metadata)
+                .map_err(de::Error::custom)?;
+        }
+        Ok(parsed)
+    }
+}
+
+/// A map value or list element, reduced to the fields needed for aligning
+#[derive(Debug)]
+enum Entry {
+    /// A plain sequence string
+    Sequence(String),
+    /// An object with its identifier and sequence fields, if present, and its
+    /// other scalar fields
+    Object {
+        id: Option<Value>,
+        sequence: Option<Value>,
+        metadata: Metadata,
+    },
+    /// Any other value, described by its JSON type
+    Other(&'static str),
+}
+
+/// Deserializes a single [`Entry`], skipping nested fields of objects without
+/// building them in memory, so records with large annotations can be streamed
+struct EntrySeed<'a> {
+    options: &'a ParseOptions,
+}
+
+impl<'de> DeserializeSeed<'de> for EntrySeed<'_> {
+    type Value = Entry;
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<Entry, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for EntrySeed<'_> {
+    type Value = Entry;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a sequence or a record")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Entry, E> {
+        Ok(Entry::Sequence(value.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Entry, E> {
+        Ok(Entry::Sequence(value))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Entry, A::Error> {
+        let (mut id, mut sequence, mut metadata) = (None, None, Metadata::new());
+        while let Some(key) = map.next_key::<String>()? {
+            if key == self.options.sequence_key {
+                sequence = Some(map.next_value()?);
+            } else if key == self.options.id_key {
+                id = Some(map.next_value()?);
+            } else if let Some(value) = map.next_value_seed(ScalarSeed)? {
+                metadata.push((key, value));
+            }
+        }
+        Ok(Entry::Object {
+            id,
+            sequence,
+            metadata,
+        })
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Entry, A::Error> {
+        while seq.next_element::<IgnoredAny>()?.is_some() {}
+        Ok(Entry::Other("an array"))
+    }
+
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<Entry, E> {
+        Ok(Entry::Other("a boolean"))
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<Entry, E> {
+        Ok(Entry::Other("a number"))
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<Entry, E> {
+        Ok(Entry::Other("a number"))
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<Entry, E> {
+        Ok(Entry::Other("a number"))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Entry, E> {
+        Ok(Entry::Other("null"))
+    }
+}
+
+/// Deserializes a metadata field as a string if it is a string, number or
+/// boolean, skipping other values
+struct ScalarSeed;
+
+impl<'de> DeserializeSeed<'de> for ScalarSeed {
+    type Value = Option<String>;
+
+    fn deserialize<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Option<String>, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ScalarSeed {
+    type Value = Option<String>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a field value")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Option<String>, E> {
+        Ok(Some(value.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Option<String>, E> {
+        Ok(Some(value))
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Option<String>, E> {
+        Ok(Some(value.to_string()))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Option<String>, E> {
+        Ok(Some(value.to_string()))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Option<String>, E> {
+        Ok(Some(value.to_string()))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Option<String>, E> {
+        Ok(Some(value.to_string()))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Option<String>, E> {
+        Ok(None)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Option<String>, A::Error> {
+        while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+        Ok(None)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Option<String>, A::Error> {
+        while seq.next_element::<IgnoredAny>()?.is_some() {}
+        Ok(None)
+    }
+}
+
+/// Takes a string field of a record
+fn string_field(value: Option<Value>, key: &str) -> Result<String, String> {
+    match value {
+        Some(Value::String(value)) => Ok(value),
+        Some(other) => Err(format!(
+            "field '{}' must be a string, found {}",
+            key,
+            value_kind(&other)
+        )),
+        None => Err(format!("record has no field '{}'", key)),
+    }
+}
+
+/// Describes the JSON type of a value for error messages
+pub fn value_kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str, mode: ParseMode) -> Result<Parsed, serde_json::Error> {
+        let options = ParseOptions {
+            mode,
+            ..ParseOptions::default()
+        };
+        Records { options: &options }.deserialize(&mut serde_json::Deserializer::from_str(json))
+    }
+
+    #[test]
+    fn test_parse_modes() {
+        let json = "{\n\"a\": \"ACGT\",\n\"b\": 42,\n\"c\": \"\",\n\"a\": \"GG\"\n}";
+
+        let error = parse(json, ParseMode::Standard).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "record 2 ('b'): sequence must be a string or an object, found a number at line 3 column 7"
+        );
+        let error = parse(r#"{"a": "ACGT", "a": "GG"}"#, ParseMode::Strict).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("identifier occurs more than once")
+        );
+
+        let parsed = parse(json, ParseMode::Lenient).unwrap();
+        assert_eq!(
+            parsed.sequences,
+            HashMap::from([("a".into(), "ACGT".into())])
+        );
+        let skipped: Vec<usize> = parsed.invalid.iter().map(|r| r.record).collect();
+        assert_eq!(skipped, [2, 3, 4]);
+    }
+
+    #[test]
+    fn test_parse_records() {
+        let expected = HashMap::from([("a".into(), "ACGT".into()), ("b".into(), "GG".into())]);
+        let nested = r#"{"a": {"sequence": "ACGT", "organism": "E. coli"}, "b": "GG"}"#;
+        assert_eq!(
+            parse(nested, ParseMode::Strict).unwrap().sequences,
+            expected
+        );
+
+        let list = r#"[{"id": "a", "sequence": "ACGT", "organism": "E. coli", "length": 4, "xrefs": ["P1"]}, {"id": "b", "sequence": "GG"}, {"sequence": "T"}]"#;
+        let parsed = parse(list, ParseMode::Lenient).unwrap();
+        assert_eq!(parsed.sequences, expected);
+        let metadata = |pairs: &[(&str, &str)]| -> Metadata {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            parsed.metadata,
+            HashMap::from([(
+                "a".to_string(),
+                metadata(&[("organism", "E. coli"), ("length", "4")])
+            )])
+        );
+        assert_eq!(
+            parsed.invalid[0].to_string(),
+            "record 3: record has no field 'id'"
+        );
+
+        let options = ParseOptions {
+            id_key: "accession".to_string(),
+            ..ParseOptions::default()
+        };
+        let list = r#"[{"accession": "a", "sequence": "ACGT", "features": [{"start": 1}]}]"#;
+        let parsed = Records { options: &options }
+            .deserialize(&mut serde_json::Deserializer::from_str(list))
+            .unwrap();
+        assert_eq!(parsed.sequences["a"], "ACGT");
+    }
+
+    #[test]
+    fn test_parse_yaml() {
+        let yaml = "Q6A0I3: MAVMT\n1234:\n  sequence: ACGT\n  organism: E. coli\n";
+        let options = ParseOptions::default();
+        let parsed = Records { options: &options }
+            .deserialize(serde_yaml::Deserializer::from_str(yaml))
+            .unwrap();
+        assert_eq!(parsed.sequences["Q6A0I3"], "MAVMT");
+        assert_eq!(parsed.sequences["1234"], "ACGT");
+
+        let list = "- id: a\n  sequence: ACGT\n- id: b\n  sequence: 42\n";
+        let error = Records { options: &options }
+            .deserialize(serde_yaml::Deserializer::from_str(list))
+            .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("record 2 ('b'): field 'sequence' must be a string, found a number")
+        );
+    }
+}
@@ -0,0 +1,316 @@
+//! Queue of alignment jobs submitted to the daemon.
+//!
+//! Jobs wait in a bounded queue until one of a fixed number of workers picks them
+//! up, so concurrent submissions share the daemon's threads instead of competing
+//! for them. Each job counts the pairs it has aligned, which clients poll as
+//! progress, and can be cancelled while queued or running. The results of
+//! completed jobs are kept in memory, best scores first, until the job is deleted
+//! or more than a configured number of finished jobs have accumulated.
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use crate::align::AlignmentResult;
+
+/// Lifecycle state of a job
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    /// Waiting for a free worker
+    Queued,
+    /// Being aligned
+    Running,
+    /// All pairs were aligned and the results are available
+    Completed,
+    /// Cancelled before all pairs were aligned
+    Cancelled,
+}
+
+/// Sequences aligned by a job
+#[derive(Debug)]
+pub struct JobInput {
+    /// Query sequences by identifier
+    pub queries: HashMap<String, String>,
+    /// Reference set the queries are aligned against, kept alive while the job
+    /// waits even if its dataset is dropped or replaced
+    pub reference: Arc<HashMap<String, String>>,
+}
+
+/// An alignment job and its progress
+#[derive(Debug)]
+pub struct Job {
+    /// Identifier assigned on submission
+    pub id: u64,
+    /// Name of the reference dataset
+    pub dataset: String,
+    /// Number of pairs to align
+    pub total: usize,
+    /// Sequences to align, taken by the worker running the job
+    input: Mutex<Option<JobInput>>,
+    state: Mutex<JobState>,
+    done: AtomicUsize,
+    cancelled: AtomicBool,
+    results: Mutex<Vec<AlignmentResult>>,
+}
+
+/// Progress of a job as reported to clients
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobStatus {
+    /// Identifier of the job
+    pub id: u64,
+    /// Name of the reference dataset
+    pub dataset: String,
+    /// Lifecycle state
+    pub state: JobState,
+    /// Number of pairs aligned so far
+    pub done: usize,
+    /// Number of pairs to align
+    pub total: usize,
+}
+
+impl Job {
+    /// Returns the current progress of the job
+    pub fn status(&self) -> JobStatus {
+        JobStatus {
+            id: self.id,
+            dataset: self.dataset.clone(),
+            state: *lock(&self.state),
+            done: self.done.load(Ordering::Relaxed),
+            total: self.total,
+        }
+    }
+
+    /// Records that a pair was aligned and returns `false` once the job has been
+    /// cancelled and the remaining pairs should be skipped
+    pub fn advance(&self) -> bool {
+        self.done.fetch_add(1, Ordering::Relaxed);
+        !self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Returns the results of a completed job
+    pub fn results(&self) -> Option<MutexGuard<'_, Vec<AlignmentResult>>> {
+        (*lock(&self.state) == JobState::Completed).then(|| lock(&self.results))
+    }
+
+    /// Stores the results of a finished run sorted by descending score, unless the
+    /// job was cancelled meanwhile
+    pub fn finish(&self, mut results: Vec<AlignmentResult>) {
+        let mut state = lock(&self.state);
+        if self.cancelled.load(Ordering::Relaxed) {
+            *state = JobState::Cancelled;
+        } else {
+            // Skipped pairs have no score and sort last
+            results.sort_by(|a, b| {
+                (Reverse(a.score), &a.query_id, &a.subject_id).cmp(&(
+                    Reverse(b.score),
+                    &b.query_id,
+                    &b.subject_id,
+                ))
+            });
+            *lock(&self.results) = results;
+            *state = JobState::Completed;
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(
+            *lock(&self.state),
+            JobState::Completed | JobState::Cancelled
+        )
+    }
+}
+
+/// Submitted jobs and the queue of jobs waiting for a worker
+#[derive(Debug)]
+pub struct JobQueue {
+    jobs: Mutex<BTreeMap<u64, Arc<Job>>>,
+    pending: Mutex<Pending>,
+    available: Condvar,
+    next_id: AtomicU64,
+    max_queued: usize,
+    max_finished: usize,
+}
+
+#[derive(Debug, Default)]
+struct Pending {
+    jobs: VecDeque<Arc<Job>>,
+    closed: bool,
+}
+
+/// Error returned when a job is submitted while the queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueFull;
+
+impl JobQueue {
+    /// Creates an empty queue holding at most `max_queued` waiting jobs and keeping
+    /// the results of at most `max_finished` finished jobs
+    pub fn new(max_queued: usize, max_finished: usize) -> Self {
+        Self {
+            jobs: Mutex::new(BTreeMap::new()),
+            pending: Mutex::new(Pending::default()),
+            available: Condvar::new(),
+            next_id: AtomicU64::new(1),
+            max_queued,
+            max_finished,
+        }
+    }
+
+    /// Forgets the oldest finished jobs beyond the number of jobs whose results
+    /// are kept
+    pub fn prune(&self) {
+        let mut jobs = lock(&self.jobs);
+        let finished: Vec<u64> = jobs
+            .values()
+            .filter(|job| job.is_finished())
+            .map(|job| job.id)
+            .collect();
+        let excess = finished.len().saturating_sub(self.max_finished);
+        for id in &finished[..excess] {
+            jobs.remove(id);
+        }
+    }
+
+    /// Queues a job aligning its queries against the reference set of the named
+    /// dataset.
+    ///
+    /// # Errors
+    ///
+    /// Returns `QueueFull` if `max_queued` jobs are already waiting.
+    pub fn submit(&self, dataset: &str, input: JobInput) -> Result<Arc<Job>, QueueFull> {
+        let mut pending = lock(&self.pending);
+        pending
+            .jobs
+            .retain(|job| *lock(&job.state) == JobState::Queued);
+        if pending.jobs.len() >= self.max_queued {
+            return Err(QueueFull);
+        }
+        let job = Arc::new(Job {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            dataset: dataset.to_string(),
+            total: input.queries.len() * input.reference.len(),
+            input: Mutex::new(Some(input)),
+            state: Mutex::new(JobState::Queued),
+            done: AtomicUsize::new(0),
+            cancelled: AtomicBool::new(false),
+            results: Mutex::new(Vec::new()),
+        });
+        lock(&self.jobs).insert(job.id, Arc::clone(&job));
+        pending.jobs.push_back(Arc::clone(&job));
+        self.available.notify_one();
+        Ok(job)
+    }
+
+    /// Returns the job with the given identifier
+    pub fn get(&self, id: u64) -> Option<Arc<Job>> {
+        lock(&self.jobs).get(&id).cloned()
+    }
+
+    /// Returns the progress of all jobs, oldest first
+    pub fn list(&self) -> Vec<JobStatus> {
+        lock(&self.jobs).values().map(|job| job.status()).collect()
+    }
+
+    /// Cancels a queued or running job, or forgets a finished one together with
+    /// its results.
+    ///
+    /// Returns the state of the job after the call, or `None` if there is no job
+    /// with this identifier.
+    pub fn cancel(&self, id: u64) -> Option<JobStatus> {
+        let job = self.get(id)?;
+        let mut state = lock(&job.state);
+        match *state {
+            JobState::Queued => {
+                job.cancelled.store(true, Ordering::Relaxed);
+                *state = JobState::Cancelled;
+                lock(&job.input).take();
+            }
+            // The worker notices the flag after its current pairs
+            JobState::Running => job.cancelled.store(true, Ordering::Relaxed),
+            JobState::Completed | JobState::Cancelled => {
+                lock(&self.jobs).remove(&id);
+            }
+        }
+        drop(state);
+        Some(job.status())
+    }
+
+    /// Waits for the next queued job that was not cancelled, marks it as running
+    /// and returns it with its input.
+    ///
+    /// Returns `None` once the queue has been closed.
+    pub fn next(&self) -> Option<(Arc<Job>, JobInput)> {
+        let mut pending = lock(&self.pending);
+        loop {
+            if pending.closed {
+                return None;
+            }
+            while let Some(job) = pending.jobs.pop_front() {
+                let mut state = lock(&job.state);
+                if *state != JobState::Queued {
+                    continue;
+                }
+                *state = JobState::Running;
+                drop(state);
+                let input = lock(&job.input).take().expect("queued jobs have input");
+                return Some((job, input));
+            }
+            pending = self
+                .available
+                .wait(pending)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Wakes all workers waiting in [`JobQueue::next`] and lets them stop
+    pub fn close(&self) {
+        lock(&self.pending).closed = true;
+        self.available.notify_all();
+    }
+}
+
+/// Locks a mutex, ignoring poisoning by a panicked worker since job state stays
+/// consistent between updates
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_lifecycle() {
+        let queue = JobQueue::new(2, 1);
+        let reference = Arc::new(HashMap::from([("r".to_string(), "MAVMT".to_string())]));
+        let input = || JobInput {
+            queries: HashMap::from([("q".to_string(), "MAVMT".to_string())]),
+            reference: Arc::clone(&reference),
+        };
+        let first = queue.submit("default", input()).unwrap();
+        let second = queue.submit("default", input()).unwrap();
+        assert_eq!(queue.submit("default", input()).unwrap_err(), QueueFull);
+
+        // A cancelled queued job is skipped by the workers
+        assert_eq!(queue.cancel(first.id).unwrap().state, JobState::Cancelled);
+        let (job, input) = queue.next().unwrap();
+        assert_eq!((job.id, input.queries.len()), (second.id, 1));
+        assert_eq!(job.status().state, JobState::Running);
+        assert!(job.results().is_none());
+
+        assert!(job.advance());
+        job.finish(Vec::new());
+        assert_eq!(job.results().unwrap().len(), 0);
+        assert_eq!(job.status().done, 1);
+
+        // Only the newest finished job is kept, and it is forgotten when deleted
+        queue.prune();
+        assert_eq!(queue.list().len(), 1);
+        queue.cancel(second.id).unwrap();
+        assert!(queue.list().is_empty());
+        queue.close();
+        assert!(queue.next().is_none());
+    }
+}
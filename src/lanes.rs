@@ -0,0 +1,164 @@
+//! Alignments of short sequences side by side.
+//!
+//! Aligning peptides one pair at a time spends much of the time on setting up
+//! every alignment and leaves the vector units of the CPU idle. The kernel of this
+//! module aligns a query with up to [`LANES`] subjects at once: every cell of the
+//! dynamic programming rows holds the scores of all subjects in adjacent lanes, so
+//! the recurrences run over short fixed-size arrays the compiler turns into vector
+//! instructions. Subjects shorter than the longest one are padded with a residue
+//! scoring so low against everything that no best alignment crosses it.
+//!
+//! The pair loop hands runs of pairs sharing a query, with sequences of at most
+//! [`MAX_LEN`] residues, to the kernel automatically. Only global and local
+//! alignments are computed in lanes; the ends of semi-global and overlap
+//! alignments depend on the lengths of every pair.
+
+use crate::align::{AlignMode, GapPenalties, MatcherFn, Score};
+
+/// Number of subjects aligned side by side
+pub const LANES: usize = 8;
+
+/// Longest sequence aligned in lanes
+pub const MAX_LEN: usize = 128;
+
+/// Returns `true` if alignments in `mode` of a query of `len` residues can be
+/// computed in lanes
+pub fn supports(mode: AlignMode, len: usize) -> bool {
+    matches!(mode, AlignMode::Global | AlignMode::Local) && len <= MAX_LEN
+}
+
+/// Aligns `query` with every one of at most [`LANES`] `subjects` in `mode`, global
+/// or local, returning their scores in the order of the subjects
+pub fn align<S: Score>(
+    query: &str,
+    subjects: &[&str],
+    matcher: &MatcherFn<S>,
+    mode: AlignMode,
+    gaps: GapPenalties,
+) -> Vec<S> {
+    assert!(
+        subjects.len() <= LANES,
+        "at most {} subjects per call",
+        LANES
+    );
+    let local = mode == AlignMode::Local;
+    let max = |a: S, b: S| if b > a { b } else { a };
+    let open = S::from_penalty(gaps.open);
+    let extend = S::from_penalty(gaps.extend);
+    let zero = S::from_penalty(0);
+    let neg_inf = S::from_penalty(i32::MIN / 4);
+    let gap = |len: usize| {
+        if local {
+            zero
+        } else {
+            S::from_penalty(gaps.open + gaps.extend * len as i32)
+        }
+    };
+
+    // Residues are numbered in order of appearance, with the padding last, so the
+    // scores of a query residue are looked up in a row of a small table
+    let mut index = [usize::MAX; 256];
+    let mut alphabet = Vec::new();
+    for residue in query.bytes().chain(subjects.iter().flat_map(|s| s.bytes())) {
+        if index[residue as usize] == usize::MAX {
+            index[residue as usize] = alphabet.len();
+            alphabet.push(residue);
+        }
+    }
+    let padding = alphabet.len();
+    let table: Vec<Vec<S>> = alphabet
+        .iter()
+        .map(|&a| {
+            let mut row: Vec<S> = alphabet.iter().map(|&b| matcher(a, b)).collect();
+            row.push(neg_inf);
+            row
+        })
+        .collect();
+    let width = subjects.iter().map(|s| s.len()).max().unwrap_or(0);
+    let columns: Vec<[usize; LANES]> = (0..width)
+        .map(|j| {
+            let mut column = [padding; LANES];
+            for (lane, subject) in subjects.iter().enumerate() {
+                if let Some(&residue) = subject.as_bytes().get(j) {
+                    column[lane] = index[residue as usize];
+                }
+            }
+            column
+        })
+        .collect();
+
+    // Rows of the best scores (h) and of those ending in a gap in the subject (f)
+    // as in `align_linear_with`, with a lane per subject
+    let mut h: Vec<[S; LANES]> = (0..=width).map(|j| [gap(j); LANES]).collect();
+    h[0] = [zero; LANES];
+    let mut f = vec![[neg_inf; LANES]; width + 1];
+    let mut best = [zero; LANES];
+    for (i, residue) in query.bytes().enumerate() {
+        let scores = &table[index[residue as usize]];
+        let mut diagonal = h[0];
+        h[0] = [gap(i + 1); LANES];
+        let mut e = [neg_inf; LANES];
+        for (j, column) in columns.iter().enumerate() {
+            let (left, cell) = (h[j], &mut h[j + 1]);
+            let f = &mut f[j + 1];
+            let mut next = [zero; LANES];
+            for lane in 0..LANES {
+                f[lane] = max(f[lane] + extend, cell[lane] + open + extend);
+                e[lane] = max(e[lane] + extend, left[lane] + open + extend);
+                let mut score = max(max(diagonal[lane] + scores[column[lane]], e[lane]), f[lane]);
+                if local {
+                    score = max(score, zero);
+                    best[lane] = max(best[lane], score);
+                }
+                next[lane] = score;
+            }
+            diagonal = *cell;
+            *cell = next;
+        }
+    }
+    subjects
+        .iter()
+        .enumerate()
+        .map(|(lane, subject)| {
+            if local {
+                best[lane]
+            } else {
+                h[subject.len()][lane]
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::align::align_linear;
+    use bio::scores::blosum62;
+
+    #[test]
+    fn test_lanes_match_single_alignments() {
+        let matcher: MatcherFn = blosum62;
+        let query = "MKTAYIAKQRQISFVKSHF";
+        let subjects = [
+            "MKTAYIAKQRQISFVKSHFSRQ",
+            "AYIAKQ",
+            "",
+            "WWGPNDCHYEWWGPNDCHYE",
+            "MKTAYIAKQRQISFVKSHF",
+        ];
+        for mode in [AlignMode::Global, AlignMode::Local] {
+            for gaps in [
+                GapPenalties::default(),
+                GapPenalties::from_costs(5, 2).unwrap(),
+            ] {
+                let expected: Vec<i32> = subjects
+                    .iter()
+                    .map(|subject| align_linear(query, subject, &matcher, mode, gaps))
+                    .collect();
+                assert_eq!(align(query, &subjects, &matcher, mode, gaps), expected);
+            }
+        }
+        assert!(!supports(AlignMode::Semiglobal, 10));
+        assert!(!supports(AlignMode::Global, MAX_LEN + 1));
+    }
+}
@@ -13,7 +13,7 @@
 //!   <input>    Path to input JSON file containing sequences
 //!
 //! Options:
-//!   -o, --output <FILE>     Path to output file (tab-separated format)
+//!   -o, --output <FILE>     Path to output file (tab-separated format), `-` for stdout
 //!   -f, --fraction <FLOAT>  Fraction for pre-filtering using k-mer matches (0.0-1.0)
 //!   -s, --scoring <TYPE>    Scoring type: blosum62 or identity [default: identity]
 //!   -h, --help             Print help
@@ -39,26 +39,135 @@
 //!
 //! # Output Format
 //!
-//! The output file will be tab-separated with the following columns:
+//! The output file will be tab-separated (or comma-separated for `.csv` files) with
+//! the following columns:
 //!
 //! ```text
 //! query_id\tsubject_id\tscore\tseq1_len\tseq2_len
 //! Q6A0I3\tADV92528.1\t...\t...\t...
 //! ```
 
+mod affinity;
 mod align;
+mod blast;
+mod cache;
+mod chimera;
+mod cluster;
+mod codon;
+mod cohesion;
+mod columns;
+mod compress;
+mod config;
+mod consensus;
+mod containment;
+mod daemon;
+mod derep;
+mod engine;
 mod error;
+mod fasta;
+mod fastq;
+mod filter;
+mod genbank;
+mod hirschberg;
+mod hits;
+mod idmap;
+mod index;
+mod input;
+mod jobs;
+mod lanes;
+mod matrix;
+mod memory;
+mod metrics;
+mod msa;
+mod mutations;
+mod myers;
+mod needle;
+mod neo4j;
+mod numbering;
+mod orf;
+mod packed;
+mod paf;
+mod pair;
+mod pairs;
+mod profile;
+mod pssm;
+mod realign;
+mod repl;
+mod report;
+mod rerun;
+mod sam;
+mod sanity;
+mod schema;
+mod search;
+mod seqstats;
+mod sink;
+mod sites;
+mod source;
+mod stress;
+mod table;
+mod taxonomy;
+mod template;
+mod threshold;
+mod translate;
+mod twopass;
 mod utils;
+mod validate;
+mod watch;
+mod wfa;
+mod workspace;
 
-use align::{MatcherFn, align_all_streaming};
+use affinity::PinStrategy;
+use align::{
+    AlignMode, ExecutionOptions, GAP_EXTEND, GAP_OPEN, GapPenalties, MAX_RESIDUE_SCORE, MatcherFn,
+    Observers, Schedule, Score, Scorer, align_all_streaming, self_score,
+};
 use bio::scores::blosum62;
-use clap::{Parser, ValueEnum};
-use std::fs::File;
+use blast::{BlastParams, Statistics};
+use cache::{Eviction, ResultCache};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use columns::Column;
+use compress::{Compression, Compressor};
+use engine::{AlignmentEngine, Banded, EngineKind};
+use filter::{FilterChain, PairFilter};
+use hits::TopHits;
+use idmap::{IdMap, MapStage, Unmapped};
+use input::{ParseMode, ParseOptions, Parsed};
+use memory::{MemoryEstimate, MemoryTracker, format_bytes};
+use metrics::Metrics;
+use neo4j::Neo4jSink;
+use packed::PackedInput;
+use pairs::{
+    CrossProduct, Excluding, FullMatrix, KmerCandidates, PairGenerator, PairList, Previous, Shard,
+    Sharded, Triangle,
+};
+use profile::{Profiler, Stage};
+use report::ErrorReport;
+use rerun::RunMetadata;
+use sanity::SanityReport;
+use sink::{
+    BATCH_SIZE, Blast6Sink, DelimitedSink, JsonLinesSink, OutputFormat, PafSink, ParquetSink,
+    ResultSink, SamSink, SqliteSink,
+};
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
 use std::io::{BufWriter, Write};
-use std::path::PathBuf;
-use std::sync::mpsc;
-use std::time::Instant;
-use utils::parse_input;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, mpsc};
+use std::time::{Duration, Instant};
+use taxonomy::{TaxonCounts, Taxonomy};
+use template::{Template, TemplateSink};
+use threshold::ScoreSample;
+use tracing::{Span, info, info_span, warn};
+use tracing_subscriber::EnvFilter;
+use translate::GeneticCode;
+use utils::{parse_input_sets_with, parse_inputs_with, read_result_ids, read_result_pairs};
+use validate::Lenient;
+
+#[cfg(feature = "track-allocations")]
+#[global_allocator]
+static ALLOCATOR: memory::TrackingAllocator = memory::TrackingAllocator;
 
 /// Supported scoring matrices for sequence alignment
 #[derive(Debug, Copy, Clone, ValueEnum)]
@@ -69,34 +178,748 @@ enum ScoringType {
     Identity,
 }
 
-/// Command-line arguments for the sequence alignment tool
+impl ScoringType {
+    /// Returns the scoring function for this scoring type
+    fn matcher(self) -> MatcherFn {
+        match self {
+            ScoringType::Blosum62 => Matcher::Blosum62.score(),
+            ScoringType::Identity => Matcher::Identity.score(),
+        }
+    }
+}
+
+/// Command-line interface for the sequence alignment tool.
+///
+/// Without a subcommand the tool aligns every pair of input sequences (all-vs-all).
 #[derive(Parser, Debug)]
-#[command(author, version, about = "Sequence alignment tool")]
+#[command(
+    author,
+    version,
+    about = "Sequence alignment tool",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    args_override_self = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    args: Args,
+}
+
+/// Additional modes of operation besides the default all-vs-all alignment
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Simulate divergent copies of the input sequences and report how many
+    /// of these true pairs survive the k-mer pre-filter
+    Stress(stress::StressArgs),
+    /// Watch a directory and align each newly appearing sequence file against
+    /// a reference set, appending the results to an output file
+    Watch(watch::WatchArgs),
+    /// Print the JSON Schema of the accepted input documents
+    Schema,
+    /// Align two sequences given on the command line or in two files and print
+    /// the score, identity and alignment
+    Pair(pair::PairArgs),
+    /// Load a sequence set once and explore it with interactive commands such as
+    /// `align A B`, `top A 10` and `filter identity>0.5`
+    Repl(repl::ReplArgs),
+    /// Keep a reference set loaded and run alignment jobs submitted over a Unix
+    /// domain socket
+    Daemon(daemon::DaemonArgs),
+    /// Submit query sequences to a running daemon and write the results
+    Client(daemon::ClientArgs),
+    /// Align selected pairs of a previous run again with traceback and write
+    /// their identity or alignments
+    Realign(realign::RealignArgs),
+    /// Write a k-mer index of target sequences for the `search` subcommand
+    Index(index::IndexArgs),
+    /// Find the best-scoring targets of every query in an index written by the
+    /// `index` subcommand
+    Search(search::SearchArgs),
+    /// Cluster the input sequences by pairwise identity and write a representative
+    /// of every cluster and the members of all clusters
+    Cluster(cluster::ClusterArgs),
+    /// Remove exact and, above an identity threshold, near-duplicate sequences and
+    /// write the kept sequences and which kept sequence every removed one maps to
+    Derep(derep::DerepArgs),
+    /// Check whether sequences are better explained as the join of two parents
+    /// than by a single one, and report the breakpoints of likely chimeras
+    Chimera(chimera::ChimeraArgs),
+    /// Number the residues of every sequence by the residues of a reference they
+    /// align with, in the numbering scheme of the reference
+    Numbering(numbering::NumberingArgs),
+    /// Report the residues of every sequence aligned with selected positions of a
+    /// reference, e.g. the residues of an active site
+    Sites(sites::SitesArgs),
+    /// Align pairs of proteins and project the alignments onto their coding
+    /// sequences, writing codon-aligned nucleotide sequences
+    Codon(codon::CodonArgs),
+    /// Report the length, composition, GC content or molecular weight and
+    /// isoelectric point of every sequence and flag outliers
+    Seqstats(seqstats::SeqStatsArgs),
+    /// Write the consensus sequence of every alignment of a Stockholm or aligned
+    /// FASTA file
+    Consensus(consensus::ConsensusArgs),
+    /// Report the residues, gaps, entropy and conservation of every column of
+    /// the alignments of a Stockholm or aligned FASTA file
+    Conservation(consensus::ConservationArgs),
+    /// Align every sequence with the profiles of the alignments of a Stockholm or
+    /// aligned FASTA file and write the scores
+    Profile(pssm::ProfileArgs),
+    /// Run a previous alignment run again from the metadata sidecar of its
+    /// results, after checking that its inputs did not change
+    Rerun(rerun::RerunArgs),
+}
+
+/// Command-line arguments for the sequence alignment tool
+#[derive(clap::Args, Debug)]
 struct Args {
-    /// Path to input JSON file containing sequences.
-    /// The file should contain a JSON object where keys are sequence identifiers
-    /// and values are the sequences as strings.
-    #[arg(help = "Path to input JSON file containing sequences")]
-    input: PathBuf,
+    /// Paths or URLs of the input JSON, YAML, FASTA, FASTQ, GenBank, EMBL, alignment or
+    /// SQLite files containing sequences. The file should contain an object where keys
+    /// are sequence identifiers and values are the sequences as strings or objects
+    /// with a sequence field, or a list of objects with an `id` and a sequence
+    /// field.
+    /// Files ending in `.yaml` or `.yml` are read as YAML; see the README for the
+    /// extensions of the other formats. The sequences of several inputs are merged
+    /// into one set; an identifier may only occur in several inputs with the same
+    /// sequence.
+    #[arg(
+        required_unless_present = "query",
+        value_name = "INPUT",
+        help = "Paths or URLs of the input JSON, YAML, FASTA, FASTQ, GenBank, EMBL, alignment or SQLite files"
+    )]
+    inputs: Vec<PathBuf>,
 
-    /// Path to output file (optional).
+    /// Path to output file (optional), or `-` for standard output.
     /// If provided, results will be written in tab-separated format with columns:
     /// query_id, subject_id, score, seq1_len, seq2_len
-    #[arg(short, long, help = "Path to output file")]
+    #[arg(short, long, help = "Path to output file, or - for standard output")]
     output: Option<PathBuf>,
 
+    /// Format of the output file. Defaults to the format matching the extension
+    /// of the output file, `.csv`, `.jsonl`, `.ndjson`, `.parquet`, `.sam`, `.paf`,
+    /// `.sqlite`, `.sqlite3` or `.db`, and to tab-separated values otherwise.
+    #[arg(
+        long,
+        value_enum,
+        requires = "output",
+        conflicts_with_all = ["incremental", "resume"],
+        help = "Format of the output file"
+    )]
+    output_format: Option<OutputFormat>,
+
+    /// Compress the output file while it is written. Defaults to the compression
+    /// matching the extension of the output file, `.gz`, `.zst`, `.bz2` or `.xz`,
+    /// which is left out when detecting the output format.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        requires = "output",
+        help = "Compress the output file with gzip, zstd, bzip2 or xz"
+    )]
+    compress: Option<Compression>,
+
+    /// Comma-separated columns of the output, written in this order instead of the
+    /// default columns: `query_id` (`qid`, `qseqid`), `subject_id` (`sid`,
+    /// `sseqid`), `score`, `status`, `error`, `seq1_len` (`qlen`), `seq2_len`
+    /// (`slen`), `identity`, `pident`, `psimilarity` (`ppos`), `gaps`, `aligned_len`
+    /// (`length`), `cigar`, `max_score`, `score_ratio`, `containment`, `contained`
+    /// and the columns of `--taxonomy`. The columns taken from the alignment,
+    /// from `identity` to `cigar`, need an engine with traceback. Columns that are
+    /// not computed for a pair, e.g. because it was skipped, are left empty, or are
+    /// missing in typed formats. Applies to tsv, csv, jsonl, parquet and sqlite output.
+    #[arg(
+        long,
+        value_name = "LIST",
+        requires = "output",
+        conflicts_with_all = ["incremental", "resume"],
+        help = "Comma-separated columns of the output, e.g. qid,sid,score,pident"
+    )]
+    columns: Option<String>,
+
+    /// Template of the lines of the output instead of a format, with placeholders
+    /// such as `{qid}` for the columns accepted by `--columns` and `{pident:.1}`
+    /// for a number of decimals. `{{` and `}}` are literal braces. No header is
+    /// written.
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        requires = "output",
+        conflicts_with_all = ["output_format", "columns", "incremental", "resume"],
+        help = "Template of the output lines, e.g. \"{qid},{sid},{pident:.1}\""
+    )]
+    template: Option<String>,
+
+    /// Path to the results file of a previous run over part of the input.
+    /// Only pairs involving sequences that do not occur in it are aligned, and
+    /// the new results are appended to it with the columns of its header.
+    #[arg(
+        long,
+        conflicts_with = "output",
+        help = "Append results for new sequences to an existing results file"
+    )]
+    incremental: Option<PathBuf>,
+
+    /// Path to the results file of an interrupted run over the same input, e.g.
+    /// after a write error. Pairs that already have a row in it are not aligned
+    /// again, and the remaining results are appended to it with the columns of
+    /// its header.
+    #[arg(
+        long,
+        conflicts_with_all = ["output", "incremental"],
+        help = "Append the missing results of an interrupted run to its results file"
+    )]
+    resume: Option<PathBuf>,
+
+    /// URI of the HTTP API of a Neo4j server, e.g. `http://localhost:7474`, to
+    /// write the aligned pairs to instead of a results file. Every pair becomes a
+    /// `PAIRWISE_ALIGNED` relationship with its score and identity between the
+    /// `Protein` nodes of its sequences, matched by `accession_id` and created if
+    /// missing. Bolt URIs are not supported. `ALIGNER_NEO4J_URI` sets it for runs
+    /// without another output.
+    #[arg(
+        long,
+        value_name = "URI",
+        conflicts_with_all = ["output", "incremental", "resume"],
+        help = "Write aligned pairs as relationships to the Neo4j server at this URI"
+    )]
+    neo4j_uri: Option<String>,
+
+    /// User to authenticate as at the Neo4j server
+    #[arg(
+        long,
+        value_name = "USER",
+        env = "ALIGNER_NEO4J_USER",
+        default_value = "neo4j",
+        help = "User to authenticate as at the Neo4j server"
+    )]
+    neo4j_user: String,
+
+    /// Password of the Neo4j user. Without one, requests are not authenticated.
+    /// Best given as `ALIGNER_NEO4J_PASS`, so it stays out of the shell history.
+    #[arg(
+        long,
+        value_name = "PASSWORD",
+        env = "ALIGNER_NEO4J_PASS",
+        hide_env_values = true,
+        help = "Password of the Neo4j user"
+    )]
+    neo4j_pass: Option<String>,
+
+    /// Database of the Neo4j server the relationships are written to
+    #[arg(
+        long,
+        value_name = "NAME",
+        env = "ALIGNER_NEO4J_DATABASE",
+        default_value = "neo4j",
+        help = "Database of the Neo4j server to write to"
+    )]
+    neo4j_database: String,
+
+    /// Skip malformed input records (sequences that are not strings or empty,
+    /// repeated identifiers) and repair non-ASCII characters in sequences, both
+    /// of which are rejected otherwise. `transliterate` (the default when no value
+    /// is given) replaces characters with an ASCII equivalent where one exists and
+    /// removes the others, `strip` removes all of them. Skipped records and
+    /// repaired sequences are listed in the error report.
+    #[arg(
+        long,
+        value_enum,
+        num_args = 0..=1,
+        default_missing_value = "transliterate",
+        help = "Skip malformed records and repair non-ASCII characters instead of failing"
+    )]
+    lenient: Option<Lenient>,
+
+    /// Fail on empty sequences and repeated identifiers in the input, in addition
+    /// to records whose sequence is not a string.
+    #[arg(
+        long,
+        conflicts_with = "lenient",
+        help = "Reject empty sequences and repeated identifiers"
+    )]
+    strict: bool,
+
+    /// Field holding the sequence when the input records are objects, either as
+    /// values of the identifier map or as elements of a list of records.
+    #[arg(
+        long,
+        default_value = "sequence",
+        help = "Field containing the sequence in input records"
+    )]
+    sequence_key: String,
+
+    /// Field holding the identifier when the input is a list of records, e.g.
+    /// `accession` for records exported from a REST API.
+    #[arg(
+        long,
+        default_value = "id",
+        help = "Field containing the identifier in input records"
+    )]
+    id_key: String,
+
+    /// Feature type to read from GenBank and EMBL input instead of the full record
+    /// sequences. `CDS` features are read as protein sequences, from their
+    /// `/translation` qualifier or translated with the standard genetic code, and
+    /// identified by protein ID, locus tag or gene name.
+    #[arg(
+        long,
+        value_name = "TYPE",
+        help = "Read features of this type (e.g. CDS) from GenBank/EMBL input"
+    )]
+    feature: Option<String>,
+
+    /// Replace the nucleotide input by the proteins of its open reading frames in
+    /// all six frames, from a start codon to the next stop codon, identified by
+    /// the sequence and the coordinates of the frame, e.g. `contig1:1201-1650:-`.
+    #[arg(
+        long,
+        conflicts_with = "quality_weighted",
+        help = "Align the translated open reading frames of nucleotide input"
+    )]
+    find_orfs: bool,
+
+    /// Minimum length in nucleotides of the open reading frames, stop codon
+    /// included.
+    #[arg(
+        long,
+        value_name = "LEN",
+        default_value_t = 300,
+        requires = "find_orfs",
+        help = "Minimum length of open reading frames in nucleotides"
+    )]
+    min_orf_len: usize,
+
+    /// NCBI number of the genetic code translating open reading frames and CDS
+    /// features without a `/transl_table` qualifier, e.g. 11 for bacteria,
+    /// archaea and plastids or 2 for vertebrate mitochondria.
+    #[arg(
+        long,
+        value_name = "ID",
+        default_value = "1",
+        value_parser = translate::parse_gencode,
+        help = "NCBI genetic code used for translation"
+    )]
+    gencode: &'static GeneticCode,
+
+    /// Format of the input, detected from the file extension by default. One of
+    /// `json`, `yaml`, `fasta`, `fastq`, `csv`, `tsv`, `genbank`, `stockholm`, `afa`,
+    /// `sqlite` or `url`.
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        value_parser = source::parse_format,
+        help = "Format of the input, overriding detection by file extension"
+    )]
+    input_format: Option<String>,
+
+    /// Field delimiter of CSV and TSV input, a single character or `tab`.
+    /// Defaults to a comma for CSV and a tab for TSV input.
+    #[arg(
+        long,
+        value_name = "CHAR",
+        value_parser = table::parse_delimiter,
+        help = "Field delimiter of CSV/TSV input"
+    )]
+    input_delimiter: Option<u8>,
+
+    /// Maximum length of a sequence. Runs with longer sequences are refused
+    /// before any alignment starts, since the alignment time grows with the
+    /// product of the sequence lengths.
+    #[arg(long, help = "Refuse to run if a sequence is longer than this")]
+    max_seq_len: Option<usize>,
+
+    /// Compute scores in linear space instead of keeping the traceback matrix of
+    /// each alignment. Scores are identical; this is chosen automatically when the
+    /// estimated memory use exceeds the available memory. Alignments with
+    /// traceback are then computed with Hirschberg's algorithm.
+    #[arg(long, help = "Align in linear space to reduce memory use")]
+    low_memory: bool,
+
+    /// Keep nucleotide sequences in 2 bits per base and protein sequences in 5
+    /// bits per residue while aligning. The linear engine decodes the residues as
+    /// it aligns; other engines unpack every pair first. Sequences with other
+    /// characters are kept as they are. Not available with candidate k-mers,
+    /// whose index is built from the unpacked sequences.
+    #[arg(
+        long,
+        conflicts_with = "candidate_kmer",
+        help = "Pack sequences into 2 (nucleotide) or 5 (protein) bits per residue"
+    )]
+    pack_sequences: bool,
+
+    /// Algorithm aligning the pairs. Defaults to the full dynamic programming
+    /// matrices, or to linear space where the run needs it. The traceback engine
+    /// adds an `identity` column to the results; the myers engine computes edit
+    /// distances instead of scores; the wfa engine aligns similar sequences fastest
+    /// with identity scoring. Also available as `--algorithm`.
+    #[arg(
+        long,
+        value_enum,
+        alias = "algorithm",
+        help = "Algorithm used to align pairs"
+    )]
+    engine: Option<EngineKind>,
+
+    /// Align pairs end to end (global, Needleman-Wunsch), align their
+    /// best-scoring subsequences (local, Smith-Waterman), align the shorter
+    /// sequence end to end within the longer one (semiglobal) or align them with
+    /// free gaps at the ends of both (overlap). Local scores are not dominated by
+    /// the terminal gaps of divergent proteins sharing a conserved domain,
+    /// semi-global scores not by the length difference of a fragment and its
+    /// full-length sequence, and overlap scores not by sequences truncated at
+    /// different ends. The identity of the traceback engine covers the aligned
+    /// part only.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = AlignMode::Global,
+        help = "Align pairs globally, locally, semi-globally or with free end gaps"
+    )]
+    mode: AlignMode,
+
+    /// Cost of opening a gap, charged once per gap besides the cost of extending
+    /// it by each of its residues, so a gap of one residue costs 11 by default.
+    /// Nucleotide comparisons often call for lower costs than proteins.
+    #[arg(
+        long,
+        value_name = "COST",
+        default_value_t = -GAP_OPEN as u32,
+        help = "Cost of opening a gap"
+    )]
+    gap_open: u32,
+
+    /// Cost of every residue of a gap
+    #[arg(
+        long,
+        value_name = "COST",
+        default_value_t = -GAP_EXTEND as u32,
+        help = "Cost of extending a gap by a residue"
+    )]
+    gap_extend: u32,
+
+    /// Restrict global alignments to a band of diagonals around the path between
+    /// the corners of the dynamic programming matrix, widened by this many
+    /// diagonals on either side. Much faster for similar sequences of similar
+    /// length, whose best alignments stay within the band; pairs whose best
+    /// alignment leaves it score lower than without a band.
+    #[arg(
+        long,
+        value_name = "WIDTH",
+        conflicts_with_all = ["engine", "quality_weighted"],
+        help = "Align globally within a band of this many diagonals"
+    )]
+    band: Option<usize>,
+
+    /// Score every pair without traceback first and align only pairs scoring at
+    /// least this again with traceback, adding an `identity` column that is empty
+    /// for the other pairs. Much cheaper than `--engine traceback` when few pairs
+    /// are similar. Integer scores only.
+    #[arg(
+        long,
+        value_name = "SCORE",
+        help = "Compute the identity only for pairs scoring at least this"
+    )]
+    traceback_min_score: Option<f64>,
+
+    /// Report the alignment of every pair as a CIGAR string in a `cigar` column,
+    /// with `=` and `X` for identical and different residues, `I` and `D` for
+    /// residues of only the query or only the subject, and `S` and `N` for the
+    /// unaligned ends of the query and the subject. Selects the traceback engine
+    /// unless another one is given; with `--traceback-min-score`, only pairs
+    /// aligned again with traceback have one. Integer scores only.
+    #[arg(long, help = "Report the alignment of every pair as a CIGAR string")]
+    traceback: bool,
+
+    /// Weight the score of every mismatch by the probability that both bases were
+    /// called correctly, according to the base qualities of FASTQ input, so that
+    /// sequencing errors in raw reads cost less. Pairs of reads are then aligned in
+    /// linear space without the result cache.
+    #[arg(
+        long,
+        conflicts_with_all = ["engine", "traceback_min_score"],
+        help = "Down-weight mismatches at low-quality bases of FASTQ reads"
+    )]
+    quality_weighted: bool,
+
+    /// Maximum time spent aligning a single pair, e.g. `30s` or `5m`. Pairs taking
+    /// longer are abandoned and reported instead of stalling the run. Alignments are
+    /// then computed in linear space, which can be interrupted.
+    #[arg(
+        long,
+        value_parser = utils::parse_duration,
+        help = "Abandon alignments of single pairs that take longer than this"
+    )]
+    pair_timeout: Option<Duration>,
+
+    /// Path of the JSON report listing pairs that timed out or failed and sequences
+    /// that were repaired. Defaults to `<output>.errors.json`, written only if there
+    /// were problems.
+    #[arg(long, help = "Write the report of failed pairs to this file")]
+    errors: Option<PathBuf>,
+
+    /// Start the run even if the estimated memory use exceeds the available memory.
+    #[arg(long, help = "Skip the check of estimated against available memory")]
+    ignore_memory_estimate: bool,
+
+    /// Path of a JSON report on the input written before aligning: the length
+    /// distribution, the alphabet of the sequences, exact duplicates, residues
+    /// outside the alphabet and the number of pairs likely to pass the pre-filters,
+    /// estimated from a sample of the pairs. A summary is printed as well.
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write a sanity report on the input before aligning"
+    )]
+    sanity_report: Option<PathBuf>,
+
+    /// Stop after printing the sanity report instead of aligning
+    #[arg(long, help = "Only print the sanity report, without aligning")]
+    sanity_only: bool,
+
+    /// Tab-separated file mapping sequence identifiers (first column) to the
+    /// names used instead (second column), e.g. accessions to gene names.
+    #[arg(long, help = "Rename sequence identifiers using a mapping file")]
+    id_map: Option<PathBuf>,
+
+    /// Whether identifiers are renamed right after reading the input or only
+    /// in the written results.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = MapStage::Input,
+        requires = "id_map",
+        help = "Apply the ID map to the input or only to the output"
+    )]
+    id_map_stage: MapStage,
+
+    /// Handling of sequences whose identifier does not occur in the ID map.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Unmapped::Error,
+        requires = "id_map",
+        help = "Handling of identifiers missing from the ID map"
+    )]
+    unmapped_ids: Unmapped,
+
+    /// Input of query sequences, aligned only with the sequences of `--target`
+    /// instead of all input sequences with each other. Read like the positional
+    /// inputs, which it replaces.
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "target",
+        conflicts_with_all = ["inputs", "full_matrix", "candidate_kmer", "pairs"],
+        help = "Align the sequences of this input only with those of --target"
+    )]
+    query: Option<PathBuf>,
+
+    /// Input of target sequences every query of `--query` is aligned with.
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "query",
+        help = "Input of the target sequences aligned with every query"
+    )]
+    target: Option<PathBuf>,
+
+    /// Tab-separated table listing the substitutions, deletions and insertions of
+    /// every query relative to the only sequence of `--target`, numbered by the
+    /// positions of the target, e.g. `A123T,del125-127`.
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "target",
+        help = "Write the mutations of every query relative to the single target"
+    )]
+    mutations: Option<PathBuf>,
+
+    /// Align every pair in both directions instead of each unordered pair once,
+    /// for substitution matrices that are not symmetric.
+    #[arg(
+        long,
+        conflicts_with = "resume",
+        help = "Align each pair of sequences in both directions"
+    )]
+    full_matrix: bool,
+
+    /// Only align pairs of sequences sharing k-mers of this length, found through
+    /// an index of the k-mers of all sequences instead of enumerating all pairs.
+    #[arg(
+        long,
+        value_name = "K",
+        conflicts_with = "full_matrix",
+        help = "Only align pairs sharing k-mers of this length, found with a k-mer index"
+    )]
+    candidate_kmer: Option<usize>,
+
+    /// Number of distinct k-mers a pair must share to be aligned with
+    /// `--candidate-kmer`.
+    #[arg(
+        long,
+        default_value = "1",
+        requires = "candidate_kmer",
+        help = "Number of k-mers a candidate pair must share"
+    )]
+    candidate_min_shared: usize,
+
+    /// Tab-separated file listing the pairs to align, with the query and subject
+    /// identifier of a pair in the first two columns of every line, instead of
+    /// aligning all pairs of input sequences.
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["full_matrix", "candidate_kmer"],
+        help = "Align only the pairs listed in this tab-separated file"
+    )]
+    pairs: Option<PathBuf>,
+
+    /// Align in two passes: first estimate the k-mer distance of every pair from
+    /// MinHash sketches and keep the pairs within this distance in
+    /// `<output>.sketch.tsv`, then align only those. A complete file of the same
+    /// distance left by an earlier run is reused instead of repeating the first
+    /// pass.
+    #[arg(
+        long,
+        value_name = "MAX_DISTANCE",
+        value_parser = cluster::parse_identity,
+        help = "Align only pairs within this sketch distance, found in a first pass"
+    )]
+    two_pass: Option<f64>,
+
+    /// Align only one shard of the pairs, given as `INDEX/COUNT` with a zero-based
+    /// index, so COUNT processes with the same input can split a run between them.
+    /// Every shard writes its own output.
+    #[arg(long, help = "Align only this shard of the pairs, e.g. 0/4")]
+    shard: Option<Shard>,
+
+    /// Write only the N best-scoring subjects of every sequence, once all pairs
+    /// are aligned, instead of a row per pair. Pairs count for both of their
+    /// sequences unless `--full-matrix` aligns them in both orders.
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "output",
+        conflicts_with_all = ["incremental", "resume"],
+        help = "Write only the N best hits of every sequence"
+    )]
+    top_hits: Option<NonZeroUsize>,
+
+    /// Write only the best-scoring subject of every sequence, like `--top-hits 1`.
+    #[arg(
+        long,
+        requires = "output",
+        conflicts_with_all = ["top_hits", "incremental", "resume"],
+        help = "Write only the best hit of every sequence"
+    )]
+    best_hit_only: bool,
+
+    /// Drop best hits scoring below the threshold suggested from the score
+    /// distribution of the run, which separates the scores of homologous pairs
+    /// from those of unrelated ones. Without a separate mode of homologous scores
+    /// all hits are kept.
+    #[arg(
+        long,
+        requires = "output",
+        help = "Drop best hits below the suggested score threshold"
+    )]
+    auto_threshold: bool,
+
+    /// Align every aligned pair once more with the shorter sequence end to end
+    /// within the longer one, and report the fraction of the shorter sequence
+    /// found in the longer one and which sequence of the pair is contained.
+    #[arg(
+        long,
+        requires = "output",
+        conflicts_with_all = ["incremental", "resume"],
+        help = "Report which sequence of a pair is contained in the other"
+    )]
+    containment: bool,
+
+    /// Report the score of the shorter sequence of every aligned pair aligned with
+    /// itself, the highest score the pair can reach, in a `max_score` column and
+    /// the fraction of it the pair reached in a `score_ratio` column. The score of
+    /// every sequence with itself is computed once before the run.
+    #[arg(
+        long,
+        requires = "output",
+        conflicts_with_all = ["incremental", "resume"],
+        help = "Report the highest score every pair can reach and the fraction reached"
+    )]
+    max_score: bool,
+
+    /// Fraction of the shorter sequence that must lie within the longer one, while
+    /// the alignment covers less than this fraction of the longer one, for a pair to
+    /// be flagged as a containment.
+    #[arg(
+        long,
+        value_name = "FRACTION",
+        default_value = "0.95",
+        requires = "containment",
+        value_parser = cluster::parse_identity,
+        help = "Fraction of the shorter sequence within the longer one flagging a containment"
+    )]
+    min_containment: f64,
+
+    /// Tab-separated table annotating sequences, e.g. with their lineage: a header
+    /// naming the identifier column and then the annotation columns, and a row per
+    /// sequence. Its columns are added to every row for the row's subject, and the
+    /// written hits are counted per taxon.
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "output",
+        conflicts_with_all = ["incremental", "resume"],
+        help = "Annotate results with the taxonomy of their subjects"
+    )]
+    taxonomy: Option<PathBuf>,
+
+    /// Path of the hit counts per taxon of every taxonomy column. Defaults to
+    /// `<output>.taxa.tsv`.
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "taxonomy",
+        help = "Write the hit counts per taxon to this file"
+    )]
+    taxon_counts: Option<PathBuf>,
+
     /// Fraction for pre-filtering sequences using k-mer matches (between 0 and 1).
     /// Higher values are more stringent. If provided, sequences sharing fewer k-mers
     /// than this threshold will be skipped, improving performance.
     #[arg(short, long, help = "Fraction for pre-filtering using k-mer matches")]
     fraction: Option<f32>,
 
+    /// Named set of options suiting a kind of data: `protein-remote`,
+    /// `protein-close`, `dna-close` or a preset of the configuration file, see
+    /// [`config`]. Options given on the command line override those of the preset.
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Use the options of a named preset, e.g. protein-remote"
+    )]
+    preset: Option<String>,
+
     /// Scoring type to use for alignment.
     /// BLOSUM62 is recommended for protein sequences, while Identity scoring
     /// works for both protein and nucleotide sequences.
     #[arg(short, long, value_enum, default_value_t = ScoringType::Identity, help = "Scoring type to use for alignment")]
     scoring: ScoringType,
 
+    /// Substitution matrix file in NCBI layout used instead of a built-in scoring
+    /// type. Matrices with fractional entries are scored with floating-point
+    /// numbers, and scores in the output have decimals.
+    #[arg(
+        long,
+        conflicts_with = "scoring",
+        help = "Score with a substitution matrix read from a file"
+    )]
+    matrix: Option<PathBuf>,
+
     /// Minimum number of k-mer matches required for alignment.
     #[arg(
         short,
@@ -106,13 +929,109 @@ struct Args {
     )]
     min_matches: usize,
 
+    /// Filter a pair has to pass to be aligned, given as `NAME:ARGS`:
+    /// `length:MIN_RATIO`, `kmer:FRACTION[:MIN_MATCHES]`,
+    /// `sketch:MIN_SIMILARITY[:K[:SIZE]]`, `embedding:MIN_SIMILARITY` or
+    /// `script:PATH`. Repeat to chain filters; they run in the given order after
+    /// the k-mer pre-filter of `--fraction`.
+    #[arg(
+        long = "filter",
+        value_name = "SPEC",
+        value_parser = filter::parse_filter,
+        help = "Add a filter pairs have to pass to be aligned"
+    )]
+    filters: Vec<Arc<dyn PairFilter>>,
+
     /// Number of threads to use for parallel processing.
     #[arg(
         short,
         long,
+        env = "ALIGNER_THREADS",
         help = "Number of threads to use for parallel processing. If not provided, the number of threads will be determined automatically."
     )]
     threads: Option<usize>,
+
+    /// Placement of worker threads on cores and NUMA nodes.
+    /// `numa` additionally keeps a copy of the sequences in each node's local memory.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = PinStrategy::None,
+        help = "Pin worker threads to cores or spread them over NUMA nodes"
+    )]
+    pin_threads: PinStrategy,
+
+    /// Strategy for distributing pairs over worker threads.
+    /// `dynamic` uses work stealing, `static` splits the pairs into fixed chunks.
+    /// `per-query` aligns the targets of one query at a time in parallel chunks.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Schedule::Dynamic,
+        help = "Scheduling strategy for the pair loop"
+    )]
+    schedule: Schedule,
+
+    /// Number of pairs handed to a worker thread at once.
+    /// Larger chunks reduce scheduling overhead for short sequences.
+    #[arg(long, help = "Number of pairs per parallel task")]
+    chunk_size: Option<usize>,
+
+    /// Path to a JSON file receiving per-stage wall and CPU times and the
+    /// busy fraction of each worker thread.
+    #[arg(long, help = "Write a timing profile of the run to a JSON file")]
+    profile: Option<PathBuf>,
+
+    /// Directory holding cached alignment scores.
+    /// Scores are keyed by the sequences and alignment parameters, so pairs that were
+    /// aligned in any previous run with the same settings are not aligned again.
+    #[arg(
+        long,
+        help = "Reuse and store alignment scores in this cache directory"
+    )]
+    cache_dir: Option<PathBuf>,
+
+    /// Maximum number of scores kept in the cache; the least recently used
+    /// scores are evicted first.
+    #[arg(
+        long,
+        requires = "cache_dir",
+        help = "Maximum number of cached scores to keep"
+    )]
+    cache_max_entries: Option<usize>,
+
+    /// Number of days after which cached scores that were not used are evicted.
+    #[arg(
+        long,
+        requires = "cache_dir",
+        help = "Evict cached scores unused for this many days"
+    )]
+    cache_max_age: Option<u64>,
+
+    /// Address (e.g. `127.0.0.1:9184`) on which run metrics are served for
+    /// Prometheus at `/metrics` while the alignment is running.
+    #[arg(long, help = "Serve Prometheus metrics on this address")]
+    metrics_addr: Option<SocketAddr>,
+
+    /// OTLP/HTTP endpoint of an OpenTelemetry collector receiving run metrics,
+    /// e.g. `http://localhost:4318/v1/metrics`.
+    #[cfg(feature = "otel")]
+    #[arg(long, help = "Export run metrics to an OpenTelemetry collector")]
+    otlp_endpoint: Option<String>,
+
+    /// Interval between two metric exports in seconds.
+    #[cfg(feature = "otel")]
+    #[arg(
+        long,
+        default_value = "10",
+        help = "Interval between metric exports in seconds"
+    )]
+    otlp_interval: u64,
+
+    /// Command-line arguments of the run, recorded in the metadata sidecar of its
+    /// results
+    #[arg(skip)]
+    command_line: Vec<String>,
 }
 
 /// Scoring function wrapper that supports built-in and custom scoring matrices
@@ -137,71 +1056,1052 @@ impl Matcher {
 }
 
 fn main() {
-    let args = Args::parse();
+    // Before parsing, so the variables of the `.env` file supply options
+    if let Err(e) = config::load_env_file() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    let command = Cli::command();
+    let subcommands: Vec<&str> = command
+        .get_subcommands()
+        .flat_map(|subcommand| {
+            std::iter::once(subcommand.get_name()).chain(subcommand.get_all_aliases())
+        })
+        .chain(["help"])
+        .collect();
+    let arguments = config::expand_preset(std::env::args_os().collect(), &subcommands)
+        .unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        });
+    let cli = Cli::parse_from(&arguments);
+
+    // Log spans and events to stderr, filtered by RUST_LOG (e.g. `RUST_LOG=aligner=debug`)
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
+
+    match cli.command {
+        Some(Command::Stress(args)) => exit_on_error(stress::run(args)),
+        Some(Command::Watch(args)) => exit_on_error(watch::run(args)),
+        Some(Command::Schema) => print!("{}", schema::INPUT_SCHEMA),
+        Some(Command::Pair(args)) => exit_on_error(pair::run(args)),
+        Some(Command::Repl(args)) => exit_on_error(repl::run(args)),
+        Some(Command::Daemon(args)) => exit_on_error(daemon::run(args)),
+        Some(Command::Client(args)) => exit_on_error(daemon::run_client(args)),
+        Some(Command::Realign(args)) => exit_on_error(realign::run(args)),
+        Some(Command::Index(args)) => exit_on_error(index::run(args)),
+        Some(Command::Search(args)) => exit_on_error(search::run(args)),
+        Some(Command::Cluster(args)) => exit_on_error(cluster::run(args)),
+        Some(Command::Derep(args)) => exit_on_error(derep::run(args)),
+        Some(Command::Chimera(args)) => exit_on_error(chimera::run(args)),
+        Some(Command::Numbering(args)) => exit_on_error(numbering::run(args)),
+        Some(Command::Sites(args)) => exit_on_error(sites::run(args)),
+        Some(Command::Codon(args)) => exit_on_error(codon::run(args)),
+        Some(Command::Seqstats(args)) => exit_on_error(seqstats::run(args)),
+        Some(Command::Consensus(args)) => exit_on_error(consensus::run_consensus(args)),
+        Some(Command::Conservation(args)) => exit_on_error(consensus::run_conservation(args)),
+        Some(Command::Profile(args)) => exit_on_error(pssm::run(args)),
+        Some(Command::Rerun(args)) => replay(args),
+        None => {
+            let mut args = cli.args;
+            // With the options of the preset, so a rerun does not depend on it
+            args.command_line = arguments
+                .iter()
+                .skip(1)
+                .map(|argument| argument.to_string_lossy().into_owned())
+                .collect();
+            if args.output.is_none() && args.incremental.is_none() && args.resume.is_none() {
+                args.neo4j_uri = args.neo4j_uri.or_else(config::neo4j_uri);
+            }
+            run_with_scoring(args)
+        }
+    }
+}
+
+/// Runs the alignment run recorded in a metadata sidecar again
+fn replay(args: rerun::RerunArgs) {
+    let metadata = rerun::prepare(args).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    });
+    let program = std::iter::once("aligner".to_string());
+    let cli = Cli::try_parse_from(program.chain(metadata.arguments.iter().cloned()))
+        .unwrap_or_else(|e| {
+            eprintln!("Error: the arguments of the run are not valid: {}", e);
+            std::process::exit(1);
+        });
+    if cli.command.is_some() {
+        eprintln!("Error: the sidecar does not record an alignment run");
+        std::process::exit(1);
+    }
+    let mut args = cli.args;
+    args.output = Some(metadata.output);
+    args.command_line = metadata.arguments;
+    run_with_scoring(args)
+}
+
+/// Scoring scheme of a run, as far as the result cache and the length guard need
+/// to know it
+struct Scheme {
+    /// Name of the scheme, keeping the cached scores of different schemes apart
+    name: String,
+    /// Whether pairs score the same in both orders
+    symmetric: bool,
+    /// Largest change of the score a single aligned pair of residues can cause
+    max_residue_score: i32,
+}
+
+/// Runs the all-vs-all alignment with the scoring scheme selected by the arguments,
+/// using floating-point scores for matrices with fractional entries
+fn run_with_scoring(args: Args) {
+    let Some(path) = &args.matrix else {
+        let scheme = Scheme {
+            name: format!("{:?}", args.scoring),
+            symmetric: true,
+            max_residue_score: MAX_RESIDUE_SCORE,
+        };
+        let matcher = args.scoring.matcher();
+        return run(args, matcher, scheme);
+    };
+    let installed = matrix::SubstitutionMatrix::read(path).and_then(|matrix| {
+        let scheme = Scheme {
+            name: format!("matrix:{}", matrix.fingerprint()),
+            symmetric: matrix.is_symmetric(),
+            max_residue_score: matrix.max_residue_score(),
+        };
+        let integral = matrix.is_integral();
+        matrix::install(matrix)?;
+        Ok((scheme, integral))
+    });
+    match installed {
+        Ok((scheme, true)) => run::<i32>(args, matrix::score_i32, scheme),
+        Ok((scheme, false)) => run::<f32>(args, matrix::score_f32, scheme),
+        Err(e) => {
+            eprintln!("Error reading substitution matrix: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Prints the error of a failed subcommand and exits with a non-zero status
+fn exit_on_error(result: Result<(), error::AlignerError>) {
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
 
+/// Runs the default all-vs-all alignment of the input sequences.
+///
+/// `scheme` describes the scoring scheme of `match_fn` to the result cache.
+fn run<S: Score>(args: Args, match_fn: MatcherFn<S>, scheme: Scheme) {
     // Validate fraction if provided
-    if let Some(fraction) = args.fraction {
-        if !(0.0..=1.0).contains(&fraction) {
-            eprintln!("Error: fraction must be between 0 and 1");
+    if let Some(fraction) = args.fraction
+        && !(0.0..=1.0).contains(&fraction)
+    {
+        eprintln!("Error: fraction must be between 0 and 1");
+        std::process::exit(1);
+    }
+    let gaps = GapPenalties::from_costs(args.gap_open, args.gap_extend).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    });
+    if args.band.is_some() && args.mode != AlignMode::Global {
+        eprintln!("Error: --band only applies to global alignments");
+        std::process::exit(1);
+    }
+    if args.engine == Some(EngineKind::Myers) {
+        if args.mode == AlignMode::Local {
+            eprintln!(
+                "Error: edit distances are computed for global, semiglobal or overlap alignments"
+            );
+            std::process::exit(1);
+        }
+        if args.traceback_min_score.is_some() {
+            eprintln!("Error: --traceback-min-score does not apply to edit distances");
+            std::process::exit(1);
+        }
+    }
+    if args.engine == Some(EngineKind::Wfa)
+        && (args.matrix.is_some() || !matches!(args.scoring, ScoringType::Identity))
+    {
+        eprintln!(
+            "Error: --engine wfa needs equal scores for all matches and all mismatches; use --scoring identity"
+        );
+        std::process::exit(1);
+    }
+    if args.auto_threshold && args.top_hits.is_none() && !args.best_hit_only {
+        eprintln!("Error: --auto-threshold requires --top-hits or --best-hit-only");
+        std::process::exit(1);
+    }
+    // Rows are only appended to uncompressed tab- or comma-separated files
+    for (option, path) in [
+        ("--incremental", &args.incremental),
+        ("--resume", &args.resume),
+    ] {
+        if let Some(path) = path
+            && (Compression::from_extension(path).is_some()
+                || OutputFormat::from_path(path).delimiter().is_none())
+        {
+            eprintln!(
+                "Error: {} appends to uncompressed tsv or csv results, not to {}",
+                option,
+                path.display()
+            );
             std::process::exit(1);
         }
     }
 
-    let input = match parse_input(&args.input) {
-        Ok(input) => input,
+    let hit_limit = args
+        .top_hits
+        .map(NonZeroUsize::get)
+        .or(args.best_hit_only.then_some(1));
+    let execution = ExecutionOptions {
+        num_threads: args.threads,
+        pinning: args.pin_threads,
+        schedule: args.schedule,
+        chunk_size: args.chunk_size,
+        // The score threshold is suggested from the scores of all pairs
+        top_hits: hit_limit.filter(|_| !args.auto_threshold),
+    };
+    // Worker threads are set up before parsing, packing or the first pass start
+    // rayon's default pool
+    if let Err(e) = execution.build_global_pool() {
+        eprintln!("Error: failed to initialize thread pool: {}", e);
+        std::process::exit(1);
+    }
+
+    let memory = Arc::new(MemoryTracker::new());
+    let profiler = args.profile.as_ref().map(|_| Arc::new(Profiler::new()));
+
+    memory.begin("parse");
+    let input_paths = &match (&args.query, &args.target) {
+        (Some(query), Some(target)) => vec![query.clone(), target.clone()],
+        _ => args.inputs.clone(),
+    };
+    let _run_span = info_span!(
+        "run",
+        input = %input_paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    )
+    .entered();
+    let options = ParseOptions {
+        mode: match (args.strict, args.lenient) {
+            (true, _) => ParseMode::Strict,
+            (false, Some(_)) => ParseMode::Lenient,
+            (false, None) => ParseMode::Standard,
+        },
+        sequence_key: args.sequence_key.clone(),
+        id_key: args.id_key.clone(),
+        feature: args.feature.clone(),
+        format: args.input_format.clone(),
+        delimiter: args.input_delimiter,
+        genetic_code: args.gencode,
+    };
+    // With --query and --target, also the identifiers of the queries and targets
+    let parse = || {
+        if args.query.is_none() {
+            return parse_inputs_with(input_paths, &options).map(|parsed| (parsed, None));
+        }
+        parse_input_sets_with(input_paths, &options).map(|(parsed, mut sets)| {
+            let targets = sets.pop().unwrap_or_default();
+            let queries = sets.pop().unwrap_or_default();
+            (parsed, Some((queries, targets)))
+        })
+    };
+    let parsed = match &profiler {
+        Some(profiler) => profiler.sequential("parse", parse),
+        None => parse(),
+    };
+    let (
+        Parsed {
+            sequences: mut input,
+            invalid,
+            qualities,
+            metadata: _,
+        },
+        mut sides,
+    ) = match parsed {
+        Ok(parsed) => parsed,
         Err(e) => {
             eprintln!("Error reading input file: {}", e);
             std::process::exit(1);
         }
     };
 
-    let match_fn = match args.scoring {
-        ScoringType::Blosum62 => Matcher::Blosum62.score(),
-        ScoringType::Identity => Matcher::Identity.score(),
+    let mut qualities = if args.quality_weighted {
+        if qualities.is_empty() {
+            eprintln!("Warning: the input has no base qualities to weight mismatches with");
+        }
+        qualities
+    } else {
+        HashMap::new()
     };
 
+    let mut errors = ErrorReport::new();
+    if !invalid.is_empty() {
+        eprintln!(
+            "Warning: skipped {} malformed input records, e.g. {}",
+            invalid.len(),
+            invalid[0]
+        );
+        errors.add_invalid(&invalid);
+    }
+    match validate::check_ascii(&mut input, args.lenient) {
+        Ok(repaired) if repaired.is_empty() => {}
+        Ok(repaired) => {
+            eprintln!(
+                "Warning: removed or replaced non-ASCII characters in {} sequences",
+                repaired.len()
+            );
+            errors.add_repaired(&repaired, "removed or replaced non-ASCII characters");
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if args.find_orfs {
+        let sequences = input.len();
+        let empty = orf::translate_all(&mut input, args.min_orf_len, args.gencode, sides.as_mut());
+        eprintln!(
+            "Found {} open reading frames of at least {} nucleotides in {} sequences \
+             with genetic code {} ({})",
+            input.len(),
+            args.min_orf_len,
+            sequences - empty,
+            args.gencode.id,
+            args.gencode.name
+        );
+        if input.is_empty() {
+            eprintln!("Error: the input has no open reading frames to align");
+            std::process::exit(1);
+        }
+    }
+
+    // Higher gap costs and matrix entries lower the length up to which scores
+    // cannot overflow
+    let safe_len = gaps.max_safe_len(scheme.max_residue_score);
+    let max_len = args
+        .max_seq_len
+        .map_or(safe_len, |max_len| max_len.min(safe_len));
+    if let Err(e) = validate::check_lengths(&input, Some(max_len)) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+
+    // Rename sequences now, or keep the map to rename them when writing results
+    let mut output_names = None;
+    if let Some(path) = &args.id_map {
+        let renamed = IdMap::read(path).and_then(|map| {
+            let unmapped = map.check(&mut input, args.unmapped_ids)?;
+            if unmapped > 0 {
+                eprintln!(
+                    "Warning: {} sequence identifiers are not in the ID map",
+                    unmapped
+                );
+            }
+            match args.id_map_stage {
+                MapStage::Input => {
+                    input = map.rename(std::mem::take(&mut input))?;
+                    qualities = std::mem::take(&mut qualities)
+                        .into_iter()
+                        .map(|(id, quality)| (map.name(&id).to_string(), quality))
+                        .collect();
+                    if let Some((queries, targets)) = &mut sides {
+                        for ids in [queries, targets] {
+                            *ids = std::mem::take(ids)
+                                .into_iter()
+                                .map(|id| map.name(&id).to_string())
+                                .collect();
+                        }
+                    }
+                }
+                MapStage::Output => output_names = Some(map),
+            }
+            Ok(())
+        });
+        if let Err(e) = renamed {
+            eprintln!("Error applying ID map: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // File the results are appended to instead of creating a new one
+    let appended = args.incremental.as_ref().or(args.resume.as_ref());
+    let appended_format = appended.map_or(OutputFormat::Tsv, |path| OutputFormat::from_path(path));
+    // Results written to standard output must not be mixed with the summary
+    let to_stdout = args.output.as_deref() == Some(Path::new("-"));
+    let results_path = args.output.as_ref().filter(|_| !to_stdout).or(appended);
+    macro_rules! summary {
+        ($($arg:tt)*) => {
+            if to_stdout {
+                eprintln!($($arg)*)
+            } else {
+                println!($($arg)*)
+            }
+        };
+    }
+
+    // Identifiers with tabs or line breaks would corrupt the output rows
+    let sanitized = idmap::sanitize_ids(&mut input);
+    for renamed in &sanitized {
+        if let Some(quality) = qualities.remove(&renamed.original) {
+            qualities.insert(renamed.name.clone(), quality);
+        }
+        if let Some((queries, targets)) = &mut sides {
+            for ids in [queries, targets] {
+                if ids.remove(&renamed.original) {
+                    ids.insert(renamed.name.clone());
+                }
+            }
+        }
+    }
+    if !sanitized.is_empty() {
+        match results_path {
+            Some(output) => {
+                let path = idmap::sanitized_map_path(output);
+                if let Err(e) = idmap::write_sanitized(&path, &sanitized, false) {
+                    eprintln!("Error writing sanitized identifiers: {}", e);
+                    std::process::exit(1);
+                }
+                eprintln!(
+                    "Warning: sanitized {} sequence identifiers, original identifiers are listed in {}",
+                    sanitized.len(),
+                    path.display()
+                );
+            }
+            None => eprintln!(
+                "Warning: sanitized {} sequence identifiers",
+                sanitized.len()
+            ),
+        }
+    }
+
+    let linear_space = args.low_memory
+        || (!args.ignore_memory_estimate && needs_linear_space(&input, args.threads));
+    let engine = select_engine::<S>(&args, linear_space).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    });
+    let planner = args.traceback_min_score.map(|min_score| {
+        Arc::new(
+            engine::plan(engine, min_score, args.pair_timeout.is_some()).unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }),
+        )
+    });
+    let identity = engine.capabilities().traceback || planner.is_some();
+    if args.traceback && !identity {
+        eprintln!(
+            "Error: --traceback needs an engine computing the alignment, such as --engine traceback"
+        );
+        std::process::exit(1);
+    }
+
+    let cache = args.cache_dir.as_ref().map(|dir| {
+        let mut parameters = format!(
+            "scoring={};gap_open={};gap_extend={}",
+            scheme.name, gaps.open, gaps.extend
+        );
+        // Asymmetric matrices score the two orders of a pair apart
+        if !scheme.symmetric {
+            parameters.push_str(";ordered");
+        }
+        // Left out for global alignments, so caches of earlier runs stay valid
+        if args.mode != AlignMode::Global {
+            parameters.push_str(&format!(";mode={}", args.mode.as_str()));
+        }
+        if let Some(width) = args.band {
+            parameters.push_str(&format!(";band={}", width));
+        }
+        // Edit distances do not depend on the scoring scheme, but are kept apart
+        if args.engine == Some(EngineKind::Myers) {
+            parameters.push_str(";engine=myers");
+        }
+        let eviction = Eviction {
+            max_entries: args.cache_max_entries,
+            max_age: args
+                .cache_max_age
+                .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+        };
+        let cache = ResultCache::open(dir, &parameters, eviction).unwrap_or_else(|e| {
+            eprintln!("Error opening result cache: {}", e);
+            std::process::exit(1);
+        });
+        Arc::new(cache.with_ordered_pairs(!scheme.symmetric))
+    });
+
     let start = Instant::now();
+    // Pairs are listed by the alignment thread, which then starts the align stage
+    memory.begin("index");
+
+    let metrics = Arc::new(Metrics::new());
+    if let Some(addr) = args.metrics_addr {
+        let job = input_paths[0].file_name().map_or_else(
+            || "aligner".to_string(),
+            |n| n.to_string_lossy().into_owned(),
+        );
+        if let Err(e) = metrics::serve_prometheus(addr, Arc::clone(&metrics), job) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+    #[cfg(feature = "otel")]
+    let otlp = args.otlp_endpoint.as_ref().map(|endpoint| {
+        let interval = std::time::Duration::from_secs(args.otlp_interval.max(1));
+        metrics::OtlpExporter::start(Arc::clone(&metrics), endpoint, interval).unwrap_or_else(|e| {
+            eprintln!("Error starting metrics export: {}", e);
+            std::process::exit(1);
+        })
+    });
+
+    // A row cut off when the previous run ended is dropped, so its pair is not
+    // counted as written and the first new row does not continue it
+    if let Some(path) = appended {
+        match utils::drop_partial_row(path) {
+            Ok(0) => {}
+            Ok(bytes) => eprintln!(
+                "Warning: dropped the unfinished last row of {} ({} bytes)",
+                path.display(),
+                bytes
+            ),
+            Err(e) => {
+                eprintln!("Error reading previous results: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Sequences or pairs already compared in a previous run
+    let previous = if let Some(path) = &args.incremental {
+        let known = previous_sequences(path, &input, output_names.as_ref()).unwrap_or_else(|e| {
+            eprintln!("Error reading previous results: {}", e);
+            std::process::exit(1);
+        });
+        let new = input.keys().filter(|id| !known.contains(*id)).count();
+        println!("Incremental run: {} new of {} sequences", new, input.len());
+        Some(Previous::Sequences(known))
+    } else if let Some(path) = &args.resume {
+        let mut pairs = read_result_pairs(path).unwrap_or_else(|e| {
+            eprintln!("Error reading previous results: {}", e);
+            std::process::exit(1);
+        });
+        if let Some(map) = &output_names {
+            pairs = map.original_pairs(&input, pairs);
+        }
+        println!("Resuming run: {} pairs already written", pairs.len());
+        Some(Previous::from_pairs(pairs))
+    } else {
+        None
+    };
+
+    if let Some(path) = &args.mutations
+        && let Some((queries, targets)) = &sides
+    {
+        let Some(reference) = targets.iter().next().filter(|_| targets.len() == 1) else {
+            eprintln!(
+                "Error: --mutations needs a single target sequence, found {}",
+                targets.len()
+            );
+            std::process::exit(1);
+        };
+        // The mutations are read from the traceback of the bio aligner
+        let Some(matcher) = S::integer_matcher(match_fn) else {
+            eprintln!("Error: --mutations needs a matrix with integer scores");
+            std::process::exit(1);
+        };
+        match mutations::write_table(
+            path,
+            &input,
+            queries,
+            reference,
+            &matcher,
+            output_names.as_ref(),
+        ) {
+            Ok(rows) => eprintln!(
+                "Wrote the mutations of {} queries relative to {} to {}",
+                rows,
+                reference,
+                path.display()
+            ),
+            Err(e) => {
+                eprintln!("Error writing mutations: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Sequences subjects are drawn from, the references of SAM records, and their
+    // residues, the database size of BLAST e-values
+    let mut references: Vec<(&String, usize)> = match &sides {
+        Some((_, targets)) => targets.iter().map(|id| (id, input[id].len())).collect(),
+        None => input.iter().map(|(id, seq)| (id, seq.len())).collect(),
+    };
+    references.sort_unstable();
+    let database_len: usize = references.iter().map(|(_, len)| len).sum();
+    let references: Vec<(String, usize)> = references
+        .into_iter()
+        .map(|(id, len)| {
+            let name = output_names
+                .as_ref()
+                .map_or(id.as_str(), |map| map.name(id));
+            (name.to_string(), len)
+        })
+        .collect();
+
+    let mut generator: Box<dyn PairGenerator> = if let Some((queries, targets)) = sides {
+        Box::new(CrossProduct { queries, targets })
+    } else if let Some(path) = &args.pairs {
+        let list = PairList::read(path).unwrap_or_else(|e| {
+            eprintln!("Error reading pair list: {}", e);
+            std::process::exit(1);
+        });
+        let missing = list.missing(&input);
+        if missing > 0 {
+            eprintln!(
+                "Warning: skipping {} of {} listed pairs with sequences missing from the input",
+                missing,
+                list.len()
+            );
+        }
+        Box::new(list)
+    } else {
+        match args.candidate_kmer {
+            Some(k) => Box::new(KmerCandidates {
+                k,
+                min_shared: args.candidate_min_shared,
+            }),
+            None if args.full_matrix => Box::new(FullMatrix),
+            None => Box::new(Triangle),
+        }
+    };
+    // Shards are taken before leaving out previous pairs, so a resumed shard
+    // covers the same pairs as the interrupted one
+    if let Some(shard) = args.shard {
+        generator = Box::new(Sharded {
+            inner: generator,
+            shard,
+        });
+    }
+    // The first pass is persisted, so the second one aligns the pairs of its file
+    if let Some(max_distance) = args.two_pass {
+        let Some(output) = results_path else {
+            eprintln!("Error: --two-pass keeps its first pass next to an output file");
+            std::process::exit(1);
+        };
+        let path = twopass::state_path(output);
+        if twopass::is_complete(&path, max_distance) {
+            summary!("Two-pass: reusing the first pass in {}", path.display());
+        } else {
+            let pairs = generator.pairs(&input);
+            match twopass::first_pass(&input, &pairs, max_distance, &path) {
+                Ok(kept) => summary!(
+                    "Two-pass: {} of {} pairs within sketch distance {} written to {}",
+                    kept,
+                    pairs.len(),
+                    max_distance,
+                    path.display()
+                ),
+                Err(e) => {
+                    eprintln!("Error writing the first pass: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        generator = Box::new(PairList::read(&path).unwrap_or_else(|e| {
+            eprintln!("Error reading the first pass: {}", e);
+            std::process::exit(1);
+        }));
+    }
+    if let Some(previous) = previous {
+        generator = Box::new(Excluding {
+            inner: generator,
+            previous,
+        });
+    }
+
+    if args.sanity_report.is_some() || args.sanity_only {
+        // A chain of its own, so the sample does not count in the filter statistics
+        let filters = FilterChain::from_options(args.fraction, args.min_matches, &args.filters);
+        let report = SanityReport::new(&input, &generator.pairs(&input), &filters);
+        for line in report.summary() {
+            eprintln!("{}", line);
+        }
+        if let Some(path) = &args.sanity_report
+            && let Err(e) = report.write(path)
+        {
+            eprintln!("Error writing sanity report: {}", e);
+            std::process::exit(1);
+        }
+        if args.sanity_only {
+            return;
+        }
+    }
+
+    let taxonomy = args.taxonomy.as_ref().map(|path| {
+        Arc::new(Taxonomy::read(path).unwrap_or_else(|e| {
+            eprintln!("Error reading taxonomy: {}", e);
+            std::process::exit(1);
+        }))
+    });
+    let mut taxon_counts = TaxonCounts::default();
+    let containment = args.containment.then_some(args.min_containment);
+
+    // Set up the result sink if an output is specified, remembering where this
+    // run's rows start in case a write fails
+    let format = args
+        .output_format
+        .or_else(|| args.output.as_deref().map(OutputFormat::from_path))
+        .filter(|_| args.template.is_none())
+        // Templated lines are written like tab-separated rows
+        .unwrap_or(OutputFormat::Tsv);
+    let compression = args.compress.or_else(|| {
+        args.output
+            .as_deref()
+            .filter(|_| !to_stdout)
+            .and_then(Compression::from_extension)
+    });
+    if compression.is_some() && format == OutputFormat::Sqlite {
+        eprintln!("Error: SQLite output cannot be compressed");
+        std::process::exit(1);
+    }
+    // blast6, sam and paf output align the pairs again with the bio aligner
+    let integer_matcher = S::integer_matcher(match_fn);
+    if matches!(
+        format,
+        OutputFormat::Blast6 | OutputFormat::Sam | OutputFormat::Paf
+    ) && integer_matcher.is_none()
+    {
+        eprintln!("Error: blast6, sam and paf output need a matrix with integer scores");
+        std::process::exit(1);
+    }
+    // The statistics were estimated for BLOSUM62 itself with the default gap
+    // penalties, and BLAST parsers expect numeric e-values and bit scores
+    if format == OutputFormat::Blast6
+        && !(matches!(args.scoring, ScoringType::Blosum62)
+            && args.matrix.is_none()
+            && gaps == GapPenalties::default())
+    {
+        eprintln!(
+            "Error: --output-format blast6 needs e-values, which are only known for -s blosum62 without --matrix and with the default gap penalties"
+        );
+        std::process::exit(1);
+    }
+    let columns = args.columns.as_deref().map(|names| {
+        if matches!(
+            format,
+            OutputFormat::Blast6 | OutputFormat::Sam | OutputFormat::Paf
+        ) {
+            eprintln!("Error: --columns does not apply to blast6, sam and paf output");
+            std::process::exit(1);
+        }
+        Column::parse_list(names, taxonomy.as_deref()).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        })
+    });
+    // Rows appended to a previous results file keep its columns
+    let columns = columns.or_else(|| {
+        let path = appended?;
+        let columns = utils::read_result_header(path)
+            .map_err(|e| e.to_string())
+            .and_then(|header| {
+                Column::parse_header(
+                    &header,
+                    appended_format.delimiter().unwrap_or(b'\t'),
+                    taxonomy.as_deref(),
+                )
+            });
+        Some(columns.unwrap_or_else(|e| {
+            eprintln!("Error: cannot append to {}: {}", path.display(), e);
+            std::process::exit(1);
+        }))
+    });
+    let template = args.template.as_deref().map(|template| {
+        Template::parse(template, taxonomy.as_deref()).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        })
+    });
+    if let Err(e) = check_alignment_columns(columns.as_deref(), template.as_ref(), identity) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    // CIGAR strings are kept for --traceback, a selected cigar column and SAM and
+    // PAF records
+    let cigar = args.traceback
+        || matches!(format, OutputFormat::Sam | OutputFormat::Paf)
+        || columns
+            .iter()
+            .flatten()
+            .copied()
+            .chain(template.iter().flat_map(Template::columns))
+            .any(|column| column == Column::Cigar);
+    // Selected columns need the maximum scores as well
+    let max_score = args.max_score
+        || columns
+            .iter()
+            .flatten()
+            .copied()
+            .chain(template.iter().flat_map(Template::columns))
+            .any(|column| matches!(column, Column::MaxScore | Column::ScoreRatio));
+    let self_scores: Option<HashMap<String, S>> = max_score.then(|| {
+        input
+            .iter()
+            .map(|(id, seq)| (id.clone(), self_score(seq, &match_fn)))
+            .collect()
+    });
+    let new_sink = |writer: Box<dyn Write + Send>| -> Box<dyn ResultSink<S>> {
+        let writer: Box<dyn Write + Send> = match compression {
+            Some(compression) => Box::new(BufWriter::new(Compressor::new(compression, writer))),
+            None => writer,
+        };
+        if let Some(template) = &template {
+            return Box::new(
+                TemplateSink::new(writer, template.clone())
+                    .with_containment(containment)
+                    .with_taxonomy(taxonomy.clone()),
+            );
+        }
+        match format {
+            OutputFormat::Jsonl => Box::new(
+                JsonLinesSink::new(writer)
+                    .with_taxonomy(taxonomy.clone())
+                    .with_containment(containment)
+                    .with_columns(columns.clone()),
+            ),
+            OutputFormat::Blast6 => Box::new(Blast6Sink::new(writer)),
+            OutputFormat::Sam => Box::new(SamSink::new(writer, references.clone())),
+            OutputFormat::Paf => Box::new(PafSink::new(writer)),
+            OutputFormat::Parquet => Box::new(
+                ParquetSink::new(writer)
+                    .with_identity(identity)
+                    .with_cigar(args.traceback)
+                    .with_max_score(max_score)
+                    .with_containment(containment)
+                    .with_taxonomy(taxonomy.clone())
+                    .with_columns(columns.clone()),
+            ),
+            // Databases are opened by path, see below
+            OutputFormat::Sqlite => unreachable!("SQLite output is not written to a stream"),
+            OutputFormat::Tsv | OutputFormat::Csv => Box::new(
+                DelimitedSink::new(writer, format)
+                    // Edit distances of 1 score -1 like pairs that were not aligned
+                    .with_status(args.engine == Some(EngineKind::Myers))
+                    .with_identity(identity)
+                    .with_cigar(args.traceback)
+                    .with_max_score(max_score)
+                    .with_containment(containment)
+                    .with_taxonomy(taxonomy.clone())
+                    .with_columns(columns.clone()),
+            ),
+        }
+    };
+    let (mut sink, output_start): (Option<Box<dyn ResultSink<S>>>, u64) =
+        match (&args.output, appended) {
+            (Some(_), _) if to_stdout && format == OutputFormat::Sqlite => {
+                eprintln!("Error: SQLite output cannot be written to standard output");
+                std::process::exit(1);
+            }
+            (Some(_), _) if to_stdout => (
+                Some(new_sink(Box::new(BufWriter::new(std::io::stdout())))),
+                0,
+            ),
+            (Some(path), _) if format == OutputFormat::Sqlite => (
+                Some(Box::new(
+                    SqliteSink::new(path)
+                        .with_identity(identity)
+                        .with_cigar(args.traceback)
+                        .with_max_score(max_score)
+                        .with_containment(containment)
+                        .with_taxonomy(taxonomy.clone())
+                        .with_columns(columns.clone()),
+                )),
+                0,
+            ),
+            (Some(path), _) => {
+                let file = File::create(path).unwrap_or_else(|e| {
+                    eprintln!("Error: cannot create {}: {}", path.display(), e);
+                    std::process::exit(1);
+                });
+                (Some(new_sink(Box::new(BufWriter::new(file)))), 0)
+            }
+            (None, Some(path)) => {
+                let file = OpenOptions::new()
+                    .append(true)
+                    .open(path)
+                    .unwrap_or_else(|e| {
+                        eprintln!("Error: cannot open {} for appending: {}", path.display(), e);
+                        std::process::exit(1);
+                    });
+                let start = file.metadata().map_or(0, |metadata| metadata.len());
+                let sink = DelimitedSink::new(BufWriter::new(file), appended_format)
+                    .with_identity(identity)
+                    .with_cigar(args.traceback)
+                    .with_max_score(max_score)
+                    .with_containment(containment)
+                    .with_taxonomy(taxonomy.clone())
+                    .with_columns(columns.clone());
+                (Some(Box::new(sink.without_header())), start)
+            }
+            (None, None) => match &args.neo4j_uri {
+                Some(uri) => {
+                    let sink = Neo4jSink::new(
+                        uri,
+                        &args.neo4j_database,
+                        &args.neo4j_user,
+                        args.neo4j_pass.as_deref(),
+                    )
+                    .unwrap_or_else(|e| {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    });
+                    (Some(Box::new(sink)), 0)
+                }
+                None => (None, 0),
+            },
+        };
+    if let Some(sink) = &mut sink
+        && let Err(e) = sink.open()
+    {
+        eprintln!("Error: cannot write the header of the results: {}", e);
+        std::process::exit(1);
+    }
 
-    // Set up output writer if path is specified
-    let mut writer = args.output.map(|path| {
-        let file = File::create(path).expect("Failed to create output file");
-        let mut writer = BufWriter::new(file);
-        // Write CSV header
-        writeln!(writer, "query_id\tsubject_id\tscore\tseq1_len\tseq2_len")
-            .expect("Failed to write header");
-        writer
+    let filters = Arc::new(FilterChain::from_options(
+        args.fraction,
+        args.min_matches,
+        &args.filters,
+    ));
+
+    // Everything reading the residues from the input is done by now
+    let packed = args.pack_sequences.then(|| {
+        let packed = PackedInput::pack(&mut input);
+        info!(
+            sequences = packed.len(),
+            residues = packed.residues(),
+            bytes = packed.size(),
+            "packed sequences"
+        );
+        packed
     });
 
+    let blast_params = integer_matcher
+        .filter(|_| format == OutputFormat::Blast6)
+        .map(|matcher| BlastParams {
+            matcher,
+            gaps,
+            statistics: Statistics::BLOSUM62,
+            database_len,
+        });
+
     // Create channel for streaming results
     let (tx, rx) = mpsc::channel();
 
     // Spawn the alignment computation using rayon's threading
+    let worker_profiler = profiler.clone();
+    let worker_metrics = Arc::clone(&metrics);
+    let worker_cache = cache.clone();
+    let worker_filters = Arc::clone(&filters);
+    let worker_planner = planner.clone();
+    let worker_memory = Arc::clone(&memory);
+    let run_span = Span::current();
     let computation_handle = std::thread::spawn(move || {
+        let _run_span = run_span.entered();
+        let observers = Observers {
+            profiler: worker_profiler.as_deref(),
+            metrics: Some(&worker_metrics),
+            memory: Some(&worker_memory),
+        };
+        let scorer = Scorer {
+            matcher: &match_fn,
+            engine: match &worker_planner {
+                Some(planner) => planner.as_ref(),
+                None => engine,
+            },
+            mode: args.mode,
+            gaps,
+            cache: worker_cache.as_deref(),
+            timeout: args.pair_timeout,
+            qualities: Some(&qualities).filter(|qualities| !qualities.is_empty()),
+            packed: packed.as_ref(),
+            containment: args.containment,
+            blast: blast_params,
+            sam: integer_matcher.filter(|_| format == OutputFormat::Sam),
+            paf: integer_matcher.filter(|_| format == OutputFormat::Paf),
+            cigar,
+            self_scores: self_scores.as_ref(),
+        };
         align_all_streaming(
             &input,
-            &match_fn,
-            args.fraction,
-            args.min_matches,
+            scorer,
+            &worker_filters,
+            generator.as_ref(),
             tx,
-            args.threads,
+            &execution,
+            observers,
         )
     });
 
-    // Process results as they arrive
-    let mut total_results = 0;
-    for result in rx {
-        total_results += 1;
-        if let Some(ref mut w) = writer {
-            writeln!(
-                w,
-                "{}\t{}\t{}\t{}\t{}",
-                result.query_id,
-                result.subject_id,
-                result.score.unwrap_or(-1),
-                result.seq1_len,
-                result.seq2_len
-            )
-            .expect("Failed to write result");
+    // Best hits are collected instead of written while the pairs are aligned
+    let mut top_hits =
+        hit_limit.map(|limit| TopHits::new(limit, !args.full_matrix && args.query.is_none()));
+
+    // Process results as they arrive, handing them to the sink in batches
+    let mut scores = ScoreSample::default();
+    let mut write_error = None;
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    for mut result in rx {
+        if errors.record(&result) {
+            metrics.record_failed();
+            warn!(
+                query_id = %result.query_id,
+                subject_id = %result.subject_id,
+                status = ?result.status,
+                error = result.error.as_deref().unwrap_or_default(),
+                "pair not aligned"
+            );
+            continue;
+        }
+        metrics.record_result(result.score.map(Into::into));
+        if let Some(score) = result.score {
+            scores.offer(score.into());
+        }
+        let Some(sink) = &mut sink else {
+            continue;
+        };
+        if let Some(hits) = &mut top_hits {
+            hits.offer(result);
+            continue;
+        }
+        if let Some(map) = &output_names {
+            result.query_id = map.name(&result.query_id).to_string();
+            result.subject_id = map.name(&result.subject_id).to_string();
+        }
+        if let Some(taxonomy) = &taxonomy {
+            taxon_counts.add(taxonomy, &result.subject_id);
+        }
+        batch.push(result);
+        if batch.len() == BATCH_SIZE {
+            let written = memory.measure("write", || {
+                profile::measure(profiler.as_deref(), Stage::Write, || {
+                    sink.write_batch(&batch)
+                })
+            });
+            batch.clear();
+            // Stop receiving, which makes the worker skip the remaining pairs
+            if let Err(e) = written {
+                write_error = Some(e);
+                break;
+            }
         }
     }
 
@@ -210,17 +2110,341 @@ fn main() {
         .join()
         .expect("Computation thread panicked");
 
+    let threshold = scores.threshold();
+    memory.begin("write");
+    if let Some(mut sink) = sink
+        && write_error.is_none()
+    {
+        if let Some(hits) = top_hits {
+            batch = hits.into_results();
+            if let Some(threshold) = threshold.filter(|_| args.auto_threshold) {
+                batch.retain(|result| {
+                    result
+                        .score
+                        .is_some_and(|score| score.into() >= threshold.score)
+                });
+            }
+            if let Some(map) = &output_names {
+                for result in &mut batch {
+                    result.query_id = map.name(&result.query_id).to_string();
+                    result.subject_id = map.name(&result.subject_id).to_string();
+                }
+            }
+            if let Some(taxonomy) = &taxonomy {
+                for result in &batch {
+                    taxon_counts.add(taxonomy, &result.subject_id);
+                }
+            }
+        }
+        let closed = profile::measure(profiler.as_deref(), Stage::Write, || {
+            sink.write_batch(&batch)?;
+            sink.close()
+        });
+        if let Err(e) = closed {
+            write_error = Some(e);
+        }
+    }
+    memory.finish();
+
+    metrics.finish();
+    #[cfg(feature = "otel")]
+    if let Some(otlp) = otlp {
+        otlp.shutdown();
+    }
+
     let duration = start.elapsed().as_secs_f32();
-    println!("Processed {} alignments in {:.2}s", total_results, duration);
+    let summary = metrics.snapshot();
+    summary!(
+        "Processed {} {} alignments in {:.2}s ({:.0} pairs/s, {:.1}% skipped by pre-filter)",
+        summary.pairs_done(),
+        args.mode.as_str(),
+        duration,
+        summary.pairs_per_second(),
+        summary.skip_ratio() * 100.0
+    );
+    match threshold {
+        Some(threshold) => summary!(
+            "Score threshold: {:.1} separates random scores (mean {:.1}, sd {:.1}) from homologous ones (mean {:.1}, sd {:.1}, {:.1}% of pairs) among {} sampled scores{}",
+            threshold.score,
+            threshold.random.mean,
+            threshold.random.sd,
+            threshold.homologous.mean,
+            threshold.homologous.sd,
+            threshold.homologous.weight * 100.0,
+            scores.len(),
+            if args.auto_threshold {
+                ", applied to the best hits"
+            } else {
+                ""
+            }
+        ),
+        None => summary!(
+            "Score threshold: no separate mode of homologous scores among {} sampled scores",
+            scores.len()
+        ),
+    }
+    if let Some(planner) = &planner {
+        summary!(
+            "Traceback: {} pairs scored at least {}",
+            planner.traced(),
+            args.traceback_min_score.unwrap_or_default()
+        );
+    }
+    for stats in filters.stats() {
+        summary!(
+            "Filter {}: rejected {} of {} pairs",
+            stats.name,
+            stats.rejected,
+            stats.checked
+        );
+    }
+    if let Some(taxonomy) = &taxonomy {
+        let (hits, classified, top) = taxon_counts.summary();
+        let column = taxonomy.columns().last().expect("taxonomies have columns");
+        match top {
+            Some((taxon, top_hits)) => summary!(
+                "Taxonomy: {} of {} hits have a classified {}, most often {} ({} hits)",
+                classified,
+                hits,
+                column,
+                taxon,
+                top_hits
+            ),
+            None => summary!(
+                "Taxonomy: none of {} hits have a classified {}",
+                hits,
+                column
+            ),
+        }
+        let counts_path = args
+            .taxon_counts
+            .clone()
+            .or_else(|| results_path.map(|output| taxonomy::counts_path(output)));
+        if let Some(path) = counts_path {
+            match taxon_counts.write(taxonomy, &path) {
+                Ok(()) => summary!("Taxon counts written to {}", path.display()),
+                Err(e) => eprintln!("Error writing taxon counts: {}", e),
+            }
+        }
+    }
+    if errors.timed_out > 0 {
+        summary!(
+            "{} pairs exceeded the pair timeout and were not written",
+            errors.timed_out
+        );
+    }
+    if errors.failed > 0 {
+        summary!("{} pairs failed and were not written", errors.failed);
+    }
+    if let (Some(e), Some(path)) = (&write_error, results_path)
+        && format == OutputFormat::Sqlite
+    {
+        // Batches are committed in transactions, so there are no partial rows
+        eprintln!("Error writing results: {}", e);
+        eprintln!(
+            "Results of the completed batches remain in {}",
+            path.display()
+        );
+    } else if let (Some(e), Some(path)) = (&write_error, results_path)
+        && compression.is_some()
+    {
+        // The compressed stream cannot be cut back to complete rows
+        eprintln!("Error writing results: {}", e);
+        eprintln!(
+            "The compressed results in {} are incomplete; --resume cannot continue them",
+            path.display()
+        );
+    } else if let (Some(e), Some(path)) = (&write_error, results_path) {
+        eprintln!("Error writing results: {}", e);
+        // Rows this run wrote, without the header of a new results file
+        let header = u64::from(appended.is_none() && template.is_none());
+        match utils::truncate_partial_row(path, output_start) {
+            Ok(Some(rows)) => eprintln!(
+                "{} results were written to {} before the error; continue with --resume {}",
+                rows.saturating_sub(header),
+                path.display(),
+                path.display()
+            ),
+            Ok(None) => {}
+            Err(e) => eprintln!("Error checking partial results: {}", e),
+        }
+    } else if let (Some(e), Some(uri)) = (&write_error, &args.neo4j_uri) {
+        // Batches are committed in transactions, so the graph has no partial batch
+        eprintln!("Error writing results to Neo4j at {}: {}", uri, e);
+    }
+    let report_path = args.errors.clone().or_else(|| {
+        results_path
+            .filter(|_| !errors.is_empty())
+            .map(|output| report::report_path(output))
+    });
+    if let Some(path) = report_path {
+        match errors.write(&path) {
+            Ok(()) => summary!(
+                "Error report: {} timed out, {} failed, {} repaired sequences, {} skipped records, written to {}",
+                errors.timed_out,
+                errors.failed,
+                errors.repaired,
+                errors.invalid,
+                path.display()
+            ),
+            Err(e) => eprintln!("Error writing error report: {}", e),
+        }
+    }
+    if let Some(cache) = &cache {
+        match cache.save() {
+            Ok(entries) => summary!(
+                "Result cache: {} hits, {} new scores, {} entries stored",
+                cache.hits(),
+                cache.added(),
+                entries
+            ),
+            Err(e) => eprintln!("Error saving result cache: {}", e),
+        }
+    }
+    summary!("{}", memory);
+
+    if let (Some(profiler), Some(path)) = (&profiler, &args.profile)
+        && let Err(e) = profiler.write(path)
+    {
+        eprintln!("Error writing profile: {}", e);
+        std::process::exit(1);
+    }
+
+    if write_error.is_some() {
+        std::process::exit(1);
+    }
+
+    // Complete results of a new file are recorded for `aligner rerun`
+    if let Some(output) = args.output.as_deref().filter(|_| !to_stdout) {
+        let inputs: Vec<&Path> = args
+            .inputs
+            .iter()
+            .chain(&args.query)
+            .chain(&args.target)
+            .chain(&args.pairs)
+            .chain(&args.id_map)
+            .chain(&args.taxonomy)
+            .chain(&args.matrix)
+            .map(PathBuf::as_path)
+            .collect();
+        let path = rerun::metadata_path(output);
+        match RunMetadata::new(args.command_line.clone(), &inputs, output)
+            .and_then(|metadata| metadata.write(&path))
+        {
+            Ok(()) => summary!("Run metadata written to {}", path.display()),
+            Err(e) => eprintln!("Error writing run metadata: {}", e),
+        }
+    }
+}
+
+/// Selects the engine scoring the pairs, the traceback engine for `--traceback`
+/// unless another one is given
+fn select_engine<S: Score>(
+    args: &Args,
+    linear_space: bool,
+) -> Result<&'static dyn AlignmentEngine<S>, error::AlignerError> {
+    // Scored first by the default engine if only some pairs are traced back
+    let traceback = args.traceback && args.traceback_min_score.is_none();
+    match args.band {
+        // Lives for the rest of the run, like the engines without parameters
+        Some(width) => Ok(Box::leak(Box::new(Banded { width }))),
+        None => engine::select::<S>(
+            args.engine.or(traceback.then_some(EngineKind::Traceback)),
+            linear_space,
+            args.pair_timeout.is_some(),
+        ),
+    }
+}
+
+/// Checks that the selected columns and template placeholders can be filled in.
+///
+/// Returns an error naming the first column taken from the alignment of a pair
+/// if the run does not compute alignments (`aligned` is `false`), instead of
+/// writing it empty for every pair.
+fn check_alignment_columns(
+    columns: Option<&[Column]>,
+    template: Option<&Template>,
+    aligned: bool,
+) -> Result<(), String> {
+    if aligned {
+        return Ok(());
+    }
+    let mut selected = columns
+        .into_iter()
+        .flatten()
+        .copied()
+        .chain(template.into_iter().flat_map(Template::columns));
+    match selected.find(|column| column.needs_alignment()) {
+        Some(column) => Err(format!(
+            "the {} column needs an engine computing the alignment; use --traceback or --engine traceback",
+            column.name(None)
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Reads the sequences compared in a previous run from its results, translating
+/// names mapped with an output `--id-map` back to the input identifiers
+fn previous_sequences(
+    path: &Path,
+    input: &HashMap<String, String>,
+    output_names: Option<&IdMap>,
+) -> Result<HashSet<String>, error::AlignerError> {
+    let known = read_result_ids(path)?;
+    Ok(match output_names {
+        Some(map) => map.originals(input, &known),
+        None => known,
+    })
+}
+
+/// Compares the estimated memory use of the run with the available memory.
+///
+/// Returns `true` if the run only fits into memory with linear-space alignment,
+/// and exits if it does not fit at all.
+fn needs_linear_space(input: &HashMap<String, String>, threads: Option<usize>) -> bool {
+    let Some(available) = memory::available_memory() else {
+        return false;
+    };
+    let threads = threads.unwrap_or_else(num_cpus::get);
+    let estimate = MemoryEstimate::new(input, threads);
+    info!(
+        input = estimate.input,
+        pairs = estimate.pairs,
+        alignment = estimate.alignment,
+        available,
+        "estimated memory use"
+    );
+    if estimate.total(false) <= available {
+        return false;
+    }
+    if estimate.total(true) <= available {
+        eprintln!(
+            "Warning: estimated memory use of {} exceeds the available {}, aligning in linear space",
+            format_bytes(estimate.total(false)),
+            format_bytes(available)
+        );
+        return true;
+    }
+    eprintln!(
+        "Error: estimated memory use of {} (input {}, pair list {}, alignments {}) exceeds the available {}; \
+         use --ignore-memory-estimate to run anyway",
+        format_bytes(estimate.total(true)),
+        format_bytes(estimate.input),
+        format_bytes(estimate.pairs),
+        format_bytes(estimate.alignment_linear),
+        format_bytes(available)
+    );
+    std::process::exit(1);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Write;
 
     #[test]
     fn test_parse_input() {
-        let input = parse_input("tests/data/test_input.json").unwrap();
+        let input = utils::parse_input("tests/data/test_input.json").unwrap();
         assert_eq!(input.len(), 2);
         assert!(input.contains_key("Q6A0I3"));
         assert!(input.contains_key("ADV92528.1"));
@@ -233,4 +2457,165 @@ mod tests {
             "MANPYERGPNPTDALLEARSGPFSVSEENVSRLSASGFGGGTIYYPRENNTYGAVAISPGYTGTEASIAWLGERIASHGFVVITIDTITTLDQPDSRAEQLNAALNHMINRASSTVRSRIDSSRLAVMGHSMGGGGSLRLASQRPDLKAAIPLTPWHLNKNWSSVRVPTLIIGADLDTIAPVLTHARPFYNSLPTSISKAYLELDGATHFAPNIPNKIIGKYSVAWLKRFVDNDTRYTQFLCPGPRDGLFGEVEEYRSTCPF"
         );
     }
+
+    #[test]
+    fn test_parse_inputs_merged() {
+        let dir = std::env::temp_dir().join(format!("aligner-merge-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fasta = dir.join("more.fasta");
+        let conflicting = dir.join("conflicting.fasta");
+        std::fs::write(&fasta, ">extra\nMAVMT\n").unwrap();
+        std::fs::write(&conflicting, ">Q6A0I3\nMAVMT\n").unwrap();
+        let json = PathBuf::from("tests/data/test_input.json");
+
+        // The same record in two inputs is merged
+        let paths = [json.clone(), fasta.clone(), json.clone()];
+        let parsed = parse_inputs_with(&paths, &ParseOptions::default()).unwrap();
+        assert_eq!(parsed.sequences.len(), 3);
+        assert_eq!(parsed.sequences["extra"], "MAVMT");
+
+        let paths = [json, conflicting.clone()];
+        let error = parse_inputs_with(&paths, &ParseOptions::default()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Invalid input: sequence 'Q6A0I3' differs between tests/data/test_input.json and {}",
+                conflicting.display()
+            )
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_alignment_columns_need_traceback() {
+        let parse = |extra: &[&str]| {
+            let arguments = ["aligner", "in.fasta", "-o", "out.tsv"];
+            Cli::try_parse_from(arguments.iter().chain(extra))
+                .unwrap()
+                .args
+        };
+        let check = |args: &Args| {
+            let engine = select_engine::<i32>(args, false).unwrap();
+            let columns = args
+                .columns
+                .as_deref()
+                .map(|names| Column::parse_list(names, None).unwrap());
+            let template = args
+                .template
+                .as_deref()
+                .map(|template| Template::parse(template, None).unwrap());
+            check_alignment_columns(
+                columns.as_deref(),
+                template.as_ref(),
+                engine.capabilities().traceback,
+            )
+        };
+
+        let args = parse(&["--columns", "qid,sid,pident"]);
+        assert_eq!(
+            check(&args).unwrap_err(),
+            "the pident column needs an engine computing the alignment; use --traceback or --engine traceback"
+        );
+        let args = parse(&["--template", "{qid}\t{length}"]);
+        assert!(
+            check(&args)
+                .unwrap_err()
+                .starts_with("the aligned_len column")
+        );
+        assert!(check(&parse(&["--columns", "qid,sid,pident", "--traceback"])).is_ok());
+        assert!(check(&parse(&["--columns", "qid,sid,score,max_score"])).is_ok());
+    }
+
+    #[test]
+    fn test_incremental_pairs() {
+        let dir = std::env::temp_dir().join(format!("aligner-incremental-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let results = dir.join("previous.tsv");
+        let id_map = dir.join("names.tsv");
+        // P1 and P2 were written under their mapped names, gone is no longer in the input
+        std::fs::write(
+            &results,
+            "query_id\tsubject_id\tscore\ngeneB\tgeneA\t12\nP3\tgeneA\t4\nP3\tgeneB\t7\ngone\tP3\t1\n",
+        )
+        .unwrap();
+        std::fs::write(&id_map, "P1\tgeneA\nP2\tgeneB\n").unwrap();
+        let input: HashMap<String, String> = ["P1", "P2", "P3", "N1", "N2"]
+            .into_iter()
+            .map(|id| (id.to_string(), "MAVKL".to_string()))
+            .collect();
+        let map = IdMap::read(&id_map).unwrap();
+
+        let known = previous_sequences(&results, &input, Some(&map)).unwrap();
+        assert_eq!(known, HashSet::from(["P1", "P2", "P3"].map(String::from)));
+        let generator = Excluding {
+            inner: Box::new(Triangle),
+            previous: Previous::Sequences(known),
+        };
+        let pairs: Vec<String> = generator
+            .pairs(&input)
+            .iter()
+            .map(|(query_id, subject_id)| format!("{}-{}", query_id, subject_id))
+            .collect();
+        // Only new-vs-old and new-vs-new pairs are left
+        assert_eq!(
+            pairs,
+            [
+                "N2-N1", "P1-N1", "P1-N2", "P2-N1", "P2-N2", "P3-N1", "P3-N2"
+            ]
+        );
+
+        // Without the map the mapped names match no input sequence
+        let known = previous_sequences(&results, &input, None).unwrap();
+        assert!(!known.contains("P1") && known.contains("P3"));
+
+        // Comma-separated results are read with their quoted fields
+        let results = dir.join("previous.csv");
+        std::fs::write(
+            &results,
+            "score,subject_id,query_id\n4,P1,\"P3, variant\"\n7,P2,P1\n",
+        )
+        .unwrap();
+        assert_eq!(
+            read_result_pairs(&results).unwrap(),
+            [
+                ("P3, variant".to_string(), "P1".to_string()),
+                ("P1".to_string(), "P2".to_string())
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_truncate_partial_row() {
+        let path = std::env::temp_dir().join(format!("aligner-partial-{}.tsv", std::process::id()));
+        std::fs::write(&path, "header\nold\n").unwrap();
+        let start = std::fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "a\tb\t3\nc\td\t").unwrap();
+
+        assert_eq!(utils::truncate_partial_row(&path, start).unwrap(), Some(1));
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "header\nold\na\tb\t3\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        // A row cut off at the end of a previous run is not counted as written
+        let path = std::env::temp_dir().join(format!("aligner-resume-{}.tsv", std::process::id()));
+        let rows = "query_id\tsubject_id\tscore\na\tb\t3\nc\tb\t-13\n";
+        std::fs::write(&path, format!("{}d\tb\t6", rows)).unwrap();
+        assert_eq!(utils::drop_partial_row(&path).unwrap(), 5);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), rows);
+        assert_eq!(read_result_pairs(&path).unwrap().len(), 2);
+        assert_eq!(utils::drop_partial_row(&path).unwrap(), 0);
+        std::fs::write(&path, "query_id\tsubject_id\tscore").unwrap();
+        assert_eq!(utils::drop_partial_row(&path).unwrap(), 0);
+        std::fs::remove_file(&path).unwrap();
+
+        // Devices are left alone rather than read until they end
+        if cfg!(unix) {
+            let device = std::path::Path::new("/dev/zero");
+            assert_eq!(utils::truncate_partial_row(device, 0).unwrap(), None);
+        }
+    }
 }
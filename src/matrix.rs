@@ -0,0 +1,212 @@
+//! Substitution matrices read from files.
+//!
+//! Matrices use the layout of the NCBI matrix files: a header line with the column
+//! symbols followed by one line per row symbol with its scores, separated by
+//! whitespace. Lines starting with `#` are comments. Scores may be fractional (e.g.
+//! log-odds in bits or scores derived from embeddings); a matrix containing only
+//! whole numbers is used with integer scores.
+//!
+//! Alignment scoring functions are plain function pointers, so the matrix of a run
+//! is installed once in a global table that they read from.
+
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::error::AlignerError;
+
+/// Matrix of the current run, read by [`score_i32`] and [`score_f32`]
+static MATRIX: OnceLock<SubstitutionMatrix> = OnceLock::new();
+
+/// Scores for every pair of bytes, looked up by `a * 256 + b`
+#[derive(Debug, Clone)]
+pub struct SubstitutionMatrix {
+    scores: Vec<f32>,
+    integral: bool,
+}
+
+impl SubstitutionMatrix {
+    /// Reads a matrix file.
+    ///
+    /// Symbols are matched case-insensitively. Pairs involving symbols that do not
+    /// occur in the matrix get the lowest score of the matrix.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AlignerError::Io` if the file cannot be read, and
+    /// `AlignerError::InvalidInput` if the header is missing, a symbol is not a
+    /// single character or occurs twice, or a row has an unknown symbol, the wrong
+    /// number of scores, or a score that is not a finite number.
+    pub fn read(path: &Path) -> Result<Self, AlignerError> {
+        let content = fs::read_to_string(path)?;
+        let invalid = |line: usize, message: String| {
+            AlignerError::InvalidInput(format!("{}:{}: {}", path.display(), line, message))
+        };
+
+        let mut lines = content
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+        let (header_line, header) = lines
+            .next()
+            .ok_or_else(|| invalid(1, "missing header line".to_string()))?;
+        let columns = parse_symbols(header).map_err(|e| invalid(header_line, e))?;
+
+        let mut entries: Vec<(u8, u8, f32)> = Vec::new();
+        let mut seen_rows = HashSet::new();
+        for (number, line) in lines {
+            let mut fields = line.split_whitespace();
+            let row = fields.next().unwrap_or_default();
+            let row = match parse_symbols(row).map_err(|e| invalid(number, e))?[..] {
+                [row] if columns.contains(&row) => row,
+                _ => return Err(invalid(number, format!("unknown row symbol '{}'", row))),
+            };
+            if !seen_rows.insert(row) {
+                return Err(invalid(
+                    number,
+                    format!("row '{}' occurs twice", row as char),
+                ));
+            }
+            let values: Vec<&str> = fields.collect();
+            if values.len() != columns.len() {
+                return Err(invalid(
+                    number,
+                    format!("expected {} scores, found {}", columns.len(), values.len()),
+                ));
+            }
+            for (&column, value) in columns.iter().zip(values) {
+                let score: f32 = value
+                    .parse()
+                    .ok()
+                    .filter(|score: &f32| score.is_finite())
+                    .ok_or_else(|| invalid(number, format!("invalid score '{}'", value)))?;
+                entries.push((row, column, score));
+            }
+        }
+
+        let lowest = entries
+            .iter()
+            .map(|&(_, _, score)| score)
+            .fold(f32::INFINITY, f32::min);
+        if !lowest.is_finite() {
+            return Err(invalid(header_line, "matrix has no rows".to_string()));
+        }
+        let mut scores = vec![lowest; 256 * 256];
+        for &(row, column, score) in &entries {
+            for a in [row.to_ascii_uppercase(), row.to_ascii_lowercase()] {
+                for b in [column.to_ascii_uppercase(), column.to_ascii_lowercase()] {
+                    scores[a as usize * 256 + b as usize] = score;
+                }
+            }
+        }
+        let integral = entries.iter().all(|&(_, _, score)| score.fract() == 0.0);
+        Ok(Self { scores, integral })
+    }
+
+    /// Returns `true` if all scores are whole numbers
+    pub fn is_integral(&self) -> bool {
+        self.integral
+    }
+
+    /// Returns `true` if every pair of symbols scores the same in both orders
+    pub fn is_symmetric(&self) -> bool {
+        (0..256).all(|a| (0..a).all(|b| self.scores[a * 256 + b] == self.scores[b * 256 + a]))
+    }
+
+    /// Returns the largest distance of a score from zero, rounded up, which bounds
+    /// the change of an alignment score by a single residue
+    pub fn max_residue_score(&self) -> i32 {
+        let max = self
+            .scores
+            .iter()
+            .fold(0.0f32, |max, score| max.max(score.abs()));
+        // Saturates at `i32::MAX` for scores beyond the range of an `i32`
+        max.ceil() as i32
+    }
+
+    /// Returns a digest of all scores, identifying the matrix in the result cache
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        for score in &self.scores {
+            hasher.update(score.to_le_bytes());
+        }
+        hasher.finalize()[..8]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    fn score(&self, a: u8, b: u8) -> f32 {
+        self.scores[a as usize * 256 + b as usize]
+    }
+}
+
+/// Makes `matrix` the matrix used by [`score_i32`] and [`score_f32`].
+///
+/// # Errors
+///
+/// Returns `AlignerError::Config` if a matrix was already installed.
+pub fn install(matrix: SubstitutionMatrix) -> Result<(), AlignerError> {
+    MATRIX
+        .set(matrix)
+        .map_err(|_| AlignerError::Config("a substitution matrix is already in use".to_string()))
+}
+
+/// Scores a pair of symbols with the installed matrix as an integer
+pub fn score_i32(a: u8, b: u8) -> i32 {
+    installed().score(a, b) as i32
+}
+
+/// Scores a pair of symbols with the installed matrix
+pub fn score_f32(a: u8, b: u8) -> f32 {
+    installed().score(a, b)
+}
+
+fn installed() -> &'static SubstitutionMatrix {
+    MATRIX.get().expect("substitution matrix is installed")
+}
+
+/// Parses whitespace-separated single-character symbols
+fn parse_symbols(line: &str) -> Result<Vec<u8>, String> {
+    let mut symbols = Vec::new();
+    for symbol in line.split_whitespace() {
+        match symbol.as_bytes() {
+            [byte] if byte.is_ascii_graphic() => {
+                if symbols.contains(byte) {
+                    return Err(format!("symbol '{}' occurs twice", symbol));
+                }
+                symbols.push(*byte);
+            }
+            _ => return Err(format!("'{}' is not a single ASCII symbol", symbol)),
+        }
+    }
+    Ok(symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_matrix() {
+        let path = std::env::temp_dir().join(format!("aligner-matrix-{}.txt", std::process::id()));
+        fs::write(&path, "# test\n   A    C\nA 1.5 -0.5\nC -0.5 2\n").unwrap();
+        let matrix = SubstitutionMatrix::read(&path).unwrap();
+        assert!(!matrix.is_integral());
+        assert_eq!(matrix.score(b'a', b'C'), -0.5);
+        assert_eq!(matrix.score(b'A', b'W'), -0.5);
+        assert!(matrix.is_symmetric());
+        assert_eq!(matrix.max_residue_score(), 2);
+
+        fs::write(&path, "A C\nA 1 0\n").unwrap();
+        assert!(SubstitutionMatrix::read(&path).unwrap().is_integral());
+        fs::write(&path, "A C\nA 1 0\nC -1 1\n").unwrap();
+        assert!(!SubstitutionMatrix::read(&path).unwrap().is_symmetric());
+        fs::write(&path, "A C\nA 1\n").unwrap();
+        assert!(SubstitutionMatrix::read(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,375 @@
+//! Memory usage reporting.
+//!
+//! This module measures the peak resident set size (RSS) of each stage of a run
+//! (parsing, indexing, alignment, writing) so the end-of-run summary can show where memory
+//! is spent. On Linux the peak RSS is read from `/proc/self/status` and reset
+//! between stages through `/proc/self/clear_refs`.
+//!
+//! With the `track-allocations` feature enabled, a counting global allocator
+//! additionally records the peak heap usage and the number of allocations per stage.
+//!
+//! Before a run starts, [`MemoryEstimate`] predicts the memory needed for the input,
+//! the pair list and the alignment matrices, so runs that would exhaust the machine's
+//! memory can be refused or switched to linear-space alignment up front.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::align::{alignment_memory, pair_cost};
+
+#[cfg(feature = "track-allocations")]
+use std::alloc::{GlobalAlloc, Layout, System};
+#[cfg(feature = "track-allocations")]
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+#[cfg(feature = "track-allocations")]
+static CURRENT_BYTES: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "track-allocations")]
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "track-allocations")]
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Global allocator wrapper that counts allocations and tracks peak heap usage.
+///
+/// Install it with `#[global_allocator]`; all bookkeeping uses relaxed atomics.
+#[cfg(feature = "track-allocations")]
+pub struct TrackingAllocator;
+
+#[cfg(feature = "track-allocations")]
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        CURRENT_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            CURRENT_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+            record_alloc(new_size);
+        }
+        new_ptr
+    }
+}
+
+#[cfg(feature = "track-allocations")]
+fn record_alloc(size: usize) {
+    let current = CURRENT_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK_BYTES.fetch_max(current, Ordering::Relaxed);
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Memory usage measured for a single stage of a run
+#[derive(Debug, Clone)]
+pub struct StageMemory {
+    /// Name of the stage (e.g. `parse`, `index`, `align`, `write`)
+    pub name: &'static str,
+    /// Peak resident set size during the stage in bytes, if available
+    pub peak_rss: Option<u64>,
+    /// Peak heap usage during the stage in bytes, if allocation tracking is enabled
+    pub peak_heap: Option<u64>,
+    /// Number of allocations during the stage, if allocation tracking is enabled
+    pub allocations: Option<u64>,
+}
+
+/// Collects per-stage memory measurements over the course of a run.
+///
+/// The tracker is shared with the alignment thread, which measures building the
+/// pair list as the `index` stage. A stage entered more than once, like `write`
+/// for results streamed during the alignment, is reported once with the highest
+/// peaks and the allocations of all its spans.
+#[derive(Debug, Default)]
+pub struct MemoryTracker {
+    state: Mutex<TrackerState>,
+}
+
+#[derive(Debug, Default)]
+struct TrackerState {
+    stages: Vec<StageMemory>,
+    current: Option<(&'static str, u64)>,
+}
+
+impl MemoryTracker {
+    /// Creates an empty tracker
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts measuring a new stage, finishing the previous one if still open
+    pub fn begin(&self, name: &'static str) {
+        self.switch(Some(name));
+    }
+
+    /// Finishes the currently open stage and records its measurements
+    pub fn finish(&self) {
+        self.switch(None);
+    }
+
+    /// Measures `f` as the stage `name`, then returns to the stage open before
+    pub fn measure<T>(&self, name: &'static str, f: impl FnOnce() -> T) -> T {
+        let previous = self.switch(Some(name));
+        let value = f();
+        self.switch(previous);
+        value
+    }
+
+    /// Returns the highest peak RSS over all finished stages
+    pub fn peak_rss(&self) -> Option<u64> {
+        self.lock().stages.iter().filter_map(|s| s.peak_rss).max()
+    }
+
+    /// Finishes the open stage and starts `next`, returning the name of the finished stage
+    fn switch(&self, next: Option<&'static str>) -> Option<&'static str> {
+        let mut state = self.lock();
+        let finished = state.current.take().map(|(name, allocations_before)| {
+            let (peak_heap, allocations) = heap_stats(allocations_before);
+            state.record(StageMemory {
+                name,
+                peak_rss: peak_rss(),
+                peak_heap,
+                allocations,
+            });
+            name
+        });
+        if let Some(name) = next {
+            // Stages are listed in the order they are first entered
+            if !state.stages.iter().any(|s| s.name == name) {
+                state.stages.push(StageMemory {
+                    name,
+                    peak_rss: None,
+                    peak_heap: None,
+                    allocations: None,
+                });
+            }
+            reset_peak_rss();
+            state.current = Some((name, reset_heap_peak()));
+        }
+        finished
+    }
+
+    fn lock(&self) -> MutexGuard<'_, TrackerState> {
+        self.state.lock().expect("Memory tracker lock poisoned")
+    }
+}
+
+impl TrackerState {
+    /// Adds the measurements of a finished span to its stage
+    fn record(&mut self, span: StageMemory) {
+        let Some(stage) = self.stages.iter_mut().find(|s| s.name == span.name) else {
+            self.stages.push(span);
+            return;
+        };
+        stage.peak_rss = stage.peak_rss.max(span.peak_rss);
+        stage.peak_heap = stage.peak_heap.max(span.peak_heap);
+        stage.allocations = match (stage.allocations, span.allocations) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+    }
+}
+
+impl fmt::Display for MemoryTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.peak_rss() {
+            Some(peak) => write!(f, "Peak memory: {}", format_bytes(peak))?,
+            None => write!(f, "Peak memory: unavailable")?,
+        }
+        for stage in &self.lock().stages {
+            write!(f, "\n  {:<8}", stage.name)?;
+            if let Some(rss) = stage.peak_rss {
+                write!(f, " rss {:>10}", format_bytes(rss))?;
+            }
+            if let Some(heap) = stage.peak_heap {
+                write!(f, "  heap {:>10}", format_bytes(heap))?;
+            }
+            if let Some(allocations) = stage.allocations {
+                write!(f, "  allocations {}", allocations)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Predicted memory use of an all-vs-all run, in bytes
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryEstimate {
+    /// Parsed sequences and identifiers
+    pub input: u64,
+    /// List of all pairs to align
+    pub pairs: u64,
+    /// Matrices of the largest alignments running at the same time
+    pub alignment: u64,
+    /// Matrices of the same alignments in linear-space mode
+    pub alignment_linear: u64,
+}
+
+impl MemoryEstimate {
+    /// Estimates the memory needed to align all pairs of `input` on `threads` threads.
+    ///
+    /// The alignment estimate assumes the worst case, in which the most expensive
+    /// pairs are aligned at the same time.
+    pub fn new(input: &HashMap<String, String>, threads: usize) -> Self {
+        let entry_overhead = 2 * std::mem::size_of::<String>() as u64 + 16;
+        let input_bytes = input
+            .iter()
+            .map(|(id, seq)| (id.len() + seq.len()) as u64 + entry_overhead)
+            .sum();
+
+        let n = input.len() as u64;
+        let pairs = n * n.saturating_sub(1) / 2 * std::mem::size_of::<(&String, &String)>() as u64;
+
+        // The most expensive pairs are formed by the longest sequences
+        let mut lengths: Vec<usize> = input.values().map(String::len).collect();
+        lengths.sort_unstable_by(|a, b| b.cmp(a));
+        lengths.truncate(threads + 1);
+        let mut largest: Vec<(usize, usize)> = lengths
+            .iter()
+            .enumerate()
+            .flat_map(|(i, &len1)| lengths[i + 1..].iter().map(move |&len2| (len1, len2)))
+            .collect();
+        largest.sort_unstable_by_key(|&(len1, len2)| std::cmp::Reverse(pair_cost(len1, len2)));
+        largest.truncate(threads);
+        let concurrent = |linear_space| {
+            largest
+                .iter()
+                .map(|&(len1, len2)| alignment_memory(len1, len2, linear_space))
+                .sum()
+        };
+
+        Self {
+            input: input_bytes,
+            pairs,
+            alignment: concurrent(false),
+            alignment_linear: concurrent(true),
+        }
+    }
+
+    /// Total estimate with full or linear-space alignment
+    pub fn total(&self, linear_space: bool) -> u64 {
+        let alignment = if linear_space {
+            self.alignment_linear
+        } else {
+            self.alignment
+        };
+        self.input + self.pairs + alignment
+    }
+}
+
+/// Reads the memory available for new allocations in bytes (Linux only)
+pub fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|l| l.starts_with("MemAvailable:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Formats a byte count using binary units (KiB, MiB, GiB)
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Reads the peak resident set size of the process in bytes (Linux only)
+fn peak_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Resets the kernel's peak RSS counter so the next reading covers only the new stage.
+///
+/// Failures are ignored; the reading then covers the whole run up to that point.
+fn reset_peak_rss() {
+    let _ = std::fs::write("/proc/self/clear_refs", "5");
+}
+
+#[cfg(feature = "track-allocations")]
+fn reset_heap_peak() -> u64 {
+    PEAK_BYTES.store(CURRENT_BYTES.load(Ordering::Relaxed), Ordering::Relaxed);
+    ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+#[cfg(not(feature = "track-allocations"))]
+fn reset_heap_peak() -> u64 {
+    0
+}
+
+#[cfg(feature = "track-allocations")]
+fn heap_stats(allocations_before: u64) -> (Option<u64>, Option<u64>) {
+    let peak = PEAK_BYTES.load(Ordering::Relaxed) as u64;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+    (Some(peak), Some(allocations))
+}
+
+#[cfg(not(feature = "track-allocations"))]
+fn heap_stats(_allocations_before: u64) -> (Option<u64>, Option<u64>) {
+    (None, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stage_names(tracker: &MemoryTracker) -> Vec<&'static str> {
+        tracker.lock().stages.iter().map(|s| s.name).collect()
+    }
+
+    #[test]
+    fn test_stages_are_recorded_once_in_order() {
+        let tracker = MemoryTracker::new();
+        tracker.begin("parse");
+        tracker.begin("index");
+        tracker.begin("align");
+        assert_eq!(tracker.measure("write", || 42), 42);
+        // Back in the alignment after the streamed write
+        assert_eq!(tracker.lock().current.map(|(name, _)| name), Some("align"));
+        tracker.measure("write", || ());
+        tracker.begin("write");
+        tracker.finish();
+        tracker.finish();
+
+        assert_eq!(stage_names(&tracker), ["parse", "index", "align", "write"]);
+        assert!(tracker.lock().current.is_none());
+        let report = tracker.to_string();
+        assert!(report.starts_with("Peak memory: "));
+        assert_eq!(report.lines().count(), 5);
+    }
+
+    #[test]
+    fn test_record_merges_spans_of_a_stage() {
+        let mut state = TrackerState::default();
+        let span = |peak_rss, allocations| StageMemory {
+            name: "write",
+            peak_rss,
+            peak_heap: None,
+            allocations,
+        };
+        state.record(span(Some(10), Some(3)));
+        state.record(span(Some(30), Some(4)));
+        state.record(span(None, None));
+
+        assert_eq!(state.stages.len(), 1);
+        assert_eq!(state.stages[0].peak_rss, Some(30));
+        assert_eq!(state.stages[0].allocations, Some(7));
+    }
+}
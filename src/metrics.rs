@@ -0,0 +1,642 @@
+//! Run metrics for monitoring long-running alignments.
+//!
+//! This module keeps lock-free counters for the pairs processed during a run
+//! (aligned, skipped by the pre-filter, queued for writing) and a histogram of
+//! alignment scores. The metrics can be scraped from a Prometheus endpoint
+//! (`--metrics-addr`) and, with the `otel` feature, exported to an OpenTelemetry
+//! collector via OTLP. The daemon serves the state of its jobs at its own
+//! `/metrics` endpoint instead, see [`jobs_to_prometheus`].
+
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "otel")]
+use std::sync::OnceLock;
+
+use crate::error::AlignerError;
+use crate::jobs::{JobState, JobStatus};
+
+/// Upper bounds of the score histogram buckets; higher scores are only counted
+/// in the implicit `+Inf` bucket
+pub const SCORE_BUCKETS: [f64; 12] = [
+    0.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0, 25000.0,
+];
+
+/// Counters and score histogram of a single run
+#[derive(Debug)]
+pub struct Metrics {
+    started: Instant,
+    finished: AtomicBool,
+    pairs_total: AtomicU64,
+    results_sent: AtomicU64,
+    pairs_aligned: AtomicU64,
+    pairs_skipped: AtomicU64,
+    pairs_failed: AtomicU64,
+    score_buckets: [AtomicU64; SCORE_BUCKETS.len()],
+    /// Bit pattern of the `f64` sum of all recorded scores
+    score_sum: AtomicU64,
+    #[cfg(feature = "otel")]
+    score_histogram: OnceLock<opentelemetry::metrics::Histogram<f64>>,
+}
+
+/// Point-in-time copy of the run metrics
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricsSnapshot {
+    /// Time since the run started
+    pub elapsed: Duration,
+    /// Whether the run is still in progress
+    pub running: bool,
+    /// Number of pairs scheduled for alignment
+    pub pairs_total: u64,
+    /// Number of pairs that were aligned
+    pub pairs_aligned: u64,
+    /// Number of pairs rejected by the pre-filter
+    pub pairs_skipped: u64,
+    /// Number of pairs abandoned because they timed out or failed
+    pub pairs_failed: u64,
+    /// Number of results produced by workers but not yet consumed by the writer
+    pub queue_depth: u64,
+    /// Count of scores at or below each bound of [`SCORE_BUCKETS`] (cumulative)
+    pub score_buckets: Vec<u64>,
+    /// Sum of all recorded scores
+    pub score_sum: f64,
+}
+
+impl MetricsSnapshot {
+    /// Number of results consumed so far
+    pub fn pairs_done(&self) -> u64 {
+        self.pairs_aligned + self.pairs_skipped + self.pairs_failed
+    }
+
+    /// Average number of pairs completed per second since the start of the run
+    pub fn pairs_per_second(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.pairs_done() as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    /// Fraction of completed pairs that were rejected by the pre-filter
+    pub fn skip_ratio(&self) -> f64 {
+        match self.pairs_done() {
+            0 => 0.0,
+            done => self.pairs_skipped as f64 / done as f64,
+        }
+    }
+
+    /// Fraction of scheduled pairs that have been completed
+    pub fn progress(&self) -> f64 {
+        match self.pairs_total {
+            0 => 0.0,
+            total => self.pairs_done() as f64 / total as f64,
+        }
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    ///
+    /// `job` labels the per-job progress gauge (e.g. the input file name).
+    pub fn to_prometheus(&self, job: &str) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+            write_metric(&mut out, name, kind, help, samples)
+        };
+        let plain = |value: String| vec![(String::new(), value)];
+        let job = escape_label(job);
+
+        metric(
+            "aligner_jobs_in_flight",
+            "gauge",
+            "Alignment jobs currently running",
+            &plain(u8::from(self.running).to_string()),
+        );
+        metric(
+            "aligner_pairs_scheduled",
+            "gauge",
+            "Pairs scheduled for alignment",
+            &plain(self.pairs_total.to_string()),
+        );
+        metric(
+            "aligner_pairs_aligned_total",
+            "counter",
+            "Pairs aligned",
+            &plain(self.pairs_aligned.to_string()),
+        );
+        metric(
+            "aligner_pairs_skipped_total",
+            "counter",
+            "Pairs rejected by the pre-filter",
+            &plain(self.pairs_skipped.to_string()),
+        );
+        metric(
+            "aligner_pairs_failed_total",
+            "counter",
+            "Pairs abandoned because they timed out or failed",
+            &plain(self.pairs_failed.to_string()),
+        );
+        metric(
+            "aligner_pairs_per_second",
+            "gauge",
+            "Pairs completed per second since the start of the run",
+            &plain(format!("{:.3}", self.pairs_per_second())),
+        );
+        metric(
+            "aligner_queue_depth",
+            "gauge",
+            "Results waiting to be written",
+            &plain(self.queue_depth.to_string()),
+        );
+        metric(
+            "aligner_job_progress",
+            "gauge",
+            "Fraction of scheduled pairs completed per job",
+            &[(
+                format!("{{job=\"{}\"}}", job),
+                format!("{:.6}", self.progress()),
+            )],
+        );
+
+        let mut buckets: Vec<(String, String)> = SCORE_BUCKETS
+            .iter()
+            .zip(&self.score_buckets)
+            .map(|(bound, count)| (format!("_bucket{{le=\"{}\"}}", bound), count.to_string()))
+            .collect();
+        buckets.push((
+            "_bucket{le=\"+Inf\"}".to_string(),
+            self.pairs_aligned.to_string(),
+        ));
+        buckets.push(("_sum".to_string(), self.score_sum.to_string()));
+        buckets.push(("_count".to_string(), self.pairs_aligned.to_string()));
+        metric("aligner_score", "histogram", "Alignment scores", &buckets);
+
+        out
+    }
+}
+
+/// Renders the jobs of the daemon in the Prometheus text exposition format: the
+/// numbers of running and queued jobs and the progress of every job it keeps,
+/// labelled by job identifier and dataset
+pub fn jobs_to_prometheus(jobs: &[JobStatus]) -> String {
+    let mut out = String::new();
+    let count = |state: JobState| {
+        let jobs = jobs.iter().filter(|job| job.state == state).count();
+        vec![(String::new(), jobs.to_string())]
+    };
+    write_metric(
+        &mut out,
+        "aligner_jobs_in_flight",
+        "gauge",
+        "Alignment jobs currently running",
+        &count(JobState::Running),
+    );
+    write_metric(
+        &mut out,
+        "aligner_jobs_queued",
+        "gauge",
+        "Alignment jobs waiting for a worker",
+        &count(JobState::Queued),
+    );
+    let labels = |job: &JobStatus| {
+        format!(
+            "{{job=\"{}\",dataset=\"{}\"}}",
+            job.id,
+            escape_label(&job.dataset)
+        )
+    };
+    let progress: Vec<(String, String)> = jobs
+        .iter()
+        .map(|job| {
+            let progress = match job.total {
+                0 => 0.0,
+                total => job.done as f64 / total as f64,
+            };
+            (labels(job), format!("{:.6}", progress))
+        })
+        .collect();
+    write_metric(
+        &mut out,
+        "aligner_job_progress",
+        "gauge",
+        "Fraction of scheduled pairs completed per job",
+        &progress,
+    );
+    let done: Vec<(String, String)> = jobs
+        .iter()
+        .map(|job| (labels(job), job.done.to_string()))
+        .collect();
+    write_metric(
+        &mut out,
+        "aligner_job_pairs_done",
+        "gauge",
+        "Pairs aligned per job",
+        &done,
+    );
+    out
+}
+
+/// Writes the help, type and samples of a metric, each sample as its labels (or
+/// suffix) and value
+fn write_metric(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: &[(String, String)],
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{}{} {}", name, labels, value);
+    }
+}
+
+/// Escapes a label value of the Prometheus text format
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    /// Creates an empty set of metrics; the run clock starts now
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            finished: AtomicBool::new(false),
+            pairs_total: AtomicU64::new(0),
+            results_sent: AtomicU64::new(0),
+            pairs_aligned: AtomicU64::new(0),
+            pairs_skipped: AtomicU64::new(0),
+            pairs_failed: AtomicU64::new(0),
+            score_buckets: Default::default(),
+            score_sum: AtomicU64::new(0f64.to_bits()),
+            #[cfg(feature = "otel")]
+            score_histogram: OnceLock::new(),
+        }
+    }
+
+    /// Adds pairs to the number of pairs scheduled for alignment
+    pub fn add_total_pairs(&self, pairs: u64) {
+        self.pairs_total.fetch_add(pairs, Ordering::Relaxed);
+    }
+
+    /// Records that a worker sent a result to the writer
+    pub fn record_sent(&self) {
+        self.results_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a result consumed by the writer, with `None` for skipped pairs
+    pub fn record_result(&self, score: Option<f64>) {
+        let Some(score) = score else {
+            self.pairs_skipped.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+
+        if let Some(bucket) = SCORE_BUCKETS.iter().position(|&bound| score <= bound) {
+            self.score_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        let _ = self
+            .score_sum
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| {
+                Some((f64::from_bits(sum) + score).to_bits())
+            });
+        self.pairs_aligned.fetch_add(1, Ordering::Relaxed);
+
+        #[cfg(feature = "otel")]
+        if let Some(histogram) = self.score_histogram.get() {
+            histogram.record(score, &[]);
+        }
+    }
+
+    /// Records a pair that was abandoned because it timed out or failed
+    pub fn record_failed(&self) {
+        self.pairs_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Marks the run as finished
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns a copy of the current values
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let pairs_aligned = self.pairs_aligned.load(Ordering::Relaxed);
+        let pairs_skipped = self.pairs_skipped.load(Ordering::Relaxed);
+        let pairs_failed = self.pairs_failed.load(Ordering::Relaxed);
+        let sent = self.results_sent.load(Ordering::Relaxed);
+        let score_buckets = self
+            .score_buckets
+            .iter()
+            .scan(0, |cumulative, bucket| {
+                *cumulative += bucket.load(Ordering::Relaxed);
+                Some(*cumulative)
+            })
+            .collect();
+        MetricsSnapshot {
+            elapsed: self.started.elapsed(),
+            running: !self.finished.load(Ordering::Relaxed),
+            pairs_total: self.pairs_total.load(Ordering::Relaxed),
+            pairs_aligned,
+            pairs_skipped,
+            pairs_failed,
+            queue_depth: sent.saturating_sub(pairs_aligned + pairs_skipped + pairs_failed),
+            score_buckets,
+            score_sum: f64::from_bits(self.score_sum.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Starts a background thread serving the metrics at `http://<addr>/metrics` in the
+/// Prometheus text format.
+///
+/// The server runs until the process exits. `job` labels the per-job progress gauge.
+///
+/// # Errors
+///
+/// Returns `AlignerError::Config` if the address cannot be bound.
+pub fn serve_prometheus(
+    addr: SocketAddr,
+    metrics: Arc<Metrics>,
+    job: String,
+) -> Result<(), AlignerError> {
+    let server = tiny_http::Server::http(addr)
+        .map_err(|e| AlignerError::Config(format!("cannot serve metrics on {}: {}", addr, e)))?;
+
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = if request.url() == "/metrics" {
+                let body = metrics.snapshot().to_prometheus(&job);
+                let content_type = tiny_http::Header::from_bytes(
+                    &b"Content-Type"[..],
+                    &b"text/plain; version=0.0.4"[..],
+                )
+                .expect("static header is valid");
+                tiny_http::Response::from_string(body).with_header(content_type)
+            } else {
+                tiny_http::Response::from_string("Not Found").with_status_code(404)
+            };
+            if let Err(e) = request.respond(response) {
+                tracing::debug!("failed to answer metrics request: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Periodic OTLP export of run metrics to an OpenTelemetry collector
+#[cfg(feature = "otel")]
+pub struct OtlpExporter {
+    provider: opentelemetry_sdk::metrics::SdkMeterProvider,
+}
+
+#[cfg(feature = "otel")]
+impl OtlpExporter {
+    /// Starts exporting `metrics` over OTLP/HTTP to `endpoint`
+    /// (e.g. `http://localhost:4318/v1/metrics`) every `interval`.
+    ///
+    /// Counters and gauges are observed from the metrics on each export; scores are
+    /// recorded into an OpenTelemetry histogram as results arrive.
+    pub fn start(
+        metrics: Arc<Metrics>,
+        endpoint: &str,
+        interval: Duration,
+    ) -> Result<Self, AlignerError> {
+        use opentelemetry::metrics::MeterProvider;
+        use opentelemetry_otlp::{MetricExporter, WithExportConfig};
+        use opentelemetry_sdk::Resource;
+        use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+
+        let exporter = MetricExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .map_err(|e| AlignerError::Config(format!("invalid OTLP exporter: {}", e)))?;
+        let reader = PeriodicReader::builder(exporter)
+            .with_interval(interval)
+            .build();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(reader)
+            .with_resource(Resource::builder().with_service_name("aligner").build())
+            .build();
+        register_instruments(&provider.meter("aligner"), &metrics);
+
+        Ok(Self { provider })
+    }
+
+    /// Exports the final values and stops the exporter
+    pub fn shutdown(self) {
+        if let Err(e) = self.provider.shutdown() {
+            tracing::warn!("failed to export final metrics: {}", e);
+        }
+    }
+}
+
+/// Registers the OpenTelemetry instruments of `metrics` with `meter`: counters
+/// and gauges observed from snapshots on every collection and the score
+/// histogram, which results are recorded into as they arrive
+#[cfg(feature = "otel")]
+fn register_instruments(meter: &opentelemetry::metrics::Meter, metrics: &Arc<Metrics>) {
+    let observed = |read: fn(&MetricsSnapshot) -> u64| {
+        let metrics = Arc::clone(metrics);
+        move |observer: &dyn opentelemetry::metrics::AsyncInstrument<u64>| {
+            observer.observe(read(&metrics.snapshot()), &[])
+        }
+    };
+    meter
+        .u64_observable_counter("aligner.pairs.aligned")
+        .with_description("Pairs aligned")
+        .with_callback(observed(|s| s.pairs_aligned))
+        .build();
+    meter
+        .u64_observable_counter("aligner.pairs.skipped")
+        .with_description("Pairs rejected by the pre-filter")
+        .with_callback(observed(|s| s.pairs_skipped))
+        .build();
+    meter
+        .u64_observable_counter("aligner.pairs.failed")
+        .with_description("Pairs abandoned because they timed out or failed")
+        .with_callback(observed(|s| s.pairs_failed))
+        .build();
+    meter
+        .u64_observable_gauge("aligner.pairs.total")
+        .with_description("Pairs scheduled for alignment")
+        .with_callback(observed(|s| s.pairs_total))
+        .build();
+    meter
+        .u64_observable_gauge("aligner.queue.depth")
+        .with_description("Results waiting to be written")
+        .with_callback(observed(|s| s.queue_depth))
+        .build();
+
+    let rate_metrics = Arc::clone(metrics);
+    meter
+        .f64_observable_gauge("aligner.pairs.rate")
+        .with_description("Pairs completed per second")
+        .with_unit("{pair}/s")
+        .with_callback(move |o| o.observe(rate_metrics.snapshot().pairs_per_second(), &[]))
+        .build();
+    let skip_metrics = Arc::clone(metrics);
+    meter
+        .f64_observable_gauge("aligner.pairs.skip_ratio")
+        .with_description("Fraction of pairs rejected by the pre-filter")
+        .with_callback(move |o| o.observe(skip_metrics.snapshot().skip_ratio(), &[]))
+        .build();
+
+    let histogram = meter
+        .f64_histogram("aligner.score")
+        .with_description("Alignment scores")
+        .with_boundaries(SCORE_BUCKETS.to_vec())
+        .build();
+    let _ = metrics.score_histogram.set(histogram);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records the results of a run: 10 pairs scheduled, 6 sent, 2 aligned, 1
+    /// skipped and 1 failed, leaving 2 in the queue
+    fn record_run(metrics: &Metrics) {
+        metrics.add_total_pairs(10);
+        for _ in 0..6 {
+            metrics.record_sent();
+        }
+        metrics.record_result(Some(5.0));
+        metrics.record_result(Some(300.0));
+        metrics.record_result(None);
+        metrics.record_failed();
+    }
+
+    #[test]
+    fn test_snapshot_and_prometheus() {
+        let metrics = Metrics::new();
+        record_run(&metrics);
+        let snapshot = metrics.snapshot();
+        assert_eq!(
+            (
+                snapshot.pairs_done(),
+                snapshot.queue_depth,
+                snapshot.skip_ratio(),
+                snapshot.progress()
+            ),
+            (4, 2, 0.25, 0.4)
+        );
+        assert!(snapshot.pairs_per_second() > 0.0 && snapshot.running);
+        // Cumulative counts: 5 falls into the bucket up to 10, 300 into the one up
+        // to 500
+        assert_eq!(snapshot.score_buckets, [0, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2]);
+        assert_eq!(snapshot.score_sum, 305.0);
+
+        let text = snapshot.to_prometheus("in\"put");
+        for line in [
+            "aligner_pairs_aligned_total 2",
+            "aligner_pairs_skipped_total 1",
+            "aligner_pairs_failed_total 1",
+            "aligner_queue_depth 2",
+            "aligner_job_progress{job=\"in\\\"put\"} 0.400000",
+            "aligner_score_bucket{le=\"10\"} 1",
+            "aligner_score_bucket{le=\"+Inf\"} 2",
+            "aligner_score_sum 305",
+            "aligner_score_count 2",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {:?}", line);
+        }
+        metrics.finish();
+        assert!(!metrics.snapshot().running);
+    }
+
+    #[cfg(feature = "otel")]
+    #[test]
+    fn test_otel_instruments() {
+        use opentelemetry::metrics::MeterProvider;
+        use opentelemetry_sdk::error::OTelSdkResult;
+        use opentelemetry_sdk::metrics::data::{AggregatedMetrics, MetricData, ResourceMetrics};
+        use opentelemetry_sdk::metrics::exporter::PushMetricExporter;
+        use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider, Temporality};
+        use std::collections::HashMap;
+        use std::sync::Mutex;
+
+        /// Value of a metric, or the sum and bucket counts of a histogram
+        type Exported = (f64, Vec<u64>);
+
+        /// Last exported value of every metric by name
+        #[derive(Clone, Default)]
+        struct Captured(Arc<Mutex<HashMap<String, Exported>>>);
+
+        impl PushMetricExporter for Captured {
+            async fn export(&self, metrics: &ResourceMetrics) -> OTelSdkResult {
+                let mut captured = self.0.lock().unwrap();
+                for metric in metrics.scope_metrics().flat_map(|scope| scope.metrics()) {
+                    let value = match metric.data() {
+                        AggregatedMetrics::U64(MetricData::Sum(sum)) => {
+                            (sum.data_points().next().unwrap().value() as f64, Vec::new())
+                        }
+                        AggregatedMetrics::U64(MetricData::Gauge(gauge)) => (
+                            gauge.data_points().next().unwrap().value() as f64,
+                            Vec::new(),
+                        ),
+                        AggregatedMetrics::F64(MetricData::Gauge(gauge)) => {
+                            (gauge.data_points().next().unwrap().value(), Vec::new())
+                        }
+                        AggregatedMetrics::F64(MetricData::Histogram(histogram)) => {
+                            let point = histogram.data_points().next().unwrap();
+                            assert_eq!(point.count(), 2);
+                            (point.sum(), point.bucket_counts().collect())
+                        }
+                        _ => continue,
+                    };
+                    captured.insert(metric.name().to_string(), value);
+                }
+                Ok(())
+            }
+
+            fn force_flush(&self) -> OTelSdkResult {
+                Ok(())
+            }
+
+            fn shutdown_with_timeout(&self, _timeout: Duration) -> OTelSdkResult {
+                Ok(())
+            }
+
+            fn temporality(&self) -> Temporality {
+                Temporality::Cumulative
+            }
+        }
+
+        let exporter = Captured::default();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(
+                PeriodicReader::builder(exporter.clone())
+                    .with_interval(Duration::from_secs(3600))
+                    .build(),
+            )
+            .build();
+        let metrics = Arc::new(Metrics::new());
+        register_instruments(&provider.meter("aligner"), &metrics);
+        record_run(&metrics);
+        provider.force_flush().unwrap();
+
+        let captured = exporter.0.lock().unwrap().clone();
+        let value = |name: &str| captured[name].0;
+        assert_eq!(value("aligner.pairs.aligned"), 2.0);
+        assert_eq!(value("aligner.pairs.skipped"), 1.0);
+        assert_eq!(value("aligner.pairs.failed"), 1.0);
+        assert_eq!(value("aligner.pairs.total"), 10.0);
+        assert_eq!(value("aligner.queue.depth"), 2.0);
+        assert_eq!(value("aligner.pairs.skip_ratio"), 0.25);
+        assert!(value("aligner.pairs.rate") > 0.0);
+        // Bucket counts are not cumulative, with a last bucket above 25000
+        let (sum, buckets) = &captured["aligner.score"];
+        assert_eq!(*sum, 305.0);
+        assert_eq!(buckets, &[0, 1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+        provider.shutdown().unwrap();
+    }
+}
@@ -0,0 +1,430 @@
+//! Reading of multiple sequence alignments in Stockholm and aligned FASTA format.
+//!
+//! An alignment is checked to have rows of equal length when it is loaded. Both
+//! `-` and `.` are read as gaps, and columns that are gaps in every row, which
+//! remain when sequences are taken from a larger alignment, are removed. As input
+//! of a run the rows are aligned pairwise without their gaps, so an existing
+//! alignment can serve as input directly. The `consensus`, `conservation` and
+//! `profile` subcommands keep the columns and summarize them instead.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use tracing::info;
+
+use crate::error::AlignerError;
+use crate::input::{ParseOptions, Parsed};
+use crate::seqstats::Kind;
+
+/// Characters marking gaps in alignment rows
+const GAPS: [u8; 2] = [b'-', b'.'];
+
+/// A multiple sequence alignment with rows of equal length
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Msa {
+    /// Name of the alignment, from the `#=GF ID` line of Stockholm files
+    pub name: Option<String>,
+    /// Identifiers of the rows, in file order
+    pub ids: Vec<String>,
+    /// Aligned rows, with gaps as `-`
+    pub rows: Vec<Vec<u8>>,
+}
+
+impl Msa {
+    /// Builds an alignment from its rows, normalizing gaps to `-` and removing
+    /// columns that are gaps in every row.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message naming the first row whose length differs from the first
+    /// row.
+    fn from_rows(rows: Vec<(String, Vec<u8>)>) -> Result<Self, String> {
+        let columns = rows.first().map_or(0, |(_, row)| row.len());
+        if let Some((id, row)) = rows.iter().find(|(_, row)| row.len() != columns) {
+            return Err(format!(
+                "row '{}' has {} columns, expected {}",
+                id,
+                row.len(),
+                columns
+            ));
+        }
+
+        let occupied: Vec<bool> = (0..columns)
+            .map(|column| rows.iter().any(|(_, row)| !GAPS.contains(&row[column])))
+            .collect();
+        let (ids, rows) = rows
+            .into_iter()
+            .map(|(id, row)| {
+                let row = row
+                    .iter()
+                    .zip(&occupied)
+                    .filter(|(_, occupied)| **occupied)
+                    .map(|(c, _)| if GAPS.contains(c) { b'-' } else { *c })
+                    .collect();
+                (id, row)
+            })
+            .unzip();
+        Ok(Self {
+            name: None,
+            ids,
+            rows,
+        })
+    }
+
+    /// Returns the number of columns
+    pub fn columns(&self) -> usize {
+        self.rows.first().map_or(0, Vec::len)
+    }
+
+    /// Returns the rows without gaps, in upper case
+    pub fn ungapped(&self) -> impl Iterator<Item = (&str, String)> {
+        self.ids.iter().zip(&self.rows).map(|(id, row)| {
+            let sequence = row
+                .iter()
+                .filter(|c| **c != b'-')
+                .map(|c| c.to_ascii_uppercase() as char)
+                .collect();
+            (id.as_str(), sequence)
+        })
+    }
+
+    /// Returns the alphabet of the alignment, told apart by its residues
+    pub fn kind(&self) -> Kind {
+        let residues: String = self.ungapped().map(|(_, sequence)| sequence).collect();
+        Kind::detect(&residues)
+    }
+
+    /// Summarizes the residues of every column
+    pub fn column_stats(&self) -> Vec<ColumnStats> {
+        (0..self.columns())
+            .map(|column| ColumnStats::of(self.rows.iter().map(|row| row[column])))
+            .collect()
+    }
+
+    /// Returns the consensus sequence: the most frequent residue of every column
+    /// that is a gap in at most `max_gaps` of the rows. Columns whose most
+    /// frequent residue makes up less than `min_frequency` of their residues get
+    /// the ambiguity symbol of the alphabet, `X` or `N`.
+    pub fn consensus(&self, min_frequency: f64, max_gaps: f64) -> String {
+        let ambiguous = match self.kind() {
+            Kind::Protein => 'X',
+            Kind::Nucleotide => 'N',
+        };
+        self.column_stats()
+            .iter()
+            .filter(|stats| stats.gaps <= max_gaps)
+            .filter_map(|stats| {
+                let residue = char::from(stats.residue?);
+                Some(if stats.frequency >= min_frequency {
+                    residue
+                } else {
+                    ambiguous
+                })
+            })
+            .collect()
+    }
+}
+
+/// Residues of one column of an alignment
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnStats {
+    /// Number of rows of each upper-case residue, without gaps
+    pub counts: BTreeMap<u8, usize>,
+    /// Most frequent residue, the first in alphabetical order on ties, or `None`
+    /// if the column only has gaps
+    pub residue: Option<u8>,
+    /// Fraction of the residues of the column that are the most frequent one
+    pub frequency: f64,
+    /// Fraction of the rows with a gap in the column
+    pub gaps: f64,
+    /// Shannon entropy of the residues of the column in bits, 0 if all residues
+    /// are the same
+    pub entropy: f64,
+}
+
+impl ColumnStats {
+    /// Summarizes the symbols of a column, with gaps as `-`
+    pub fn of(column: impl IntoIterator<Item = u8>) -> Self {
+        let mut counts = BTreeMap::new();
+        let mut rows = 0;
+        for symbol in column {
+            rows += 1;
+            if symbol != b'-' {
+                *counts.entry(symbol.to_ascii_uppercase()).or_insert(0) += 1;
+            }
+        }
+        let residues: usize = counts.values().sum();
+        // The first of equally frequent residues wins
+        let top = counts
+            .iter()
+            .rev()
+            .max_by_key(|(_, count)| **count)
+            .map(|(residue, count)| (*residue, *count));
+        let entropy = counts
+            .values()
+            .map(|&count| {
+                let p = count as f64 / residues as f64;
+                p * (1.0 / p).log2()
+            })
+            .sum();
+        Self {
+            residue: top.map(|(residue, _)| residue),
+            frequency: top.map_or(0.0, |(_, count)| count as f64 / residues as f64),
+            gaps: if rows == 0 {
+                0.0
+            } else {
+                (rows - residues) as f64 / rows as f64
+            },
+            entropy,
+            counts,
+        }
+    }
+
+    /// Returns the conservation of the column between 0 and 1: one minus its
+    /// entropy relative to that of residues spread evenly over the alphabet,
+    /// scaled down by the fraction of gaps
+    pub fn conservation(&self, kind: Kind) -> f64 {
+        let alphabet: f64 = match kind {
+            Kind::Protein => 20.0,
+            Kind::Nucleotide => 4.0,
+        };
+        ((1.0 - self.entropy / alphabet.log2()) * (1.0 - self.gaps)).clamp(0.0, 1.0)
+    }
+}
+
+/// Format of an alignment file
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MsaFormat {
+    /// Stockholm, possibly with several alignments and interleaved blocks
+    Stockholm,
+    /// FASTA with gapped sequences of equal length
+    AlignedFasta,
+}
+
+/// Returns the format of an alignment file by its extension: Stockholm for `.sto`
+/// and `.stk`, aligned FASTA otherwise
+pub fn format_of(path: &Path) -> MsaFormat {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("sto" | "stk") => MsaFormat::Stockholm,
+        _ => MsaFormat::AlignedFasta,
+    }
+}
+
+/// Reads the alignments of a file in the format of its extension.
+///
+/// Alignments without an `#=GF ID` line are named after the file, numbered from 1
+/// if the file holds several.
+///
+/// # Errors
+///
+/// Returns `AlignerError::Io` if the file cannot be read, and
+/// `AlignerError::InvalidInput` if it is malformed, its rows differ in length or
+/// it holds no alignment.
+pub fn read_alignments(path: &Path) -> Result<Vec<Msa>, AlignerError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut alignments = match format_of(path) {
+        MsaFormat::Stockholm => parse_stockholm(reader),
+        MsaFormat::AlignedFasta => parse_fasta(reader).map(|msa| vec![msa]),
+    }
+    .map_err(|e| AlignerError::InvalidInput(format!("{}: {}", path.display(), e)))?;
+    if alignments.iter().all(|msa| msa.ids.is_empty()) {
+        return Err(AlignerError::InvalidInput(format!(
+            "{}: no aligned sequences",
+            path.display()
+        )));
+    }
+
+    let stem = path
+        .file_stem()
+        .map_or("alignment".into(), |stem| stem.to_string_lossy());
+    let several = alignments.len() > 1;
+    for (index, msa) in alignments.iter_mut().enumerate() {
+        if msa.name.is_none() {
+            msa.name = Some(if several {
+                format!("{}_{}", stem, index + 1)
+            } else {
+                stem.to_string()
+            });
+        }
+    }
+    Ok(alignments)
+}
+
+/// Reads an alignment file and returns its rows without gaps.
+///
+/// # Errors
+///
+/// Returns `AlignerError::Io` if the file cannot be read, and
+/// `AlignerError::InvalidInput` if it is malformed, its rows differ in length, or,
+/// unless the parse mode is lenient, a row is empty or repeats an identifier.
+pub fn read(
+    path: &Path,
+    format: MsaFormat,
+    options: &ParseOptions,
+) -> Result<Parsed, AlignerError> {
+    let reader = BufReader::new(File::open(path)?);
+    let alignments = match format {
+        MsaFormat::Stockholm => parse_stockholm(reader),
+        MsaFormat::AlignedFasta => parse_fasta(reader).map(|msa| vec![msa]),
+    };
+    let to_error = |e: String| AlignerError::InvalidInput(format!("{}: {}", path.display(), e));
+
+    let mut parsed = Parsed::default();
+    let mut record = 0;
+    for msa in alignments.map_err(to_error)? {
+        info!(
+            path = %path.display(),
+            sequences = msa.ids.len(),
+            columns = msa.columns(),
+            "read alignment"
+        );
+        for (id, sequence) in msa.ungapped() {
+            record += 1;
+            parsed
+                .add(options.mode, record, id.to_string(), Ok(sequence))
+                .map_err(to_error)?;
+        }
+    }
+    Ok(parsed)
+}
+
+/// Parses Stockholm alignments, concatenating the rows of interleaved blocks and
+/// skipping annotation lines
+fn parse_stockholm(reader: impl BufRead) -> Result<Vec<Msa>, String> {
+    let mut alignments = Vec::new();
+    let mut rows: Vec<(String, Vec<u8>)> = Vec::new();
+    // Position of each identifier in `rows`, for appending interleaved blocks
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let mut name = None;
+    let mut open = false;
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        let line = line.trim();
+        if line.starts_with("# STOCKHOLM") {
+            open = true;
+            continue;
+        }
+        if let Some(id) = line.strip_prefix("#=GF ID") {
+            name = Some(id.trim().to_string()).filter(|id| !id.is_empty());
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if !open {
+            return Err(format!(
+                "line {}: expected a '# STOCKHOLM' header",
+                index + 1
+            ));
+        }
+        if line == "//" {
+            let mut msa = Msa::from_rows(std::mem::take(&mut rows))?;
+            msa.name = name.take();
+            alignments.push(msa);
+            positions.clear();
+            open = false;
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let (Some(id), Some(segment), None) = (fields.next(), fields.next(), fields.next()) else {
+            return Err(format!(
+                "line {}: expected an identifier and a sequence",
+                index + 1
+            ));
+        };
+        match positions.get(id) {
+            Some(&position) => rows[position].1.extend_from_slice(segment.as_bytes()),
+            None => {
+                positions.insert(id.to_string(), rows.len());
+                rows.push((id.to_string(), segment.as_bytes().to_vec()));
+            }
+        }
+    }
+    if open {
+        return Err("last alignment is not terminated by '//'".to_string());
+    }
+    Ok(alignments)
+}
+
+/// Parses an aligned FASTA file, identifying rows by the first word of their
+/// header
+fn parse_fasta(reader: impl BufRead) -> Result<Msa, String> {
+    let mut rows: Vec<(String, Vec<u8>)> = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if let Some(header) = line.strip_prefix('>') {
+            let id = header.split_whitespace().next().unwrap_or_default();
+            rows.push((id.to_string(), Vec::new()));
+        } else if let Some((_, row)) = rows.last_mut() {
+            row.extend(line.bytes().filter(|c| !c.is_ascii_whitespace()));
+        } else if !line.trim().is_empty() {
+            return Err(format!(
+                "line {}: sequence before the first header",
+                index + 1
+            ));
+        }
+    }
+    Msa::from_rows(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_alignments() {
+        let stockholm = "\
+# STOCKHOLM 1.0
+#=GF ID test
+seq1  AC.-GT
+seq2  A-..gT
+#=GC SS_cons ......
+
+seq1  -W
+seq2  -Y
+//
+";
+        let alignments = parse_stockholm(stockholm.as_bytes()).unwrap();
+        assert_eq!(alignments.len(), 1);
+        assert_eq!(alignments[0].name.as_deref(), Some("test"));
+        // The third, fourth and seventh columns are gaps in both rows
+        assert_eq!(alignments[0].rows, [b"ACGTW".to_vec(), b"A-gTY".to_vec()]);
+        let ungapped: Vec<_> = alignments[0].ungapped().collect();
+        assert_eq!(ungapped[1], ("seq2", "AGTY".to_string()));
+
+        let fasta = ">seq1 first\nMA-V\nK\n>seq2\nMAL--\n";
+        let msa = parse_fasta(fasta.as_bytes()).unwrap();
+        assert_eq!(msa.ids, ["seq1", "seq2"]);
+        assert_eq!(msa.columns(), 5);
+
+        let error = parse_fasta(">a\nMAV\n>b\nMA\n".as_bytes()).unwrap_err();
+        assert_eq!(error, "row 'b' has 2 columns, expected 3");
+    }
+
+    #[test]
+    fn test_columns_and_consensus() {
+        let fasta = ">a\nMKV-W\n>b\nMKI-W\n>c\nMRL-Y\n>d\nMK-AW\n";
+        let msa = parse_fasta(fasta.as_bytes()).unwrap();
+        let stats = msa.column_stats();
+        assert_eq!(stats[0].residue, Some(b'M'));
+        assert_eq!(
+            (stats[0].frequency, stats[0].gaps, stats[0].entropy),
+            (1.0, 0.0, 0.0)
+        );
+        assert_eq!(stats[0].conservation(Kind::Protein), 1.0);
+        assert_eq!(stats[1].frequency, 0.75);
+        // Three different residues and a gap
+        assert_eq!((stats[2].residue, stats[2].gaps), (Some(b'I'), 0.25));
+        assert!((stats[2].entropy - 3f64.log2()).abs() < 1e-9);
+        assert!(stats[2].conservation(Kind::Protein) < stats[1].conservation(Kind::Protein));
+        assert_eq!(stats[3].gaps, 0.75);
+
+        // The mostly gapped column is left out, the column without a majority is
+        // ambiguous
+        assert_eq!(msa.consensus(0.5, 0.5), "MKXW");
+        assert_eq!(msa.consensus(0.0, 1.0), "MKIAW");
+    }
+}
@@ -0,0 +1,180 @@
+//! Mutation lists relative to a reference sequence.
+//!
+//! For enzyme engineering, the variants of a query relative to the wild type are
+//! the result that matters, not the alignment score. The mutations are read from
+//! the traceback of the global alignment of the query with the reference and
+//! numbered by reference positions, starting at 1: substitutions as `A123T`,
+//! deletions of reference residues as `del125` or `del125-127`, and residues
+//! inserted between two reference positions as `ins125-126GS`, with position 0
+//! for insertions before the first residue.
+
+use bio::alignment::{Alignment, AlignmentOperation};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::align::MatcherFn;
+use crate::error::AlignerError;
+use crate::idmap::IdMap;
+use crate::pair::align_pair;
+
+/// A difference of a query from the reference
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mutation {
+    /// Reference residue at `position` replaced by another residue
+    Substitution {
+        position: usize,
+        from: char,
+        to: char,
+    },
+    /// Reference residues `start` to `end` missing from the query
+    Deletion { start: usize, end: usize },
+    /// Query residues between reference positions `after` and `after + 1`
+    Insertion { after: usize, residues: String },
+}
+
+impl fmt::Display for Mutation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mutation::Substitution { position, from, to } => {
+                write!(f, "{}{}{}", from, position, to)
+            }
+            Mutation::Deletion { start, end } if start == end => write!(f, "del{}", start),
+            Mutation::Deletion { start, end } => write!(f, "del{}-{}", start, end),
+            Mutation::Insertion { after, residues } => {
+                write!(f, "ins{}-{}{}", after, after + 1, residues)
+            }
+        }
+    }
+}
+
+/// Lists the mutations of `query` relative to `reference` from the global
+/// alignment of the query (x) with the reference (y)
+pub fn call(alignment: &Alignment, query: &str, reference: &str) -> Vec<Mutation> {
+    let (query, reference) = (query.as_bytes(), reference.as_bytes());
+    let mut mutations: Vec<Mutation> = Vec::new();
+    let (mut x, mut y) = (alignment.xstart, alignment.ystart);
+    for op in &alignment.operations {
+        match op {
+            AlignmentOperation::Match => {
+                x += 1;
+                y += 1;
+            }
+            AlignmentOperation::Subst => {
+                mutations.push(Mutation::Substitution {
+                    position: y + 1,
+                    from: char::from(reference[y]),
+                    to: char::from(query[x]),
+                });
+                x += 1;
+                y += 1;
+            }
+            AlignmentOperation::Del => {
+                y += 1;
+                match mutations.last_mut() {
+                    Some(Mutation::Deletion { end, .. }) if *end + 1 == y => *end = y,
+                    _ => mutations.push(Mutation::Deletion { start: y, end: y }),
+                }
+            }
+            AlignmentOperation::Ins => {
+                let residue = char::from(query[x]);
+                x += 1;
+                match mutations.last_mut() {
+                    Some(Mutation::Insertion { after, residues }) if *after == y => {
+                        residues.push(residue)
+                    }
+                    _ => mutations.push(Mutation::Insertion {
+                        after: y,
+                        residues: residue.to_string(),
+                    }),
+                }
+            }
+            AlignmentOperation::Xclip(n) => x += n,
+            AlignmentOperation::Yclip(n) => y += n,
+        }
+    }
+    mutations
+}
+
+/// Joins mutations with commas, or returns `WT` if there are none
+pub fn format_list(mutations: &[Mutation]) -> String {
+    if mutations.is_empty() {
+        return "WT".to_string();
+    }
+    mutations
+        .iter()
+        .map(Mutation::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Writes a tab-separated table with the mutations of every query relative to
+/// the reference, queries in identifier order, and returns the number of rows.
+/// With `names`, identifiers are written under their output names.
+///
+/// # Errors
+///
+/// Returns an error if the table cannot be written.
+pub fn write_table(
+    path: &Path,
+    sequences: &HashMap<String, String>,
+    queries: &HashSet<String>,
+    reference_id: &str,
+    matcher: &MatcherFn,
+    names: Option<&IdMap>,
+) -> Result<usize, AlignerError> {
+    let reference = &sequences[reference_id];
+    let mut queries: Vec<&String> = queries.iter().filter(|id| *id != reference_id).collect();
+    queries.sort_unstable();
+    let mutations: Vec<Vec<Mutation>> = queries
+        .par_iter()
+        .map(|id| {
+            let query = &sequences[*id];
+            call(&align_pair(query, reference, matcher), query, reference)
+        })
+        .collect();
+
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "query_id\treference_id\tmutations\tcount")?;
+    for (id, mutations) in queries.iter().zip(&mutations) {
+        let (query_name, reference_name) = match names {
+            Some(map) => (map.name(id), map.name(reference_id)),
+            None => (id.as_str(), reference_id),
+        };
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}",
+            query_name,
+            reference_name,
+            format_list(mutations),
+            mutations.len()
+        )?;
+    }
+    out.flush()?;
+    Ok(queries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ScoringType;
+
+    #[test]
+    fn test_call_mutations() {
+        let reference = "MKTAYIAKQRQISFVKSHFSRQLEERLGLIEVQAPILSRVGDGTQDNLSGAEKAVQVKVKA";
+        let matcher = ScoringType::Identity.matcher();
+        let mutations =
+            |query: &str| call(&align_pair(query, reference, &matcher), query, reference);
+
+        let deletion = mutations("MKTAWIAKQRQISFVKSHFSLEERLGLIEVQAPILSRVGDGTQDNLSGAEKAVQVKVKA");
+        assert_eq!(format_list(&deletion), "Y5W,del21-22");
+        assert_eq!(deletion[1], Mutation::Deletion { start: 21, end: 22 });
+        let insertion =
+            mutations("MKTAYIAKQRQISFVKSHFSRQLEERLGLIEVQAPILSRVWWGDGTQDNLSGAEKAVQVKVKA");
+        assert_eq!(format_list(&insertion), "ins40-41WW");
+        assert_eq!(format_list(&mutations(reference)), "WT");
+    }
+}
@@ -0,0 +1,225 @@
+//! Edit distances with Myers' bit-parallel algorithm.
+//!
+//! When only the number of differences between two sequences matters, not a
+//! substitution matrix score, `--engine myers` computes their edit distance: the
+//! fewest substitutions, insertions and deletions turning one into the other. Myers'
+//! algorithm (1999), in the multi-word form of Hyyrö (2003), keeps the differences
+//! between adjacent cells of a column of the dynamic programming matrix as bits of
+//! machine words, so every residue of the longer sequence updates 64 cells at once
+//! with a handful of word operations.
+//!
+//! Global alignments give the edit distance of the whole sequences, semi-global
+//! ones that of the shorter sequence to its best match within the longer one and
+//! overlap alignments ignore the unaligned ends of both. Local alignments would
+//! always have a distance of zero and are not supported. Distances are reported as
+//! negative scores, so that pairs with fewer differences still score higher.
+
+use std::time::Instant;
+
+use crate::align::AlignMode;
+use crate::packed::PackedSeq;
+
+/// Number of residues of the longer sequence between checks of the deadline
+const DEADLINE_INTERVAL: usize = 256;
+
+/// Returns the edit distance of two sequences in `mode`, or `None` if `deadline`
+/// passed before it was computed
+///
+/// # Panics
+///
+/// Panics if `mode` is [`AlignMode::Local`].
+pub fn edit_distance(
+    seq1: &[u8],
+    seq2: &[u8],
+    mode: AlignMode,
+    deadline: Option<Instant>,
+) -> Option<u32> {
+    distance_with(
+        (seq1.len(), |i| seq1[i]),
+        (seq2.len(), |j| seq2[j]),
+        mode,
+        deadline,
+    )
+}
+
+/// Returns the edit distance of two packed sequences like [`edit_distance`],
+/// decoding every residue as it is reached
+pub fn edit_distance_packed(
+    seq1: &PackedSeq,
+    seq2: &PackedSeq,
+    mode: AlignMode,
+    deadline: Option<Instant>,
+) -> Option<u32> {
+    distance_with(
+        (seq1.len(), |i| seq1.get(i)),
+        (seq2.len(), |j| seq2.get(j)),
+        mode,
+        deadline,
+    )
+}
+
+/// Computes the edit distance of sequences given by their length and a function
+/// returning their residues. The shorter sequence is the pattern whose residues
+/// are the bits of the words, the longer one is walked residue by residue.
+fn distance_with(
+    x: (usize, impl Fn(usize) -> u8),
+    y: (usize, impl Fn(usize) -> u8),
+    mode: AlignMode,
+    deadline: Option<Instant>,
+) -> Option<u32> {
+    assert!(
+        mode != AlignMode::Local,
+        "local alignments have no edit distance"
+    );
+    // The ends of the longer sequence are the free ones of semi-global alignments
+    if x.0 <= y.0 {
+        scan(x, y, mode, deadline)
+    } else {
+        scan(y, x, mode, deadline)
+    }
+}
+
+/// Advances one word of a column by a residue of the text, given the equality
+/// bits `eq` of the residue and the difference `carry` of the cells below the
+/// word in the previous column. Returns the difference passed to the next word.
+#[inline]
+fn advance(pv: &mut u64, mv: &mut u64, eq: u64, carry: i32) -> i32 {
+    let negative = u64::from(carry < 0);
+    let xv = eq | *mv;
+    let eq = eq | negative;
+    let xh = ((eq & *pv).wrapping_add(*pv) ^ *pv) | eq;
+    let mut ph = *mv | !(xh | *pv);
+    let mut mh = *pv & xh;
+    let out = (ph >> 63) as i32 - (mh >> 63) as i32;
+    ph = (ph << 1) | u64::from(carry > 0);
+    mh = (mh << 1) | negative;
+    *pv = mh | !(xv | ph);
+    *mv = ph & xv;
+    out
+}
+
+fn scan(
+    (m, pattern): (usize, impl Fn(usize) -> u8),
+    (n, text): (usize, impl Fn(usize) -> u8),
+    mode: AlignMode,
+    deadline: Option<Instant>,
+) -> Option<u32> {
+    // Gaps before the pattern are free in semi-global and overlap alignments,
+    // gaps before the text only in overlap alignments
+    let free_text = mode != AlignMode::Global;
+    let free_pattern = mode == AlignMode::Overlap;
+    if m == 0 {
+        return Some(if free_text { 0 } else { n as u32 });
+    }
+
+    // Residues of the pattern are numbered in order of appearance; residues of the
+    // text missing from it match nothing
+    let words = m.div_ceil(64);
+    let mut index = [0usize; 256];
+    let mut peq = vec![0u64; words];
+    for i in 0..m {
+        let residue = pattern(i) as usize;
+        if index[residue] == 0 {
+            index[residue] = peq.len() / words;
+            peq.resize(peq.len() + words, 0);
+        }
+        peq[index[residue] * words + i / 64] |= 1 << (i % 64);
+    }
+
+    // Vertical differences of the cells of the current column, one bit per row,
+    // and the score of the last row of every word
+    let first = if free_pattern { 0 } else { u64::MAX };
+    let mut pv = vec![first; words];
+    let mut mv = vec![0u64; words];
+    let mut bottom: Vec<i64> = (1..=words)
+        .map(|w| if free_pattern { 0 } else { 64 * w as i64 })
+        .collect();
+    // Rows past the end of the pattern in the last word
+    let padding = match m % 64 {
+        0 => 0,
+        rows => u64::MAX << rows,
+    };
+    let last_row = |pv: &[u64], mv: &[u64], bottom: &[i64]| {
+        let (pv, mv) = (pv[words - 1] & padding, mv[words - 1] & padding);
+        bottom[words - 1] - i64::from(pv.count_ones()) + i64::from(mv.count_ones())
+    };
+
+    let mut best = last_row(&pv, &mv, &bottom);
+    for j in 0..n {
+        let eq = &peq[index[text(j) as usize] * words..][..words];
+        let mut carry = i32::from(!free_text);
+        for w in 0..words {
+            carry = advance(&mut pv[w], &mut mv[w], eq[w], carry);
+            bottom[w] += i64::from(carry);
+        }
+        if free_text {
+            best = best.min(last_row(&pv, &mv, &bottom));
+        }
+        if j % DEADLINE_INTERVAL == 0 && deadline.is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return None;
+        }
+    }
+    if !free_text {
+        best = last_row(&pv, &mv, &bottom);
+    }
+    if free_pattern {
+        // Gaps after the text are free as well, so the best cell of the last column
+        // counts, found by adding up its differences from the top
+        let mut score = 0i64;
+        for (w, (&pv, &mv)) in pv.iter().zip(&mv).enumerate() {
+            for bit in 0..(m - 64 * w).min(64) {
+                score += i64::from((pv >> bit & 1) as u8) - i64::from((mv >> bit & 1) as u8);
+                best = best.min(score);
+            }
+        }
+    }
+    Some(best as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::align::{GapPenalties, MatcherFn, align_linear};
+
+    #[test]
+    fn test_edit_distance() {
+        // A unit-cost alignment scores minus the edit distance
+        let unit: MatcherFn = |a, b| if a == b { 0 } else { -1 };
+        let gaps = GapPenalties {
+            open: 0,
+            extend: -1,
+        };
+        let long = "MKTAYIAKQRQISFVKSHFSRQ".repeat(4) + "WWGPNDCHYE";
+        let pairs = [
+            ("kitten", "sitting"),
+            ("MKTAYIAKQRQISFVKSHF", "AYIAKQ"),
+            ("", "MAVMT"),
+            ("GATTACA", "GATTACA"),
+            (long.as_str(), "MKTAYIAKQRQISFVKSHFSRQWWGPNDCHYE"),
+            (long.as_str(), &long[3..90]),
+        ];
+        for mode in [AlignMode::Global, AlignMode::Semiglobal, AlignMode::Overlap] {
+            for (seq1, seq2) in pairs {
+                for (x, y) in [(seq1, seq2), (seq2, seq1)] {
+                    let expected = -align_linear(x, y, &unit, mode, gaps);
+                    let distance = edit_distance(x.as_bytes(), y.as_bytes(), mode, None);
+                    assert_eq!(distance, Some(expected as u32), "{:?} {} {}", mode, x, y);
+                }
+            }
+        }
+        assert_eq!(
+            edit_distance(b"kitten", b"sitting", AlignMode::Global, None),
+            Some(3)
+        );
+
+        let (x, y) = (
+            PackedSeq::pack("ACGTTGCAACGT").unwrap(),
+            PackedSeq::pack("ACGTGCAACGGT").unwrap(),
+        );
+        assert_eq!(
+            edit_distance_packed(&x, &y, AlignMode::Global, None),
+            edit_distance(b"ACGTTGCAACGT", b"ACGTGCAACGGT", AlignMode::Global, None)
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! Pairwise alignments in the text layout of EMBOSS needle.
+//!
+//! For inspecting hits by hand, `realign --needle` writes every alignment the way
+//! EMBOSS `needle` does with its default `srspair` format: a commented block with
+//! the length, identity, similarity, gaps and score of the alignment, followed by
+//! the aligned sequences in lines of 50 columns with their residue positions and
+//! a markup line between them. The markup has `|` for identical residues, `:` for
+//! residues with a positive score, `.` for residues with a score of zero and a
+//! space for other residues and gaps. Tools reading needle output, such as
+//! Biopython's `AlignIO` with the `emboss` format, read it as well.
+
+use bio::alignment::AlignmentOperation;
+use std::io::{self, Write};
+
+use crate::align::{GAP_EXTEND, GAP_OPEN, MatcherFn};
+use crate::pair::align_pair;
+
+/// Number of alignment columns per line, as in EMBOSS
+const LINE_WIDTH: usize = 50;
+
+/// Width names are padded or cut to at the start of sequence lines
+const NAME_WIDTH: usize = 13;
+
+/// Line of `#` opening and closing the header of a file
+const HEADER_RULE: &str = "########################################";
+
+/// Line of `=` enclosing the summary of an alignment
+const SUMMARY_RULE: &str = "#=======================================";
+
+/// Line of `-` closing a file
+const FOOTER_RULE: &str = "#---------------------------------------";
+
+/// Writes the header of a file of alignments
+///
+/// # Errors
+///
+/// Returns an error if writing fails.
+pub fn write_header(out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "{}", HEADER_RULE)?;
+    writeln!(out, "# Program: aligner")?;
+    writeln!(out, "# Align_format: srspair")?;
+    writeln!(out, "{}", HEADER_RULE)?;
+    writeln!(out)
+}
+
+/// Writes the footer of a file of alignments
+///
+/// # Errors
+///
+/// Returns an error if writing fails.
+pub fn write_footer(out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "{}", FOOTER_RULE)?;
+    writeln!(out, "{}", FOOTER_RULE)
+}
+
+/// Aligns two identified sequences and writes the summary and alignment.
+/// `matrix` names the scoring matrix in the summary, e.g. `EBLOSUM62`.
+///
+/// # Errors
+///
+/// Returns an error if writing fails.
+pub fn write_alignment(
+    out: &mut impl Write,
+    (id1, seq1): (&str, &str),
+    (id2, seq2): (&str, &str),
+    matcher: &MatcherFn,
+    matrix: &str,
+) -> io::Result<()> {
+    let alignment = align_pair(seq1, seq2, matcher);
+    let (seq1, seq2) = (seq1.as_bytes(), seq2.as_bytes());
+
+    // Aligned rows and markup, with the residues of both sequences in every column
+    let (mut row1, mut markup, mut row2) = (Vec::new(), Vec::new(), Vec::new());
+    let (mut x, mut y) = (alignment.xstart, alignment.ystart);
+    let (mut identical, mut similar, mut gaps) = (0, 0, 0);
+    for op in &alignment.operations {
+        match op {
+            AlignmentOperation::Match | AlignmentOperation::Subst => {
+                let (a, b) = (seq1[x], seq2[y]);
+                let mark = if a.eq_ignore_ascii_case(&b) {
+                    identical += 1;
+                    similar += 1;
+                    b'|'
+                } else {
+                    match matcher(a, b) {
+                        score if score > 0 => {
+                            similar += 1;
+                            b':'
+                        }
+                        0 => b'.',
+                        _ => b' ',
+                    }
+                };
+                row1.push(a);
+                markup.push(mark);
+                row2.push(b);
+                x += 1;
+                y += 1;
+            }
+            AlignmentOperation::Ins => {
+                row1.push(seq1[x]);
+                markup.push(b' ');
+                row2.push(b'-');
+                gaps += 1;
+                x += 1;
+            }
+            AlignmentOperation::Del => {
+                row1.push(b'-');
+                markup.push(b' ');
+                row2.push(seq2[y]);
+                gaps += 1;
+                y += 1;
+            }
+            AlignmentOperation::Xclip(n) => x += n,
+            AlignmentOperation::Yclip(n) => y += n,
+        }
+    }
+    let length = row1.len();
+    let percent = |count: usize| {
+        if length == 0 {
+            0.0
+        } else {
+            100.0 * count as f64 / length as f64
+        }
+    };
+
+    writeln!(out, "{}", SUMMARY_RULE)?;
+    writeln!(out, "#")?;
+    writeln!(out, "# Aligned_sequences: 2")?;
+    writeln!(out, "# 1: {}", id1)?;
+    writeln!(out, "# 2: {}", id2)?;
+    writeln!(out, "# Matrix: {}", matrix)?;
+    // EMBOSS charges the gap penalty for the first residue of a gap and the
+    // extension penalty for every further one
+    writeln!(out, "# Gap_penalty: {:.1}", -(GAP_OPEN + GAP_EXTEND) as f64)?;
+    writeln!(out, "# Extend_penalty: {:.1}", -GAP_EXTEND as f64)?;
+    writeln!(out, "#")?;
+    writeln!(out, "# Length: {}", length)?;
+    for (label, count) in [
+        ("Identity", identical),
+        ("Similarity", similar),
+        ("Gaps", gaps),
+    ] {
+        let fraction = format!("{}/{}", count, length);
+        writeln!(
+            out,
+            "# {:<14}{:>7} ({:.1}%)",
+            format!("{}:", label),
+            fraction,
+            percent(count)
+        )?;
+    }
+    writeln!(out, "# Score: {:.1}", alignment.score as f64)?;
+    writeln!(out, "#")?;
+    writeln!(out, "#")?;
+    writeln!(out, "{}", SUMMARY_RULE)?;
+    writeln!(out)?;
+
+    let name = |id: &str| id.chars().take(NAME_WIDTH).collect::<String>();
+    let (name1, name2) = (name(id1), name(id2));
+    // Positions of the last residue written of each sequence
+    let (mut end1, mut end2) = (alignment.xstart, alignment.ystart);
+    let line = |out: &mut dyn Write, name: &str, row: &[u8], last: &mut usize| {
+        let residues = row.iter().filter(|residue| **residue != b'-').count();
+        let first = *last + usize::from(residues > 0);
+        *last += residues;
+        writeln!(
+            out,
+            "{:<NAME_WIDTH$} {:>6} {} {:>6}",
+            name,
+            first,
+            String::from_utf8_lossy(row),
+            last
+        )
+    };
+    for start in (0..length).step_by(LINE_WIDTH) {
+        let end = (start + LINE_WIDTH).min(length);
+        line(out, &name1, &row1[start..end], &mut end1)?;
+        writeln!(
+            out,
+            "{:NAME_WIDTH$} {:6} {}",
+            "",
+            "",
+            String::from_utf8_lossy(&markup[start..end])
+        )?;
+        line(out, &name2, &row2[start..end], &mut end2)?;
+        writeln!(out)?;
+    }
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ScoringType;
+
+    #[test]
+    fn test_needle_alignment() {
+        let mut out = Vec::new();
+        write_alignment(
+            &mut out,
+            ("a", "MAVMTKL"),
+            ("b", "MAVMKL"),
+            &ScoringType::Identity.matcher(),
+            "identity",
+        )
+        .unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("# Length: 7\n"));
+        assert!(text.contains("# Identity:         6/7 (85.7%)\n"));
+        assert!(text.contains("# Gaps:             1/7 (14.3%)\n"));
+        assert!(text.contains(
+            "a                  1 MAVMTKL      7\n                     |||| ||\nb                  1 MAVM-KL      6\n"
+        ));
+    }
+}
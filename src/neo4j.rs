@@ -0,0 +1,166 @@
+//! Writing alignment results into a Neo4j graph.
+//!
+//! PyEED keeps its proteins as `Protein` nodes of a Neo4j database. Instead of
+//! importing a results file afterwards, [`Neo4jSink`] connects the nodes of every
+//! aligned pair with a `PAIRWISE_ALIGNED` relationship as the results arrive. The
+//! rows of a batch are sent to the transactional HTTP API of the server in a single
+//! request, which commits them in one transaction.
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde_json::{Value, json};
+use std::io;
+
+use crate::align::{AlignmentResult, PairStatus, Score};
+use crate::error::AlignerError;
+use crate::sink::ResultSink;
+
+/// Indexes `Protein` nodes by their `accession_id`, the sequence identifier in
+/// PyEED
+const CREATE_INDEX: &str = "CREATE INDEX protein_accession_id IF NOT EXISTS \
+     FOR (protein:Protein) ON (protein.accession_id)";
+
+/// Merges the nodes of every row and their relationship, replacing the score and
+/// identity of a relationship written by an earlier run
+const MERGE_EDGES: &str = "UNWIND $rows AS row \
+     MERGE (query:Protein {accession_id: row.query_id}) \
+     MERGE (subject:Protein {accession_id: row.subject_id}) \
+     MERGE (query)-[edge:PAIRWISE_ALIGNED]->(subject) \
+     SET edge.score = row.score, edge.identity = row.identity";
+
+/// Writes aligned pairs as `(:Protein)-[:PAIRWISE_ALIGNED {score, identity}]->(:Protein)`
+/// relationships, creating missing nodes. Pairs that were not aligned are left
+/// out, as is the identity where it is not computed.
+pub struct Neo4jSink {
+    /// Endpoint committing a transaction per request
+    endpoint: String,
+    /// Value of the `Authorization` header, if credentials are given
+    authorization: Option<String>,
+}
+
+impl Neo4jSink {
+    /// Creates a sink writing to the database `database` of the Neo4j server at
+    /// `uri`, e.g. `http://localhost:7474`, authenticating as `user` if a password
+    /// is given.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `uri` is not an HTTP(S) URI, such as a Bolt URI.
+    pub fn new(
+        uri: &str,
+        database: &str,
+        user: &str,
+        password: Option<&str>,
+    ) -> Result<Self, AlignerError> {
+        if !uri.starts_with("http://") && !uri.starts_with("https://") {
+            return Err(AlignerError::Config(format!(
+                "Neo4j URI {} is not an HTTP(S) URI; results are written through the HTTP API, e.g. http://localhost:7474",
+                uri
+            )));
+        }
+        Ok(Self {
+            endpoint: format!("{}/db/{}/tx/commit", uri.trim_end_matches('/'), database),
+            authorization: password.map(|password| {
+                format!(
+                    "Basic {}",
+                    STANDARD.encode(format!("{}:{}", user, password))
+                )
+            }),
+        })
+    }
+
+    /// Runs `statement` with `parameters` in a transaction of its own
+    fn commit(&self, statement: &str, parameters: Value) -> io::Result<()> {
+        let body = json!({
+            "statements": [{ "statement": statement, "parameters": parameters }]
+        });
+        let mut request = ureq::post(&self.endpoint)
+            .set("Content-Type", "application/json")
+            .set("Accept", "application/json");
+        if let Some(authorization) = &self.authorization {
+            request = request.set("Authorization", authorization);
+        }
+        let response = request
+            .send_string(&body.to_string())
+            .map_err(io::Error::other)?;
+        let response: Value = serde_json::from_reader(response.into_reader())?;
+        // The server answers failed statements with a list of errors and status 200
+        match response["errors"]
+            .as_array()
+            .and_then(|errors| errors.first())
+        {
+            Some(error) => Err(io::Error::other(format!(
+                "Neo4j error {}: {}",
+                error["code"].as_str().unwrap_or_default(),
+                error["message"].as_str().unwrap_or_default()
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parameters of [`MERGE_EDGES`] for the aligned pairs among `results`
+fn edge_rows<S: Score>(results: &[AlignmentResult<S>]) -> Vec<Value> {
+    results
+        .iter()
+        .filter(|result| result.status == PairStatus::Aligned)
+        .filter_map(|result| {
+            Some(json!({
+                "query_id": result.query_id,
+                "subject_id": result.subject_id,
+                "score": result.score?,
+                "identity": result.identity,
+            }))
+        })
+        .collect()
+}
+
+impl<S: Score> ResultSink<S> for Neo4jSink {
+    /// Makes sure `Protein` nodes are looked up by an index
+    fn open(&mut self) -> io::Result<()> {
+        self.commit(CREATE_INDEX, json!({}))
+    }
+
+    fn write_batch(&mut self, results: &[AlignmentResult<S>]) -> io::Result<()> {
+        let rows = edge_rows(results);
+        if rows.is_empty() {
+            return Ok(());
+        }
+        self.commit(MERGE_EDGES, json!({ "rows": rows }))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Every batch is committed when it is written
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edge_rows() {
+        let result = |subject_id: &str, score, status| AlignmentResult::<i32> {
+            identity: Some(0.5),
+            ..AlignmentResult::for_test("a", subject_id, score, status)
+        };
+        let rows = edge_rows(&[
+            result("b", Some(3), PairStatus::Aligned),
+            result("c", None, PairStatus::Skipped),
+        ]);
+        assert_eq!(
+            rows,
+            [json!({ "query_id": "a", "subject_id": "b", "score": 3, "identity": 0.5 })]
+        );
+
+        assert!(Neo4jSink::new("bolt://localhost:7687", "neo4j", "neo4j", None).is_err());
+        let sink =
+            Neo4jSink::new("http://localhost:7474/", "neo4j", "neo4j", Some("secret")).unwrap();
+        assert_eq!(sink.endpoint, "http://localhost:7474/db/neo4j/tx/commit");
+        assert_eq!(
+            sink.authorization.as_deref(),
+            Some("Basic bmVvNGo6c2VjcmV0")
+        );
+    }
+}
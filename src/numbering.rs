@@ -0,0 +1,241 @@
+//! Projection of residue numbering onto a reference.
+//!
+//! Homologous residues get different positions in sequences of different
+//! lengths. The `numbering` subcommand aligns every query globally with a
+//! reference sequence and numbers the query residues by the reference residues
+//! they are aligned with, so residues can be compared by one canonical scheme
+//! across a family. By default reference residues are numbered from 1; a scheme
+//! file can give them any labels instead, e.g. the numbering of a mature protein
+//! or the Ambler numbering of β-lactamases. Query residues inserted relative to
+//! the reference have no reference label.
+
+use bio::alignment::{Alignment, AlignmentOperation};
+use indicatif::ParallelProgressIterator;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::ScoringType;
+use crate::error::AlignerError;
+use crate::pair::{align_pair, normalize, read_single};
+use crate::utils::{parse_input, setup_progress_bar};
+use crate::validate::{check_ascii, check_lengths};
+
+/// Command-line arguments for the `numbering` subcommand
+#[derive(clap::Args, Debug)]
+pub struct NumberingArgs {
+    /// Input file with the sequences to number, in any supported input format
+    input: PathBuf,
+
+    /// Input file with the reference sequence, the only sequence of the file
+    #[arg(long)]
+    reference: PathBuf,
+
+    /// File with the label of every reference residue, one per line in the order
+    /// of the reference, in the first tab-separated column; residues are numbered
+    /// from 1 by default
+    #[arg(long)]
+    scheme: Option<PathBuf>,
+
+    /// Path of the tab-separated table with a row per query residue
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Scoring type to use for alignment
+    #[arg(short, long, value_enum, default_value_t = ScoringType::Identity)]
+    scoring: ScoringType,
+}
+
+/// Labels of the residues of a reference sequence
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scheme {
+    labels: Vec<String>,
+}
+
+impl Scheme {
+    /// Numbers the residues of a reference of length `len` from 1
+    pub fn sequential(len: usize) -> Self {
+        Self {
+            labels: (1..=len).map(|position| position.to_string()).collect(),
+        }
+    }
+
+    /// Reads the labels of the residues of a reference of length `len` from the
+    /// first column of every non-empty line of a file.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AlignerError::InvalidInput` if the file does not have a label for
+    /// every residue or repeats a label, and the errors of reading the file.
+    pub fn read(path: &Path, len: usize) -> Result<Self, AlignerError> {
+        let text = fs::read_to_string(path)?;
+        let labels: Vec<String> = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                line.split('\t')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string()
+            })
+            .collect();
+        if labels.len() != len {
+            return Err(AlignerError::InvalidInput(format!(
+                "{} has {} labels, expected one for each of the {} reference residues",
+                path.display(),
+                labels.len(),
+                len
+            )));
+        }
+        let mut seen = HashMap::new();
+        for (i, label) in labels.iter().enumerate() {
+            if let Some(first) = seen.insert(label.as_str(), i) {
+                return Err(AlignerError::InvalidInput(format!(
+                    "{}: label '{}' of residue {} repeats residue {}",
+                    path.display(),
+                    label,
+                    i + 1,
+                    first + 1
+                )));
+            }
+        }
+        Ok(Self { labels })
+    }
+
+    /// Returns the label of the reference residue with zero-based index `i`
+    pub fn label(&self, i: usize) -> &str {
+        &self.labels[i]
+    }
+
+    /// Returns the zero-based index of the reference residue with a label
+    pub fn position(&self, label: &str) -> Option<usize> {
+        self.labels.iter().position(|l| l == label)
+    }
+}
+
+/// Returns the zero-based index of the reference residue every query residue is
+/// aligned with in an alignment of the query (x) with the reference (y), `None`
+/// for query residues aligned with a gap
+pub fn project(alignment: &Alignment) -> Vec<Option<usize>> {
+    let mut positions = Vec::with_capacity(alignment.xlen);
+    positions.resize(alignment.xstart, None);
+    let mut y = alignment.ystart;
+    for op in &alignment.operations {
+        match op {
+            AlignmentOperation::Match | AlignmentOperation::Subst => {
+                positions.push(Some(y));
+                y += 1;
+            }
+            AlignmentOperation::Ins => positions.push(None),
+            AlignmentOperation::Del => y += 1,
+            AlignmentOperation::Xclip(n) => positions.extend(std::iter::repeat_n(None, *n)),
+            AlignmentOperation::Yclip(n) => y += n,
+        }
+    }
+    positions.resize(alignment.xlen, None);
+    positions
+}
+
+/// Reads the only sequence of a reference file, with whitespace removed and in
+/// upper case.
+///
+/// # Errors
+///
+/// Returns `AlignerError::InvalidInput` if the file does not contain exactly one
+/// sequence or it contains non-ASCII characters, and the errors of reading it.
+pub fn read_reference(path: &Path) -> Result<(String, String), AlignerError> {
+    let (id, sequence) = read_single(path)?;
+    let sequence = normalize(&id, &sequence)?;
+    Ok((id, sequence))
+}
+
+/// Runs the `numbering` subcommand.
+///
+/// # Errors
+///
+/// Returns an error if an input cannot be read or is invalid, or the output
+/// cannot be written.
+pub fn run(args: NumberingArgs) -> Result<(), AlignerError> {
+    let mut queries = parse_input(&args.input)?;
+    check_ascii(&mut queries, None)?;
+    check_lengths(&queries, None)?;
+    let (reference_id, reference) = read_reference(&args.reference)?;
+    let scheme = match &args.scheme {
+        Some(path) => Scheme::read(path, reference.len())?,
+        None => Scheme::sequential(reference.len()),
+    };
+    info!(
+        queries = queries.len(),
+        reference = %reference_id,
+        "numbering sequences"
+    );
+
+    let mut queries: Vec<(&String, &String)> = queries.iter().collect();
+    queries.sort_unstable();
+    let matcher = args.scoring.matcher();
+    let progress = setup_progress_bar(queries.len() as u64);
+    let projections: Vec<Vec<Option<usize>>> = queries
+        .par_iter()
+        .progress_with(progress)
+        .map(|(_, query)| project(&align_pair(query, &reference, &matcher)))
+        .collect();
+
+    let mut out = BufWriter::new(File::create(&args.output)?);
+    writeln!(
+        out,
+        "query_id\tquery_position\tquery_residue\treference_label\treference_residue"
+    )?;
+    let mut numbered = 0;
+    for ((query_id, query), positions) in queries.iter().zip(&projections) {
+        for (i, (residue, position)) in query.chars().zip(positions).enumerate() {
+            numbered += usize::from(position.is_some());
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}",
+                query_id,
+                i + 1,
+                residue,
+                position.map_or("", |p| scheme.label(p)),
+                position.map_or('-', |p| char::from(reference.as_bytes()[p]))
+            )?;
+        }
+    }
+    out.flush()?;
+    let residues: usize = queries.iter().map(|(_, query)| query.len()).sum();
+    eprintln!(
+        "Numbered {} of {} residues of {} sequences by {}",
+        numbered,
+        residues,
+        queries.len(),
+        reference_id
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project() {
+        let reference = "MKTAYIAKQRQISFVKSHFSRQLEERLGLIEVQAPILSRVGDGTQDNLSGAEKAVQVKVKA";
+        let matcher = ScoringType::Identity.matcher();
+        let positions = |query: &str| project(&align_pair(query, reference, &matcher));
+
+        // Deletion of reference residues 21-22
+        let deletion = positions("MKTAYIAKQRQISFVKSHFSLEERLGLIEVQAPILSRVGDGTQDNLSGAEKAVQVKVKA");
+        assert_eq!(deletion.len(), reference.len() - 2);
+        assert_eq!(&deletion[19..21], [Some(19), Some(22)]);
+        // Insertion after reference residue 40
+        let insertion =
+            positions("MKTAYIAKQRQISFVKSHFSRQLEERLGLIEVQAPILSRVWWGDGTQDNLSGAEKAVQVKVKA");
+        assert_eq!(&insertion[39..43], [Some(39), None, None, Some(40)]);
+
+        let scheme = Scheme::sequential(reference.len());
+        assert_eq!(scheme.label(22), "23");
+    }
+}
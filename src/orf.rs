@@ -0,0 +1,144 @@
+//! Open reading frames of nucleotide sequences.
+//!
+//! With `--find-orfs`, nucleotide input such as assembled contigs is replaced by
+//! the proteins it encodes before any alignment, so raw contigs can be compared
+//! with protein sequences directly. All six reading frames are scanned for open
+//! reading frames from a start codon to the next stop codon of the genetic code
+//! selected with `--gencode`, taking the first start codon after the previous
+//! stop codon of the frame. Reading frames of at least `--min-orf-len`
+//! nucleotides, stop codon included, are translated with the same code, with
+//! methionine for the start codon. Every protein is identified by its sequence
+//! and the one-based coordinates of its reading frame on the forward strand,
+//! e.g. `contig1:1201-1650:-` for a reading frame on the reverse strand.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::translate::{GeneticCode, reverse_complement};
+
+/// An open reading frame of a nucleotide sequence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Orf {
+    /// One-based first and last nucleotide of the reading frame on the forward
+    /// strand, stop codon included
+    pub start: usize,
+    pub end: usize,
+    /// Whether the reading frame is on the reverse strand
+    pub reverse: bool,
+    /// Translation of the reading frame, without the stop codon
+    pub protein: String,
+}
+
+impl Orf {
+    /// Returns the identifier of the reading frame of a sequence
+    pub fn id(&self, sequence_id: &str) -> String {
+        format!(
+            "{}:{}-{}:{}",
+            sequence_id,
+            self.start,
+            self.end,
+            if self.reverse { '-' } else { '+' }
+        )
+    }
+}
+
+/// Returns the open reading frames of at least `min_len` nucleotides of a
+/// sequence with a genetic code, on the forward strand first and by position
+pub fn find(sequence: &str, min_len: usize, code: &GeneticCode) -> Vec<Orf> {
+    let forward = sequence.to_ascii_uppercase().replace('U', "T");
+    let reverse = reverse_complement(&forward);
+    let mut orfs = Vec::new();
+    for (strand, is_reverse) in [(&forward, false), (&reverse, true)] {
+        let mut found = Vec::new();
+        for frame in 0..3 {
+            let codons = strand.as_bytes().get(frame..).unwrap_or_default();
+            let mut start = None;
+            for (i, codon) in codons.chunks_exact(3).enumerate() {
+                if code.is_stop(codon) {
+                    if let Some(first) = start.take() {
+                        found.push((frame + 3 * first, frame + 3 * i + 3));
+                    }
+                } else if start.is_none() && code.is_start(codon) {
+                    start = Some(i);
+                }
+            }
+        }
+        found.retain(|(from, to)| to - from >= min_len);
+        found.sort_unstable();
+        orfs.extend(found.into_iter().map(|(from, to)| {
+            let protein = code.translate_cds(&strand[from..to]);
+            let (start, end) = if is_reverse {
+                (strand.len() - to + 1, strand.len() - from)
+            } else {
+                (from + 1, to)
+            };
+            Orf {
+                start,
+                end,
+                reverse: is_reverse,
+                protein,
+            }
+        }));
+    }
+    orfs
+}
+
+/// Replaces nucleotide sequences by the proteins of their open reading frames of
+/// at least `min_len` nucleotides with a genetic code, and the identifiers of the sequences in
+/// `sides` by those of their proteins. Returns the number of sequences without
+/// any reading frame.
+pub fn translate_all(
+    input: &mut HashMap<String, String>,
+    min_len: usize,
+    code: &GeneticCode,
+    sides: Option<&mut (HashSet<String>, HashSet<String>)>,
+) -> usize {
+    let mut proteins = HashMap::new();
+    let mut sources: HashMap<String, Vec<String>> = HashMap::new();
+    let mut empty = 0;
+    for (id, sequence) in input.drain() {
+        let orfs = find(&sequence, min_len, code);
+        empty += usize::from(orfs.is_empty());
+        let ids = sources.entry(id.clone()).or_default();
+        for orf in orfs {
+            let orf_id = orf.id(&id);
+            ids.push(orf_id.clone());
+            proteins.insert(orf_id, orf.protein);
+        }
+    }
+    *input = proteins;
+    if let Some((queries, targets)) = sides {
+        for ids in [queries, targets] {
+            *ids = std::mem::take(ids)
+                .iter()
+                .flat_map(|id| sources.get(id).into_iter().flatten().cloned())
+                .collect();
+        }
+    }
+    empty
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_orfs() {
+        // MKTAYIAK* in the second frame, and its reverse complement downstream
+        let orf = "ATGAAAACCGCGTATATTGCGAAATAA";
+        let sequence = format!("C{}GG{}", orf, reverse_complement(orf));
+        let orfs = find(&sequence, 27, GeneticCode::STANDARD);
+        assert_eq!(orfs.len(), 2);
+        assert_eq!(
+            (orfs[0].start, orfs[0].end, orfs[0].reverse),
+            (2, 28, false)
+        );
+        assert_eq!(orfs[0].protein, "MKTAYIAK");
+        assert_eq!(orfs[0].id("contig"), "contig:2-28:+");
+        assert_eq!(
+            (orfs[1].start, orfs[1].end, orfs[1].reverse),
+            (31, 57, true)
+        );
+        assert_eq!(orfs[1].protein, "MKTAYIAK");
+        assert!(find(&sequence, 30, GeneticCode::STANDARD).is_empty());
+    }
+}
@@ -0,0 +1,236 @@
+//! Sequences packed into a few bits per residue.
+//!
+//! Genome-scale inputs spend most of their memory on the residues, one byte each.
+//! With `--pack-sequences`, nucleotide sequences of `A`, `C`, `G` and `T` are kept
+//! in 2 bits per base and protein sequences in 5 bits per residue, a quarter and
+//! about two thirds of their size. Sequences with other characters, e.g. `N` or
+//! lowercase residues, are kept as they are.
+//!
+//! Engines declaring [`Capabilities::packed`](crate::engine::Capabilities) decode
+//! the residues of a pair as their kernel reaches them; all other engines, and
+//! whatever else needs the residues of a pair, e.g. pre-filters, unpack the pair
+//! while it is aligned. Decoding makes the alignments slower, so packing pays off
+//! where memory rather than time limits a run.
+
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+/// Residues a sequence is packed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Alphabet {
+    /// `ACGT`, 2 bits per base
+    Nucleotide,
+    /// The amino acids with ambiguity codes, stops and gaps, 5 bits per residue
+    Protein,
+}
+
+const NUCLEOTIDES: &[u8] = b"ACGT";
+const AMINO_ACIDS: &[u8] = b"ACDEFGHIKLMNPQRSTVWYBJOUXZ*-";
+
+/// Returns the table from characters to their codes in `symbols`
+const fn codes(symbols: &[u8]) -> [u8; 256] {
+    let mut table = [u8::MAX; 256];
+    let mut code = 0;
+    while code < symbols.len() {
+        table[symbols[code] as usize] = code as u8;
+        code += 1;
+    }
+    table
+}
+
+const NUCLEOTIDE_CODES: [u8; 256] = codes(NUCLEOTIDES);
+const AMINO_ACID_CODES: [u8; 256] = codes(AMINO_ACIDS);
+
+impl Alphabet {
+    fn codes(self) -> &'static [u8; 256] {
+        match self {
+            Self::Nucleotide => &NUCLEOTIDE_CODES,
+            Self::Protein => &AMINO_ACID_CODES,
+        }
+    }
+
+    fn bits(self) -> u32 {
+        match self {
+            Self::Nucleotide => 2,
+            Self::Protein => 5,
+        }
+    }
+
+    /// Number of residues in a word; residues never straddle two words
+    fn per_word(self) -> usize {
+        (u64::BITS / self.bits()) as usize
+    }
+}
+
+/// A sequence packed into a few bits per residue
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackedSeq {
+    alphabet: Alphabet,
+    len: usize,
+    words: Box<[u64]>,
+}
+
+impl PackedSeq {
+    /// Packs `seq` with the smallest alphabet holding all its residues, or returns
+    /// `None` if it has residues of neither alphabet
+    pub fn pack(seq: &str) -> Option<Self> {
+        Self::with_alphabet(seq, Alphabet::Nucleotide)
+            .or_else(|| Self::with_alphabet(seq, Alphabet::Protein))
+    }
+
+    fn with_alphabet(seq: &str, alphabet: Alphabet) -> Option<Self> {
+        let (codes, bits, per_word) = (alphabet.codes(), alphabet.bits(), alphabet.per_word());
+        let words = seq
+            .as_bytes()
+            .chunks(per_word)
+            .map(|residues| {
+                residues
+                    .iter()
+                    .enumerate()
+                    .try_fold(0u64, |word, (k, &residue)| {
+                        let code = codes[residue as usize];
+                        (code != u8::MAX).then(|| word | u64::from(code) << (k as u32 * bits))
+                    })
+            })
+            .collect::<Option<_>>()?;
+        Some(Self {
+            alphabet,
+            len: seq.len(),
+            words,
+        })
+    }
+
+    /// Returns the number of residues
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the residue at `index`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    #[inline]
+    pub fn get(&self, index: usize) -> u8 {
+        assert!(index < self.len, "residue {} of {}", index, self.len);
+        // Constant widths, so the kernel decodes with shifts and masks
+        match self.alphabet {
+            Alphabet::Nucleotide => self.decode::<2>(index, NUCLEOTIDES),
+            Alphabet::Protein => self.decode::<5>(index, AMINO_ACIDS),
+        }
+    }
+
+    #[inline(always)]
+    fn decode<const BITS: u32>(&self, index: usize, symbols: &[u8]) -> u8 {
+        let per_word = (u64::BITS / BITS) as usize;
+        let word = self.words[index / per_word];
+        let code = (word >> ((index % per_word) as u32 * BITS)) & ((1 << BITS) - 1);
+        symbols[code as usize]
+    }
+
+    /// Returns the sequence with its residues unpacked
+    pub fn unpack(&self) -> String {
+        (0..self.len)
+            .map(|index| char::from(self.get(index)))
+            .collect()
+    }
+
+    /// Returns the number of bytes holding the residues
+    pub fn size(&self) -> usize {
+        self.words.len() * size_of::<u64>()
+    }
+}
+
+/// The packed sequences of an input by identifier
+#[derive(Debug, Default)]
+pub struct PackedInput {
+    sequences: HashMap<String, PackedSeq>,
+}
+
+impl PackedInput {
+    /// Packs the sequences of `input` that fit an alphabet, leaving their entries
+    /// in `input` empty, so the residues are only held packed
+    pub fn pack(input: &mut HashMap<String, String>) -> Self {
+        let sequences = input
+            .par_iter_mut()
+            .filter_map(|(id, seq)| {
+                let packed = PackedSeq::pack(seq)?;
+                *seq = String::new();
+                Some((id.clone(), packed))
+            })
+            .collect();
+        Self { sequences }
+    }
+
+    /// Returns the packed sequence with identifier `id`, or `None` if it was not
+    /// packed
+    pub fn get(&self, id: &str) -> Option<&PackedSeq> {
+        self.sequences.get(id)
+    }
+
+    /// Returns the number of packed sequences
+    pub fn len(&self) -> usize {
+        self.sequences.len()
+    }
+
+    /// Returns the number of bytes holding the residues of the packed sequences
+    pub fn size(&self) -> usize {
+        self.sequences.values().map(PackedSeq::size).sum()
+    }
+
+    /// Returns the number of residues of the packed sequences
+    pub fn residues(&self) -> usize {
+        self.sequences.values().map(PackedSeq::len).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::align::{AlignMode, GapPenalties, MatcherFn, align_linear, align_packed_until};
+    use bio::scores::blosum62;
+
+    #[test]
+    fn test_pack_sequences() {
+        let dna = "ACGTTGCA".repeat(9);
+        let packed = PackedSeq::pack(&dna).unwrap();
+        assert_eq!(packed.alphabet, Alphabet::Nucleotide);
+        assert_eq!(packed.size(), 24);
+        assert_eq!(packed.unpack(), dna);
+
+        let protein = "MKTAYIAKQRQISFVKSHF*";
+        let packed = PackedSeq::pack(protein).unwrap();
+        assert_eq!(packed.alphabet, Alphabet::Protein);
+        assert_eq!(packed.get(4), b'Y');
+        assert_eq!(packed.unpack(), protein);
+
+        assert_eq!(
+            PackedSeq::pack("ACGTN").unwrap().alphabet,
+            Alphabet::Protein
+        );
+        assert_eq!(PackedSeq::pack("acgt"), None);
+
+        let matcher: MatcherFn = blosum62;
+        let (other, gaps) = ("MKTAYIAKQRQNISFVHF", GapPenalties::default());
+        for mode in [AlignMode::Global, AlignMode::Local, AlignMode::Semiglobal] {
+            let unpacked = align_linear(protein, other, &matcher, mode, gaps);
+            let other = PackedSeq::pack(other).unwrap();
+            assert_eq!(
+                align_packed_until(&packed, &other, &matcher, mode, gaps, None),
+                Some(unpacked)
+            );
+        }
+        assert_eq!(PackedSeq::pack("").unwrap().len(), 0);
+
+        let mut input: HashMap<String, String> = [("a", dna.as_str()), ("b", "acgt")]
+            .into_iter()
+            .map(|(id, seq)| (id.to_string(), seq.to_string()))
+            .collect();
+        let packed = PackedInput::pack(&mut input);
+        assert_eq!(packed.len(), 1);
+        assert_eq!(packed.get("a").unwrap().unpack(), dna);
+        assert_eq!(packed.get("b"), None);
+        assert_eq!(input["a"], "");
+        assert_eq!(input["b"], "acgt");
+    }
+}
@@ -0,0 +1,127 @@
+//! PAF records of aligned pairs.
+//!
+//! With `--output-format paf`, every aligned pair is aligned once more with
+//! traceback and written in the Pairwise mApping Format of minimap2, so results
+//! can be processed by tools of its ecosystem. The aligned block of a pair spans
+//! from its first to its last column with residues of both sequences; query and
+//! target residues outside of it are unaligned overhangs. Coordinates are
+//! zero-based and half-open as in PAF, and the block is described by a CIGAR
+//! string with `M`, `I` and `D` operations.
+
+use bio::alignment::AlignmentOperation;
+use std::fmt::Write;
+
+use crate::align::{GapPenalties, MatcherFn};
+use crate::pair::align_pair_with;
+
+/// The aligned block of a query with its target
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PafAlignment {
+    /// Zero-based start and exclusive end of the block on the query
+    pub qstart: usize,
+    pub qend: usize,
+    /// Zero-based start and exclusive end of the block on the target
+    pub tstart: usize,
+    pub tend: usize,
+    /// Number of identical residues in the block
+    pub matches: usize,
+    /// Number of columns of the block, gaps included
+    pub block_len: usize,
+    /// Columns of the block that are not identical residues
+    pub edit_distance: usize,
+    /// Score of the alignment
+    pub score: i32,
+    /// CIGAR string of the block
+    pub cigar: String,
+}
+
+impl PafAlignment {
+    /// Aligns a query globally with a target and describes its aligned block
+    pub fn new(query: &str, target: &str, matcher: &MatcherFn, gaps: GapPenalties) -> Self {
+        let alignment = align_pair_with(query, target, matcher, gaps);
+        // Column operations with the query and target positions before each
+        let mut columns = Vec::with_capacity(alignment.operations.len());
+        let (mut x, mut y) = (alignment.xstart, alignment.ystart);
+        for op in &alignment.operations {
+            match op {
+                AlignmentOperation::Xclip(n) => x += n,
+                AlignmentOperation::Yclip(n) => y += n,
+                _ => {
+                    columns.push((*op, x, y));
+                    x += usize::from(*op != AlignmentOperation::Del);
+                    y += usize::from(*op != AlignmentOperation::Ins);
+                }
+            }
+        }
+        let aligned = |op: &AlignmentOperation| {
+            matches!(op, AlignmentOperation::Match | AlignmentOperation::Subst)
+        };
+        let first = columns.iter().position(|(op, _, _)| aligned(op));
+        let last = columns.iter().rposition(|(op, _, _)| aligned(op));
+        let (Some(first), Some(last)) = (first, last) else {
+            return Self {
+                qstart: 0,
+                qend: 0,
+                tstart: 0,
+                tend: 0,
+                matches: 0,
+                block_len: 0,
+                edit_distance: 0,
+                score: alignment.score,
+                cigar: String::new(),
+            };
+        };
+        let block = &columns[first..=last];
+
+        let mut ops: Vec<(char, usize)> = Vec::new();
+        let mut matches = 0;
+        for (op, _, _) in block {
+            let kind = match op {
+                AlignmentOperation::Ins => 'I',
+                AlignmentOperation::Del => 'D',
+                _ => 'M',
+            };
+            matches += usize::from(*op == AlignmentOperation::Match);
+            match ops.last_mut() {
+                Some((last, count)) if *last == kind => *count += 1,
+                _ => ops.push((kind, 1)),
+            }
+        }
+        let mut cigar = String::new();
+        for (kind, len) in &ops {
+            write!(cigar, "{}{}", len, kind).expect("writing to a string cannot fail");
+        }
+        let (_, qstart, tstart) = block[0];
+        let (_, qend, tend) = block[block.len() - 1];
+        Self {
+            qstart,
+            qend: qend + 1,
+            tstart,
+            tend: tend + 1,
+            matches,
+            block_len: block.len(),
+            edit_distance: block.len() - matches,
+            score: alignment.score,
+            cigar,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ScoringType;
+
+    #[test]
+    fn test_paf_alignment() {
+        let target = "MKTAYIAKQRQISFVKSHFSRQLEERLGLIEVQAPILSRVGDGTQDNLSGAEKAVQVKVKA";
+        // Residues 21-40 of the target with a substitution
+        let query = "RQLEERLGLIDVQAPILSRV";
+        let matcher = ScoringType::Blosum62.matcher();
+        let paf = PafAlignment::new(query, target, &matcher, GapPenalties::default());
+        assert_eq!((paf.qstart, paf.qend), (0, 20));
+        assert_eq!((paf.tstart, paf.tend), (20, 40));
+        assert_eq!((paf.matches, paf.block_len, paf.edit_distance), (19, 20, 1));
+        assert_eq!(paf.cigar, "20M");
+    }
+}
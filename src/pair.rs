@@ -0,0 +1,196 @@
+//! Alignment of a single pair of sequences.
+//!
+//! The `pair` subcommand aligns two sequences given on the command line or read
+//! from two single-record files and prints the score, the identity and the
+//! alignment itself, for quick checks that do not warrant an input file. With
+//! `--mutations`, it also lists the mutations of the first sequence relative to
+//! the second.
+
+use bio::alignment::Alignment;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::ScoringType;
+use crate::align::{GapPenalties, MatcherFn, Summary};
+use crate::error::AlignerError;
+use crate::mutations;
+use crate::utils::parse_input;
+use crate::workspace::{self, NO_CLIPS};
+
+/// Number of alignment columns printed per line
+const LINE_WIDTH: usize = 60;
+
+/// Command-line arguments for the `pair` subcommand
+#[derive(clap::Args, Debug)]
+pub struct PairArgs {
+    /// Two input files with one sequence each, in any supported input format
+    #[arg(
+        num_args = 2,
+        value_names = ["FILE1", "FILE2"],
+        required_unless_present_all = ["seq1", "seq2"],
+        conflicts_with_all = ["seq1", "seq2"]
+    )]
+    files: Vec<PathBuf>,
+
+    /// First sequence
+    #[arg(long, requires = "seq2")]
+    seq1: Option<String>,
+
+    /// Second sequence
+    #[arg(long, requires = "seq1")]
+    seq2: Option<String>,
+
+    /// Also list the mutations of the first sequence relative to the second,
+    /// numbered by the positions of the second, e.g. `A123T,del125-127`
+    #[arg(long)]
+    mutations: bool,
+
+    /// Scoring type to use for alignment
+    #[arg(short, long, value_enum, default_value_t = ScoringType::Identity)]
+    scoring: ScoringType,
+}
+
+/// Runs the `pair` subcommand.
+///
+/// # Errors
+///
+/// Returns `AlignerError::InvalidInput` if a file does not contain exactly one
+/// sequence or a sequence contains non-ASCII characters, and the errors of
+/// reading the files.
+pub fn run(args: PairArgs) -> Result<(), AlignerError> {
+    let ((id1, seq1), (id2, seq2)) = match (args.seq1, args.seq2) {
+        (Some(seq1), Some(seq2)) => (("seq1".to_string(), seq1), ("seq2".to_string(), seq2)),
+        _ => (read_single(&args.files[0])?, read_single(&args.files[1])?),
+    };
+    let (seq1, seq2) = (normalize(&id1, &seq1)?, normalize(&id2, &seq2)?);
+
+    let matcher = args.scoring.matcher();
+    write_alignment(
+        &mut io::stdout().lock(),
+        (&id1, &seq1),
+        (&id2, &seq2),
+        &matcher,
+    )?;
+    if args.mutations {
+        let mutations = mutations::call(&align_pair(&seq1, &seq2, &matcher), &seq1, &seq2);
+        println!();
+        println!("Mutations: {}", mutations::format_list(&mutations));
+    }
+    Ok(())
+}
+
+/// Computes the global alignment of two sequences with traceback
+pub fn align_pair(seq1: &str, seq2: &str, matcher: &MatcherFn) -> Alignment {
+    align_pair_with(seq1, seq2, matcher, GapPenalties::default())
+}
+
+/// Computes the global alignment of two sequences with traceback and `gaps`
+pub fn align_pair_with(
+    seq1: &str,
+    seq2: &str,
+    matcher: &MatcherFn,
+    gaps: GapPenalties,
+) -> Alignment {
+    workspace::with_aligner(
+        seq1.len(),
+        seq2.len(),
+        *matcher,
+        gaps,
+        NO_CLIPS,
+        |aligner| aligner.global(seq1.as_bytes(), seq2.as_bytes()),
+    )
+}
+
+/// Aligns two identified sequences and writes the score, identity and alignment.
+///
+/// # Errors
+///
+/// Returns an error if writing fails.
+pub fn write_alignment(
+    out: &mut impl Write,
+    (id1, seq1): (&str, &str),
+    (id2, seq2): (&str, &str),
+    matcher: &MatcherFn,
+) -> io::Result<()> {
+    let alignment = align_pair(seq1, seq2, matcher);
+    let summary = Summary::of(&alignment, seq1.as_bytes(), seq2.as_bytes(), matcher);
+
+    writeln!(out, "Query:    {} ({} residues)", id1, seq1.len())?;
+    writeln!(out, "Subject:  {} ({} residues)", id2, seq2.len())?;
+    writeln!(out, "Score:    {}", alignment.score)?;
+    writeln!(
+        out,
+        "Identity: {}/{} ({:.1}%)",
+        summary.matches,
+        summary.columns,
+        100.0 * summary.identity()
+    )?;
+    writeln!(out, "Gaps:     {}/{}", summary.gaps, summary.columns)?;
+    writeln!(out)?;
+    write!(
+        out,
+        "{}",
+        alignment.pretty(seq1.as_bytes(), seq2.as_bytes(), LINE_WIDTH)
+    )
+}
+
+/// Reads the only sequence of a file
+pub fn read_single(path: &Path) -> Result<(String, String), AlignerError> {
+    let sequences = parse_input(path)?;
+    if sequences.len() != 1 {
+        return Err(AlignerError::InvalidInput(format!(
+            "{} contains {} sequences, expected exactly one",
+            path.display(),
+            sequences.len()
+        )));
+    }
+    Ok(sequences.into_iter().next().expect("one sequence"))
+}
+
+/// Removes whitespace from a sequence and converts it to upper case
+pub fn normalize(id: &str, sequence: &str) -> Result<String, AlignerError> {
+    if !sequence.is_ascii() {
+        return Err(AlignerError::InvalidInput(format!(
+            "sequence {} contains non-ASCII characters",
+            id
+        )));
+    }
+    Ok(sequence
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .map(|c| c.to_ascii_uppercase())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let alignment = align_pair("MAVMTKL", "MAVMKL", &ScoringType::Identity.matcher());
+        let summary = Summary::of(
+            &alignment,
+            b"MAVMTKL",
+            b"MAVMKL",
+            &ScoringType::Identity.matcher(),
+        );
+        assert_eq!(
+            summary,
+            Summary {
+                matches: 6,
+                positives: 6,
+                gaps: 1,
+                columns: 7
+            }
+        );
+        assert!((summary.identity() - 6.0 / 7.0).abs() < 1e-9);
+
+        // Isoleucine and valine score 3 with BLOSUM62, tryptophan and alanine -3
+        let blosum62 = ScoringType::Blosum62.matcher();
+        let alignment = align_pair("MAIMTKW", "MAVMTKA", &blosum62);
+        let summary = Summary::of(&alignment, b"MAIMTKW", b"MAVMTKA", &blosum62);
+        assert_eq!((summary.matches, summary.positives), (5, 6));
+        assert!((summary.similarity() - 6.0 / 7.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,386 @@
+//! Enumeration of the pairs of sequences to align.
+//!
+//! A run aligns the pairs produced by a [`PairGenerator`]. By default every
+//! unordered pair of distinct sequences is aligned once; other generators align
+//! both orders of each pair, every query with every target of two sets, only pairs
+//! sharing k-mers, the pairs listed in a file, or a shard of the pairs of another
+//! generator. Generators enumerate sequences in identifier order, so the
+//! same input always yields the same pairs in the same order, which lets separate
+//! processes split a run into shards.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::error::AlignerError;
+
+/// A pair of sequence identifiers, query first
+pub type Pair<'a> = (&'a String, &'a String);
+
+/// Produces the pairs of sequences a run aligns
+pub trait PairGenerator: Send + Sync {
+    /// Returns the pairs of identifiers of `input` to align
+    fn pairs<'a>(&self, input: &'a HashMap<String, String>) -> Vec<Pair<'a>>;
+
+    /// Returns the sorted query and target identifiers of `input` if the pairs are
+    /// every query with every other target, so they can be aligned one query at a
+    /// time without listing them
+    fn sides<'a>(
+        &self,
+        _input: &'a HashMap<String, String>,
+    ) -> Option<(Vec<&'a String>, Vec<&'a String>)> {
+        None
+    }
+}
+
+/// Returns the identifiers of `input` in sorted order
+fn sorted_ids(input: &HashMap<String, String>) -> Vec<&String> {
+    let mut ids: Vec<&String> = input.keys().collect();
+    ids.sort();
+    ids
+}
+
+/// Every unordered pair of distinct sequences, the default of all-vs-all runs
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Triangle;
+
+impl PairGenerator for Triangle {
+    fn pairs<'a>(&self, input: &'a HashMap<String, String>) -> Vec<Pair<'a>> {
+        let ids = sorted_ids(input);
+        ids.iter()
+            .enumerate()
+            .flat_map(|(i, query_id)| {
+                ids[..i]
+                    .iter()
+                    .map(move |subject_id| (*query_id, *subject_id))
+            })
+            .collect()
+    }
+}
+
+/// Every ordered pair of distinct sequences, for scoring schemes whose score
+/// depends on which sequence is the query
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FullMatrix;
+
+impl PairGenerator for FullMatrix {
+    fn pairs<'a>(&self, input: &'a HashMap<String, String>) -> Vec<Pair<'a>> {
+        let ids = sorted_ids(input);
+        ids.iter()
+            .flat_map(|query_id| {
+                ids.iter()
+                    .filter(move |subject_id| subject_id != &query_id)
+                    .map(move |subject_id| (*query_id, *subject_id))
+            })
+            .collect()
+    }
+}
+
+/// Every pair of a query and a target sequence, for runs comparing two sets of
+/// sequences instead of all sequences with each other. A sequence in both sets is
+/// not aligned with itself.
+#[derive(Debug, Clone, Default)]
+pub struct CrossProduct {
+    /// Identifiers of the query sequences
+    pub queries: HashSet<String>,
+    /// Identifiers of the target sequences
+    pub targets: HashSet<String>,
+}
+
+impl PairGenerator for CrossProduct {
+    fn pairs<'a>(&self, input: &'a HashMap<String, String>) -> Vec<Pair<'a>> {
+        let ids = sorted_ids(input);
+        let targets: Vec<&String> = ids
+            .iter()
+            .copied()
+            .filter(|id| self.targets.contains(*id))
+            .collect();
+        ids.iter()
+            .filter(|id| self.queries.contains(**id))
+            .flat_map(|query_id| {
+                targets
+                    .iter()
+                    .filter(move |subject_id| subject_id != &query_id)
+                    .map(move |subject_id| (*query_id, *subject_id))
+            })
+            .collect()
+    }
+
+    fn sides<'a>(
+        &self,
+        input: &'a HashMap<String, String>,
+    ) -> Option<(Vec<&'a String>, Vec<&'a String>)> {
+        let ids = sorted_ids(input);
+        let side =
+            |set: &HashSet<String>| ids.iter().copied().filter(|id| set.contains(*id)).collect();
+        Some((side(&self.queries), side(&self.targets)))
+    }
+}
+
+/// Unordered pairs of sequences sharing at least `min_shared` distinct k-mers,
+/// found through an index of the k-mers of all sequences instead of comparing
+/// every pair
+#[derive(Debug, Clone, Copy)]
+pub struct KmerCandidates {
+    /// Length of the indexed k-mers
+    pub k: usize,
+    /// Number of distinct k-mers a pair must share
+    pub min_shared: usize,
+}
+
+impl PairGenerator for KmerCandidates {
+    fn pairs<'a>(&self, input: &'a HashMap<String, String>) -> Vec<Pair<'a>> {
+        let ids = sorted_ids(input);
+        // Positions in `ids` of the sequences containing each k-mer
+        let mut index: HashMap<&[u8], Vec<usize>> = HashMap::new();
+        for (position, id) in ids.iter().enumerate() {
+            let kmers: HashSet<&[u8]> = input[*id].as_bytes().windows(self.k.max(1)).collect();
+            for kmer in kmers {
+                index.entry(kmer).or_default().push(position);
+            }
+        }
+
+        let mut shared: HashMap<(usize, usize), usize> = HashMap::new();
+        for positions in index.values() {
+            for (i, &query) in positions.iter().enumerate() {
+                for &subject in &positions[..i] {
+                    *shared.entry((query, subject)).or_default() += 1;
+                }
+            }
+        }
+        let mut candidates: Vec<(usize, usize)> = shared
+            .into_iter()
+            .filter(|(_, count)| *count >= self.min_shared)
+            .map(|(pair, _)| pair)
+            .collect();
+        candidates.sort_unstable();
+        candidates
+            .into_iter()
+            .map(|(query, subject)| (ids[query], ids[subject]))
+            .collect()
+    }
+}
+
+/// The pairs listed in a file, in the order of the file, to re-score a curated
+/// set of pairs instead of comparing all sequences
+#[derive(Debug, Clone, Default)]
+pub struct PairList {
+    pairs: Vec<(String, String)>,
+}
+
+impl PairList {
+    /// Reads a tab-separated list with the query and subject identifier of a pair
+    /// in the first two columns of every line. Further columns and lines starting
+    /// with `#` are ignored, and a pair listed more than once is aligned once.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AlignerError::Io` if the file cannot be read, and
+    /// `AlignerError::InvalidInput` if a line has fewer than two columns.
+    pub fn read(path: &Path) -> Result<Self, AlignerError> {
+        let mut pairs = Vec::new();
+        let mut seen = HashSet::new();
+        for (index, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut columns = line.split('\t');
+            let (Some(query_id), Some(subject_id)) = (columns.next(), columns.next()) else {
+                return Err(AlignerError::InvalidInput(format!(
+                    "{}:{}: expected a query and a subject identifier separated by a tab",
+                    path.display(),
+                    index + 1
+                )));
+            };
+            let pair = (query_id.to_string(), subject_id.to_string());
+            if seen.insert(pair.clone()) {
+                pairs.push(pair);
+            }
+        }
+        Ok(Self { pairs })
+    }
+
+    /// Returns the number of listed pairs
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    /// Returns the number of listed pairs with a sequence missing from `input`
+    pub fn missing(&self, input: &HashMap<String, String>) -> usize {
+        self.pairs
+            .iter()
+            .filter(|(query_id, subject_id)| {
+                !input.contains_key(query_id) || !input.contains_key(subject_id)
+            })
+            .count()
+    }
+}
+
+impl PairGenerator for PairList {
+    fn pairs<'a>(&self, input: &'a HashMap<String, String>) -> Vec<Pair<'a>> {
+        self.pairs
+            .iter()
+            .filter_map(|(query_id, subject_id)| {
+                let (query_id, _) = input.get_key_value(query_id)?;
+                let (subject_id, _) = input.get_key_value(subject_id)?;
+                Some((query_id, subject_id))
+            })
+            .collect()
+    }
+}
+
+/// One of several shards of a run, given as `INDEX/COUNT` with a zero-based index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    /// Zero-based index of this shard
+    pub index: usize,
+    /// Number of shards the run is split into
+    pub count: usize,
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid shard '{}', expected INDEX/COUNT such as 0/4",
+                value
+            )
+        };
+        let (index, count) = value.split_once('/').ok_or_else(invalid)?;
+        let (index, count) = (
+            index.trim().parse().map_err(|_| invalid())?,
+            count.trim().parse().map_err(|_| invalid())?,
+        );
+        if index >= count {
+            return Err(format!(
+                "shard index {} must be smaller than the number of shards {}",
+                index, count
+            ));
+        }
+        Ok(Self { index, count })
+    }
+}
+
+/// Every `count`-th pair of another generator, starting at the shard index
+pub struct Sharded {
+    /// Generator whose pairs are split into shards
+    pub inner: Box<dyn PairGenerator>,
+    /// Shard of the pairs to keep
+    pub shard: Shard,
+}
+
+impl PairGenerator for Sharded {
+    fn pairs<'a>(&self, input: &'a HashMap<String, String>) -> Vec<Pair<'a>> {
+        self.inner
+            .pairs(input)
+            .into_iter()
+            .skip(self.shard.index)
+            .step_by(self.shard.count)
+            .collect()
+    }
+}
+
+/// Results of a previous run that are not computed again
+#[derive(Debug, Clone)]
+pub enum Previous {
+    /// Sequences already compared with each other; pairs of two of them are left out
+    Sequences(HashSet<String>),
+    /// Pairs whose results were already written, by query and subject identifier in
+    /// both orders
+    Pairs(HashMap<String, HashSet<String>>),
+}
+
+impl Previous {
+    /// Creates the set of finished pairs from pairs of identifiers
+    pub fn from_pairs(pairs: impl IntoIterator<Item = (String, String)>) -> Self {
+        let mut done: HashMap<String, HashSet<String>> = HashMap::new();
+        for (query_id, subject_id) in pairs {
+            done.entry(query_id.clone())
+                .or_default()
+                .insert(subject_id.clone());
+            done.entry(subject_id).or_default().insert(query_id);
+        }
+        Self::Pairs(done)
+    }
+
+    /// Returns `true` if the pair does not need to be aligned again
+    pub fn contains(&self, query_id: &str, subject_id: &str) -> bool {
+        match self {
+            Self::Sequences(ids) => ids.contains(query_id) && ids.contains(subject_id),
+            Self::Pairs(done) => done
+                .get(query_id)
+                .is_some_and(|subjects| subjects.contains(subject_id)),
+        }
+    }
+}
+
+/// The pairs of another generator that were not compared in a previous run
+pub struct Excluding {
+    /// Generator whose pairs are filtered
+    pub inner: Box<dyn PairGenerator>,
+    /// Pairs compared in the previous run
+    pub previous: Previous,
+}
+
+impl PairGenerator for Excluding {
+    fn pairs<'a>(&self, input: &'a HashMap<String, String>) -> Vec<Pair<'a>> {
+        let mut pairs = self.inner.pairs(input);
+        pairs.retain(|(query_id, subject_id)| !self.previous.contains(query_id, subject_id));
+        pairs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pair_generators() {
+        let input: HashMap<String, String> = [("c", "MAVMT"), ("a", "MAVKL"), ("b", "WWWWW")]
+            .into_iter()
+            .map(|(id, seq)| (id.to_string(), seq.to_string()))
+            .collect();
+        let ids = |pairs: Vec<Pair>| -> Vec<String> {
+            pairs.iter().map(|(q, s)| format!("{}-{}", q, s)).collect()
+        };
+
+        assert_eq!(ids(Triangle.pairs(&input)), ["b-a", "c-a", "c-b"]);
+        assert_eq!(FullMatrix.pairs(&input).len(), 6);
+        let cross = CrossProduct {
+            queries: ["c", "a"].map(String::from).into(),
+            targets: ["a", "b", "z"].map(String::from).into(),
+        };
+        assert_eq!(ids(cross.pairs(&input)), ["a-b", "c-a", "c-b"]);
+        let candidates = KmerCandidates {
+            k: 3,
+            min_shared: 1,
+        };
+        assert_eq!(ids(candidates.pairs(&input)), ["c-a"]);
+        let list = PairList {
+            pairs: vec![
+                ("c".to_string(), "b".to_string()),
+                ("a".to_string(), "z".to_string()),
+            ],
+        };
+        assert_eq!(ids(list.pairs(&input)), ["c-b"]);
+        assert_eq!(list.missing(&input), 1);
+
+        let sharded = Sharded {
+            inner: Box::new(Triangle),
+            shard: "1/2".parse().unwrap(),
+        };
+        assert_eq!(ids(sharded.pairs(&input)), ["c-a"]);
+        assert!("2/2".parse::<Shard>().is_err());
+
+        let excluding = Excluding {
+            inner: Box::new(Triangle),
+            previous: Previous::from_pairs([("a".to_string(), "c".to_string())]),
+        };
+        assert_eq!(ids(excluding.pairs(&input)), ["b-a", "c-b"]);
+    }
+}
@@ -0,0 +1,348 @@
+//! Per-stage timing and profiling output.
+//!
+//! This module records wall-clock and CPU time for each stage of a run and the
+//! busy fraction of every worker thread, and writes them as JSON via `--profile`.
+//! Comparing wall and CPU time of a stage shows whether it is I/O- or compute-bound.
+//!
+//! Sequential stages (parsing, pair generation) are timed as a whole. Stages that
+//! run concurrently for every pair (prefiltering, alignment, writing) are summed
+//! over all calls and threads.
+
+use serde::Serialize;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::error::AlignerError;
+
+/// Stages that are executed once per pair or result and summed over all calls
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stage {
+    /// K-mer pre-filtering of a pair
+    Prefilter,
+    /// Alignment of a pair
+    Align,
+    /// Writing of a result to the output
+    Write,
+}
+
+impl Stage {
+    const ALL: [Stage; 3] = [Stage::Prefilter, Stage::Align, Stage::Write];
+
+    fn name(self) -> &'static str {
+        match self {
+            Stage::Prefilter => "prefilter",
+            Stage::Align => "align",
+            Stage::Write => "write",
+        }
+    }
+}
+
+/// Wall and CPU time summed over all calls of a per-pair stage
+#[derive(Debug, Default)]
+struct Accumulator {
+    wall_ns: AtomicU64,
+    cpu_ns: AtomicU64,
+    calls: AtomicU64,
+}
+
+/// Collects timings for the stages of a run
+#[derive(Debug)]
+pub struct Profiler {
+    start: Instant,
+    start_cpu: Duration,
+    sequential: Mutex<Vec<StageTiming>>,
+    accumulators: [Accumulator; 3],
+    thread_busy_ns: OnceLock<Vec<AtomicU64>>,
+    parallel_wall: Mutex<Duration>,
+}
+
+/// Timing of a single stage in the profile report
+#[derive(Debug, Clone, Serialize)]
+pub struct StageTiming {
+    /// Name of the stage
+    pub name: &'static str,
+    /// Wall-clock time in seconds, summed over all threads for per-pair stages
+    pub wall_secs: f64,
+    /// CPU time in seconds, summed over all threads for per-pair stages
+    pub cpu_secs: f64,
+    /// Number of times the stage was executed
+    pub calls: u64,
+}
+
+/// Busy time of a single worker thread in the profile report
+#[derive(Debug, Clone, Serialize)]
+pub struct ThreadTiming {
+    /// Rayon worker index
+    pub index: usize,
+    /// Time spent prefiltering and aligning in seconds
+    pub busy_secs: f64,
+    /// Busy time divided by the wall time of the parallel section
+    pub busy_fraction: f64,
+}
+
+/// Complete profile of a run, serialized to the `--profile` file
+#[derive(Debug, Clone, Serialize)]
+pub struct ProfileReport {
+    /// Total wall-clock time of the run in seconds
+    pub wall_secs: f64,
+    /// Total CPU time of the process in seconds
+    pub cpu_secs: f64,
+    /// Wall-clock time of the parallel pair loop in seconds
+    pub parallel_wall_secs: f64,
+    /// Timings of the individual stages
+    pub stages: Vec<StageTiming>,
+    /// Busy times of the worker threads
+    pub threads: Vec<ThreadTiming>,
+}
+
+impl Profiler {
+    /// Creates a profiler; the run's wall and CPU clocks start now
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            start_cpu: process_cpu_time(),
+            sequential: Mutex::new(Vec::new()),
+            accumulators: Default::default(),
+            thread_busy_ns: OnceLock::new(),
+            parallel_wall: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    /// Runs a sequential stage and records its wall and process CPU time
+    pub fn sequential<T>(&self, name: &'static str, f: impl FnOnce() -> T) -> T {
+        let wall = Instant::now();
+        let cpu = process_cpu_time();
+        let value = f();
+        let timing = StageTiming {
+            name,
+            wall_secs: wall.elapsed().as_secs_f64(),
+            cpu_secs: (process_cpu_time().saturating_sub(cpu)).as_secs_f64(),
+            calls: 1,
+        };
+        self.sequential
+            .lock()
+            .expect("Profiler lock poisoned")
+            .push(timing);
+        value
+    }
+
+    /// Runs one call of a per-pair stage and adds its wall and thread CPU time.
+    ///
+    /// When called on a rayon worker, prefilter and alignment time also count
+    /// towards that worker's busy time.
+    pub fn measure<T>(&self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        let wall = Instant::now();
+        let cpu = thread_cpu_time();
+        let value = f();
+        let wall_ns = wall.elapsed().as_nanos() as u64;
+        let cpu_ns = thread_cpu_time().saturating_sub(cpu).as_nanos() as u64;
+
+        let accumulator = &self.accumulators[stage as usize];
+        accumulator.wall_ns.fetch_add(wall_ns, Ordering::Relaxed);
+        accumulator.cpu_ns.fetch_add(cpu_ns, Ordering::Relaxed);
+        accumulator.calls.fetch_add(1, Ordering::Relaxed);
+
+        if stage != Stage::Write
+            && let (Some(index), Some(busy)) =
+                (rayon::current_thread_index(), self.thread_busy_ns.get())
+            && let Some(slot) = busy.get(index)
+        {
+            slot.fetch_add(wall_ns, Ordering::Relaxed);
+        }
+        value
+    }
+
+    /// Times the parallel pair loop, tracking busy time for `num_threads` workers
+    pub fn parallel<T>(&self, num_threads: usize, f: impl FnOnce() -> T) -> T {
+        let _ = self
+            .thread_busy_ns
+            .set((0..num_threads).map(|_| AtomicU64::new(0)).collect());
+        let wall = Instant::now();
+        let value = f();
+        *self.parallel_wall.lock().expect("Profiler lock poisoned") += wall.elapsed();
+        value
+    }
+
+    /// Assembles the report from all timings recorded so far
+    pub fn report(&self) -> ProfileReport {
+        let mut stages = self
+            .sequential
+            .lock()
+            .expect("Profiler lock poisoned")
+            .clone();
+        for stage in Stage::ALL {
+            let accumulator = &self.accumulators[stage as usize];
+            stages.push(StageTiming {
+                name: stage.name(),
+                wall_secs: nanos_to_secs(accumulator.wall_ns.load(Ordering::Relaxed)),
+                cpu_secs: nanos_to_secs(accumulator.cpu_ns.load(Ordering::Relaxed)),
+                calls: accumulator.calls.load(Ordering::Relaxed),
+            });
+        }
+
+        let parallel_wall_secs = self
+            .parallel_wall
+            .lock()
+            .expect("Profiler lock poisoned")
+            .as_secs_f64();
+        let threads = self
+            .thread_busy_ns
+            .get()
+            .map(|busy| {
+                busy.iter()
+                    .enumerate()
+                    .map(|(index, ns)| {
+                        let busy_secs = nanos_to_secs(ns.load(Ordering::Relaxed));
+                        ThreadTiming {
+                            index,
+                            busy_secs,
+                            busy_fraction: if parallel_wall_secs > 0.0 {
+                                busy_secs / parallel_wall_secs
+                            } else {
+                                0.0
+                            },
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        ProfileReport {
+            wall_secs: self.start.elapsed().as_secs_f64(),
+            cpu_secs: process_cpu_time()
+                .saturating_sub(self.start_cpu)
+                .as_secs_f64(),
+            parallel_wall_secs,
+            stages,
+            threads,
+        }
+    }
+
+    /// Writes the report as pretty-printed JSON to `path`
+    pub fn write(&self, path: &Path) -> Result<(), AlignerError> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, &self.report())?;
+        Ok(())
+    }
+}
+
+/// Runs `f` as a call of `stage` on the profiler, or directly if profiling is disabled
+pub fn measure<T>(profiler: Option<&Profiler>, stage: Stage, f: impl FnOnce() -> T) -> T {
+    match profiler {
+        Some(profiler) => profiler.measure(stage, f),
+        None => f(),
+    }
+}
+
+fn nanos_to_secs(nanos: u64) -> f64 {
+    nanos as f64 / 1e9
+}
+
+/// CPU time consumed by the whole process
+fn process_cpu_time() -> Duration {
+    cpu_time(libc::CLOCK_PROCESS_CPUTIME_ID)
+}
+
+/// CPU time consumed by the calling thread
+fn thread_cpu_time() -> Duration {
+    cpu_time(libc::CLOCK_THREAD_CPUTIME_ID)
+}
+
+fn cpu_time(clock: libc::clockid_t) -> Duration {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `ts` is a valid, writable timespec and the clock ids are supported on
+    // all unix platforms; on failure the zeroed value is returned.
+    unsafe { libc::clock_gettime(clock, &mut ts) };
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
+
+    #[test]
+    fn test_profile_report() {
+        let profiler = Profiler::new();
+        let parsed = profiler.sequential("parse", || (0..1000).sum::<u64>());
+        assert_eq!(parsed, 499500);
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let scores: Vec<u64> = pool.install(|| {
+            profiler.parallel(rayon::current_num_threads(), || {
+                (0..8u64)
+                    .into_par_iter()
+                    .map(|i| {
+                        let kept = measure(Some(&profiler), Stage::Prefilter, || i % 2 == 0);
+                        measure(Some(&profiler), Stage::Align, || {
+                            std::thread::sleep(Duration::from_millis(2));
+                            u64::from(kept) * i
+                        })
+                    })
+                    .collect()
+            })
+        });
+        assert_eq!(scores.iter().sum::<u64>(), 12);
+        profiler.measure(Stage::Write, || ());
+
+        let report = profiler.report();
+        let calls: Vec<(&str, u64)> = report
+            .stages
+            .iter()
+            .map(|stage| (stage.name, stage.calls))
+            .collect();
+        assert_eq!(
+            calls,
+            [("parse", 1), ("prefilter", 8), ("align", 8), ("write", 1)]
+        );
+        for stage in &report.stages {
+            assert!(stage.wall_secs >= 0.0 && stage.cpu_secs >= 0.0);
+        }
+        // Eight sleeps of 2 ms spread over the workers
+        assert!(report.stages[2].wall_secs >= 0.016);
+        assert!(report.parallel_wall_secs >= 0.008);
+        assert!(report.wall_secs >= report.parallel_wall_secs && report.cpu_secs >= 0.0);
+        assert_eq!(report.threads.len(), 2);
+        for thread in &report.threads {
+            assert!((0.0..=1.0).contains(&thread.busy_fraction));
+        }
+        let busy: f64 = report.threads.iter().map(|thread| thread.busy_secs).sum();
+        assert!(busy >= report.stages[2].wall_secs);
+
+        let json = serde_json::to_value(&report).unwrap();
+        let mut fields: Vec<&str> = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        fields.sort_unstable();
+        assert_eq!(
+            fields,
+            [
+                "cpu_secs",
+                "parallel_wall_secs",
+                "stages",
+                "threads",
+                "wall_secs"
+            ]
+        );
+        assert_eq!(json["stages"][0]["name"], "parse");
+        assert!(json["stages"][1]["wall_secs"].is_f64());
+        assert!(json["stages"][1]["cpu_secs"].is_f64());
+        assert_eq!(json["stages"][1]["calls"], 8);
+        assert_eq!(json["threads"][0]["index"], 0);
+        assert!(json["threads"][0]["busy_secs"].is_f64());
+        assert!(json["threads"][0]["busy_fraction"].is_f64());
+    }
+}
@@ -0,0 +1,209 @@
+//! Comparison of sequences with the profile of an existing alignment.
+//!
+//! The `profile` subcommand turns every alignment of a Stockholm or aligned
+//! FASTA file into a position-specific scoring matrix and aligns each input
+//! sequence globally with it. A residue aligned with a column scores the average
+//! of its scores against the residues of the column, so a sequence is compared
+//! with the whole family at once instead of with its members one by one. Columns
+//! that are gaps in many rows cost less to skip: deleting a column costs the gap
+//! penalties scaled by the fraction of rows with a residue in it.
+
+use indicatif::ParallelProgressIterator;
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::ScoringType;
+use crate::align::{GapPenalties, MatcherFn};
+use crate::error::AlignerError;
+use crate::msa::{Msa, read_alignments};
+use crate::utils::{parse_input, setup_progress_bar};
+use crate::validate::{check_ascii, check_lengths};
+
+/// Command-line arguments for the `profile` subcommand
+#[derive(clap::Args, Debug)]
+pub struct ProfileArgs {
+    /// Input file with the sequences to compare, in any supported input format
+    input: PathBuf,
+
+    /// Alignment file whose alignments are the profiles, read as Stockholm if it
+    /// ends in `.sto` or `.stk` and as aligned FASTA otherwise
+    #[arg(long)]
+    alignment: PathBuf,
+
+    /// Path of the tab-separated table with a row per sequence and profile
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Scoring type to use for alignment
+    #[arg(short, long, value_enum, default_value_t = ScoringType::Identity)]
+    scoring: ScoringType,
+}
+
+/// Position-specific scores of the columns of an alignment
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    /// Name of the alignment
+    pub name: String,
+    /// Score of every byte aligned with every column
+    scores: Vec<[f64; 256]>,
+    /// Fraction of the rows with a residue in every column
+    occupancy: Vec<f64>,
+}
+
+impl Profile {
+    /// Builds the profile of an alignment, scoring residues with `matcher`
+    pub fn new(msa: &Msa, matcher: &MatcherFn) -> Self {
+        let stats = msa.column_stats();
+        let scores = stats
+            .iter()
+            .map(|column| {
+                let residues: usize = column.counts.values().sum();
+                std::array::from_fn(|byte| {
+                    let byte = (byte as u8).to_ascii_uppercase();
+                    column
+                        .counts
+                        .iter()
+                        .map(|(&residue, &count)| count as f64 * matcher(byte, residue) as f64)
+                        .sum::<f64>()
+                        / residues.max(1) as f64
+                })
+            })
+            .collect();
+        Self {
+            name: msa.name.clone().unwrap_or_default(),
+            scores,
+            occupancy: stats.iter().map(|column| 1.0 - column.gaps).collect(),
+        }
+    }
+
+    /// Returns the number of columns
+    pub fn columns(&self) -> usize {
+        self.scores.len()
+    }
+
+    /// Returns the score of the best global alignment of `query` with the
+    /// profile, with affine `gaps`
+    pub fn score(&self, query: &[u8], gaps: GapPenalties) -> f64 {
+        let (open, extend) = (f64::from(gaps.open), f64::from(gaps.extend));
+        // Best scores of the previous query residue by column, overall and of
+        // alignments ending in an inserted query residue (Gotoh)
+        let mut best = Vec::with_capacity(self.columns() + 1);
+        best.push(0.0);
+        let mut deleted = f64::NEG_INFINITY;
+        for (j, occupancy) in self.occupancy.iter().enumerate() {
+            deleted = (best[j] + open * occupancy).max(deleted) + extend * occupancy;
+            best.push(deleted);
+        }
+        let mut inserted = vec![f64::NEG_INFINITY; self.columns() + 1];
+
+        for &residue in query {
+            let mut diagonal = best[0];
+            inserted[0] = (best[0] + open).max(inserted[0]) + extend;
+            best[0] = inserted[0];
+            let mut deleted = f64::NEG_INFINITY;
+            for j in 1..=self.columns() {
+                let occupancy = self.occupancy[j - 1];
+                inserted[j] = (best[j] + open).max(inserted[j]) + extend;
+                deleted = (best[j - 1] + open * occupancy).max(deleted) + extend * occupancy;
+                let matched = diagonal + self.scores[j - 1][residue as usize];
+                diagonal = best[j];
+                best[j] = matched.max(inserted[j]).max(deleted);
+            }
+        }
+        best[self.columns()]
+    }
+}
+
+/// Runs the `profile` subcommand.
+///
+/// # Errors
+///
+/// Returns an error if the input or the alignment cannot be read or is invalid,
+/// or the output cannot be written.
+pub fn run(args: ProfileArgs) -> Result<(), AlignerError> {
+    let mut queries = parse_input(&args.input)?;
+    check_ascii(&mut queries, None)?;
+    check_lengths(&queries, None)?;
+    let matcher = args.scoring.matcher();
+    let profiles: Vec<Profile> = read_alignments(&args.alignment)?
+        .iter()
+        .map(|msa| Profile::new(msa, &matcher))
+        .collect();
+    info!(
+        queries = queries.len(),
+        profiles = profiles.len(),
+        "comparing sequences with profiles"
+    );
+
+    let mut queries: Vec<(&String, &String)> = queries.iter().collect();
+    queries.sort_unstable();
+    let gaps = GapPenalties::default();
+    let progress = setup_progress_bar(queries.len() as u64);
+    let scores: Vec<Vec<f64>> = queries
+        .par_iter()
+        .progress_with(progress)
+        .map(|(_, query)| {
+            profiles
+                .iter()
+                .map(|profile| profile.score(query.as_bytes(), gaps))
+                .collect()
+        })
+        .collect();
+
+    let mut out = BufWriter::new(File::create(&args.output)?);
+    writeln!(out, "query_id\tprofile\tscore\tquery_len\tprofile_len")?;
+    for ((query_id, query), scores) in queries.iter().zip(&scores) {
+        for (profile, score) in profiles.iter().zip(scores) {
+            writeln!(
+                out,
+                "{}\t{}\t{:.2}\t{}\t{}",
+                query_id,
+                profile.name,
+                score,
+                query.len(),
+                profile.columns()
+            )?;
+        }
+    }
+    out.flush()?;
+
+    eprintln!(
+        "Compared {} sequences with {} profiles",
+        queries.len(),
+        profiles.len()
+    );
+    eprintln!("Results written to {}", args.output.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_score() {
+        let matcher = ScoringType::Identity.matcher();
+        let msa = Msa {
+            name: Some("family".to_string()),
+            ids: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            rows: vec![b"MKA".to_vec(), b"MRA".to_vec(), b"MK-".to_vec()],
+        };
+        let profile = Profile::new(&msa, &matcher);
+        assert_eq!(profile.columns(), 3);
+        let gaps = GapPenalties::default();
+
+        // Residues score their average against the column, two thirds of K
+        assert!((profile.score(b"MKA", gaps) - (1.0 + 2.0 / 3.0 + 1.0)).abs() < 1e-9);
+        assert!(profile.score(b"MKA", gaps) > profile.score(b"MRA", gaps));
+        // Skipping the last column, a gap in a third of the rows, costs two thirds
+        // of a gap
+        assert!((profile.score(b"MK", gaps) - (1.0 + 2.0 / 3.0 - 11.0 * 2.0 / 3.0)).abs() < 1e-9);
+        // An inserted residue costs a full gap
+        assert!((profile.score(b"MKWA", gaps) - (1.0 + 2.0 / 3.0 + 1.0 - 11.0)).abs() < 1e-9);
+        // Without residues, all columns are deleted in one gap
+        assert!((profile.score(b"", gaps) - (-10.0 - (1.0 + 1.0 + 2.0 / 3.0))).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,214 @@
+//! Re-alignment of selected pairs of a previous run.
+//!
+//! Large runs are scored without traceback. The `realign` subcommand takes the
+//! interesting pairs of such a run, e.g. the best hits filtered from its results
+//! file, and aligns them again with traceback, writing the results with their
+//! identity or, with `--emit-alignment`, the alignments themselves.
+
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::ScoringType;
+use crate::align::{AlignMode, AlignmentResult, GapPenalties, PairStatus, align_with_identity};
+use crate::error::AlignerError;
+use crate::needle;
+use crate::pair::write_alignment;
+use crate::sink::{DelimitedSink, OutputFormat, ResultSink};
+use crate::utils::{parse_input, read_result_pairs};
+use crate::validate::{check_ascii, check_lengths};
+
+/// Command-line arguments for the `realign` subcommand
+#[derive(clap::Args, Debug)]
+pub struct RealignArgs {
+    /// Input file with the sequences of the previous run
+    input: PathBuf,
+
+    /// Tab-separated file whose first two columns are the query and subject
+    /// identifiers of the pairs to align, after a header line, such as a filtered
+    /// results file
+    #[arg(long)]
+    pairs: PathBuf,
+
+    /// Path of the output file, standard output if not given
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Write the score, identity and alignment of every pair as text instead of
+    /// a results table
+    #[arg(long)]
+    emit_alignment: bool,
+
+    /// Write the alignment of every pair in the text layout of EMBOSS needle,
+    /// with identity, similarity and gaps, instead of a results table
+    #[arg(long, conflicts_with = "emit_alignment")]
+    needle: bool,
+
+    /// Scoring type to use for alignment, as in the previous run
+    #[arg(short, long, value_enum, default_value_t = ScoringType::Identity)]
+    scoring: ScoringType,
+}
+
+/// Runs the `realign` subcommand.
+///
+/// # Errors
+///
+/// Returns `AlignerError::InvalidInput` if a pair names a sequence missing from
+/// the input, and the errors of reading the input and pairs files or writing the
+/// output.
+pub fn run(args: RealignArgs) -> Result<(), AlignerError> {
+    let mut input = parse_input(&args.input)?;
+    check_ascii(&mut input, None)?;
+    check_lengths(&input, None)?;
+
+    let pairs = read_result_pairs(&args.pairs)?;
+    if let Some(id) = pairs
+        .iter()
+        .flat_map(|(query_id, subject_id)| [query_id, subject_id])
+        .find(|id| !input.contains_key(*id))
+    {
+        return Err(AlignerError::InvalidInput(format!(
+            "{} names sequence '{}', which is not in {}",
+            args.pairs.display(),
+            id,
+            args.input.display()
+        )));
+    }
+    info!(pairs = pairs.len(), "re-aligning pairs");
+
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    let matcher = args.scoring.matcher();
+    if args.needle {
+        let matrix = match args.scoring {
+            ScoringType::Blosum62 => "EBLOSUM62",
+            ScoringType::Identity => "identity",
+        };
+        let alignments: Vec<Vec<u8>> = pairs
+            .par_iter()
+            .map(|(query_id, subject_id)| {
+                let mut text = Vec::new();
+                needle::write_alignment(
+                    &mut text,
+                    (query_id, &input[query_id]),
+                    (subject_id, &input[subject_id]),
+                    &matcher,
+                    matrix,
+                )
+                .expect("writing to memory cannot fail");
+                text
+            })
+            .collect();
+        needle::write_header(&mut out)?;
+        for text in &alignments {
+            out.write_all(text)?;
+        }
+        needle::write_footer(&mut out)?;
+        out.flush()?;
+        return Ok(());
+    }
+    if args.emit_alignment {
+        let alignments: Vec<Vec<u8>> = pairs
+            .par_iter()
+            .map(|(query_id, subject_id)| {
+                let mut text = Vec::new();
+                write_alignment(
+                    &mut text,
+                    (query_id, &input[query_id]),
+                    (subject_id, &input[subject_id]),
+                    &matcher,
+                )
+                .expect("writing to memory cannot fail");
+                text
+            })
+            .collect();
+        for (i, text) in alignments.iter().enumerate() {
+            if i > 0 {
+                writeln!(out)?;
+            }
+            out.write_all(text)?;
+        }
+        out.flush()?;
+        return Ok(());
+    }
+
+    let results: Vec<AlignmentResult> = pairs
+        .par_iter()
+        .map(|(query_id, subject_id)| {
+            let (query_seq, subject_seq) = (&input[query_id], &input[subject_id]);
+            let (score, identity) = align_with_identity(
+                query_seq,
+                subject_seq,
+                &matcher,
+                AlignMode::Global,
+                GapPenalties::default(),
+            );
+            AlignmentResult {
+                query_id: query_id.clone(),
+                subject_id: subject_id.clone(),
+                score: Some(score),
+                status: PairStatus::Aligned,
+                error: None,
+                identity: Some(identity),
+                summary: None,
+                containment: None,
+                blast: None,
+                sam: None,
+                paf: None,
+                cigar: None,
+                max_score: None,
+                seq1_len: query_seq.len(),
+                seq2_len: subject_seq.len(),
+            }
+        })
+        .collect();
+    let format = args
+        .output
+        .as_deref()
+        .map_or(OutputFormat::Tsv, OutputFormat::from_path);
+    let mut sink = DelimitedSink::new(out, format).with_identity(true);
+    ResultSink::<i32>::open(&mut sink)?;
+    sink.write_batch(&results)?;
+    ResultSink::<i32>::close(&mut sink)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_realign_pairs() {
+        let dir = std::env::temp_dir().join(format!("aligner-realign-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.json");
+        let pairs = dir.join("pairs.tsv");
+        let output = dir.join("out.tsv");
+        fs::write(&input, r#"{"a": "MAVMTKL", "b": "MAVMKL", "c": "WWW"}"#).unwrap();
+        fs::write(&pairs, "query_id\tsubject_id\tscore\na\tb\t5\n").unwrap();
+
+        let args = |pairs: &PathBuf| RealignArgs {
+            input: input.clone(),
+            pairs: pairs.clone(),
+            output: Some(output.clone()),
+            emit_alignment: false,
+            needle: false,
+            scoring: ScoringType::Identity,
+        };
+        run(args(&pairs)).unwrap();
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "query_id\tsubject_id\tscore\tseq1_len\tseq2_len\tidentity\na\tb\t-5\t7\t6\t0.857\n"
+        );
+
+        fs::write(&pairs, "query_id\tsubject_id\na\tz\n").unwrap();
+        let error = run(args(&pairs)).unwrap_err();
+        assert!(error.to_string().contains("sequence 'z'"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
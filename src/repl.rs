@@ -0,0 +1,385 @@
+//! Interactive exploration of a sequence set.
+//!
+//! The `repl` subcommand loads and validates the input once and then reads
+//! commands from standard input, so exploratory queries do not pay for loading
+//! the sequences every time. `top` aligns one sequence against all others and keeps
+//! the hits, which `filter` then narrows down step by step.
+
+use rayon::ThreadPoolBuilder;
+use rayon::prelude::*;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::ScoringType;
+use crate::align::{AlignMode, GapPenalties, MatcherFn, align_with_identity};
+use crate::error::AlignerError;
+use crate::pair::write_alignment;
+use crate::utils::parse_input;
+use crate::validate::{check_ascii, check_lengths};
+
+/// Number of hits printed by `top` without a count
+const DEFAULT_TOP: usize = 10;
+
+const HELP: &str = "\
+Commands:
+  align <ID> <ID>          align two sequences and show the alignment
+  top <ID> [N]             align a sequence against all others and show the N best hits
+  filter <FIELD><OP><X>    keep hits with score, identity or length compared by
+                           <, <=, =, >= or > to X, e.g. filter identity>0.5
+  ids                      list the identifiers of the loaded sequences
+  help                     show this help
+  quit                     leave the session";
+
+/// Command-line arguments for the `repl` subcommand
+#[derive(clap::Args, Debug)]
+pub struct ReplArgs {
+    /// Path to the input file with the sequences to explore
+    input: PathBuf,
+
+    /// Scoring type to use for alignment
+    #[arg(short, long, value_enum, default_value_t = ScoringType::Identity)]
+    scoring: ScoringType,
+
+    /// Number of threads to use for aligning against all sequences
+    #[arg(short, long, env = "ALIGNER_THREADS")]
+    threads: Option<usize>,
+}
+
+/// A property of a hit that can be filtered on
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Field {
+    Score,
+    Identity,
+    Length,
+}
+
+/// Comparison of a hit property with a value
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+/// A filter condition such as `identity>0.5`
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Condition {
+    field: Field,
+    comparison: Comparison,
+    value: f64,
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text: String = text.split_whitespace().collect();
+        let start = text
+            .find(['<', '=', '>'])
+            .ok_or_else(|| format!("missing comparison in '{}'", text))?;
+        let (field, rest) = text.split_at(start);
+        let field = match field {
+            "score" => Field::Score,
+            "identity" => Field::Identity,
+            "length" => Field::Length,
+            _ => return Err(format!("unknown field '{}'", field)),
+        };
+        let (comparison, value) = [
+            ("<=", Comparison::LessOrEqual),
+            (">=", Comparison::GreaterOrEqual),
+            ("<", Comparison::Less),
+            (">", Comparison::Greater),
+            ("=", Comparison::Equal),
+        ]
+        .into_iter()
+        .find_map(|(operator, comparison)| rest.strip_prefix(operator).map(|v| (comparison, v)))
+        .expect("rest starts with a comparison character");
+        let value = value
+            .parse()
+            .map_err(|_| format!("invalid value '{}'", value))?;
+        Ok(Self {
+            field,
+            comparison,
+            value,
+        })
+    }
+}
+
+impl Condition {
+    fn matches(&self, hit: &Hit) -> bool {
+        let actual = match self.field {
+            Field::Score => f64::from(hit.score),
+            Field::Identity => hit.identity,
+            Field::Length => hit.length as f64,
+        };
+        match self.comparison {
+            Comparison::Less => actual < self.value,
+            Comparison::LessOrEqual => actual <= self.value,
+            Comparison::Equal => actual == self.value,
+            Comparison::GreaterOrEqual => actual >= self.value,
+            Comparison::Greater => actual > self.value,
+        }
+    }
+}
+
+/// A command of the session
+#[derive(Debug, Clone, PartialEq)]
+enum Command {
+    Align(String, String),
+    Top(String, usize),
+    Filter(Condition),
+    Ids,
+    Help,
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let arguments: Vec<&str> = words.collect();
+        match (name, arguments.as_slice()) {
+            ("align", [first, second]) => Ok(Command::Align(first.to_string(), second.to_string())),
+            ("top", [id]) => Ok(Command::Top(id.to_string(), DEFAULT_TOP)),
+            ("top", [id, count]) => count
+                .parse()
+                .map(|count| Command::Top(id.to_string(), count))
+                .map_err(|_| format!("invalid count '{}'", count)),
+            ("filter", [_, ..]) => arguments.concat().parse().map(Command::Filter),
+            ("ids", []) => Ok(Command::Ids),
+            ("help", []) => Ok(Command::Help),
+            ("quit" | "exit", []) => Ok(Command::Quit),
+            ("align" | "top" | "filter" | "ids" | "help" | "quit" | "exit", _) => Err(format!(
+                "wrong number of arguments for '{}', see 'help'",
+                name
+            )),
+            _ => Err(format!("unknown command '{}', see 'help'", name)),
+        }
+    }
+}
+
+/// Result of aligning the query of `top` against another sequence
+#[derive(Debug, Clone, PartialEq)]
+struct Hit {
+    subject_id: String,
+    score: i32,
+    identity: f64,
+    length: usize,
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{:.3}\t{}",
+            self.subject_id, self.score, self.identity, self.length
+        )
+    }
+}
+
+/// Sequences and hits of an interactive session
+struct Session {
+    sequences: HashMap<String, String>,
+    matcher: MatcherFn,
+    /// Hits of the last `top` command, narrowed by later filters
+    hits: Vec<Hit>,
+}
+
+impl Session {
+    fn sequence(&self, id: &str) -> Result<&str, String> {
+        self.sequences
+            .get(id)
+            .map(String::as_str)
+            .ok_or_else(|| format!("no sequence with identifier '{}'", id))
+    }
+
+    /// Executes a command, returning `false` once the session should end
+    fn execute(&mut self, command: Command, out: &mut impl Write) -> io::Result<bool> {
+        match command {
+            Command::Align(first, second) => {
+                match (self.sequence(&first), self.sequence(&second)) {
+                    (Ok(seq1), Ok(seq2)) => {
+                        write_alignment(out, (&first, seq1), (&second, seq2), &self.matcher)?
+                    }
+                    (Err(e), _) | (_, Err(e)) => writeln!(out, "Error: {}", e)?,
+                }
+            }
+            Command::Top(query_id, count) => match self.sequence(&query_id) {
+                Ok(query) => {
+                    self.hits = self.search(&query_id, query);
+                    write_hits(out, &self.hits, count)?;
+                }
+                Err(e) => writeln!(out, "Error: {}", e)?,
+            },
+            Command::Filter(condition) => {
+                self.hits.retain(|hit| condition.matches(hit));
+                write_hits(out, &self.hits, self.hits.len())?;
+            }
+            Command::Ids => {
+                let mut ids: Vec<&String> = self.sequences.keys().collect();
+                ids.sort();
+                for id in ids {
+                    writeln!(out, "{}", id)?;
+                }
+            }
+            Command::Help => writeln!(out, "{}", HELP)?,
+            Command::Quit => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Aligns a query against all other sequences, best hits first
+    fn search(&self, query_id: &str, query: &str) -> Vec<Hit> {
+        let mut hits: Vec<Hit> = self
+            .sequences
+            .par_iter()
+            .filter(|(id, _)| id.as_str() != query_id)
+            .map(|(id, subject)| {
+                let (score, identity) = align_with_identity(
+                    query,
+                    subject,
+                    &self.matcher,
+                    AlignMode::Global,
+                    GapPenalties::default(),
+                );
+                Hit {
+                    subject_id: id.clone(),
+                    score,
+                    identity,
+                    length: subject.len(),
+                }
+            })
+            .collect();
+        hits.sort_by(|a, b| {
+            (Reverse(a.score), &a.subject_id).cmp(&(Reverse(b.score), &b.subject_id))
+        });
+        hits
+    }
+}
+
+/// Writes the first `count` hits as a table
+fn write_hits(out: &mut impl Write, hits: &[Hit], count: usize) -> io::Result<()> {
+    writeln!(out, "subject_id\tscore\tidentity\tlength")?;
+    for hit in hits.iter().take(count) {
+        writeln!(out, "{}", hit)?;
+    }
+    if hits.len() > count {
+        writeln!(out, "... {} more hits", hits.len() - count)?;
+    }
+    Ok(())
+}
+
+/// Reads and executes commands until `quit` or the end of the input
+fn run_session(
+    session: &mut Session,
+    input: impl BufRead,
+    out: &mut impl Write,
+    prompt: bool,
+) -> io::Result<()> {
+    let mut lines = input.lines();
+    loop {
+        if prompt {
+            write!(out, "aligner> ")?;
+            out.flush()?;
+        }
+        let Some(line) = lines.next() else {
+            return Ok(());
+        };
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let keep_going = match line.parse() {
+            Ok(command) => session.execute(command, out)?,
+            Err(e) => {
+                writeln!(out, "Error: {}", e)?;
+                true
+            }
+        };
+        out.flush()?;
+        if !keep_going {
+            return Ok(());
+        }
+    }
+}
+
+/// Runs the `repl` subcommand.
+///
+/// # Errors
+///
+/// Returns an error if the input cannot be read or fails validation, or if
+/// reading commands or writing results fails. Errors in single commands are
+/// printed and the session continues.
+pub fn run(args: ReplArgs) -> Result<(), AlignerError> {
+    if let Some(n) = args.threads {
+        ThreadPoolBuilder::new()
+            .num_threads(n)
+            .build_global()
+            .expect("Failed to initialize thread pool");
+    }
+    let mut sequences = parse_input(&args.input)?;
+    check_ascii(&mut sequences, None)?;
+    check_lengths(&sequences, None)?;
+    eprintln!(
+        "Loaded {} sequences; type 'help' for the available commands",
+        sequences.len()
+    );
+
+    let mut session = Session {
+        sequences,
+        matcher: args.scoring.matcher(),
+        hits: Vec::new(),
+    };
+    let stdin = io::stdin();
+    let prompt = stdin.is_terminal();
+    run_session(&mut session, stdin.lock(), &mut io::stdout().lock(), prompt)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session() {
+        let sequences = [("a", "MAVMTKL"), ("b", "MAVMTKV"), ("c", "WWWWWWW")]
+            .into_iter()
+            .map(|(id, seq)| (id.to_string(), seq.to_string()))
+            .collect();
+        let mut session = Session {
+            sequences,
+            matcher: ScoringType::Identity.matcher(),
+            hits: Vec::new(),
+        };
+        let commands = "top a 1\nfilter identity >= 0.5\nfrobnicate\nquit\nids\n";
+        let mut out = Vec::new();
+        run_session(&mut session, commands.as_bytes(), &mut out, false).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out,
+            "subject_id\tscore\tidentity\tlength\n\
+             b\t6\t0.857\t7\n\
+             ... 1 more hits\n\
+             subject_id\tscore\tidentity\tlength\n\
+             b\t6\t0.857\t7\n\
+             Error: unknown command 'frobnicate', see 'help'\n"
+        );
+        assert_eq!(
+            "score<=-3".parse::<Condition>(),
+            Ok(Condition {
+                field: Field::Score,
+                comparison: Comparison::LessOrEqual,
+                value: -3.0
+            })
+        );
+    }
+}
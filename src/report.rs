@@ -0,0 +1,168 @@
+//! Report of the problems encountered during a run.
+//!
+//! Pairs that timed out or failed are left out of the output, and in lenient mode
+//! malformed records are skipped and sequences are aligned in a repaired form. All of
+//! them are collected while the run progresses and written to a JSON report at the
+//! end, so a long run that completes with failures still records which pairs are
+//! missing and why.
+
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::align::{AlignmentResult, PairStatus};
+use crate::error::AlignerError;
+use crate::input::InvalidRecord;
+
+/// Kind of problem recorded in the report
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProblemKind {
+    /// The alignment of a pair exceeded the per-pair timeout
+    Timeout,
+    /// Processing a pair panicked
+    Panic,
+    /// A sequence failed validation and was repaired
+    Repaired,
+    /// A malformed input record was skipped
+    Invalid,
+}
+
+/// A single problem with a pair or a sequence
+#[derive(Debug, Clone, Serialize)]
+pub struct Problem {
+    /// Kind of problem
+    pub kind: ProblemKind,
+    /// Identifier of the query sequence, or of the affected sequence
+    pub query_id: String,
+    /// Identifier of the subject sequence, if the problem concerns a pair
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject_id: Option<String>,
+    /// Description of the problem
+    pub message: String,
+}
+
+/// Problems of a run, with counts by kind
+#[derive(Debug, Default, Serialize)]
+pub struct ErrorReport {
+    /// Number of pairs that exceeded the per-pair timeout
+    pub timed_out: usize,
+    /// Number of pairs whose processing panicked
+    pub failed: usize,
+    /// Number of sequences repaired before aligning
+    pub repaired: usize,
+    /// Number of malformed input records that were skipped
+    pub invalid: usize,
+    /// All problems in the order they were encountered
+    pub problems: Vec<Problem>,
+}
+
+impl ErrorReport {
+    /// Creates an empty report
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records sequences that were repaired during validation
+    pub fn add_repaired(&mut self, ids: &[String], message: &str) {
+        self.repaired += ids.len();
+        self.problems.extend(ids.iter().map(|id| Problem {
+            kind: ProblemKind::Repaired,
+            query_id: id.clone(),
+            subject_id: None,
+            message: message.to_string(),
+        }));
+    }
+
+    /// Records input records that were skipped while parsing
+    pub fn add_invalid(&mut self, records: &[InvalidRecord]) {
+        self.invalid += records.len();
+        self.problems.extend(records.iter().map(|record| Problem {
+            kind: ProblemKind::Invalid,
+            query_id: record.id.clone(),
+            subject_id: None,
+            message: format!("record {}: {}", record.record, record.reason),
+        }));
+    }
+
+    /// Records a result if its pair timed out or failed.
+    ///
+    /// Returns `true` if the result was recorded as a problem and must not be
+    /// written to the output.
+    pub fn record<S>(&mut self, result: &AlignmentResult<S>) -> bool {
+        let (kind, message) = match result.status {
+            PairStatus::Timeout => {
+                self.timed_out += 1;
+                (ProblemKind::Timeout, "alignment exceeded the pair timeout")
+            }
+            PairStatus::Failed => {
+                self.failed += 1;
+                (
+                    ProblemKind::Panic,
+                    result.error.as_deref().unwrap_or("unknown error"),
+                )
+            }
+            PairStatus::Aligned | PairStatus::Skipped => return false,
+        };
+        self.problems.push(Problem {
+            kind,
+            query_id: result.query_id.clone(),
+            subject_id: Some(result.subject_id.clone()),
+            message: message.to_string(),
+        });
+        true
+    }
+
+    /// Returns `true` if no problems were recorded
+    pub fn is_empty(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Writes the report as pretty-printed JSON.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AlignerError::Io` if the file cannot be written.
+    pub fn write(&self, path: &Path) -> Result<(), AlignerError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Returns the path of the error report written next to `output`
+pub fn report_path(output: &Path) -> PathBuf {
+    let mut name = output.as_os_str().to_owned();
+    name.push(".errors.json");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_failures() {
+        let mut report = ErrorReport::new();
+        let mut result = AlignmentResult::for_test("a", "b", Some(3), PairStatus::Aligned);
+        assert!(!report.record(&result));
+
+        result.score = None;
+        result.status = PairStatus::Failed;
+        result.error = Some("boom".to_string());
+        assert!(report.record(&result));
+        report.add_repaired(&["c".to_string()], "non-ASCII characters repaired");
+
+        assert_eq!(
+            (report.failed, report.timed_out, report.repaired),
+            (1, 0, 1)
+        );
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["problems"][0]["kind"], "panic");
+        assert_eq!(json["problems"][0]["message"], "boom");
+        assert!(json["problems"][1].get("subject_id").is_none());
+    }
+}
@@ -0,0 +1,238 @@
+//! Metadata sidecars of runs and their replay.
+//!
+//! Every run writing its results to a file records how they were made in a
+//! sidecar next to them, `<output>.meta.json`: the version of the aligner, the
+//! command-line arguments, the working directory they were given in and the
+//! SHA-256 digest of every file the results depend on. `aligner rerun
+//! <output>.meta.json` runs the same command again after checking that none of
+//! these files changed, so published results can be reproduced from their
+//! sidecar alone.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::error::AlignerError;
+
+/// Command-line arguments for the `rerun` subcommand
+#[derive(clap::Args, Debug)]
+pub struct RerunArgs {
+    /// Metadata sidecar of the run, `<output>.meta.json`
+    metadata: PathBuf,
+
+    /// Write the results to this file instead of over those of the run
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Directory the relative paths of the run are resolved in, instead of the
+    /// working directory of the run, e.g. after copying the inputs elsewhere
+    #[arg(short = 'C', long)]
+    directory: Option<PathBuf>,
+}
+
+/// A file the results of a run depend on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputDigest {
+    /// Path or URL as given on the command line
+    pub path: PathBuf,
+    /// Hexadecimal SHA-256 digest of the file, `None` for URLs
+    pub sha256: Option<String>,
+}
+
+/// What a run was made with, written next to its results
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunMetadata {
+    /// Version of the aligner
+    pub version: String,
+    /// Command-line arguments, without the program name
+    pub arguments: Vec<String>,
+    /// Directory the relative paths of the arguments were resolved in
+    pub working_directory: PathBuf,
+    /// Files the results depend on
+    pub inputs: Vec<InputDigest>,
+    /// Path of the results
+    pub output: PathBuf,
+}
+
+/// Returns the path of the metadata sidecar written next to `output`
+pub fn metadata_path(output: &Path) -> PathBuf {
+    let mut name = output.as_os_str().to_owned();
+    name.push(".meta.json");
+    PathBuf::from(name)
+}
+
+/// Returns `true` if `path` is an input downloaded from a URL
+fn is_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with("http://") || path.starts_with("https://"))
+}
+
+/// Returns the hexadecimal SHA-256 digest of the file at `path`
+fn digest(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut BufReader::new(File::open(path)?), &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+impl RunMetadata {
+    /// Records a run with `arguments` that read `inputs` and wrote `output`,
+    /// computing the digests of the inputs.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AlignerError::Io` if an input cannot be read.
+    pub fn new(
+        arguments: Vec<String>,
+        inputs: &[&Path],
+        output: &Path,
+    ) -> Result<Self, AlignerError> {
+        let inputs = inputs
+            .iter()
+            .map(|&path| {
+                let sha256 = if is_url(path) {
+                    None
+                } else {
+                    Some(digest(path)?)
+                };
+                Ok(InputDigest {
+                    path: path.to_path_buf(),
+                    sha256,
+                })
+            })
+            .collect::<Result<_, io::Error>>()?;
+        Ok(Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            arguments,
+            working_directory: std::env::current_dir()?,
+            inputs,
+            output: output.to_path_buf(),
+        })
+    }
+
+    /// Writes the metadata as pretty-printed JSON.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AlignerError::Io` if the file cannot be written.
+    pub fn write(&self, path: &Path) -> Result<(), AlignerError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Reads the metadata written by [`RunMetadata::write`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is not a metadata sidecar.
+    pub fn read(path: &Path) -> Result<Self, AlignerError> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    /// Checks that the inputs still have the digests of the run, relative to
+    /// the current directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AlignerError::InvalidInput` if an input is missing or changed.
+    pub fn verify(&self) -> Result<(), AlignerError> {
+        for input in &self.inputs {
+            let Some(expected) = &input.sha256 else {
+                warn!(path = %input.path.display(), "downloaded input cannot be verified");
+                continue;
+            };
+            let actual = digest(&input.path).map_err(|e| {
+                AlignerError::InvalidInput(format!("{}: {}", input.path.display(), e))
+            })?;
+            if &actual != expected {
+                return Err(AlignerError::InvalidInput(format!(
+                    "{} changed since the run (SHA-256 {} instead of {})",
+                    input.path.display(),
+                    actual,
+                    expected
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prepares the replay of a run: reads its sidecar, changes into the directory
+/// its paths are relative to and verifies its inputs.
+///
+/// # Returns
+///
+/// The metadata of the run, with the output replaced if another one was given
+///
+/// # Errors
+///
+/// Returns an error if the sidecar cannot be read, the directory does not exist
+/// or an input changed.
+pub fn prepare(args: RerunArgs) -> Result<RunMetadata, AlignerError> {
+    let mut metadata = RunMetadata::read(&args.metadata)?;
+    // Resolved before leaving the directory it was given in
+    if let Some(output) = &args.output {
+        metadata.output = std::path::absolute(output)?;
+    }
+    let directory = args
+        .directory
+        .unwrap_or_else(|| metadata.working_directory.clone());
+    std::env::set_current_dir(&directory).map_err(|e| {
+        AlignerError::Config(format!("cannot change into {}: {}", directory.display(), e))
+    })?;
+    if metadata.version != env!("CARGO_PKG_VERSION") {
+        eprintln!(
+            "Warning: the run was made with version {} of the aligner, this is version {}",
+            metadata.version,
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+    metadata.verify()?;
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_verify_inputs() {
+        let dir = std::env::temp_dir().join(format!("aligner-rerun-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.fasta");
+        fs::write(&input, ">a\nMKTAYIAKQR\n").unwrap();
+        let output = dir.join("out.tsv");
+        let arguments = vec![
+            input.display().to_string(),
+            "-o".to_string(),
+            output.display().to_string(),
+        ];
+        let url = Path::new("https://example.org/seqs.fasta");
+        let metadata = RunMetadata::new(arguments, &[&input, url], &output).unwrap();
+        assert_eq!(metadata.inputs[1].sha256, None);
+
+        let path = metadata_path(&output);
+        assert_eq!(path, dir.join("out.tsv.meta.json"));
+        metadata.write(&path).unwrap();
+        let read = RunMetadata::read(&path).unwrap();
+        assert_eq!(read, metadata);
+        read.verify().unwrap();
+
+        fs::write(&input, ">a\nMKTAYIAKQQ\n").unwrap();
+        assert!(matches!(
+            read.verify(),
+            Err(AlignerError::InvalidInput(message)) if message.contains("changed since the run")
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,110 @@
+//! SAM records of aligned pairs.
+//!
+//! With `--output-format sam`, every aligned pair is aligned once more with
+//! traceback and written as a SAM record of the query mapped onto the subject as
+//! reference, so results can be viewed in genome browsers such as IGV or piped
+//! into samtools. Query residues before the first or after the last aligned
+//! subject residue are soft-clipped, and leading subject residues without a query
+//! residue shift the mapping position instead of being deletions.
+
+use bio::alignment::AlignmentOperation;
+use std::fmt::Write;
+
+use crate::align::{GapPenalties, MatcherFn};
+use crate::pair::align_pair_with;
+
+/// A query aligned with its subject as reference
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SamAlignment {
+    /// One-based position of the first subject residue aligned with the query
+    pub pos: usize,
+    /// CIGAR string of the alignment, with `=` and `X` for identical and different
+    /// residues
+    pub cigar: String,
+    /// Edit distance of the aligned part of the query to the subject
+    pub edit_distance: usize,
+    /// Score of the alignment
+    pub score: i32,
+    /// Query sequence
+    pub seq: String,
+}
+
+impl SamAlignment {
+    /// Aligns a query globally with a subject and describes the alignment in SAM
+    /// terms
+    pub fn new(query: &str, subject: &str, matcher: &MatcherFn, gaps: GapPenalties) -> Self {
+        let alignment = align_pair_with(query, subject, matcher, gaps);
+        let mut ops: Vec<(char, usize)> = Vec::new();
+        for op in &alignment.operations {
+            let (kind, len) = match op {
+                AlignmentOperation::Match => ('=', 1),
+                AlignmentOperation::Subst => ('X', 1),
+                AlignmentOperation::Ins => ('I', 1),
+                AlignmentOperation::Del => ('D', 1),
+                AlignmentOperation::Xclip(n) => ('S', *n),
+                AlignmentOperation::Yclip(n) => ('D', *n),
+            };
+            match ops.last_mut() {
+                Some((last, count)) if *last == kind => *count += len,
+                _ => ops.push((kind, len)),
+            }
+        }
+
+        // Subject residues outside the aligned part are not part of the record
+        let mut pos = alignment.ystart + 1;
+        if let Some(&('D', len)) = ops.first() {
+            pos += len;
+            ops.remove(0);
+        }
+        if let Some(&('D', _)) = ops.last() {
+            ops.pop();
+        }
+        // Query residues outside the aligned part are soft-clipped
+        let last = ops.len().saturating_sub(1);
+        for i in [0, last] {
+            if let Some(op) = ops.get_mut(i)
+                && op.0 == 'I'
+            {
+                op.0 = 'S';
+            }
+        }
+
+        let mut cigar = String::new();
+        let mut edit_distance = 0;
+        for (kind, len) in &ops {
+            write!(cigar, "{}{}", len, kind).expect("writing to a string cannot fail");
+            if matches!(kind, 'X' | 'I' | 'D') {
+                edit_distance += len;
+            }
+        }
+        if cigar.is_empty() {
+            cigar.push('*');
+        }
+        Self {
+            pos,
+            cigar,
+            edit_distance,
+            score: alignment.score,
+            seq: query.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ScoringType;
+
+    #[test]
+    fn test_sam_alignment() {
+        let subject = "MKTAYIAKQRQISFVKSHFSRQLEERLGLIEVQAPILSRVGDGTQDNLSGAEKAVQVKVKA";
+        // Residues 21-40 of the subject with a substitution
+        let query = "RQLEERLGLIDVQAPILSRV";
+        let matcher = ScoringType::Blosum62.matcher();
+        let sam = SamAlignment::new(query, subject, &matcher, GapPenalties::default());
+        assert_eq!(sam.pos, 21);
+        assert_eq!(sam.cigar, "10=1X9=");
+        assert_eq!(sam.edit_distance, 1);
+        assert_eq!(sam.seq, query);
+    }
+}
@@ -0,0 +1,242 @@
+//! Sanity report of the input of a run.
+//!
+//! Before the pairs are aligned, `--sanity-report` summarizes the input: the
+//! distribution of sequence lengths, the alphabet of the sequences (see
+//! [`crate::seqstats::Kind`]), exact duplicates, residues outside the alphabet of
+//! their sequence, and how many of the pairs the pre-filters are likely to pass,
+//! estimated from a sample of the pairs. A wrong input file shows up here within
+//! seconds rather than after hours of aligning it.
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::index::sample;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::cache::sequence_hash;
+use crate::filter::FilterChain;
+use crate::pairs::Pair;
+use crate::seqstats::Kind;
+
+/// Number of pairs passed through the pre-filters to estimate their pass rate
+pub const PAIR_SAMPLE: usize = 10_000;
+
+/// Summary of the input of a run
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SanityReport {
+    /// Number of sequences
+    pub sequences: usize,
+    /// Distribution of the sequence lengths
+    pub lengths: Lengths,
+    /// Number of protein and nucleotide sequences
+    pub alphabet: Alphabet,
+    /// Exact duplicates among the sequences
+    pub duplicates: Duplicates,
+    /// Number of sequences containing every residue outside the alphabet of the
+    /// sequence, e.g. `X` in proteins or `N` in nucleotide sequences
+    pub suspicious_residues: BTreeMap<char, usize>,
+    /// Expected number of pairs passing the pre-filters
+    pub pairs: PairEstimate,
+}
+
+/// Distribution of the sequence lengths
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct Lengths {
+    pub min: usize,
+    pub median: usize,
+    pub mean: f64,
+    pub max: usize,
+}
+
+/// Number of sequences of each alphabet
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct Alphabet {
+    pub protein: usize,
+    pub nucleotide: usize,
+}
+
+/// Exact duplicates among the sequences
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct Duplicates {
+    /// Number of sequences occurring more than once
+    pub distinct: usize,
+    /// Number of sequences that are copies of another, i.e. would be removed by
+    /// keeping one sequence of every group of duplicates
+    pub copies: usize,
+}
+
+/// Expected number of pairs passing the pre-filters
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct PairEstimate {
+    /// Number of pairs of the run
+    pub total: usize,
+    /// Number of pairs passed through the pre-filters
+    pub sampled: usize,
+    /// Number of sampled pairs passing the pre-filters
+    pub passed: usize,
+    /// Expected number of pairs passing the pre-filters
+    pub estimated: usize,
+}
+
+impl SanityReport {
+    /// Summarizes `input` and estimates how many of `pairs` pass `filters` from a
+    /// sample of at most [`PAIR_SAMPLE`] pairs
+    pub fn new(input: &HashMap<String, String>, pairs: &[Pair], filters: &FilterChain) -> Self {
+        let mut lengths: Vec<usize> = input.values().map(|seq| seq.len()).collect();
+        lengths.sort_unstable();
+        let lengths = match (lengths.first(), lengths.last()) {
+            (Some(&min), Some(&max)) => Lengths {
+                min,
+                median: lengths[lengths.len() / 2],
+                mean: lengths.iter().sum::<usize>() as f64 / lengths.len() as f64,
+                max,
+            },
+            _ => Lengths::default(),
+        };
+
+        let mut alphabet = Alphabet::default();
+        let mut suspicious_residues: BTreeMap<char, usize> = BTreeMap::new();
+        let mut copies: HashMap<u128, usize> = HashMap::new();
+        for seq in input.values() {
+            let kind = Kind::detect(seq);
+            match kind {
+                Kind::Protein => alphabet.protein += 1,
+                Kind::Nucleotide => alphabet.nucleotide += 1,
+            }
+            let mut residues: Vec<char> = seq
+                .chars()
+                .map(|residue| residue.to_ascii_uppercase())
+                .filter(|residue| !kind.is_standard(*residue))
+                .collect();
+            residues.sort_unstable();
+            residues.dedup();
+            for residue in residues {
+                *suspicious_residues.entry(residue).or_insert(0) += 1;
+            }
+            *copies.entry(sequence_hash(seq)).or_insert(0) += 1;
+        }
+        let duplicates = Duplicates {
+            distinct: copies.values().filter(|count| **count > 1).count(),
+            copies: copies.values().map(|count| count - 1).sum(),
+        };
+
+        // Seeded, so the same input always gives the same estimate
+        let mut rng = StdRng::seed_from_u64(0);
+        let sampled = pairs.len().min(PAIR_SAMPLE);
+        let passed = sample(&mut rng, pairs.len(), sampled)
+            .into_iter()
+            .filter(|&i| {
+                let (query_id, subject_id) = pairs[i];
+                filters.check(&input[query_id], &input[subject_id]).is_ok()
+            })
+            .count();
+        let estimated = if sampled == 0 {
+            0
+        } else {
+            (pairs.len() as f64 * passed as f64 / sampled as f64).round() as usize
+        };
+
+        Self {
+            sequences: input.len(),
+            lengths,
+            alphabet,
+            duplicates,
+            suspicious_residues,
+            pairs: PairEstimate {
+                total: pairs.len(),
+                sampled,
+                passed,
+                estimated,
+            },
+        }
+    }
+
+    /// Writes the report as JSON to `path`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut out, self)?;
+        writeln!(out)?;
+        out.flush()
+    }
+
+    /// Returns the lines of a human-readable summary of the report
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!(
+                "Sanity: {} sequences ({} proteins, {} nucleotide sequences), lengths {}-{} (median {}, mean {:.1})",
+                self.sequences,
+                self.alphabet.protein,
+                self.alphabet.nucleotide,
+                self.lengths.min,
+                self.lengths.max,
+                self.lengths.median,
+                self.lengths.mean
+            ),
+            format!(
+                "Sanity: {} sequences are copies of {} others",
+                self.duplicates.copies, self.duplicates.distinct
+            ),
+        ];
+        if !self.suspicious_residues.is_empty() {
+            let residues: Vec<String> = self
+                .suspicious_residues
+                .iter()
+                .map(|(residue, sequences)| format!("{} in {}", residue, sequences))
+                .collect();
+            lines.push(format!(
+                "Sanity: residues outside the alphabet: {}",
+                residues.join(", ")
+            ));
+        }
+        lines.push(format!(
+            "Sanity: {} of {} sampled pairs pass the pre-filters, about {} of {} pairs",
+            self.pairs.passed, self.pairs.sampled, self.pairs.estimated, self.pairs.total
+        ));
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pairs::{PairGenerator, Triangle};
+
+    #[test]
+    fn test_sanity_report() {
+        let input: HashMap<String, String> = [
+            ("a", "MKTAYIAKQRQISFVKSHFSRQ"),
+            ("b", "MKTAYIAKQRQISFVKSHFSRQ"),
+            ("c", "MKTAYIAKQXQISFVKSHFSRQLEE"),
+            ("d", "ACGTACGTNACGT"),
+        ]
+        .into_iter()
+        .map(|(id, seq)| (id.to_string(), seq.to_string()))
+        .collect();
+        let pairs = Triangle.pairs(&input);
+        let filters = FilterChain::from_options(Some(0.5), 1, &[]);
+        let report = SanityReport::new(&input, &pairs, &filters);
+
+        assert_eq!((report.lengths.min, report.lengths.max), (13, 25));
+        assert_eq!(
+            (report.alphabet.protein, report.alphabet.nucleotide),
+            (3, 1)
+        );
+        assert_eq!(
+            (report.duplicates.distinct, report.duplicates.copies),
+            (1, 1)
+        );
+        assert_eq!(
+            report.suspicious_residues,
+            BTreeMap::from([('N', 1), ('X', 1)])
+        );
+        // Only the pairs among the three proteins share enough k-mers
+        assert_eq!((report.pairs.total, report.pairs.sampled), (6, 6));
+        assert_eq!(report.pairs.estimated, 3);
+    }
+}
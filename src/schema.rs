@@ -0,0 +1,173 @@
+//! JSON Schema of the input documents.
+//!
+//! The schema shipped in `schema/input.schema.json` describes the accepted input
+//! shapes. It is embedded in the binary, printed by the `schema` subcommand for use
+//! in editors and pipelines, and used to explain why an input document was rejected:
+//! every violation is reported with the path of the offending value, e.g.
+//! `sequences["Q6A0I3"] must be a string, found a number`.
+
+use jsonschema::error::{TypeKind, ValidationErrorKind};
+use serde_json::Value;
+
+use crate::error::AlignerError;
+use crate::input::{ParseOptions, value_kind};
+
+/// The JSON Schema of input documents
+pub const INPUT_SCHEMA: &str = include_str!("../schema/input.schema.json");
+
+/// Number of violations listed in error messages
+const REPORTED_VIOLATIONS: usize = 5;
+
+/// Returns the input schema for the record fields selected in `options`
+pub fn input_schema(options: &ParseOptions) -> Value {
+    let mut schema: Value =
+        serde_json::from_str(INPUT_SCHEMA).expect("embedded schema is valid JSON");
+    let renames = [
+        ("entry", "sequence", &options.sequence_key),
+        ("record", "sequence", &options.sequence_key),
+        ("record", "id", &options.id_key),
+    ];
+    for (definition, field, key) in renames {
+        if field == key {
+            continue;
+        }
+        let definition = &mut schema["$defs"][definition];
+        let properties = definition["properties"]
+            .as_object_mut()
+            .expect("record definitions have properties");
+        let property = properties.remove(field).expect("field is defined");
+        properties.insert(key.clone(), property);
+        for required in definition["required"]
+            .as_array_mut()
+            .expect("record definitions have required fields")
+        {
+            if required == field {
+                *required = Value::from(key.as_str());
+            }
+        }
+    }
+    schema
+}
+
+/// Checks a parsed input document against the input schema.
+///
+/// # Errors
+///
+/// Returns `AlignerError::InvalidInput` listing the first violations with the
+/// paths of the offending values.
+pub fn validate(document: &Value, options: &ParseOptions) -> Result<(), AlignerError> {
+    let validator =
+        jsonschema::validator_for(&input_schema(options)).expect("embedded schema is valid");
+
+    let violations: Vec<String> = validator
+        .iter_errors(document)
+        .map(|error| {
+            let path = describe_path(document, error.instance_path.as_str());
+            match &error.kind {
+                ValidationErrorKind::Type { kind } => {
+                    let expected: Vec<String> = match kind {
+                        TypeKind::Single(expected) => vec![expected.to_string()],
+                        TypeKind::Multiple(expected) => {
+                            expected.iter().map(|t| t.to_string()).collect()
+                        }
+                    };
+                    let expected: Vec<String> = expected
+                        .iter()
+                        .map(|t| format!("{} {}", article(t), t))
+                        .collect();
+                    format!(
+                        "{} must be {}, found {}",
+                        path,
+                        expected.join(" or "),
+                        value_kind(&error.instance)
+                    )
+                }
+                ValidationErrorKind::Required { property } => {
+                    format!("{} must have a field {}", path, property)
+                }
+                _ => format!("{}: {}", path, error),
+            }
+        })
+        .collect();
+    if violations.is_empty() {
+        return Ok(());
+    }
+
+    let listed: Vec<&str> = violations
+        .iter()
+        .take(REPORTED_VIOLATIONS)
+        .map(String::as_str)
+        .collect();
+    Err(AlignerError::InvalidInput(format!(
+        "{} values do not match the input schema: {}{}",
+        violations.len(),
+        listed.join("; "),
+        if violations.len() > REPORTED_VIOLATIONS {
+            "; ..."
+        } else {
+            ""
+        }
+    )))
+}
+
+/// Turns a JSON pointer into a path such as `sequences["Q6A0I3"]`, using the
+/// document to tell object keys from array indices
+fn describe_path(document: &Value, pointer: &str) -> String {
+    let mut path = "sequences".to_string();
+    let mut current = Some(document);
+    for segment in pointer.split('/').skip(1) {
+        let segment = segment.replace("~1", "/").replace("~0", "~");
+        current = match current {
+            Some(Value::Array(items)) => {
+                path.push_str(&format!("[{}]", segment));
+                segment
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|index| items.get(index))
+            }
+            Some(Value::Object(fields)) => {
+                path.push_str(&format!("[{:?}]", segment));
+                fields.get(&segment)
+            }
+            _ => None,
+        };
+    }
+    path
+}
+
+fn article(noun: &str) -> &'static str {
+    if noun.starts_with(['a', 'e', 'i', 'o', 'u']) {
+        "an"
+    } else {
+        "a"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate() {
+        let defaults = ParseOptions::default();
+        let renamed = ParseOptions {
+            sequence_key: "seq".to_string(),
+            id_key: "accession".to_string(),
+            ..ParseOptions::default()
+        };
+        assert!(validate(&json!({"Q6A0I3": "MAVMT"}), &defaults).is_ok());
+        assert!(validate(&json!([{"accession": "a", "seq": "ACGT"}]), &renamed).is_ok());
+
+        let error = validate(&json!({"Q6A0I3": 42, "a/b": "ACGT"}), &defaults).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid input: 1 values do not match the input schema: \
+             sequences[\"Q6A0I3\"] must be an object or a string, found a number"
+        );
+        let error = validate(&json!([{"id": "a"}, "ACGT"]), &defaults).unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("sequences[0] must have a field \"sequence\""));
+        assert!(message.contains("sequences[1] must be an object, found a string"));
+    }
+}
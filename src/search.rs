@@ -0,0 +1,203 @@
+//! Nearest-neighbor search of queries against an indexed database.
+//!
+//! The `search` subcommand loads a k-mer index written by `index`, takes the
+//! targets sharing the most k-mers with every query as candidates, scores them
+//! and keeps the best `--top` hits of every query. Only these hits are aligned
+//! again with traceback for their identity.
+
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::ScoringType;
+use crate::align::{
+    AlignMode, AlignmentResult, GapPenalties, PairStatus, align, align_with_identity,
+};
+use crate::error::AlignerError;
+use crate::hits::TopHits;
+use crate::index::KmerIndex;
+use crate::sink::{DelimitedSink, OutputFormat, ResultSink};
+use crate::utils::parse_input;
+use crate::validate::{check_ascii, check_lengths};
+
+/// Command-line arguments for the `search` subcommand
+#[derive(clap::Args, Debug)]
+pub struct SearchArgs {
+    /// Input file with the query sequences, in any supported input format
+    input: PathBuf,
+
+    /// Index of the target sequences, written by the `index` subcommand
+    #[arg(long)]
+    target_index: PathBuf,
+
+    /// Number of hits to write per query
+    #[arg(long, default_value = "5")]
+    top: NonZeroUsize,
+
+    /// Minimum number of distinct k-mers a target shares with a query to be
+    /// aligned
+    #[arg(long, default_value = "1")]
+    min_shared: usize,
+
+    /// Maximum number of candidates aligned per query, those sharing the most
+    /// k-mers first
+    #[arg(long, default_value = "200")]
+    max_candidates: usize,
+
+    /// Path of the output file, standard output if not given
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Scoring type to use for alignment
+    #[arg(short, long, value_enum, default_value_t = ScoringType::Identity)]
+    scoring: ScoringType,
+}
+
+/// Runs the `search` subcommand.
+///
+/// # Errors
+///
+/// Returns the errors of reading the queries and the index and of writing the
+/// output.
+pub fn run(args: SearchArgs) -> Result<(), AlignerError> {
+    let mut queries = parse_input(&args.input)?;
+    check_ascii(&mut queries, None)?;
+    check_lengths(&queries, None)?;
+    let index = KmerIndex::read(&args.target_index)?;
+    info!(
+        queries = queries.len(),
+        targets = index.len(),
+        "searching index"
+    );
+
+    let mut queries: Vec<(String, String)> = queries.into_iter().collect();
+    queries.sort_unstable();
+    let matcher = args.scoring.matcher();
+    let searched: Vec<(Vec<AlignmentResult>, usize)> = queries
+        .par_iter()
+        .map(|(query_id, query_seq)| {
+            let candidates = index.candidates(query_seq, args.min_shared, args.max_candidates);
+            let mut hits = TopHits::new(args.top.get(), false);
+            for &(target, _) in &candidates {
+                let target_seq = index.sequence(target);
+                hits.offer(AlignmentResult {
+                    query_id: query_id.clone(),
+                    subject_id: index.id(target).to_string(),
+                    score: Some(align(
+                        query_seq,
+                        target_seq,
+                        &matcher,
+                        AlignMode::Global,
+                        GapPenalties::default(),
+                    )),
+                    status: PairStatus::Aligned,
+                    error: None,
+                    identity: None,
+                    summary: None,
+                    containment: None,
+                    blast: None,
+                    sam: None,
+                    paf: None,
+                    cigar: None,
+                    max_score: None,
+                    seq1_len: query_seq.len(),
+                    seq2_len: target_seq.len(),
+                });
+            }
+            let hits = hits
+                .into_results()
+                .into_iter()
+                .map(|hit| {
+                    let target = index.position(&hit.subject_id).expect("hits are targets");
+                    let (_, identity) = align_with_identity(
+                        query_seq,
+                        index.sequence(target),
+                        &matcher,
+                        AlignMode::Global,
+                        GapPenalties::default(),
+                    );
+                    AlignmentResult {
+                        identity: Some(identity),
+                        summary: None,
+                        containment: None,
+                        blast: None,
+                        sam: None,
+                        paf: None,
+                        cigar: None,
+                        max_score: None,
+                        ..hit
+                    }
+                })
+                .collect();
+            (hits, candidates.len())
+        })
+        .collect();
+
+    let out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    let format = args
+        .output
+        .as_deref()
+        .map_or(OutputFormat::Tsv, OutputFormat::from_path);
+    let mut sink = DelimitedSink::new(out, format).with_identity(true);
+    ResultSink::<i32>::open(&mut sink)?;
+    let mut aligned = 0;
+    for (hits, candidates) in &searched {
+        sink.write_batch(hits)?;
+        aligned += candidates;
+    }
+    ResultSink::<i32>::close(&mut sink)?;
+    eprintln!(
+        "Searched {} queries against {} targets: {} candidate pairs aligned",
+        queries.len(),
+        index.len(),
+        aligned
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+
+    #[test]
+    fn test_search_index() {
+        let dir = std::env::temp_dir().join(format!("aligner-search-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let queries = dir.join("queries.fasta");
+        let target_index = dir.join("db.idx");
+        let output = dir.join("hits.tsv");
+        fs::write(&queries, ">q\nMAVMTKL\n").unwrap();
+        let targets: HashMap<String, String> = [("a", "MAVMTKL"), ("b", "MAVMKL"), ("c", "WWWWWW")]
+            .into_iter()
+            .map(|(id, seq)| (id.to_string(), seq.to_string()))
+            .collect();
+        KmerIndex::build(targets, 3).write(&target_index).unwrap();
+
+        run(SearchArgs {
+            input: queries,
+            target_index,
+            top: NonZeroUsize::new(5).unwrap(),
+            min_shared: 1,
+            max_candidates: 200,
+            output: Some(output.clone()),
+            scoring: ScoringType::Identity,
+        })
+        .unwrap();
+        // "c" shares no k-mer with the query and is never aligned
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "query_id\tsubject_id\tscore\tseq1_len\tseq2_len\tidentity\n\
+             q\ta\t7\t7\t7\t1.000\n\
+             q\tb\t-5\t7\t6\t0.857\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,393 @@
+//! Per-sequence statistics for checking inputs before aligning them.
+//!
+//! The `seqstats` subcommand writes the length and residue composition of every
+//! sequence, the GC content of nucleotide sequences and the molecular weight and
+//! isoelectric point of proteins. Sequences are told apart by their alphabet, so
+//! one input may hold both. Sequences that look out of place are flagged: those
+//! whose length is an outlier by its robust z-score (the deviation from the median
+//! length in units of the scaled median absolute deviation), those with many
+//! ambiguous or non-standard residues, and those of the minority alphabet.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::error::AlignerError;
+use crate::utils::parse_input;
+
+/// Command-line arguments for the `seqstats` subcommand
+#[derive(clap::Args, Debug)]
+pub struct SeqStatsArgs {
+    /// Input file with the sequences to inspect, in any supported input format
+    input: PathBuf,
+
+    /// Path of the tab-separated table with a row per sequence
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Path of a tab-separated table with the count and fraction of every residue
+    /// of every sequence
+    #[arg(long)]
+    composition: Option<PathBuf>,
+
+    /// Robust z-score of the length above which a sequence is flagged
+    #[arg(long, default_value_t = 3.5)]
+    max_length_z: f64,
+
+    /// Fraction of ambiguous or non-standard residues above which a sequence is
+    /// flagged
+    #[arg(long, default_value_t = 0.05)]
+    max_ambiguous: f64,
+}
+
+/// Alphabet of a sequence
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kind {
+    Protein,
+    Nucleotide,
+}
+
+impl Kind {
+    /// Returns the name of the alphabet, as written
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::Protein => "protein",
+            Kind::Nucleotide => "nucleotide",
+        }
+    }
+
+    /// Returns the kind of `sequence`, nucleotide if at least 90% of its letters
+    /// are nucleotides or `N`
+    pub fn detect(sequence: &str) -> Self {
+        let letters = sequence.chars().filter(char::is_ascii_alphabetic).count();
+        let nucleotides = sequence
+            .chars()
+            .filter(|c| matches!(c.to_ascii_uppercase(), 'A' | 'C' | 'G' | 'T' | 'U' | 'N'))
+            .count();
+        if letters > 0 && nucleotides * 10 >= letters * 9 {
+            Kind::Nucleotide
+        } else {
+            Kind::Protein
+        }
+    }
+
+    /// Returns `true` if `residue` is an unambiguous residue of the alphabet
+    pub fn is_standard(self, residue: char) -> bool {
+        match self {
+            Kind::Protein => AVERAGE_MASSES.iter().any(|(r, _)| *r == residue),
+            Kind::Nucleotide => matches!(residue, 'A' | 'C' | 'G' | 'T' | 'U'),
+        }
+    }
+}
+
+/// Average masses of the amino acid residues in Da, i.e. without the water lost
+/// in the peptide bond, as used by ExPASy ProtParam
+const AVERAGE_MASSES: [(char, f64); 20] = [
+    ('A', 71.0788),
+    ('R', 156.1875),
+    ('N', 114.1038),
+    ('D', 115.0886),
+    ('C', 103.1388),
+    ('E', 129.1155),
+    ('Q', 128.1307),
+    ('G', 57.0519),
+    ('H', 137.1411),
+    ('I', 113.1594),
+    ('L', 113.1594),
+    ('K', 128.1741),
+    ('M', 131.1926),
+    ('F', 147.1766),
+    ('P', 97.1167),
+    ('S', 87.0782),
+    ('T', 101.1051),
+    ('W', 186.2132),
+    ('Y', 163.1760),
+    ('V', 99.1326),
+];
+
+/// Average mass of water in Da
+const WATER_MASS: f64 = 18.01524;
+
+/// pKa values of the positively charged groups, after Bjellqvist et al.
+const POSITIVE_PKA: [(char, f64); 3] = [('K', 10.0), ('R', 12.0), ('H', 5.98)];
+
+/// pKa values of the negatively charged groups, after Bjellqvist et al.
+const NEGATIVE_PKA: [(char, f64); 4] = [('D', 4.05), ('E', 4.45), ('C', 9.0), ('Y', 10.0)];
+
+/// pKa values of the N- and C-terminus
+const N_TERMINUS_PKA: f64 = 7.5;
+const C_TERMINUS_PKA: f64 = 3.55;
+
+/// Statistics of a sequence
+#[derive(Debug, Clone)]
+pub struct SeqStats {
+    pub id: String,
+    pub kind: Kind,
+    pub length: usize,
+    /// Number of occurrences of every upper-case residue
+    pub counts: BTreeMap<char, usize>,
+    /// Fraction of ambiguous or non-standard residues
+    pub ambiguous: f64,
+    /// Fraction of G and C among the unambiguous bases, for nucleotide sequences
+    pub gc: Option<f64>,
+    /// Molecular weight in Da of the standard residues, for proteins
+    pub molecular_weight: Option<f64>,
+    /// pH at which the net charge is zero, for proteins
+    pub isoelectric_point: Option<f64>,
+    /// Reasons the sequence looks out of place, empty for most sequences
+    pub flags: Vec<&'static str>,
+}
+
+impl SeqStats {
+    /// Computes the statistics of a sequence, without flags
+    pub fn new(id: &str, sequence: &str) -> Self {
+        let kind = Kind::detect(sequence);
+        let mut counts = BTreeMap::new();
+        for residue in sequence.chars() {
+            *counts.entry(residue.to_ascii_uppercase()).or_insert(0) += 1;
+        }
+        let length = sequence.chars().count();
+        let count = |residue: char| counts.get(&residue).copied().unwrap_or(0);
+        let standard: usize = counts
+            .iter()
+            .filter(|(residue, _)| kind.is_standard(**residue))
+            .map(|(_, count)| count)
+            .sum();
+        let ambiguous = if length == 0 {
+            0.0
+        } else {
+            (length - standard) as f64 / length as f64
+        };
+        let (gc, molecular_weight, isoelectric_point) = match kind {
+            Kind::Nucleotide => {
+                let gc_bases = (count('G') + count('C')) as f64;
+                let gc = (standard > 0).then(|| gc_bases / standard as f64);
+                (gc, None, None)
+            }
+            Kind::Protein => {
+                let residues: f64 = AVERAGE_MASSES
+                    .iter()
+                    .map(|(residue, mass)| count(*residue) as f64 * mass)
+                    .sum();
+                let weight = (standard > 0).then_some(residues + WATER_MASS);
+                let pi = (standard > 0).then(|| isoelectric_point(&counts));
+                (None, weight, pi)
+            }
+        };
+        Self {
+            id: id.to_string(),
+            kind,
+            length,
+            counts,
+            ambiguous,
+            gc,
+            molecular_weight,
+            isoelectric_point,
+            flags: Vec::new(),
+        }
+    }
+}
+
+/// Net charge of a protein with the given residue counts at `ph`
+fn net_charge(counts: &BTreeMap<char, usize>, ph: f64) -> f64 {
+    let count = |residue: char| counts.get(&residue).copied().unwrap_or(0) as f64;
+    let positive = |pka: f64| 1.0 / (1.0 + 10f64.powf(ph - pka));
+    let negative = |pka: f64| 1.0 / (1.0 + 10f64.powf(pka - ph));
+    let mut charge = positive(N_TERMINUS_PKA) - negative(C_TERMINUS_PKA);
+    for (residue, pka) in POSITIVE_PKA {
+        charge += count(residue) * positive(pka);
+    }
+    for (residue, pka) in NEGATIVE_PKA {
+        charge -= count(residue) * negative(pka);
+    }
+    charge
+}
+
+/// Finds the pH between 0 and 14 at which the net charge is zero by bisection,
+/// as ExPASy ProtParam does
+fn isoelectric_point(counts: &BTreeMap<char, usize>) -> f64 {
+    let (mut low, mut high) = (0.0, 14.0);
+    while high - low > 1e-4 {
+        let ph = (low + high) / 2.0;
+        if net_charge(counts, ph) > 0.0 {
+            low = ph;
+        } else {
+            high = ph;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// Returns the median of sorted values
+fn median(sorted: &[f64]) -> f64 {
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
+
+/// Computes the statistics of all sequences, sorted by identifier, and flags
+/// length outliers, ambiguous sequences and sequences of the minority alphabet
+pub fn compute(
+    input: &HashMap<String, String>,
+    max_length_z: f64,
+    max_ambiguous: f64,
+) -> Vec<SeqStats> {
+    let mut stats: Vec<SeqStats> = input
+        .iter()
+        .map(|(id, seq)| SeqStats::new(id, seq))
+        .collect();
+    stats.sort_unstable_by(|a, b| a.id.cmp(&b.id));
+    if stats.is_empty() {
+        return stats;
+    }
+
+    let nucleotides = stats.iter().filter(|s| s.kind == Kind::Nucleotide).count();
+    let majority = if nucleotides * 2 > stats.len() {
+        Kind::Nucleotide
+    } else {
+        Kind::Protein
+    };
+    let mut lengths: Vec<f64> = stats.iter().map(|s| s.length as f64).collect();
+    lengths.sort_unstable_by(f64::total_cmp);
+    let median_length = median(&lengths);
+    let mut deviations: Vec<f64> = lengths.iter().map(|l| (l - median_length).abs()).collect();
+    deviations.sort_unstable_by(f64::total_cmp);
+    // Scaled so that the z-score of normally distributed lengths has unit variance
+    let scale = median(&deviations) / 0.6745;
+
+    for s in &mut stats {
+        if scale > 0.0 && (s.length as f64 - median_length).abs() / scale > max_length_z {
+            s.flags.push("length");
+        }
+        if s.ambiguous > max_ambiguous {
+            s.flags.push("ambiguous");
+        }
+        if s.kind != majority {
+            s.flags.push("alphabet");
+        }
+    }
+    stats
+}
+
+/// Formats an optional value with `precision` decimals, empty if it is missing
+fn optional(value: Option<f64>, precision: usize) -> String {
+    value
+        .map(|value| format!("{:.*}", precision, value))
+        .unwrap_or_default()
+}
+
+/// Runs the `seqstats` subcommand
+///
+/// # Errors
+///
+/// Returns an error if the input cannot be read or an output cannot be written.
+pub fn run(args: SeqStatsArgs) -> Result<(), AlignerError> {
+    let input = parse_input(&args.input)?;
+    info!(sequences = input.len(), "computing sequence statistics");
+    let stats = compute(&input, args.max_length_z, args.max_ambiguous);
+
+    let mut out = BufWriter::new(File::create(&args.output)?);
+    writeln!(
+        out,
+        "id\ttype\tlength\tgc\tmolecular_weight\tisoelectric_point\tambiguous\tflags"
+    )?;
+    for s in &stats {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{:.3}\t{}",
+            s.id,
+            s.kind.as_str(),
+            s.length,
+            optional(s.gc, 3),
+            optional(s.molecular_weight, 1),
+            optional(s.isoelectric_point, 2),
+            s.ambiguous,
+            s.flags.join(",")
+        )?;
+    }
+    out.flush()?;
+
+    if let Some(path) = &args.composition {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "id\tresidue\tcount\tfraction")?;
+        for s in &stats {
+            for (residue, count) in &s.counts {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{:.4}",
+                    s.id,
+                    residue,
+                    count,
+                    *count as f64 / s.length as f64
+                )?;
+            }
+        }
+        out.flush()?;
+    }
+
+    let nucleotides = stats.iter().filter(|s| s.kind == Kind::Nucleotide).count();
+    let mut lengths: Vec<f64> = stats.iter().map(|s| s.length as f64).collect();
+    lengths.sort_unstable_by(f64::total_cmp);
+    eprintln!(
+        "{} sequences ({} proteins, {} nucleotide sequences)",
+        stats.len(),
+        stats.len() - nucleotides,
+        nucleotides
+    );
+    if !lengths.is_empty() {
+        eprintln!(
+            "Lengths: min {}, median {}, max {}",
+            lengths[0],
+            median(&lengths),
+            lengths[lengths.len() - 1]
+        );
+    }
+    for flag in ["length", "ambiguous", "alphabet"] {
+        let flagged = stats.iter().filter(|s| s.flags.contains(&flag)).count();
+        if flagged > 0 {
+            eprintln!("Flagged {} sequences: {}", flag, flagged);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute() {
+        let mut input: HashMap<String, String> = (0..8)
+            .map(|i| {
+                (
+                    format!("p{}", i),
+                    "MKTAYIAKQRQISFVKSHFSRQ".repeat(1 + i % 2),
+                )
+            })
+            .collect();
+        input.insert("long".to_string(), "MKTAYIAKQR".repeat(40));
+        input.insert("dna".to_string(), "ATGCGCATTANNATGCGCAT".to_string());
+        let stats = compute(&input, 3.5, 0.05);
+        let flags: HashMap<&str, &[&str]> = stats
+            .iter()
+            .map(|s| (s.id.as_str(), s.flags.as_slice()))
+            .collect();
+        assert_eq!(flags["p0"], [] as [&str; 0]);
+        assert_eq!(flags["long"], ["length"]);
+        assert_eq!(flags["dna"], ["ambiguous", "alphabet"]);
+
+        let dna = stats.iter().find(|s| s.id == "dna").unwrap();
+        assert_eq!(dna.kind, Kind::Nucleotide);
+        assert!((dna.gc.unwrap() - 8.0 / 18.0).abs() < 1e-9);
+        assert_eq!(dna.molecular_weight, None);
+
+        let protein = SeqStats::new("p", "MKTAYIAKQRQISFVKSHFSRQ");
+        assert!((protein.molecular_weight.unwrap() - 2655.12).abs() < 0.01);
+        assert!((protein.isoelectric_point.unwrap() - 11.17).abs() < 0.01);
+    }
+}
@@ -0,0 +1,1088 @@
+//! Destinations of alignment results.
+//!
+//! Results of a run are written through the [`ResultSink`] trait, so the pair loop
+//! does not depend on where they end up. Sinks receive the results in batches and
+//! are opened before the first and closed after the last batch, which gives
+//! formats with a header or a footer a place to write them.
+
+use arrow_array::{
+    ArrayRef, Float32Array, Float64Array, Int32Array, RecordBatch, StringArray, UInt64Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use clap::ValueEnum;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{Connection, params_from_iter};
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::align::{AlignmentResult, Score};
+use crate::blast;
+use crate::columns::{Column, ColumnType, Value};
+use crate::compress;
+use crate::source::quote_identifier;
+use crate::taxonomy::Taxonomy;
+
+/// Number of results collected before they are handed to a sink
+pub const BATCH_SIZE: usize = 1024;
+
+/// A destination for alignment results
+pub trait ResultSink<S: Score> {
+    /// Prepares the destination for the first batch, e.g. by writing a header.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the destination cannot be written.
+    fn open(&mut self) -> io::Result<()>;
+
+    /// Writes a batch of results.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the destination cannot be written. Part of the batch
+    /// may have been written.
+    fn write_batch(&mut self, results: &[AlignmentResult<S>]) -> io::Result<()>;
+
+    /// Makes the results written so far durable, e.g. by flushing buffers.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the destination cannot be written.
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// Completes the output after the last batch. No batches are written after
+    /// closing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the destination cannot be written.
+    fn close(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+/// Format of the results file
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Tab-separated values
+    Tsv,
+    /// Comma-separated values, with fields quoted where necessary
+    Csv,
+    /// A JSON object per result and line (JSON Lines)
+    Jsonl,
+    /// Apache Parquet, with typed columns
+    Parquet,
+    /// The 12 columns of BLAST's tabular output (outfmt 6), from a local
+    /// alignment of every aligned pair, for BLOSUM62 scoring with the default gap
+    /// penalties
+    Blast6,
+    /// SAM records of the queries mapped onto their subjects as references
+    Sam,
+    /// PAF records of the aligned blocks of the pairs, as written by minimap2
+    Paf,
+    /// A SQLite database with the results in an indexed table
+    Sqlite,
+}
+
+impl OutputFormat {
+    /// Returns the format matching the extension of `path`, tab-separated unless
+    /// it ends in `.csv`, `.jsonl`, `.ndjson`, `.parquet`, `.sam`, `.paf`,
+    /// `.sqlite`, `.sqlite3` or `.db`. A compression extension such as `.gz` is
+    /// skipped.
+    pub fn from_path(path: &Path) -> Self {
+        let path = match (
+            compress::Compression::from_extension(path),
+            path.file_stem(),
+        ) {
+            (Some(_), Some(stem)) => Path::new(stem),
+            _ => path,
+        };
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("csv") => OutputFormat::Csv,
+            Some("jsonl" | "ndjson") => OutputFormat::Jsonl,
+            Some("parquet") => OutputFormat::Parquet,
+            Some("sam") => OutputFormat::Sam,
+            Some("paf") => OutputFormat::Paf,
+            Some("sqlite" | "sqlite3" | "db") => OutputFormat::Sqlite,
+            _ => OutputFormat::Tsv,
+        }
+    }
+
+    /// Returns the delimiter of the fields of tab- and comma-separated values, or
+    /// `None` for the other formats
+    pub fn delimiter(self) -> Option<u8> {
+        match self {
+            OutputFormat::Tsv => Some(b'\t'),
+            OutputFormat::Csv => Some(b','),
+            _ => None,
+        }
+    }
+}
+
+/// Writes results as delimiter-separated rows to a file or standard output
+pub struct DelimitedSink<W: Write> {
+    writer: W,
+    format: OutputFormat,
+    header: bool,
+    status: bool,
+    identity: bool,
+    cigar: bool,
+    max_score: bool,
+    containment: Option<f64>,
+    taxonomy: Option<Arc<Taxonomy>>,
+    /// Columns selected instead of the default ones
+    selected: Option<Vec<Column>>,
+    /// Columns written, from opening the sink
+    columns: Vec<Column>,
+}
+
+impl<W: Write> DelimitedSink<W> {
+    /// Creates a sink writing rows with a header line to `writer`, which should be
+    /// buffered
+    pub fn new(writer: W, format: OutputFormat) -> Self {
+        Self {
+            writer,
+            format,
+            header: true,
+            status: false,
+            identity: false,
+            cigar: false,
+            max_score: false,
+            containment: None,
+            taxonomy: None,
+            selected: None,
+            columns: Vec::new(),
+        }
+    }
+
+    /// Adds a `status` column after the score, as in typed formats, for scores that
+    /// cannot be told apart from the `-1` of pairs that were not aligned, such as
+    /// negated edit distances
+    pub fn with_status(mut self, status: bool) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Adds an `identity` column after the other columns, for engines that compute
+    /// the alignment with traceback. Pairs without an identity leave it empty.
+    pub fn with_identity(mut self, identity: bool) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// Adds a `cigar` column with the alignment of every pair, after the identity
+    /// column. Pairs without an alignment leave it empty.
+    pub fn with_cigar(mut self, cigar: bool) -> Self {
+        self.cigar = cigar;
+        self
+    }
+
+    /// Adds a `max_score` column with the score of the shorter sequence of a pair
+    /// aligned with itself, and a `score_ratio` column with the fraction of it the
+    /// pair reached. Pairs that were not aligned leave them empty.
+    pub fn with_max_score(mut self, max_score: bool) -> Self {
+        self.max_score = max_score;
+        self
+    }
+
+    /// Adds a `containment` column with the fraction of the shorter sequence of a
+    /// pair found in the longer one, and a `contained` column naming the `query` or
+    /// `subject` if it is contained in the other according to `min_fraction`.
+    pub fn with_containment(mut self, min_fraction: Option<f64>) -> Self {
+        self.containment = min_fraction;
+        self
+    }
+
+    /// Adds the columns of a taxonomy after the other columns, annotating every
+    /// row with the taxonomy of its subject. Subjects missing from the taxonomy
+    /// leave them empty.
+    pub fn with_taxonomy(mut self, taxonomy: Option<Arc<Taxonomy>>) -> Self {
+        self.taxonomy = taxonomy;
+        self
+    }
+
+    /// Writes exactly the given columns in the given order instead of the default
+    /// columns and those added by the other options
+    pub fn with_columns(mut self, columns: Option<Vec<Column>>) -> Self {
+        self.selected = columns;
+        self
+    }
+
+    /// Leaves out the header, for appending to an existing results file
+    pub fn without_header(mut self) -> Self {
+        self.header = false;
+        self
+    }
+
+    fn write_row(&mut self, fields: &[&dyn fmt::Display]) -> io::Result<()> {
+        for (i, field) in fields.iter().enumerate() {
+            if i > 0 {
+                let delimiter = match self.format {
+                    OutputFormat::Csv => ",",
+                    _ => "\t",
+                };
+                self.writer.write_all(delimiter.as_bytes())?;
+            }
+            match self.format {
+                OutputFormat::Csv => {
+                    let field = field.to_string();
+                    if field.contains([',', '"', '\n', '\r']) {
+                        write!(self.writer, "\"{}\"", field.replace('"', "\"\""))?;
+                    } else {
+                        self.writer.write_all(field.as_bytes())?;
+                    }
+                }
+                // Identifiers are sanitized, so fields never contain tabs or line breaks
+                _ => write!(self.writer, "{}", field)?,
+            }
+        }
+        self.writer.write_all(b"\n")
+    }
+}
+
+impl<S: Score, W: Write> ResultSink<S> for DelimitedSink<W> {
+    fn open(&mut self) -> io::Result<()> {
+        let defaults = if self.status {
+            Column::typed_defaults
+        } else {
+            Column::defaults
+        };
+        self.columns = self.selected.clone().unwrap_or_else(|| {
+            defaults(
+                self.identity,
+                self.cigar,
+                self.max_score,
+                self.containment.is_some(),
+                self.taxonomy.as_deref(),
+            )
+        });
+        if self.header {
+            // Shared, so the names can borrow from it while they are written
+            let taxonomy = self.taxonomy.clone();
+            let names: Vec<&str> = self
+                .columns
+                .iter()
+                .map(|column| column.name(taxonomy.as_deref()))
+                .collect();
+            let names: Vec<&dyn fmt::Display> =
+                names.iter().map(|name| name as &dyn fmt::Display).collect();
+            self.write_row(&names)?;
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self, results: &[AlignmentResult<S>]) -> io::Result<()> {
+        let taxonomy = self.taxonomy.clone();
+        let columns = std::mem::take(&mut self.columns);
+        let mut written = Ok(());
+        for result in results {
+            let values: Vec<Value<S>> = columns
+                .iter()
+                .map(|column| column.value(result, self.containment, taxonomy.as_deref()))
+                .collect();
+            let fields: Vec<&dyn fmt::Display> = values
+                .iter()
+                .map(|value| value as &dyn fmt::Display)
+                .collect();
+            written = self.write_row(&fields);
+            if written.is_err() {
+                break;
+            }
+        }
+        self.columns = columns;
+        written
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Writes every result as a JSON object on a line of its own, with the fields
+/// of [`AlignmentResult`] and optionally the taxonomy of the subject, or with the
+/// selected columns
+pub struct JsonLinesSink<W: Write> {
+    writer: W,
+    taxonomy: Option<Arc<Taxonomy>>,
+    containment: Option<f64>,
+    /// Columns selected instead of the fields of the results
+    columns: Option<Vec<Column>>,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    /// Creates a sink writing to `writer`, which should be buffered
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            taxonomy: None,
+            containment: None,
+            columns: None,
+        }
+    }
+
+    /// Adds a `taxonomy` object with the columns of a taxonomy for the subject of
+    /// every result. Subjects missing from the taxonomy have no such object.
+    pub fn with_taxonomy(mut self, taxonomy: Option<Arc<Taxonomy>>) -> Self {
+        self.taxonomy = taxonomy;
+        self
+    }
+
+    /// Sets the fraction flagging a containment, for a selected `contained` column
+    pub fn with_containment(mut self, min_fraction: Option<f64>) -> Self {
+        self.containment = min_fraction;
+        self
+    }
+
+    /// Writes objects with exactly the given columns as keys, in the given order,
+    /// instead of the fields of the results. Missing values are `null`.
+    pub fn with_columns(mut self, columns: Option<Vec<Column>>) -> Self {
+        self.columns = columns;
+        self
+    }
+}
+
+/// A result with the taxonomy of its subject, serialized as one object
+#[derive(serde::Serialize)]
+struct Annotated<'a, S> {
+    #[serde(flatten)]
+    result: &'a AlignmentResult<S>,
+    taxonomy: Lineage<'a>,
+}
+
+/// Taxa by taxonomy column, serialized in the order of the columns
+struct Lineage<'a> {
+    columns: &'a [String],
+    taxa: &'a [String],
+}
+
+impl serde::Serialize for Lineage<'_> {
+    fn serialize<T: serde::Serializer>(&self, serializer: T) -> Result<T::Ok, T::Error> {
+        serializer.collect_map(self.columns.iter().zip(self.taxa))
+    }
+}
+
+/// The values of selected columns of a result, serialized as an object keyed by
+/// the column names
+struct Selected<'a, S> {
+    names: &'a [&'a str],
+    values: Vec<Value<'a, S>>,
+}
+
+impl<S: Score> serde::Serialize for Selected<'_, S> {
+    fn serialize<T: serde::Serializer>(&self, serializer: T) -> Result<T::Ok, T::Error> {
+        serializer.collect_map(self.names.iter().zip(&self.values))
+    }
+}
+
+impl<S: Score, W: Write> ResultSink<S> for JsonLinesSink<W> {
+    fn open(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_batch(&mut self, results: &[AlignmentResult<S>]) -> io::Result<()> {
+        if let Some(columns) = &self.columns {
+            let taxonomy = self.taxonomy.as_deref();
+            let names: Vec<&str> = columns.iter().map(|c| c.name(taxonomy)).collect();
+            for result in results {
+                let selected = Selected {
+                    names: &names,
+                    values: columns
+                        .iter()
+                        .map(|column| column.value(result, self.containment, taxonomy))
+                        .collect(),
+                };
+                serde_json::to_writer(&mut self.writer, &selected)?;
+                self.writer.write_all(b"\n")?;
+            }
+            return Ok(());
+        }
+        for result in results {
+            let lineage = self
+                .taxonomy
+                .as_ref()
+                .and_then(|taxonomy| Some((taxonomy, taxonomy.lineage(&result.subject_id)?)));
+            match lineage {
+                Some((taxonomy, lineage)) => serde_json::to_writer(
+                    &mut self.writer,
+                    &Annotated {
+                        result,
+                        taxonomy: Lineage {
+                            columns: taxonomy.columns(),
+                            taxa: lineage,
+                        },
+                    },
+                )?,
+                None => serde_json::to_writer(&mut self.writer, result)?,
+            }
+            self.writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Writes the aligned pairs in the 12 headerless columns of BLAST's tabular
+/// output: qseqid, sseqid, pident, length, mismatch, gapopen, qstart, qend,
+/// sstart, send, evalue and bitscore. Pairs that were not aligned are left out.
+pub struct Blast6Sink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> Blast6Sink<W> {
+    /// Creates a sink writing to `writer`, which should be buffered
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<S: Score, W: Write> ResultSink<S> for Blast6Sink<W> {
+    fn open(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_batch(&mut self, results: &[AlignmentResult<S>]) -> io::Result<()> {
+        for result in results {
+            let Some(hit) = &result.blast else {
+                continue;
+            };
+            writeln!(
+                self.writer,
+                "{}\t{}\t{:.3}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.1}",
+                result.query_id,
+                result.subject_id,
+                hit.pident,
+                hit.length,
+                hit.mismatch,
+                hit.gapopen,
+                hit.qstart,
+                hit.qend,
+                hit.sstart,
+                hit.send,
+                blast::format_evalue(hit.evalue),
+                hit.bitscore
+            )?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Writes the aligned pairs as SAM records, with a header listing the subjects
+/// as reference sequences. Pairs that were not aligned are left out.
+pub struct SamSink<W: Write> {
+    writer: W,
+    /// Identifiers and lengths of the reference sequences
+    references: Vec<(String, usize)>,
+}
+
+impl<W: Write> SamSink<W> {
+    /// Creates a sink writing to `writer`, which should be buffered, with the
+    /// identifiers and lengths of all possible subjects
+    pub fn new(writer: W, references: Vec<(String, usize)>) -> Self {
+        Self { writer, references }
+    }
+}
+
+impl<S: Score, W: Write> ResultSink<S> for SamSink<W> {
+    fn open(&mut self) -> io::Result<()> {
+        writeln!(self.writer, "@HD\tVN:1.6\tSO:unsorted")?;
+        for (id, len) in &self.references {
+            writeln!(self.writer, "@SQ\tSN:{}\tLN:{}", id, len)?;
+        }
+        writeln!(
+            self.writer,
+            "@PG\tID:aligner\tPN:aligner\tVN:{}",
+            env!("CARGO_PKG_VERSION")
+        )
+    }
+
+    fn write_batch(&mut self, results: &[AlignmentResult<S>]) -> io::Result<()> {
+        for result in results {
+            let Some(sam) = &result.sam else {
+                continue;
+            };
+            writeln!(
+                self.writer,
+                "{}\t0\t{}\t{}\t255\t{}\t*\t0\t0\t{}\t*\tAS:i:{}\tNM:i:{}",
+                result.query_id,
+                result.subject_id,
+                sam.pos,
+                sam.cigar,
+                sam.seq,
+                sam.score,
+                sam.edit_distance
+            )?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Writes the aligned blocks of the pairs as PAF records: the query and target
+/// with their lengths, block coordinates and strand, the number of identical
+/// residues, the block length and a mapping quality of 255, followed by the
+/// score, edit distance and CIGAR string as tags. Pairs that were not aligned are
+/// left out.
+pub struct PafSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PafSink<W> {
+    /// Creates a sink writing to `writer`, which should be buffered
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<S: Score, W: Write> ResultSink<S> for PafSink<W> {
+    fn open(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_batch(&mut self, results: &[AlignmentResult<S>]) -> io::Result<()> {
+        for result in results {
+            let Some(paf) = &result.paf else {
+                continue;
+            };
+            writeln!(
+                self.writer,
+                "{}\t{}\t{}\t{}\t+\t{}\t{}\t{}\t{}\t{}\t{}\t255\tAS:i:{}\tNM:i:{}\tcg:Z:{}",
+                result.query_id,
+                result.seq1_len,
+                paf.qstart,
+                paf.qend,
+                result.subject_id,
+                result.seq2_len,
+                paf.tstart,
+                paf.tend,
+                paf.matches,
+                paf.block_len,
+                paf.score,
+                paf.edit_distance,
+                paf.cigar
+            )?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Writes results as rows of an Apache Parquet file with typed columns. Unlike in
+/// the delimited formats, the score of a pair that was not aligned is missing
+/// rather than -1, and a `status` column tells why.
+pub struct ParquetSink<W: Write + Send> {
+    /// Destination until the sink is opened
+    output: Option<W>,
+    /// Writer of the file from opening until closing
+    writer: Option<ArrowWriter<W>>,
+    schema: SchemaRef,
+    identity: bool,
+    cigar: bool,
+    max_score: bool,
+    containment: Option<f64>,
+    taxonomy: Option<Arc<Taxonomy>>,
+    /// Columns selected instead of the default ones
+    selected: Option<Vec<Column>>,
+    /// Columns written, from opening the sink
+    columns: Vec<Column>,
+}
+
+impl<W: Write + Send> ParquetSink<W> {
+    /// Creates a sink writing a Parquet file to `writer`
+    pub fn new(writer: W) -> Self {
+        Self {
+            output: Some(writer),
+            writer: None,
+            schema: Arc::new(Schema::empty()),
+            identity: false,
+            cigar: false,
+            max_score: false,
+            containment: None,
+            taxonomy: None,
+            selected: None,
+            columns: Vec::new(),
+        }
+    }
+
+    /// Adds an `identity` column, as in [`DelimitedSink::with_identity`]
+    pub fn with_identity(mut self, identity: bool) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// Adds a `cigar` column, as in [`DelimitedSink::with_cigar`]
+    pub fn with_cigar(mut self, cigar: bool) -> Self {
+        self.cigar = cigar;
+        self
+    }
+
+    /// Adds the `max_score` and `score_ratio` columns, as in
+    /// [`DelimitedSink::with_max_score`]
+    pub fn with_max_score(mut self, max_score: bool) -> Self {
+        self.max_score = max_score;
+        self
+    }
+
+    /// Adds the `containment` and `contained` columns, as in
+    /// [`DelimitedSink::with_containment`]
+    pub fn with_containment(mut self, min_fraction: Option<f64>) -> Self {
+        self.containment = min_fraction;
+        self
+    }
+
+    /// Adds a string column per taxonomy column, as in
+    /// [`DelimitedSink::with_taxonomy`]
+    pub fn with_taxonomy(mut self, taxonomy: Option<Arc<Taxonomy>>) -> Self {
+        self.taxonomy = taxonomy;
+        self
+    }
+
+    /// Writes exactly the given columns, as in [`DelimitedSink::with_columns`]
+    pub fn with_columns(mut self, columns: Option<Vec<Column>>) -> Self {
+        self.selected = columns;
+        self
+    }
+
+    fn writer(&mut self) -> io::Result<&mut ArrowWriter<W>> {
+        self.writer
+            .as_mut()
+            .ok_or_else(|| io::Error::other("Parquet sink is not open"))
+    }
+}
+
+impl<S: Score, W: Write + Send> ResultSink<S> for ParquetSink<W> {
+    fn open(&mut self) -> io::Result<()> {
+        self.columns = self.selected.clone().unwrap_or_else(|| {
+            Column::typed_defaults(
+                self.identity,
+                self.cigar,
+                self.max_score,
+                self.containment.is_some(),
+                self.taxonomy.as_deref(),
+            )
+        });
+        let fields: Vec<Field> = self
+            .columns
+            .iter()
+            .map(|column| {
+                let data_type = match column.column_type() {
+                    ColumnType::Text => DataType::Utf8,
+                    ColumnType::Count => DataType::UInt64,
+                    ColumnType::Real => DataType::Float64,
+                    ColumnType::Score if S::INTEGRAL => DataType::Int32,
+                    ColumnType::Score => DataType::Float32,
+                };
+                Field::new(
+                    column.name(self.taxonomy.as_deref()),
+                    data_type,
+                    column.nullable(),
+                )
+            })
+            .collect();
+        self.schema = Arc::new(Schema::new(fields));
+
+        let output = self
+            .output
+            .take()
+            .ok_or_else(|| io::Error::other("Parquet sink was already opened"))?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .build();
+        let writer = ArrowWriter::try_new(output, Arc::clone(&self.schema), Some(properties))
+            .map_err(io::Error::other)?;
+        self.writer = Some(writer);
+        Ok(())
+    }
+
+    fn write_batch(&mut self, results: &[AlignmentResult<S>]) -> io::Result<()> {
+        if results.is_empty() {
+            return Ok(());
+        }
+        let taxonomy = self.taxonomy.as_deref();
+        let columns: Vec<ArrayRef> = self
+            .columns
+            .iter()
+            .map(|column| {
+                let values = results
+                    .iter()
+                    .map(|result| column.value(result, self.containment, taxonomy));
+                let array: ArrayRef = match column.column_type() {
+                    ColumnType::Text => {
+                        Arc::new(StringArray::from_iter(values.map(|v| v.as_text())))
+                    }
+                    ColumnType::Count => Arc::new(UInt64Array::from_iter(
+                        values.map(|v| v.as_count().map(|count| count as u64)),
+                    )),
+                    ColumnType::Real => {
+                        Arc::new(Float64Array::from_iter(values.map(|v| v.as_real())))
+                    }
+                    ColumnType::Score if S::INTEGRAL => Arc::new(Int32Array::from_iter(
+                        values.map(|v| v.as_score().map(|score| score.into() as i32)),
+                    )),
+                    ColumnType::Score => Arc::new(Float32Array::from_iter(
+                        values.map(|v| v.as_score().map(|score| score.into() as f32)),
+                    )),
+                };
+                array
+            })
+            .collect();
+        let batch =
+            RecordBatch::try_new(Arc::clone(&self.schema), columns).map_err(io::Error::other)?;
+        self.writer()?.write(&batch).map_err(io::Error::other)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let writer = self.writer()?;
+        writer.flush().map_err(io::Error::other)?;
+        writer.inner_mut().flush()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        let writer = self
+            .writer
+            .take()
+            .ok_or_else(|| io::Error::other("Parquet sink is not open"))?;
+        writer.into_inner().map_err(io::Error::other)?.flush()
+    }
+}
+
+/// Table the results are written to in SQLite databases
+pub const RESULTS_TABLE: &str = "alignments";
+
+/// Writes results as rows of a table in a SQLite database, typed like the columns
+/// of [`ParquetSink`]. Every batch is written in a transaction of its own, so the
+/// rows of completed batches remain in the database if a run fails. The table is
+/// indexed by query and by subject once all rows are written.
+pub struct SqliteSink {
+    path: PathBuf,
+    /// Connection to the database from opening until closing
+    connection: Option<Connection>,
+    identity: bool,
+    cigar: bool,
+    max_score: bool,
+    containment: Option<f64>,
+    taxonomy: Option<Arc<Taxonomy>>,
+    /// Columns selected instead of the default ones
+    selected: Option<Vec<Column>>,
+    /// Columns written, from opening the sink
+    columns: Vec<Column>,
+}
+
+impl SqliteSink {
+    /// Creates a sink writing to the database at `path`, which is replaced if it
+    /// exists
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            connection: None,
+            identity: false,
+            cigar: false,
+            max_score: false,
+            containment: None,
+            taxonomy: None,
+            selected: None,
+            columns: Vec::new(),
+        }
+    }
+
+    /// Adds an `identity` column, as in [`DelimitedSink::with_identity`]
+    pub fn with_identity(mut self, identity: bool) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// Adds a `cigar` column, as in [`DelimitedSink::with_cigar`]
+    pub fn with_cigar(mut self, cigar: bool) -> Self {
+        self.cigar = cigar;
+        self
+    }
+
+    /// Adds the `max_score` and `score_ratio` columns, as in
+    /// [`DelimitedSink::with_max_score`]
+    pub fn with_max_score(mut self, max_score: bool) -> Self {
+        self.max_score = max_score;
+        self
+    }
+
+    /// Adds the `containment` and `contained` columns, as in
+    /// [`DelimitedSink::with_containment`]
+    pub fn with_containment(mut self, min_fraction: Option<f64>) -> Self {
+        self.containment = min_fraction;
+        self
+    }
+
+    /// Adds a text column per taxonomy column, as in
+    /// [`DelimitedSink::with_taxonomy`]
+    pub fn with_taxonomy(mut self, taxonomy: Option<Arc<Taxonomy>>) -> Self {
+        self.taxonomy = taxonomy;
+        self
+    }
+
+    /// Writes exactly the given columns, as in [`DelimitedSink::with_columns`]
+    pub fn with_columns(mut self, columns: Option<Vec<Column>>) -> Self {
+        self.selected = columns;
+        self
+    }
+
+    fn connection(&mut self) -> io::Result<&mut Connection> {
+        self.connection
+            .as_mut()
+            .ok_or_else(|| io::Error::other("SQLite sink is not open"))
+    }
+}
+
+impl<S: Score> ResultSink<S> for SqliteSink {
+    fn open(&mut self) -> io::Result<()> {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        self.columns = self.selected.clone().unwrap_or_else(|| {
+            Column::typed_defaults(
+                self.identity,
+                self.cigar,
+                self.max_score,
+                self.containment.is_some(),
+                self.taxonomy.as_deref(),
+            )
+        });
+        let connection = Connection::open(&self.path).map_err(io::Error::other)?;
+        let columns: Vec<String> = self
+            .columns
+            .iter()
+            .map(|column| {
+                let kind = match column.column_type() {
+                    ColumnType::Text => "TEXT",
+                    ColumnType::Count => "INTEGER",
+                    ColumnType::Real => "REAL",
+                    ColumnType::Score if S::INTEGRAL => "INTEGER",
+                    ColumnType::Score => "REAL",
+                };
+                format!(
+                    "{} {}{}",
+                    quote_identifier(column.name(self.taxonomy.as_deref())),
+                    kind,
+                    if column.nullable() { "" } else { " NOT NULL" }
+                )
+            })
+            .collect();
+        connection
+            .execute(
+                &format!(
+                    "CREATE TABLE {} ({})",
+                    quote_identifier(RESULTS_TABLE),
+                    columns.join(", ")
+                ),
+                [],
+            )
+            .map_err(io::Error::other)?;
+        self.connection = Some(connection);
+        Ok(())
+    }
+
+    fn write_batch(&mut self, results: &[AlignmentResult<S>]) -> io::Result<()> {
+        let columns = self.columns.clone();
+        let containment = self.containment;
+        let taxonomy = self.taxonomy.clone();
+        let insert = format!(
+            "INSERT INTO {} VALUES ({})",
+            quote_identifier(RESULTS_TABLE),
+            vec!["?"; columns.len()].join(", ")
+        );
+        let connection = self.connection()?;
+        let transaction = connection.transaction().map_err(io::Error::other)?;
+        {
+            let mut statement = transaction
+                .prepare_cached(&insert)
+                .map_err(io::Error::other)?;
+            for result in results {
+                let row = columns.iter().map(|column| {
+                    match column.value(result, containment, taxonomy.as_deref()) {
+                        Value::Text(text) => SqlValue::Text(text.to_string()),
+                        Value::Count(count) => SqlValue::Integer(count as i64),
+                        Value::Score(Some(score)) if S::INTEGRAL => {
+                            SqlValue::Integer(score.into() as i64)
+                        }
+                        Value::Score(Some(score)) => SqlValue::Real(score.into()),
+                        Value::Fraction(value) | Value::Percent(value) => SqlValue::Real(value),
+                        Value::Score(None) | Value::Missing => SqlValue::Null,
+                    }
+                });
+                statement
+                    .execute(params_from_iter(row))
+                    .map_err(io::Error::other)?;
+            }
+        }
+        transaction.commit().map_err(io::Error::other)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Every batch is committed when it is written
+        Ok(())
+    }
+
+    /// Indexes the table by the query and subject columns that were written
+    fn close(&mut self) -> io::Result<()> {
+        let indexed: Vec<&str> = [Column::QueryId, Column::SubjectId]
+            .into_iter()
+            .filter(|column| self.columns.contains(column))
+            .map(|column| column.name(None))
+            .collect();
+        let connection = self
+            .connection
+            .take()
+            .ok_or_else(|| io::Error::other("SQLite sink is not open"))?;
+        for column in indexed {
+            connection
+                .execute(
+                    &format!(
+                        "CREATE INDEX {} ON {} ({})",
+                        quote_identifier(&format!("{}_{}", RESULTS_TABLE, column)),
+                        quote_identifier(RESULTS_TABLE),
+                        quote_identifier(column)
+                    ),
+                    [],
+                )
+                .map_err(io::Error::other)?;
+        }
+        connection.close().map_err(|(_, e)| io::Error::other(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::align::PairStatus;
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn test_delimited_sink() {
+        let results = [AlignmentResult::<i32> {
+            seq1_len: 4,
+            seq2_len: 5,
+            ..AlignmentResult::for_test("a,1", "b", None, PairStatus::Skipped)
+        }];
+        let write = |sink: &mut dyn ResultSink<i32>| {
+            sink.open().unwrap();
+            sink.write_batch(&results).unwrap();
+            sink.close().unwrap();
+        };
+
+        let mut tsv = Vec::new();
+        write(&mut DelimitedSink::new(&mut tsv, OutputFormat::Tsv).without_header());
+        assert_eq!(tsv, b"a,1\tb\t-1\t4\t5\n");
+        let mut tsv = Vec::new();
+        write(&mut DelimitedSink::new(&mut tsv, OutputFormat::Tsv).with_status(true));
+        assert_eq!(
+            String::from_utf8(tsv).unwrap(),
+            "query_id\tsubject_id\tscore\tstatus\tseq1_len\tseq2_len\na,1\tb\t-1\tskipped\t4\t5\n"
+        );
+
+        let mut csv = Vec::new();
+        write(&mut DelimitedSink::new(&mut csv, OutputFormat::Csv).with_identity(true));
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "query_id,subject_id,score,seq1_len,seq2_len,identity\n\"a,1\",b,-1,4,5,\n"
+        );
+        assert_eq!(
+            OutputFormat::from_path(Path::new("out.CSV")),
+            OutputFormat::Csv
+        );
+        assert_eq!(
+            OutputFormat::from_path(Path::new("out.jsonl.zst")),
+            OutputFormat::Jsonl
+        );
+
+        let mut jsonl = Vec::new();
+        write(&mut JsonLinesSink::new(&mut jsonl));
+        assert_eq!(
+            String::from_utf8(jsonl).unwrap(),
+            "{\"query_id\":\"a,1\",\"subject_id\":\"b\",\"score\":null,\"status\":\"skipped\",\"seq1_len\":4,\"seq2_len\":5}\n"
+        );
+        let mut jsonl = Vec::new();
+        let columns = Column::parse_list("sid,score,pident", None).unwrap();
+        write(&mut JsonLinesSink::new(&mut jsonl).with_columns(Some(columns)));
+        assert_eq!(
+            String::from_utf8(jsonl).unwrap(),
+            "{\"subject_id\":\"b\",\"score\":null,\"pident\":null}\n"
+        );
+
+        let path =
+            std::env::temp_dir().join(format!("aligner-sink-{}.parquet", std::process::id()));
+        write(&mut ParquetSink::new(std::fs::File::create(&path).unwrap()).with_identity(true));
+        let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<RecordBatch> = reader.map(Result::unwrap).collect();
+        std::fs::remove_file(&path).unwrap();
+        let names: Vec<&str> = batches[0]
+            .schema_ref()
+            .fields()
+            .iter()
+            .map(|field| field.name().as_str())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "query_id",
+                "subject_id",
+                "score",
+                "status",
+                "seq1_len",
+                "seq2_len",
+                "identity"
+            ]
+        );
+        assert_eq!(batches[0].num_rows(), 1);
+        assert_eq!(batches[0].column(2).data_type(), &DataType::Int32);
+        assert!(batches[0].column(2).is_null(0));
+
+        let path = std::env::temp_dir().join(format!("aligner-sink-{}.sqlite", std::process::id()));
+        assert_eq!(OutputFormat::from_path(&path), OutputFormat::Sqlite);
+        write(&mut SqliteSink::new(&path).with_identity(true));
+        let connection = Connection::open(&path).unwrap();
+        let (query_id, score, status): (String, Option<i64>, String) = connection
+            .query_row(
+                "SELECT query_id, score, status FROM alignments WHERE subject_id = 'b'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            (query_id.as_str(), score, status.as_str()),
+            ("a,1", None, "skipped")
+        );
+        let indices: i64 = connection
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND tbl_name = 'alignments'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(indices, 2);
+        drop(connection);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+//! Residue occupancy of selected reference positions.
+//!
+//! The `sites` subcommand aligns every query with a reference, like the
+//! `numbering` subcommand, and writes the query residues aligned with a list of
+//! reference positions, e.g. the residues of an active site, as a wide table with
+//! a row per query and a column per position. The conservation of every position
+//! across the queries is summarized at the end of the run.
+
+use indicatif::ParallelProgressIterator;
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::ScoringType;
+use crate::error::AlignerError;
+use crate::numbering::{Scheme, project, read_reference};
+use crate::pair::align_pair;
+use crate::utils::{parse_input, setup_progress_bar};
+use crate::validate::{check_ascii, check_lengths};
+
+/// Command-line arguments for the `sites` subcommand
+#[derive(clap::Args, Debug)]
+pub struct SitesArgs {
+    /// Input file with the sequences to inspect, in any supported input format
+    input: PathBuf,
+
+    /// Input file with the reference sequence, the only sequence of the file
+    #[arg(long)]
+    reference: PathBuf,
+
+    /// Comma-separated labels of the reference positions to report, e.g.
+    /// `70,73,130,166`
+    #[arg(long, required = true, value_delimiter = ',')]
+    positions: Vec<String>,
+
+    /// File with the label of every reference residue, as for the `numbering`
+    /// subcommand; residues are numbered from 1 by default
+    #[arg(long)]
+    scheme: Option<PathBuf>,
+
+    /// Path of the tab-separated table with a row per query and a column per
+    /// position
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Scoring type to use for alignment
+    #[arg(short, long, value_enum, default_value_t = ScoringType::Identity)]
+    scoring: ScoringType,
+}
+
+/// Returns the query residue aligned with each of the given zero-based reference
+/// positions, from the projection of the query onto the reference by
+/// [`project`], and `None` where the reference residue is deleted in the query
+pub fn occupancy(query: &str, projection: &[Option<usize>], sites: &[usize]) -> Vec<Option<char>> {
+    sites
+        .iter()
+        .map(|site| {
+            let i = projection.iter().position(|p| *p == Some(*site))?;
+            query.chars().nth(i)
+        })
+        .collect()
+}
+
+/// Runs the `sites` subcommand.
+///
+/// # Errors
+///
+/// Returns `AlignerError::InvalidInput` if a position is not a label of the
+/// reference, and an error if an input cannot be read or is invalid, or the
+/// output cannot be written.
+pub fn run(args: SitesArgs) -> Result<(), AlignerError> {
+    let mut queries = parse_input(&args.input)?;
+    check_ascii(&mut queries, None)?;
+    check_lengths(&queries, None)?;
+    let (reference_id, reference) = read_reference(&args.reference)?;
+    let scheme = match &args.scheme {
+        Some(path) => Scheme::read(path, reference.len())?,
+        None => Scheme::sequential(reference.len()),
+    };
+    let sites = args
+        .positions
+        .iter()
+        .map(|label| {
+            scheme.position(label).ok_or_else(|| {
+                AlignerError::InvalidInput(format!(
+                    "position '{}' is not a residue label of reference {}",
+                    label, reference_id
+                ))
+            })
+        })
+        .collect::<Result<Vec<usize>, _>>()?;
+    info!(
+        queries = queries.len(),
+        sites = sites.len(),
+        reference = %reference_id,
+        "reporting site occupancy"
+    );
+
+    let mut queries: Vec<(&String, &String)> = queries.iter().collect();
+    queries.sort_unstable();
+    let matcher = args.scoring.matcher();
+    let progress = setup_progress_bar(queries.len() as u64);
+    let occupancies: Vec<Vec<Option<char>>> = queries
+        .par_iter()
+        .progress_with(progress)
+        .map(|(_, query)| {
+            let projection = project(&align_pair(query, &reference, &matcher));
+            occupancy(query, &projection, &sites)
+        })
+        .collect();
+
+    // Columns are named by the reference residue and label, e.g. `S70`
+    let columns: Vec<String> = sites
+        .iter()
+        .map(|&site| {
+            format!(
+                "{}{}",
+                char::from(reference.as_bytes()[site]),
+                scheme.label(site)
+            )
+        })
+        .collect();
+    let mut out = BufWriter::new(File::create(&args.output)?);
+    writeln!(out, "query_id\t{}", columns.join("\t"))?;
+    let mut conserved = vec![0; sites.len()];
+    for ((query_id, _), residues) in queries.iter().zip(&occupancies) {
+        write!(out, "{}", query_id)?;
+        for (i, residue) in residues.iter().enumerate() {
+            let residue = residue.unwrap_or('-');
+            conserved[i] += usize::from(residue == char::from(reference.as_bytes()[sites[i]]));
+            write!(out, "\t{}", residue)?;
+        }
+        writeln!(out)?;
+    }
+    out.flush()?;
+
+    eprintln!(
+        "Reported {} positions of {} in {} sequences",
+        sites.len(),
+        reference_id,
+        queries.len()
+    );
+    for (column, conserved) in columns.iter().zip(&conserved) {
+        eprintln!(
+            "  {}: conserved in {} of {} sequences",
+            column,
+            conserved,
+            queries.len()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_occupancy() {
+        let reference = "MKTAYIAKQRQISFVKSHFSRQLEERLGLIEVQAPILSRVGDGTQDNLSGAEKAVQVKVKA";
+        // Y5W and deletion of reference residues 21-22
+        let query = "MKTAWIAKQRQISFVKSHFSLEERLGLIEVQAPILSRVGDGTQDNLSGAEKAVQVKVKA";
+        let projection = project(&align_pair(
+            query,
+            reference,
+            &ScoringType::Identity.matcher(),
+        ));
+        let scheme = Scheme::sequential(reference.len());
+        let sites: Vec<usize> = ["1", "5", "21", "23"]
+            .iter()
+            .map(|label| scheme.position(label).unwrap())
+            .collect();
+        assert_eq!(
+            occupancy(query, &projection, &sites),
+            [Some('M'), Some('W'), None, Some('L')]
+        );
+        assert_eq!(scheme.position("0"), None);
+    }
+}
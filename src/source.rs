@@ -0,0 +1,432 @@
+//! Registry of the formats sequences can be read from.
+//!
+//! Every input format is a [`SequenceSource`]. The source of an input is chosen by
+//! name with `--input-format` or detected from the extension of the input path, so
+//! supporting another format means registering another source instead of adding a
+//! case to the dispatch. Inputs given as `http://` or `https://` URLs are
+//! downloaded first and then read by the source of their format. Compressed
+//! inputs are decompressed first (see [`crate::compress`]).
+
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OpenFlags};
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use tracing::{debug, info};
+
+use crate::compress::{self, Compression};
+use crate::error::AlignerError;
+use crate::fasta;
+use crate::fastq;
+use crate::genbank;
+use crate::input::{self, ParseOptions, Parsed};
+use crate::msa::{self, MsaFormat};
+use crate::table;
+
+/// Table read from SQLite databases
+pub const SQLITE_TABLE: &str = "sequences";
+
+/// A format sequences can be read from
+pub trait SequenceSource: Sync {
+    /// Name of the format, as accepted by `--input-format`
+    fn name(&self) -> &'static str;
+
+    /// Lower-case file extensions of inputs in this format
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// Returns `true` if the input at `location` is detected as this format
+    fn detects(&self, location: &Path) -> bool {
+        location
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                self.extensions()
+                    .contains(&extension.to_ascii_lowercase().as_str())
+            })
+    }
+
+    /// Reads the sequences at `location`, handling malformed records according to
+    /// the parse mode.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the input cannot be read or is malformed.
+    fn read(&self, location: &Path, options: &ParseOptions) -> Result<Parsed, AlignerError>;
+}
+
+/// JSON documents, the default for inputs without a known extension
+struct Json;
+
+impl SequenceSource for Json {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["json"]
+    }
+
+    fn read(&self, location: &Path, options: &ParseOptions) -> Result<Parsed, AlignerError> {
+        input::read_json(location, options)
+    }
+}
+
+/// YAML documents with the same shapes as JSON documents
+struct Yaml;
+
+impl SequenceSource for Yaml {
+    fn name(&self) -> &'static str {
+        "yaml"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["yaml", "yml"]
+    }
+
+    fn read(&self, location: &Path, options: &ParseOptions) -> Result<Parsed, AlignerError> {
+        input::read_yaml(location, options)
+    }
+}
+
+/// GenBank and EMBL flat files
+struct GenBank;
+
+impl SequenceSource for GenBank {
+    fn name(&self) -> &'static str {
+        "genbank"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        // NCBI downloads of nucleotide and protein records end in .gbff and .gpff
+        &["gb", "gbk", "gbff", "gpff", "genbank", "embl"]
+    }
+
+    fn read(&self, location: &Path, options: &ParseOptions) -> Result<Parsed, AlignerError> {
+        genbank::read(location, options)
+    }
+}
+
+/// FASTA files with one or more wrapped records
+struct Fasta;
+
+impl SequenceSource for Fasta {
+    fn name(&self) -> &'static str {
+        "fasta"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["fasta", "fa", "faa", "fna", "fas"]
+    }
+
+    fn read(&self, location: &Path, options: &ParseOptions) -> Result<Parsed, AlignerError> {
+        fasta::read(location, options)
+    }
+}
+
+/// FASTQ files of reads with per-base qualities
+struct Fastq;
+
+impl SequenceSource for Fastq {
+    fn name(&self) -> &'static str {
+        "fastq"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["fastq", "fq"]
+    }
+
+    fn read(&self, location: &Path, options: &ParseOptions) -> Result<Parsed, AlignerError> {
+        fastq::read(location, options)
+    }
+}
+
+/// Comma-separated tables of identifiers and sequences
+struct Csv;
+
+impl SequenceSource for Csv {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["csv"]
+    }
+
+    fn read(&self, location: &Path, options: &ParseOptions) -> Result<Parsed, AlignerError> {
+        table::read(location, options, b',')
+    }
+}
+
+/// Tab-separated tables of identifiers and sequences
+struct Tsv;
+
+impl SequenceSource for Tsv {
+    fn name(&self) -> &'static str {
+        "tsv"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["tsv", "tab"]
+    }
+
+    fn read(&self, location: &Path, options: &ParseOptions) -> Result<Parsed, AlignerError> {
+        table::read(location, options, b'\t')
+    }
+}
+
+/// Stockholm alignments
+struct Stockholm;
+
+impl SequenceSource for Stockholm {
+    fn name(&self) -> &'static str {
+        "stockholm"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["sto", "stk"]
+    }
+
+    fn read(&self, location: &Path, options: &ParseOptions) -> Result<Parsed, AlignerError> {
+        msa::read(location, MsaFormat::Stockholm, options)
+    }
+}
+
+/// Aligned FASTA files
+struct AlignedFasta;
+
+impl SequenceSource for AlignedFasta {
+    fn name(&self) -> &'static str {
+        "afa"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["afa"]
+    }
+
+    fn read(&self, location: &Path, options: &ParseOptions) -> Result<Parsed, AlignerError> {
+        msa::read(location, MsaFormat::AlignedFasta, options)
+    }
+}
+
+/// SQLite databases with a [`SQLITE_TABLE`] table whose identifier and sequence
+/// columns are named like the record fields of JSON input
+struct Sqlite;
+
+impl SequenceSource for Sqlite {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["sqlite", "sqlite3", "db"]
+    }
+
+    fn read(&self, location: &Path, options: &ParseOptions) -> Result<Parsed, AlignerError> {
+        let connection = Connection::open_with_flags(location, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let query = format!(
+            "SELECT {}, {} FROM {}",
+            quote_identifier(&options.id_key),
+            quote_identifier(&options.sequence_key),
+            quote_identifier(SQLITE_TABLE)
+        );
+        let mut statement = connection.prepare(&query)?;
+        let mut rows = statement.query([])?;
+
+        let mut parsed = Parsed::default();
+        let mut record = 0;
+        while let Some(row) = rows.next()? {
+            record += 1;
+            let id = match row.get_ref(0)? {
+                ValueRef::Text(id) => String::from_utf8_lossy(id).into_owned(),
+                ValueRef::Integer(id) => id.to_string(),
+                _ => String::new(),
+            };
+            let sequence = match row.get_ref(1)? {
+                ValueRef::Text(sequence) => String::from_utf8(sequence.to_vec())
+                    .map_err(|_| "sequence is not valid UTF-8".to_string()),
+                value => Err(format!("sequence is {} instead of text", value.data_type())),
+            };
+            parsed
+                .add(options.mode, record, id, sequence)
+                .map_err(|e| {
+                    AlignerError::InvalidInput(format!("{}: {}", location.display(), e))
+                })?;
+        }
+        Ok(parsed)
+    }
+}
+
+/// Quotes an SQL identifier such as a table or column name
+pub fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Inputs downloaded over HTTP or HTTPS
+struct Url;
+
+impl SequenceSource for Url {
+    fn name(&self) -> &'static str {
+        "url"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    fn detects(&self, location: &Path) -> bool {
+        location.to_str().is_some_and(|location| {
+            location.starts_with("http://") || location.starts_with("https://")
+        })
+    }
+
+    /// Downloads the input to a temporary file named like the last segment of the
+    /// URL path, so its format is detected from the extension as for local files
+    fn read(&self, location: &Path, options: &ParseOptions) -> Result<Parsed, AlignerError> {
+        let url = location
+            .to_str()
+            .filter(|_| self.detects(location))
+            .ok_or_else(|| {
+                AlignerError::InvalidInput(format!("{} is not an HTTP(S) URL", location.display()))
+            })?;
+        let name = url
+            .split(['?', '#'])
+            .next()
+            .and_then(|path| path.rsplit('/').next())
+            .filter(|name| !name.is_empty())
+            .unwrap_or("download");
+        let path = std::env::temp_dir().join(format!("aligner-{}-{}", std::process::id(), name));
+
+        info!(url, "downloading input");
+        let response = ureq::get(url)
+            .call()
+            // The error names the URL
+            .map_err(|e| AlignerError::Download(e.to_string()))?;
+        let downloaded = File::create(&path)
+            .and_then(|mut file| io::copy(&mut response.into_reader(), &mut file));
+        let parsed = downloaded
+            .map_err(AlignerError::from)
+            .and_then(|_| read(&path, options));
+        let _ = fs::remove_file(&path);
+        parsed
+    }
+}
+
+/// All registered sources
+static SOURCES: &[&dyn SequenceSource] = &[
+    &Json,
+    &Yaml,
+    &GenBank,
+    &Fasta,
+    &Fastq,
+    &Csv,
+    &Tsv,
+    &Stockholm,
+    &AlignedFasta,
+    &Sqlite,
+    &Url,
+];
+
+/// Returns the source registered under `name`
+fn find(name: &str) -> Option<&'static dyn SequenceSource> {
+    SOURCES.iter().copied().find(|source| source.name() == name)
+}
+
+/// Checks the value of `--input-format` against the registered sources
+pub fn parse_format(name: &str) -> Result<String, String> {
+    match find(name) {
+        Some(source) => Ok(source.name().to_string()),
+        None => Err(format!(
+            "unknown input format '{}', expected one of: {}",
+            name,
+            SOURCES
+                .iter()
+                .map(|source| source.name())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// Returns the source that reads the input at `location`: the URL source for
+/// URLs, the source named by the parse options if any, and otherwise the source
+/// detected from the extension, falling back to JSON
+fn detect(
+    location: &Path,
+    options: &ParseOptions,
+) -> Result<&'static dyn SequenceSource, AlignerError> {
+    if Url.detects(location) {
+        return Ok(&Url);
+    }
+    if let Some(name) = &options.format {
+        return find(name)
+            .ok_or_else(|| AlignerError::Config(format!("unknown input format '{}'", name)));
+    }
+    Ok(SOURCES
+        .iter()
+        .copied()
+        .find(|source| source.detects(location))
+        .unwrap_or(&Json))
+}
+
+/// Reads the sequences at `location` with the source chosen by [`detect`],
+/// decompressing compressed inputs first.
+///
+/// # Errors
+///
+/// Returns the errors of the chosen source, the errors of decompressing the
+/// input, and `AlignerError::Config` if the parse options name an unknown format.
+pub fn read(location: &Path, options: &ParseOptions) -> Result<Parsed, AlignerError> {
+    if !Url.detects(location)
+        && let Some(compression) = Compression::detect(location)?
+    {
+        let path = compress::decompress(location, compression)?;
+        let parsed = read(&path, options);
+        let _ = fs::remove_file(&path);
+        return parsed;
+    }
+    let source = detect(location, options)?;
+    debug!(format = source.name(), "reading input");
+    source.read(location, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sources() {
+        let options = ParseOptions::default();
+        let name = |location: &str| detect(Path::new(location), &options).unwrap().name();
+        assert_eq!(name("seqs.YML"), "yaml");
+        assert_eq!(name("seqs.fa"), "fasta");
+        assert_eq!(name("genomic.gbff"), "genbank");
+        assert_eq!(name("seqs.txt"), "json");
+        assert_eq!(name("https://example.org/seqs.db?raw=1"), "url");
+        assert!(parse_format("parquet").unwrap_err().contains("sqlite"));
+
+        let path = std::env::temp_dir().join(format!("aligner-source-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let connection = Connection::open(&path).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE sequences (id TEXT, sequence TEXT);
+                 INSERT INTO sequences VALUES ('a', 'MAVMT'), ('b', NULL);",
+            )
+            .unwrap();
+        let error = read(&path, &options).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("record 2 ('b'): sequence is Null")
+        );
+
+        let lenient = ParseOptions {
+            mode: input::ParseMode::Lenient,
+            ..ParseOptions::default()
+        };
+        let parsed = read(&path, &lenient).unwrap();
+        assert_eq!(parsed.sequences["a"], "MAVMT");
+        assert_eq!(parsed.invalid.len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+}
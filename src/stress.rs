@@ -0,0 +1,267 @@
+//! Pre-filter stress testing via simulated divergence.
+//!
+//! This module generates mutated copies of the input sequences at chosen identity
+//! levels and checks how many of these known homologous pairs survive the k-mer
+//! pre-filter. The resulting recall table shows at which divergence a given
+//! `--fraction`/`--min-matches` setting starts dropping true pairs.
+
+use rand::rngs::StdRng;
+use rand::seq::index::sample;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::align::worth_aligning;
+use crate::error::AlignerError;
+use crate::utils::parse_input;
+use crate::validate::{check_ascii, check_lengths};
+
+/// Command-line arguments for the `stress` subcommand
+#[derive(clap::Args, Debug)]
+pub struct StressArgs {
+    /// Path to input JSON file containing the sequences to mutate
+    input: PathBuf,
+
+    /// Path to output file (tab-separated). Defaults to standard output.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Identity levels (between 0 and 1) at which mutated copies are generated
+    #[arg(
+        short,
+        long,
+        value_delimiter = ',',
+        default_values_t = [0.95, 0.9, 0.8, 0.7, 0.6, 0.5, 0.4, 0.3]
+    )]
+    identities: Vec<f32>,
+
+    /// Pre-filter fractions (between 0 and 1) to evaluate
+    #[arg(
+        short,
+        long,
+        value_delimiter = ',',
+        default_values_t = [0.05, 0.1, 0.2, 0.3]
+    )]
+    fractions: Vec<f32>,
+
+    /// Minimum number of k-mer matches required for a pair to pass the filter
+    #[arg(short, long, default_value = "1")]
+    min_matches: usize,
+
+    /// Number of mutated copies generated per sequence and identity level
+    #[arg(short, long, default_value = "1")]
+    replicates: usize,
+
+    /// Seed for the random number generator, for reproducible runs
+    #[arg(long, default_value = "42")]
+    seed: u64,
+
+    /// Recall below which the filter is reported as dropping true pairs
+    #[arg(long, default_value = "0.95")]
+    min_recall: f32,
+}
+
+/// Outcome of the pre-filter for one identity level and fraction
+#[derive(Debug, Clone, PartialEq)]
+pub struct StressResult {
+    /// Identity of the mutated copies to their originals
+    pub identity: f32,
+    /// Pre-filter fraction used for the k-mer size
+    pub fraction: f32,
+    /// Number of simulated pairs tested
+    pub pairs: usize,
+    /// Number of simulated pairs that passed the pre-filter
+    pub retained: usize,
+}
+
+impl StressResult {
+    /// Fraction of simulated pairs that passed the pre-filter
+    pub fn recall(&self) -> f32 {
+        if self.pairs == 0 {
+            return 0.0;
+        }
+        self.retained as f32 / self.pairs as f32
+    }
+}
+
+/// Runs the `stress` subcommand and writes the recall table.
+///
+/// # Errors
+///
+/// Returns `AlignerError::Config` if an identity or fraction lies outside 0..=1,
+/// or any error raised while reading the input or writing the output.
+pub fn run(args: StressArgs) -> Result<(), AlignerError> {
+    for value in args.identities.iter().chain(&args.fractions) {
+        if !(0.0..=1.0).contains(value) {
+            return Err(AlignerError::Config(format!(
+                "identities and fractions must be between 0 and 1, got {}",
+                value
+            )));
+        }
+    }
+
+    let mut input = parse_input(&args.input)?;
+    check_ascii(&mut input, None)?;
+    check_lengths(&input, None)?;
+
+    // Sort by ID so the same seed always produces the same mutations
+    let mut ids: Vec<&String> = input.keys().collect();
+    ids.sort();
+    let sequences: Vec<&[u8]> = ids.iter().map(|id| input[*id].as_bytes()).collect();
+
+    let mut identities = args.identities.clone();
+    identities.sort_by(|a, b| b.total_cmp(a));
+
+    let results = simulate(
+        &sequences,
+        &identities,
+        &args.fractions,
+        args.min_matches,
+        args.replicates,
+        args.seed,
+    );
+
+    let sink: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(std::io::stdout()),
+    };
+    let mut writer = BufWriter::new(sink);
+    writeln!(writer, "identity\tfraction\tpairs\tretained\trecall")?;
+    for result in &results {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{:.4}",
+            result.identity,
+            result.fraction,
+            result.pairs,
+            result.retained,
+            result.recall()
+        )?;
+    }
+    writer.flush()?;
+
+    for &fraction in &args.fractions {
+        let first_drop = results
+            .iter()
+            .find(|r| r.fraction == fraction && r.recall() < args.min_recall);
+        match first_drop {
+            Some(r) => eprintln!(
+                "fraction {}: recall drops to {:.2} at {:.0}% identity",
+                fraction,
+                r.recall(),
+                r.identity * 100.0
+            ),
+            None => eprintln!(
+                "fraction {}: recall stays above {:.2} for all tested identities",
+                fraction, args.min_recall
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// Generates mutated copies of every sequence at each identity level and counts
+/// how many pass the pre-filter for each fraction.
+///
+/// Results are ordered by identity (in the given order) and then by fraction.
+pub fn simulate(
+    sequences: &[&[u8]],
+    identities: &[f32],
+    fractions: &[f32],
+    min_matches: usize,
+    replicates: usize,
+    seed: u64,
+) -> Vec<StressResult> {
+    let alphabet: Vec<u8> = sequences
+        .iter()
+        .flat_map(|seq| seq.iter().copied())
+        .collect::<BTreeSet<u8>>()
+        .into_iter()
+        .collect();
+
+    let mut results = Vec::with_capacity(identities.len() * fractions.len());
+    for (level, &identity) in identities.iter().enumerate() {
+        let retained: Vec<usize> = sequences
+            .par_iter()
+            .enumerate()
+            .map(|(index, seq)| {
+                let stream = (level * sequences.len() + index) as u64;
+                let mut rng = StdRng::seed_from_u64(seed.wrapping_add(stream));
+                let mut counts = vec![0; fractions.len()];
+                for _ in 0..replicates {
+                    let mutated = mutate(seq, identity, &alphabet, &mut rng);
+                    let original = String::from_utf8_lossy(seq);
+                    let mutated = String::from_utf8_lossy(&mutated);
+                    for (count, &fraction) in counts.iter_mut().zip(fractions) {
+                        if worth_aligning(&original, &mutated, fraction, min_matches) {
+                            *count += 1;
+                        }
+                    }
+                }
+                counts
+            })
+            .reduce(
+                || vec![0; fractions.len()],
+                |mut acc, counts| {
+                    acc.iter_mut().zip(counts).for_each(|(a, c)| *a += c);
+                    acc
+                },
+            );
+
+        for (&fraction, retained) in fractions.iter().zip(retained) {
+            results.push(StressResult {
+                identity,
+                fraction,
+                pairs: sequences.len() * replicates,
+                retained,
+            });
+        }
+    }
+    results
+}
+
+/// Returns a copy of `seq` in which randomly chosen positions are substituted so
+/// that the copy shares `identity` of its residues with the original.
+///
+/// Substitutes are drawn from `alphabet`, which must contain every residue of `seq`.
+/// If the alphabet has fewer than two letters the sequence is returned unchanged.
+fn mutate(seq: &[u8], identity: f32, alphabet: &[u8], rng: &mut impl Rng) -> Vec<u8> {
+    let mut mutated = seq.to_vec();
+    if alphabet.len() < 2 {
+        return mutated;
+    }
+
+    let substitutions = (((1.0 - identity) * seq.len() as f32).round() as usize).min(seq.len());
+    for pos in sample(rng, seq.len(), substitutions) {
+        // Draw from all letters but the last and swap in the last one on a collision,
+        // so the replacement is uniform over the residues differing from the original.
+        let mut residue = alphabet[rng.random_range(0..alphabet.len() - 1)];
+        if residue == seq[pos] {
+            residue = alphabet[alphabet.len() - 1];
+        }
+        mutated[pos] = residue;
+    }
+    mutated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mutate_reaches_identity() {
+        let seq = b"MAVMTPRRERSSLLSRALRFTAAAATALVTAVSLAAPAHAANPYERGPNPTD";
+        let alphabet: Vec<u8> = b"ACDEFGHIKLMNPQRSTVWY".to_vec();
+        let mut rng = StdRng::seed_from_u64(7);
+
+        let mutated = mutate(seq, 0.75, &alphabet, &mut rng);
+        let identical = seq.iter().zip(&mutated).filter(|(a, b)| a == b).count();
+
+        assert_eq!(mutated.len(), seq.len());
+        assert_eq!(identical, seq.len() - 13);
+    }
+}
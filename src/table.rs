@@ -0,0 +1,141 @@
+//! Parsing of delimited tables of sequences.
+//!
+//! Tabular exports are read as CSV with a configurable delimiter: a comma by
+//! default, and a tab for `.tsv` and `.tab` files. A first row naming both the
+//! identifier and the sequence column (`--id-key` and `--sequence-key`) is a
+//! header, and these columns are read from the following rows. Tables without
+//! such a header hold the identifier in the first and the sequence in the second
+//! column.
+
+use csv::{ReaderBuilder, StringRecord};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::error::AlignerError;
+use crate::input::{ParseOptions, Parsed};
+
+/// Parses the value of `--input-delimiter`: a single ASCII character, or `tab`
+/// or `\t` for a tab
+pub fn parse_delimiter(value: &str) -> Result<u8, String> {
+    match value {
+        "tab" | "\\t" | "\t" => Ok(b'\t'),
+        _ if value.len() == 1 && value.is_ascii() => Ok(value.as_bytes()[0]),
+        _ => Err(format!(
+            "invalid delimiter '{}', expected a single character or 'tab'",
+            value
+        )),
+    }
+}
+
+/// Reads the sequences of a delimited table, separated by the delimiter of the
+/// parse options or else by `default_delimiter`.
+///
+/// # Errors
+///
+/// Returns `AlignerError::Io` if the file cannot be read, and
+/// `AlignerError::InvalidInput` if it is not valid CSV, its header names only one
+/// of the two columns or, unless the parse mode is lenient, a row lacks a column.
+pub fn read(
+    path: &Path,
+    options: &ParseOptions,
+    default_delimiter: u8,
+) -> Result<Parsed, AlignerError> {
+    let to_error = |e: String| AlignerError::InvalidInput(format!("{}: {}", path.display(), e));
+    let mut reader = ReaderBuilder::new()
+        .delimiter(options.delimiter.unwrap_or(default_delimiter))
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(BufReader::new(File::open(path)?));
+
+    let mut rows = reader.records();
+    let first = rows
+        .next()
+        .transpose()
+        .map_err(|e| to_error(e.to_string()))?;
+    let (columns, first) = match first {
+        Some(first) => match header_columns(&first, options).map_err(to_error)? {
+            Some(columns) => (columns, None),
+            None => ((0, 1), Some(first)),
+        },
+        None => ((0, 1), None),
+    };
+
+    let mut parsed = Parsed::default();
+    let rows = first.into_iter().map(Ok).chain(rows);
+    for (index, row) in rows.enumerate() {
+        let row = row.map_err(|e| to_error(e.to_string()))?;
+        let id = row.get(columns.0).unwrap_or_default().trim().to_string();
+        let sequence = row.get(columns.1).map(str::to_string).ok_or_else(|| {
+            format!(
+                "row has {} columns, expected at least {}",
+                row.len(),
+                columns.0.max(columns.1) + 1
+            )
+        });
+        parsed
+            .add(options.mode, index + 1, id, sequence)
+            .map_err(to_error)?;
+    }
+    Ok(parsed)
+}
+
+/// Returns the positions of the identifier and sequence columns if `row` is a
+/// header naming both
+fn header_columns(
+    row: &StringRecord,
+    options: &ParseOptions,
+) -> Result<Option<(usize, usize)>, String> {
+    let position = |name: &str| row.iter().position(|field| field.trim() == name);
+    match (position(&options.id_key), position(&options.sequence_key)) {
+        (Some(id), Some(sequence)) => Ok(Some((id, sequence))),
+        (None, None) => Ok(None),
+        (Some(_), None) => Err(format!(
+            "header has no '{}' column for the sequences",
+            options.sequence_key
+        )),
+        (None, Some(_)) => Err(format!(
+            "header has no '{}' column for the identifiers",
+            options.id_key
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::ParseMode;
+    use std::fs;
+
+    #[test]
+    fn test_read_tables() {
+        let dir = std::env::temp_dir().join(format!("aligner-table-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let csv = dir.join("seqs.csv");
+        fs::write(
+            &csv,
+            "name,accession,sequence\nfirst,a,MAVMT\nsecond,\"b\"\n",
+        )
+        .unwrap();
+        let tsv = dir.join("seqs.tsv");
+        fs::write(&tsv, "a\tMAVMT\nb\tMAVKT\n").unwrap();
+
+        let options = ParseOptions {
+            id_key: "accession".to_string(),
+            mode: ParseMode::Lenient,
+            ..ParseOptions::default()
+        };
+        let parsed = read(&csv, &options, b',').unwrap();
+        assert_eq!(parsed.sequences["a"], "MAVMT");
+        assert_eq!(
+            parsed.invalid[0].reason,
+            "row has 2 columns, expected at least 3"
+        );
+
+        let parsed = read(&tsv, &ParseOptions::default(), b'\t').unwrap();
+        assert_eq!(parsed.sequences["b"], "MAVKT");
+        assert_eq!(parse_delimiter("tab"), Ok(b'\t'));
+        assert!(parse_delimiter(";;").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
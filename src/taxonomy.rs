@@ -0,0 +1,199 @@
+//! Annotation of results with the taxonomy of their subjects.
+//!
+//! With `--taxonomy`, a tab-separated table keyed by sequence identifier adds its
+//! columns, e.g. the ranks of a lineage, to every result row for the row's subject,
+//! so nearest-neighbor reports need no join afterwards. The written hits are
+//! counted per taxon of every column and the counts are written next to the
+//! results.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::AlignerError;
+
+/// Label of the hits whose subject is not in the taxonomy, or has no value for a
+/// column
+pub const UNCLASSIFIED: &str = "unclassified";
+
+/// Lineages or other metadata of sequences, by identifier
+#[derive(Debug, Default)]
+pub struct Taxonomy {
+    columns: Vec<String>,
+    lineages: HashMap<String, Vec<String>>,
+}
+
+impl Taxonomy {
+    /// Reads a tab-separated taxonomy table whose header names the identifier
+    /// column first and then the annotation columns, e.g.
+    /// `id<TAB>phylum<TAB>genus`. Rows with fewer fields leave the remaining
+    /// columns empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AlignerError::Io` if the file cannot be read, and
+    /// `AlignerError::InvalidInput` if it has no annotation columns, a row has more
+    /// fields than the header or an identifier occurs more than once.
+    pub fn read(path: &Path) -> Result<Self, AlignerError> {
+        let invalid = |line: usize, reason: String| {
+            AlignerError::InvalidInput(format!("{}:{}: {}", path.display(), line, reason))
+        };
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        let columns: Vec<String> = header.split('\t').skip(1).map(str::to_string).collect();
+        if columns.is_empty() {
+            return Err(invalid(
+                1,
+                "header names no columns besides the identifier".to_string(),
+            ));
+        }
+
+        let mut lineages = HashMap::new();
+        for (index, line) in lines.enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut fields = line.split('\t');
+            let id = fields.next().unwrap_or_default().to_string();
+            let mut lineage: Vec<String> = fields.map(str::to_string).collect();
+            if lineage.len() > columns.len() {
+                return Err(invalid(
+                    index + 2,
+                    format!(
+                        "row has {} fields, the header {}",
+                        lineage.len() + 1,
+                        columns.len() + 1
+                    ),
+                ));
+            }
+            lineage.resize(columns.len(), String::new());
+            if lineages.insert(id.clone(), lineage).is_some() {
+                return Err(invalid(
+                    index + 2,
+                    format!("identifier '{}' occurs more than once", id),
+                ));
+            }
+        }
+        Ok(Self { columns, lineages })
+    }
+
+    /// Returns the names of the annotation columns
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// Returns the annotation of the sequence `id`, if it has one
+    pub fn lineage(&self, id: &str) -> Option<&[String]> {
+        self.lineages.get(id).map(Vec::as_slice)
+    }
+}
+
+/// Number of written hits per taxon of every taxonomy column
+#[derive(Debug, Default)]
+pub struct TaxonCounts {
+    counts: Vec<HashMap<String, u64>>,
+}
+
+impl TaxonCounts {
+    /// Counts a hit of the subject `id`
+    pub fn add(&mut self, taxonomy: &Taxonomy, id: &str) {
+        self.counts
+            .resize_with(taxonomy.columns.len(), HashMap::default);
+        let lineage = taxonomy.lineage(id);
+        for (column, counts) in self.counts.iter_mut().enumerate() {
+            let taxon = lineage
+                .map(|lineage| lineage[column].as_str())
+                .filter(|taxon| !taxon.is_empty())
+                .unwrap_or(UNCLASSIFIED);
+            match counts.get_mut(taxon) {
+                Some(count) => *count += 1,
+                None => {
+                    counts.insert(taxon.to_string(), 1);
+                }
+            }
+        }
+    }
+
+    /// Returns the number of counted hits, the number of hits whose subject is
+    /// classified in the last column, and the most frequent taxon of that column
+    /// with its number of hits
+    pub fn summary(&self) -> (u64, u64, Option<(&str, u64)>) {
+        let Some(counts) = self.counts.last() else {
+            return (0, 0, None);
+        };
+        let total = counts.values().sum::<u64>();
+        let classified = total - counts.get(UNCLASSIFIED).copied().unwrap_or_default();
+        let top = counts
+            .iter()
+            .filter(|(taxon, _)| *taxon != UNCLASSIFIED)
+            .max_by(|(a, x), (b, y)| x.cmp(y).then_with(|| b.cmp(a)))
+            .map(|(taxon, hits)| (taxon.as_str(), *hits));
+        (total, classified, top)
+    }
+
+    /// Writes the counts as tab-separated `column`, `taxon` and `hits` rows, from
+    /// the most to the least frequent taxon of every column.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn write(&self, taxonomy: &Taxonomy, path: &Path) -> std::io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "column\ttaxon\thits")?;
+        for (column, counts) in taxonomy.columns.iter().zip(&self.counts) {
+            let mut counts: Vec<(&String, &u64)> = counts.iter().collect();
+            counts.sort_unstable_by(|(a, x), (b, y)| y.cmp(x).then_with(|| a.cmp(b)));
+            for (taxon, hits) in counts {
+                writeln!(out, "{}\t{}\t{}", column, taxon, hits)?;
+            }
+        }
+        out.flush()
+    }
+}
+
+/// Returns the path of the taxon counts written next to `output`
+pub fn counts_path(output: &Path) -> PathBuf {
+    let mut name = output.as_os_str().to_owned();
+    name.push(".taxa.tsv");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_taxonomy_counts() {
+        let dir = std::env::temp_dir().join(format!("aligner-taxonomy-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("taxonomy.tsv");
+        std::fs::write(
+            &path,
+            "id\tphylum\tgenus\na\tActinomycetota\tStreptomyces\nb\tBacillota\nc\tActinomycetota\tStreptomyces\n",
+        )
+        .unwrap();
+        let taxonomy = Taxonomy::read(&path).unwrap();
+        assert_eq!(taxonomy.lineage("b").unwrap(), ["Bacillota", ""]);
+
+        let mut counts = TaxonCounts::default();
+        for id in ["a", "b", "c", "z"] {
+            counts.add(&taxonomy, id);
+        }
+        assert_eq!(counts.summary(), (4, 2, Some(("Streptomyces", 2))));
+        let output = dir.join("counts.tsv");
+        counts.write(&taxonomy, &output).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "column\ttaxon\thits\n\
+             phylum\tActinomycetota\t2\nphylum\tBacillota\t1\nphylum\tunclassified\t1\n\
+             genus\tStreptomyces\t2\ngenus\tunclassified\t2\n"
+        );
+
+        std::fs::write(&path, "id\tgenus\na\tStreptomyces\na\tBacillus\n").unwrap();
+        let error = Taxonomy::read(&path).unwrap_err();
+        assert!(error.to_string().contains("'a' occurs more than once"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,211 @@
+//! Output rows formatted by a template.
+//!
+//! `--template "{qid},{sid},{pident:.1}"` writes a line per result with the
+//! placeholders replaced by the values of the named [`Column`]s, so bespoke
+//! downstream formats need no conversion step. A placeholder may give the number
+//! of decimals of fractional values after a colon, `{{` and `}}` stand for literal
+//! braces, and everything else is copied as is.
+
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use crate::align::{AlignmentResult, Score};
+use crate::columns::Column;
+use crate::sink::ResultSink;
+use crate::taxonomy::Taxonomy;
+
+/// A piece of a template
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    /// A column, with the number of decimals of fractional values
+    Field(Column, Option<usize>),
+}
+
+/// A parsed row template
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    /// Parses `template`, resolving placeholders to fixed columns, their aliases or
+    /// columns of `taxonomy`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a brace is not closed or escaped, a column is unknown or
+    /// a number of decimals is not of the form `.N`.
+    pub fn parse(template: &str, taxonomy: Option<&Taxonomy>) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '}' => return Err("unmatched '}' in template, write '}}' for a brace".into()),
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) if c != '{' => placeholder.push(c),
+                            _ => {
+                                return Err(format!(
+                                    "unclosed placeholder '{{{}' in template",
+                                    placeholder
+                                ));
+                            }
+                        }
+                    }
+                    let (name, spec) = match placeholder.split_once(':') {
+                        Some((name, spec)) => (name, Some(spec)),
+                        None => (placeholder.as_str(), None),
+                    };
+                    let column = Column::parse(name, taxonomy)?;
+                    let precision = spec
+                        .map(|spec| {
+                            spec.strip_prefix('.')
+                                .and_then(|digits| digits.parse().ok())
+                                .ok_or_else(|| {
+                                    format!(
+                                        "invalid format '{}' of {}, expected e.g. '.2' for two decimals",
+                                        spec, name
+                                    )
+                                })
+                        })
+                        .transpose()?;
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Field(column, precision));
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Self { segments })
+    }
+
+    /// Returns the columns of the placeholders
+    pub fn columns(&self) -> impl Iterator<Item = Column> + '_ {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::Field(column, _) => Some(*column),
+            Segment::Literal(_) => None,
+        })
+    }
+
+    /// Writes the row of `result` without a line break. `min_containment` is the
+    /// fraction flagging a containment, if containments are measured.
+    pub fn write<S: Score, W: Write>(
+        &self,
+        writer: &mut W,
+        result: &AlignmentResult<S>,
+        min_containment: Option<f64>,
+        taxonomy: Option<&Taxonomy>,
+    ) -> io::Result<()> {
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => writer.write_all(text.as_bytes())?,
+                Segment::Field(column, precision) => {
+                    let value = column.value(result, min_containment, taxonomy);
+                    match precision {
+                        Some(precision) => write!(writer, "{:.*}", precision, value)?,
+                        None => write!(writer, "{}", value)?,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes a line per result formatted by a [`Template`], without a header
+pub struct TemplateSink<W: Write> {
+    writer: W,
+    template: Template,
+    containment: Option<f64>,
+    taxonomy: Option<Arc<Taxonomy>>,
+}
+
+impl<W: Write> TemplateSink<W> {
+    /// Creates a sink writing to `writer`, which should be buffered
+    pub fn new(writer: W, template: Template) -> Self {
+        Self {
+            writer,
+            template,
+            containment: None,
+            taxonomy: None,
+        }
+    }
+
+    /// Sets the fraction flagging a containment, for `{contained}` placeholders
+    pub fn with_containment(mut self, min_fraction: Option<f64>) -> Self {
+        self.containment = min_fraction;
+        self
+    }
+
+    /// Sets the taxonomy the placeholders of its columns are filled from
+    pub fn with_taxonomy(mut self, taxonomy: Option<Arc<Taxonomy>>) -> Self {
+        self.taxonomy = taxonomy;
+        self
+    }
+}
+
+impl<S: Score, W: Write> ResultSink<S> for TemplateSink<W> {
+    fn open(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_batch(&mut self, results: &[AlignmentResult<S>]) -> io::Result<()> {
+        for result in results {
+            self.template.write(
+                &mut self.writer,
+                result,
+                self.containment,
+                self.taxonomy.as_deref(),
+            )?;
+            self.writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::align::PairStatus;
+
+    #[test]
+    fn test_template() {
+        let result = AlignmentResult::<i32> {
+            identity: Some(0.8125),
+            seq1_len: 4,
+            seq2_len: 5,
+            ..AlignmentResult::for_test("a", "b", Some(12), PairStatus::Aligned)
+        };
+        let template =
+            Template::parse("{qid},{sid}\t{pident:.1} {score:.2} {{{slen}}}", None).unwrap();
+        let mut row = Vec::new();
+        template.write(&mut row, &result, None, None).unwrap();
+        assert_eq!(String::from_utf8(row).unwrap(), "a,b\t81.2 12 {5}");
+
+        assert!(Template::parse("{qid", None).is_err());
+        assert!(Template::parse("qid}", None).is_err());
+        assert!(Template::parse("{qcov}", None).is_err());
+        assert!(Template::parse("{pident:2}", None).is_err());
+    }
+}
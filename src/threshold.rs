@@ -0,0 +1,231 @@
+//! Suggested score thresholds separating homologous from random pairs.
+//!
+//! The scores of an all-vs-all run usually fall into two modes: a large one of
+//! unrelated pairs scoring at random and a smaller one of homologous pairs. While
+//! the results arrive, [`ScoreSample`] keeps a uniform reservoir sample of the
+//! scores. At the end, a mixture of two normal distributions is fitted to the
+//! sample by expectation maximization, starting from the split of Otsu's method,
+//! and the score at which a pair becomes more likely to belong to the upper mode
+//! than to the lower one is suggested as a threshold. Samples whose fitted modes
+//! overlap too much to be told apart get no suggestion.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Number of scores kept in the sample
+pub const RESERVOIR_SIZE: usize = 100_000;
+
+/// Fewest scores a threshold is suggested for
+const MIN_SAMPLE: usize = 100;
+
+/// Smallest weight of a mode, so single outliers do not make up a mode of their own
+const MIN_WEIGHT: f64 = 0.001;
+
+/// Smallest distance of the means of the modes in pooled standard deviations
+/// (Ashman's D) for them to count as separate
+const MIN_SEPARATION: f64 = 2.0;
+
+/// Iterations of expectation maximization
+const ITERATIONS: usize = 200;
+
+/// A uniform sample of the scores of a run
+pub struct ScoreSample {
+    scores: Vec<f64>,
+    /// Number of scores offered so far
+    seen: u64,
+    rng: StdRng,
+}
+
+impl Default for ScoreSample {
+    fn default() -> Self {
+        Self {
+            scores: Vec::new(),
+            seen: 0,
+            // Seeded, so the same run always suggests the same threshold
+            rng: StdRng::seed_from_u64(0),
+        }
+    }
+}
+
+/// A normal distribution fitted to one mode of the scores
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mode {
+    /// Fraction of the scores belonging to the mode
+    pub weight: f64,
+    pub mean: f64,
+    pub sd: f64,
+}
+
+impl Mode {
+    /// Logarithm of the weighted density of the mode at `x`
+    fn log_density(&self, x: f64) -> f64 {
+        let z = (x - self.mean) / self.sd;
+        self.weight.ln() - self.sd.ln() - 0.5 * z * z
+    }
+}
+
+/// A threshold separating the two modes of the scores
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Threshold {
+    /// Lowest score more likely to belong to the upper mode than to the lower one
+    pub score: f64,
+    /// Mode of the random scores of unrelated pairs
+    pub random: Mode,
+    /// Mode of the scores of homologous pairs
+    pub homologous: Mode,
+}
+
+impl ScoreSample {
+    /// Offers the score of a pair to the sample, keeping it with the probability
+    /// that keeps the sample uniform (reservoir sampling)
+    pub fn offer(&mut self, score: f64) {
+        self.seen += 1;
+        if self.scores.len() < RESERVOIR_SIZE {
+            self.scores.push(score);
+            return;
+        }
+        let slot = self.rng.random_range(0..self.seen);
+        if let Some(kept) = self.scores.get_mut(slot as usize) {
+            *kept = score;
+        }
+    }
+
+    /// Returns the number of scores in the sample
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    /// Suggests a threshold separating the two modes of the sampled scores, or
+    /// `None` if the sample is too small or has no separate second mode
+    pub fn threshold(&self) -> Option<Threshold> {
+        if self.scores.len() < MIN_SAMPLE {
+            return None;
+        }
+        let mut scores = self.scores.clone();
+        scores.sort_by(f64::total_cmp);
+        let split = otsu(&scores)?;
+        let mode = |scores: &[f64], weight: f64| {
+            let mean = scores.iter().sum::<f64>() / scores.len() as f64;
+            let variance =
+                scores.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / scores.len() as f64;
+            Mode {
+                weight,
+                mean,
+                sd: variance.sqrt(),
+            }
+        };
+        let n = scores.len() as f64;
+        let mut modes = [
+            mode(&scores[..split], split as f64 / n),
+            mode(&scores[split..], 1.0 - split as f64 / n),
+        ];
+
+        // Scores are never exactly equal to a mode, so they cannot collapse onto one
+        let floor = (scores[scores.len() - 1] - scores[0]) * 1e-3;
+        for _ in 0..ITERATIONS {
+            let mut sums = [[0.0; 3]; 2];
+            for &x in &scores {
+                let (low, high) = (modes[0].log_density(x), modes[1].log_density(x));
+                let upper = 1.0 / (1.0 + (low - high).exp());
+                for (sum, responsibility) in sums.iter_mut().zip([1.0 - upper, upper]) {
+                    sum[0] += responsibility;
+                    sum[1] += responsibility * x;
+                    sum[2] += responsibility * x * x;
+                }
+            }
+            for (mode, [weight, sum, squares]) in modes.iter_mut().zip(sums) {
+                if weight <= 0.0 {
+                    return None;
+                }
+                let mean = sum / weight;
+                *mode = Mode {
+                    weight: weight / n,
+                    mean,
+                    sd: (squares / weight - mean * mean).max(0.0).sqrt().max(floor),
+                };
+            }
+        }
+
+        let [random, homologous] = modes;
+        let pooled = ((random.sd.powi(2) + homologous.sd.powi(2)) / 2.0).sqrt();
+        if random.weight.min(homologous.weight) < MIN_WEIGHT
+            || (homologous.mean - random.mean) / pooled < MIN_SEPARATION
+        {
+            return None;
+        }
+        // The modes are equally likely somewhere between their means
+        let upper = |x: f64| homologous.log_density(x) >= random.log_density(x);
+        let (mut low, mut high) = (random.mean, homologous.mean);
+        if upper(low) || !upper(high) {
+            return None;
+        }
+        for _ in 0..64 {
+            let middle = (low + high) / 2.0;
+            if upper(middle) {
+                high = middle;
+            } else {
+                low = middle;
+            }
+        }
+        Some(Threshold {
+            score: high,
+            random,
+            homologous,
+        })
+    }
+}
+
+/// Returns the index splitting sorted `scores` into two classes with the largest
+/// variance between them (Otsu's method), or `None` if all scores are equal
+fn otsu(scores: &[f64]) -> Option<usize> {
+    let n = scores.len() as f64;
+    let total: f64 = scores.iter().sum();
+    let mut below = 0.0;
+    let mut best: Option<(usize, f64)> = None;
+    for k in 1..scores.len() {
+        below += scores[k - 1];
+        if scores[k - 1] == scores[k] {
+            continue;
+        }
+        let (w0, w1) = (k as f64 / n, 1.0 - k as f64 / n);
+        let (mean0, mean1) = (below / k as f64, (total - below) / (n - k as f64));
+        let between = w0 * w1 * (mean0 - mean1).powi(2);
+        if best.is_none_or(|(_, best)| between > best) {
+            best = Some((k, between));
+        }
+    }
+    best.map(|(k, _)| k)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_threshold() {
+        let mut rng = StdRng::seed_from_u64(1);
+        // Roughly normal scores as sums of uniform ones
+        let mut normal = |mean: f64, sd: f64| {
+            let sum: f64 = (0..12).map(|_| rng.random::<f64>()).sum();
+            mean + sd * (sum - 6.0)
+        };
+        let mut sample = ScoreSample::default();
+        for i in 0..5_000 {
+            let score = if i % 10 == 0 {
+                normal(200.0, 20.0)
+            } else {
+                normal(20.0, 10.0)
+            };
+            sample.offer(score);
+        }
+        let threshold = sample.threshold().unwrap();
+        assert!((60.0..140.0).contains(&threshold.score), "{:?}", threshold);
+        assert!((threshold.homologous.weight - 0.1).abs() < 0.02);
+
+        let mut unimodal = ScoreSample::default();
+        for _ in 0..5_000 {
+            unimodal.offer(normal(20.0, 10.0));
+        }
+        assert_eq!(unimodal.threshold(), None);
+    }
+}
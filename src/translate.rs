@@ -0,0 +1,279 @@
+//! Translation of nucleotide sequences into proteins.
+//!
+//! Translation uses the genetic code tables of NCBI, selected by their number,
+//! e.g. 1 for the standard code and 11 for bacteria, archaea and plastids. Tables
+//! with context-dependent stop codons (27, 28 and 31) are not supported.
+
+/// A genetic code table, with codons ordered by their bases as T, C, A, G
+#[derive(Debug)]
+pub struct GeneticCode {
+    /// NCBI number of the table
+    pub id: u32,
+    /// NCBI name of the table
+    pub name: &'static str,
+    /// Amino acid of every codon, `*` for stop codons
+    amino_acids: &'static [u8; 64],
+    /// `M` for every codon that can start translation
+    starts: &'static [u8; 64],
+}
+
+/// The genetic code tables of NCBI without context-dependent stop codons
+static CODES: [GeneticCode; 23] = [
+    GeneticCode {
+        id: 1,
+        name: "Standard",
+        amino_acids: b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"---M------**--*----M---------------M----------------------------",
+    },
+    GeneticCode {
+        id: 2,
+        name: "Vertebrate Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSS**VVVVAAAADDEEGGGG",
+        starts: b"----------**--------------------MMMM----------**---M------------",
+    },
+    GeneticCode {
+        id: 3,
+        name: "Yeast Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWTTTTPPPPHHQQRRRRIIMMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"----------**----------------------MM---------------M------------",
+    },
+    GeneticCode {
+        id: 4,
+        name: "Mold, Protozoan, and Coelenterate Mitochondrial and Mycoplasma/Spiroplasma",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"--MM------**-------M------------MMMM---------------M------------",
+    },
+    GeneticCode {
+        id: 5,
+        name: "Invertebrate Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSSSVVVVAAAADDEEGGGG",
+        starts: b"---M------**--------------------MMMM---------------M------------",
+    },
+    GeneticCode {
+        id: 6,
+        name: "Ciliate, Dasycladacean and Hexamita Nuclear",
+        amino_acids: b"FFLLSSSSYYQQCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    GeneticCode {
+        id: 9,
+        name: "Echinoderm and Flatworm Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M---------------M------------",
+    },
+    GeneticCode {
+        id: 10,
+        name: "Euplotid Nuclear",
+        amino_acids: b"FFLLSSSSYY**CCCWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    GeneticCode {
+        id: 11,
+        name: "Bacterial, Archaeal and Plant Plastid",
+        amino_acids: b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"---M------**--*----M------------MMMM---------------M------------",
+    },
+    GeneticCode {
+        id: 12,
+        name: "Alternative Yeast Nuclear",
+        amino_acids: b"FFLLSSSSYY**CC*WLLLSPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-------------------M---------------M----------------------------",
+    },
+    GeneticCode {
+        id: 13,
+        name: "Ascidian Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSGGVVVVAAAADDEEGGGG",
+        starts: b"---M------------------------------MM---------------M------------",
+    },
+    GeneticCode {
+        id: 14,
+        name: "Alternative Flatworm Mitochondrial",
+        amino_acids: b"FFLLSSSSYYY*CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    GeneticCode {
+        id: 16,
+        name: "Chlorophycean Mitochondrial",
+        amino_acids: b"FFLLSSSSYY*LCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    GeneticCode {
+        id: 21,
+        name: "Trematode Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M---------------M------------",
+    },
+    GeneticCode {
+        id: 22,
+        name: "Scenedesmus obliquus Mitochondrial",
+        amino_acids: b"FFLLSS*SYY*LCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    GeneticCode {
+        id: 23,
+        name: "Thraustochytrium Mitochondrial",
+        amino_acids: b"FF*LSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"--------------------------------M--M---------------M------------",
+    },
+    GeneticCode {
+        id: 24,
+        name: "Rhabdopleuridae Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSSKVVVVAAAADDEEGGGG",
+        starts: b"---M---------------M---------------M---------------M------------",
+    },
+    GeneticCode {
+        id: 25,
+        name: "Candidate Division SR1 and Gracilibacteria",
+        amino_acids: b"FFLLSSSSYY**CCGWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"---M-------------------------------M---------------M------------",
+    },
+    GeneticCode {
+        id: 26,
+        name: "Pachysolen tannophilus Nuclear",
+        amino_acids: b"FFLLSSSSYY**CC*WLLLAPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-------------------M---------------M----------------------------",
+    },
+    GeneticCode {
+        id: 29,
+        name: "Mesodinium Nuclear",
+        amino_acids: b"FFLLSSSSYYYYCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    GeneticCode {
+        id: 30,
+        name: "Peritrich Nuclear",
+        amino_acids: b"FFLLSSSSYYEECC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    GeneticCode {
+        id: 32,
+        name: "Balanophoraceae Plastid",
+        amino_acids: b"FFLLSSSSYY*WCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"---M------*---*----M------------MMMM---------------M------------",
+    },
+    GeneticCode {
+        id: 33,
+        name: "Cephalodiscidae Mitochondrial",
+        amino_acids: b"FFLLSSSSYYY*CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSSKVVVVAAAADDEEGGGG",
+        starts: b"---M-------*-------M---------------M---------------M------------",
+    },
+];
+
+impl GeneticCode {
+    /// The standard genetic code
+    pub const STANDARD: &'static Self = &CODES[0];
+
+    /// Returns the table with an NCBI number
+    pub fn get(id: u32) -> Option<&'static Self> {
+        CODES.iter().find(|code| code.id == id)
+    }
+
+    /// Translates a nucleotide sequence, using `X` for codons with ambiguous
+    /// bases
+    pub fn translate(&self, nucleotides: &str) -> String {
+        nucleotides
+            .as_bytes()
+            .chunks_exact(3)
+            .map(|codon| codon_index(codon).map_or('X', |i| self.amino_acids[i] as char))
+            .collect()
+    }
+
+    /// Translates a coding sequence, with methionine for a first codon that can
+    /// start translation and without a terminal stop codon
+    pub fn translate_cds(&self, nucleotides: &str) -> String {
+        let mut protein = self.translate(nucleotides);
+        if self.is_start(nucleotides.as_bytes()) {
+            protein.replace_range(..1, "M");
+        }
+        if protein.ends_with('*') {
+            protein.pop();
+        }
+        protein
+    }
+
+    /// Returns whether a codon, the first three bases of `codon`, can start
+    /// translation
+    pub fn is_start(&self, codon: &[u8]) -> bool {
+        codon
+            .get(..3)
+            .and_then(codon_index)
+            .is_some_and(|i| self.starts[i] == b'M')
+    }
+
+    /// Returns whether a codon, the first three bases of `codon`, is a stop codon
+    pub fn is_stop(&self, codon: &[u8]) -> bool {
+        codon
+            .get(..3)
+            .and_then(codon_index)
+            .is_some_and(|i| self.amino_acids[i] == b'*')
+    }
+}
+
+/// Returns the index of a codon in the tables, `None` for ambiguous bases
+fn codon_index(codon: &[u8]) -> Option<usize> {
+    codon.iter().take(3).try_fold(0, |index, base| {
+        let base = match base {
+            b'T' | b'U' => 0,
+            b'C' => 1,
+            b'A' => 2,
+            b'G' => 3,
+            _ => return None,
+        };
+        Some(index * 4 + base)
+    })
+}
+
+/// Checks the value of `--gencode` against the supported tables
+pub fn parse_gencode(id: &str) -> Result<&'static GeneticCode, String> {
+    id.parse().ok().and_then(GeneticCode::get).ok_or_else(|| {
+        let mut ids: Vec<u32> = CODES.iter().map(|code| code.id).collect();
+        ids.sort_unstable();
+        format!(
+            "unknown genetic code '{}', expected one of the NCBI tables {}",
+            id,
+            ids.iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    })
+}
+
+/// Returns the reverse complement of a nucleotide sequence, with `N` for
+/// ambiguous bases
+pub fn reverse_complement(nucleotides: &str) -> String {
+    nucleotides
+        .bytes()
+        .rev()
+        .map(|base| complement(base) as char)
+        .collect()
+}
+
+/// Returns the complementary base
+fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'T' | b'U' => b'A',
+        b'C' => b'G',
+        b'G' => b'C',
+        _ => b'N',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_genetic_codes() {
+        let coding = "GTGTGAATAAGATAA";
+        assert_eq!(GeneticCode::STANDARD.translate(coding), "V*IR*");
+        let bacterial = GeneticCode::get(11).unwrap();
+        assert_eq!(bacterial.translate_cds(coding), "M*IR");
+        let mitochondrial = parse_gencode("2").unwrap();
+        assert_eq!(mitochondrial.translate(coding), "VWM**");
+        assert!(mitochondrial.is_stop(b"AGG"));
+        assert!(!GeneticCode::STANDARD.is_start(b"ATA"));
+        assert!(parse_gencode("27").is_err());
+    }
+}
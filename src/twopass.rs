@@ -0,0 +1,174 @@
+//! Runs in two passes: sketch distances first, alignments of the survivors second.
+//!
+//! For very large inputs, `--two-pass MAX_DISTANCE` splits a run into a cheap and an
+//! exact pass. The first pass sketches every sequence once with MinHash (see
+//! [`Sketch`]), estimates the k-mer distance of every pair, one minus the Jaccard
+//! similarity of their k-mer sets, and keeps the pairs within `MAX_DISTANCE` in a
+//! state file next to the output. The second pass aligns only the pairs of the
+//! state file. The file starts with a line naming the parameters of the first pass
+//! and is only renamed into place once complete, so a later run with the same
+//! parameters, e.g. after an interrupted second pass or with other alignment
+//! options, skips the first pass. It is a pair list for `--pairs` as well.
+
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::filter::{DEFAULT_SKETCH_K, DEFAULT_SKETCH_SIZE, Sketch};
+use crate::pairs::Pair;
+
+/// Returns the path of the state file written next to `output`
+pub fn state_path(output: &Path) -> PathBuf {
+    let mut name = output.as_os_str().to_owned();
+    name.push(".sketch.tsv");
+    PathBuf::from(name)
+}
+
+/// Returns the first line of a state file of the first pass keeping the pairs
+/// within `max_distance`
+fn header(max_distance: f64) -> String {
+    format!(
+        "# sketch distances k={} size={} max_distance={}",
+        DEFAULT_SKETCH_K, DEFAULT_SKETCH_SIZE, max_distance
+    )
+}
+
+/// Returns `true` if `path` holds the complete first pass keeping the pairs within
+/// `max_distance`
+pub fn is_complete(path: &Path, max_distance: f64) -> bool {
+    let Ok(file) = File::open(path) else {
+        return false;
+    };
+    let mut first = String::new();
+    BufReader::new(file).read_line(&mut first).is_ok() && first.trim_end() == header(max_distance)
+}
+
+/// Runs the first pass over `pairs`, writing the pairs within `max_distance`, with
+/// their distance, to `path`
+///
+/// The sketches are computed on rayon's global pool, so a pool with the threads of
+/// the run is set up before, see [`ExecutionOptions::build_global_pool`].
+///
+/// [`ExecutionOptions::build_global_pool`]: crate::align::ExecutionOptions::build_global_pool
+///
+/// # Returns
+///
+/// The number of pairs kept for the second pass
+///
+/// # Errors
+///
+/// Returns an error if the state file cannot be written.
+pub fn first_pass(
+    input: &HashMap<String, String>,
+    pairs: &[Pair],
+    max_distance: f64,
+    path: &Path,
+) -> io::Result<usize> {
+    let sketch = Sketch {
+        min_similarity: 1.0 - max_distance,
+        k: DEFAULT_SKETCH_K,
+        size: DEFAULT_SKETCH_SIZE,
+    };
+    let sketches: HashMap<&String, Vec<u64>> = input
+        .par_iter()
+        .map(|(id, seq)| (id, sketch.sketch(seq)))
+        .collect();
+    let kept: Vec<(&Pair, f64)> = pairs
+        .par_iter()
+        .filter_map(|pair @ (query_id, subject_id)| {
+            let similarity = sketch.compare(&sketches[query_id], &sketches[subject_id]);
+            let distance = 1.0 - similarity;
+            (distance <= max_distance).then_some((pair, distance))
+        })
+        .collect();
+
+    // Written aside first, so an interrupted pass leaves no state file behind
+    let mut name = path.as_os_str().to_owned();
+    name.push(".tmp");
+    let tmp = PathBuf::from(name);
+    let mut out = BufWriter::new(File::create(&tmp)?);
+    writeln!(out, "{}", header(max_distance))?;
+    for ((query_id, subject_id), distance) in &kept {
+        writeln!(out, "{}\t{}\t{:.4}", query_id, subject_id, distance)?;
+    }
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(kept.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pairs::{PairGenerator, PairList, Triangle};
+
+    #[test]
+    fn test_two_pass_state() {
+        let input: HashMap<String, String> = [
+            ("a", "MKTAYIAKQRQISFVKSHFSRQ"),
+            ("b", "MKTAYIAKQRQISFVKSHFSRE"),
+            ("c", "WWGPNDCHYEWWGPNDCHYE"),
+        ]
+        .into_iter()
+        .map(|(id, seq)| (id.to_string(), seq.to_string()))
+        .collect();
+        let pairs = Triangle.pairs(&input);
+        let dir = std::env::temp_dir().join(format!("aligner-twopass-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = state_path(&dir.join("out.tsv"));
+        assert!(!is_complete(&path, 0.5));
+
+        assert_eq!(first_pass(&input, &pairs, 0.5, &path).unwrap(), 1);
+        assert!(is_complete(&path, 0.5));
+        assert!(!is_complete(&path, 0.6));
+        let survivors = PairList::read(&path).unwrap();
+        assert_eq!(
+            survivors.pairs(&input),
+            [(&"b".to_string(), &"a".to_string())]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_second_pass_with_threads() {
+        use crate::align::{ExecutionOptions, Observers, Scorer, align_all_streaming};
+        use crate::filter::FilterChain;
+
+        let input: HashMap<String, String> = [
+            ("a", "MKTAYIAKQRQISFVKSHFSRQ"),
+            ("b", "MKTAYIAKQRQISFVKSHFSRE"),
+            ("c", "WWGPNDCHYEWWGPNDCHYE"),
+        ]
+        .into_iter()
+        .map(|(id, seq)| (id.to_string(), seq.to_string()))
+        .collect();
+        let dir = std::env::temp_dir().join(format!("aligner-threads-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = state_path(&dir.join("out.tsv"));
+        // The first pass starts the global pool before the second one runs
+        first_pass(&input, &Triangle.pairs(&input), 0.5, &path).unwrap();
+        let options = ExecutionOptions {
+            num_threads: Some(2),
+            ..ExecutionOptions::default()
+        };
+        assert!(options.build_global_pool().is_err());
+
+        let matcher = crate::ScoringType::Identity.matcher();
+        let scorer = Scorer::for_test(&matcher);
+        let (tx, rx) = std::sync::mpsc::channel();
+        align_all_streaming(
+            &input,
+            scorer,
+            &FilterChain::default(),
+            &PairList::read(&path).unwrap(),
+            tx,
+            &options,
+            Observers::default(),
+        );
+        let results: Vec<_> = rx.iter().collect();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].score, Some(21));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,13 +2,19 @@
 //!
 //! This module provides helper functions for progress tracking and input parsing.
 
+use csv::ReaderBuilder;
 use indicatif::ProgressBar;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufReader;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::info_span;
 
 use crate::error::AlignerError;
+use crate::input::{ParseOptions, Parsed};
+use crate::sink::OutputFormat;
+use crate::source;
 
 /// Creates and configures a progress bar for tracking alignment operations.
 ///
@@ -39,6 +45,7 @@ pub fn setup_progress_bar(total_comparisons: u64) -> ProgressBar {
 ///
 /// Reads a JSON file where keys are sequence identifiers and values are the
 /// actual sequences, and converts it into a HashMap for efficient lookup.
+/// The default [`ParseOptions`] are used.
 ///
 /// # Arguments
 ///
@@ -54,7 +61,243 @@ pub fn setup_progress_bar(total_comparisons: u64) -> ProgressBar {
 /// Returns `AlignerError::Io` if the file cannot be opened or read.
 /// Returns `AlignerError::Parse` if the JSON is malformed or doesn't match the expected format.
 pub fn parse_input(path: impl Into<PathBuf>) -> Result<HashMap<String, String>, AlignerError> {
-    let content = File::open(path.into()).map_err(AlignerError::Io)?;
-    let reader = BufReader::new(content);
-    serde_json::from_reader(reader).map_err(AlignerError::Parse)
+    parse_input_with(path, &ParseOptions::default()).map(|parsed| parsed.sequences)
+}
+
+/// Parses an input file or URL in any of the registered formats (see
+/// [`crate::source`]), handling malformed records according to the parse mode.
+///
+/// # Errors
+///
+/// Returns `AlignerError::Io` if the file cannot be opened or read.
+/// Returns `AlignerError::Parse`, `AlignerError::Yaml` or another format's error
+/// if the input is malformed or, unless the mode is lenient, contains a malformed
+/// record.
+pub fn parse_input_with(
+    path: impl Into<PathBuf>,
+    options: &ParseOptions,
+) -> Result<Parsed, AlignerError> {
+    let path = path.into();
+    let _span = info_span!("parse_input", path = %path.display(), mode = ?options.mode).entered();
+    source::read(&path, options)
+}
+
+/// Parses several inputs like [`parse_input_with`] and merges them into one set
+/// of sequences. An identifier may occur in several inputs if its sequence is the
+/// same in all of them. Skipped records of several inputs name their input.
+///
+/// # Errors
+///
+/// Returns the errors of [`parse_input_with`], and `AlignerError::InvalidInput`
+/// if two inputs hold different sequences under the same identifier.
+pub fn parse_inputs_with(
+    paths: &[PathBuf],
+    options: &ParseOptions,
+) -> Result<Parsed, AlignerError> {
+    if let [path] = paths {
+        return parse_input_with(path, options);
+    }
+    parse_input_sets_with(paths, options).map(|(parsed, _)| parsed)
+}
+
+/// Parses and merges several inputs like [`parse_inputs_with`], and also returns
+/// the identifiers read from every input, in the order of `paths`.
+///
+/// # Errors
+///
+/// Returns the errors of [`parse_inputs_with`].
+pub fn parse_input_sets_with(
+    paths: &[PathBuf],
+    options: &ParseOptions,
+) -> Result<(Parsed, Vec<HashSet<String>>), AlignerError> {
+    let mut merged = Parsed::default();
+    let mut sets = Vec::with_capacity(paths.len());
+    // Input each identifier was first read from
+    let mut origins: HashMap<String, &Path> = HashMap::new();
+    for path in paths {
+        let parsed = parse_input_with(path, options)?;
+        sets.push(parsed.sequences.keys().cloned().collect());
+        for (id, sequence) in parsed.sequences {
+            if let Some(origin) = origins.get(&id) {
+                if merged.sequences[&id] != sequence {
+                    return Err(AlignerError::InvalidInput(format!(
+                        "sequence '{}' differs between {} and {}",
+                        id,
+                        origin.display(),
+                        path.display()
+                    )));
+                }
+                continue;
+            }
+            origins.insert(id.clone(), path);
+            merged.sequences.insert(id, sequence);
+        }
+        merged
+            .invalid
+            .extend(parsed.invalid.into_iter().map(|mut record| {
+                record.reason = format!("{}: {}", path.display(), record.reason);
+                record
+            }));
+        merged.qualities.extend(parsed.qualities);
+        merged.metadata.extend(parsed.metadata);
+    }
+    Ok((merged, sets))
+}
+
+/// Reads the header line of a results file, without its line break.
+///
+/// # Errors
+///
+/// Returns `AlignerError::Io` if the file cannot be opened or read.
+pub fn read_result_header(path: &Path) -> Result<String, AlignerError> {
+    let mut header = String::new();
+    BufReader::new(File::open(path)?).read_line(&mut header)?;
+    Ok(header.trim_end_matches(['\n', '\r']).to_string())
+}
+
+/// Reads the sequence identifiers occurring in a results file.
+///
+/// The identifiers are read from the columns read by [`read_result_pairs`].
+///
+/// # Errors
+///
+/// Returns `AlignerError::Io` if the file cannot be opened or read.
+pub fn read_result_ids(path: impl Into<PathBuf>) -> Result<HashSet<String>, AlignerError> {
+    Ok(read_result_pairs(path)?
+        .into_iter()
+        .flat_map(|(query_id, subject_id)| [query_id, subject_id])
+        .collect())
+}
+
+/// Reads the pairs of sequence identifiers occurring in a results file.
+///
+/// The file is read as comma-separated values if it ends in `.csv` and as
+/// tab-separated values otherwise. The query and subject identifiers of every row
+/// after the header are taken from the `query_id` and `subject_id` columns, which
+/// the aligner writes first unless `--columns` orders them differently, or from
+/// the first two columns if the header names neither.
+///
+/// # Errors
+///
+/// Returns `AlignerError::Io` if the file cannot be opened or read, and
+/// `AlignerError::InvalidInput` if it is not valid CSV.
+pub fn read_result_pairs(path: impl Into<PathBuf>) -> Result<Vec<(String, String)>, AlignerError> {
+    let path = path.into();
+    let format = OutputFormat::from_path(&path);
+    let mut reader = ReaderBuilder::new()
+        .delimiter(format.delimiter().unwrap_or(b'\t'))
+        // Tab-separated fields are written without quotes
+        .quoting(format == OutputFormat::Csv)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(BufReader::new(File::open(&path)?));
+    let to_error = |e: csv::Error| match e.kind() {
+        csv::ErrorKind::Io(_) => AlignerError::Io(e.into()),
+        _ => AlignerError::InvalidInput(format!("{}: {}", path.display(), e)),
+    };
+
+    let mut rows = reader.records();
+    let header = rows
+        .next()
+        .transpose()
+        .map_err(to_error)?
+        .unwrap_or_default();
+    let position = |name: &str| header.iter().position(|column| column == name);
+    let (query, subject) = match (position("query_id"), position("subject_id")) {
+        (Some(query), Some(subject)) => (query, subject),
+        _ => (0, 1),
+    };
+    let mut pairs = Vec::new();
+    for row in rows {
+        let row = row.map_err(to_error)?;
+        if let (Some(query_id), Some(subject_id)) = (row.get(query), row.get(subject)) {
+            pairs.push((query_id.to_string(), subject_id.to_string()));
+        }
+    }
+    Ok(pairs)
+}
+
+/// Removes an incomplete last row from a results file after a failed write.
+///
+/// # Arguments
+///
+/// * `path` - Path to the results file
+/// * `start` - Length of the file before this run started writing to it
+///
+/// # Returns
+///
+/// The number of complete lines this run wrote to the file, or `None` if the
+/// output is not a regular file, such as a device or a pipe, which has no rows to
+/// count or cut
+///
+/// # Errors
+///
+/// Returns `AlignerError::Io` if the file cannot be read or truncated.
+pub fn truncate_partial_row(path: &Path, start: u64) -> Result<Option<u64>, AlignerError> {
+    if !std::fs::metadata(path)?.is_file() {
+        return Ok(None);
+    }
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut lines = 0;
+    let mut complete = start;
+    let mut offset = start;
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        for (index, byte) in buffer[..read].iter().enumerate() {
+            if *byte == b'\n' {
+                lines += 1;
+                complete = offset + index as u64 + 1;
+            }
+        }
+        offset += read as u64;
+    }
+    if complete < offset {
+        file.set_len(complete)?;
+    }
+    Ok(Some(lines))
+}
+
+/// Removes an unfinished last row from a results file that results are appended
+/// to, e.g. one cut off when the run writing it was killed.
+///
+/// # Returns
+///
+/// The number of bytes removed
+///
+/// # Errors
+///
+/// Returns `AlignerError::Io` if the file cannot be read or truncated.
+pub fn drop_partial_row(path: &Path) -> Result<u64, AlignerError> {
+    let mut header = Vec::new();
+    let start = BufReader::new(File::open(path)?).read_until(b'\n', &mut header)? as u64;
+    let len = std::fs::metadata(path)?.len();
+    truncate_partial_row(path, start)?;
+    Ok(len - std::fs::metadata(path)?.len())
+}
+
+/// Parses a duration such as `500ms`, `30s`, `5m` or `2h`; plain numbers are seconds.
+///
+/// Used as a clap value parser, hence the `String` error.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration '{}'", value))?;
+    let secs = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return Err(format!("unknown unit '{}' in duration '{}'", unit, value)),
+    };
+    Duration::try_from_secs_f64(secs).map_err(|e| format!("invalid duration '{}': {}", value, e))
 }
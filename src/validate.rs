@@ -0,0 +1,221 @@
+//! Validation of input sequences.
+//!
+//! Sequences are compared byte by byte, so a character outside ASCII (e.g. a curly
+//! quote pasted into a JSON value) is aligned as several unrelated bytes and silently
+//! distorts the score. This module reports such characters with the sequence and
+//! position they occur at, and optionally removes or replaces them.
+//!
+//! It also rejects sequences that are too long to be aligned, either because the
+//! user set a maximum length or because their scores could overflow.
+
+use clap::ValueEnum;
+use std::collections::HashMap;
+use tracing::warn;
+
+use crate::align::MAX_SAFE_LEN;
+use crate::error::AlignerError;
+
+/// Number of offending characters or sequences listed in error messages
+const REPORTED_ISSUES: usize = 5;
+
+/// Repair applied to non-ASCII characters in lenient mode
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Lenient {
+    /// Replace characters with an ASCII equivalent (full-width letters, dashes)
+    /// and remove all others
+    Transliterate,
+    /// Remove all non-ASCII characters
+    Strip,
+}
+
+/// A non-ASCII character found in a sequence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NonAscii {
+    /// Identifier of the sequence
+    pub id: String,
+    /// One-based character position within the sequence
+    pub position: usize,
+    /// The offending character
+    pub character: char,
+}
+
+impl std::fmt::Display for NonAscii {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "'{}' (U+{:04X}) in sequence '{}' at position {}",
+            self.character, self.character as u32, self.id, self.position
+        )
+    }
+}
+
+/// Checks all sequences for non-ASCII characters.
+///
+/// # Arguments
+///
+/// * `input` - Sequences by identifier, repaired in place in lenient mode
+/// * `lenient` - Repair to apply, or `None` to reject non-ASCII input
+///
+/// # Returns
+///
+/// The identifiers of the sequences that were repaired, sorted
+///
+/// # Errors
+///
+/// Returns `AlignerError::InvalidInput` listing the first offending characters
+/// and their positions if any sequence contains non-ASCII characters and
+/// `lenient` is `None`.
+pub fn check_ascii(
+    input: &mut HashMap<String, String>,
+    lenient: Option<Lenient>,
+) -> Result<Vec<String>, AlignerError> {
+    let mut issues: Vec<NonAscii> = input
+        .iter()
+        .filter(|(_, seq)| !seq.is_ascii())
+        .flat_map(|(id, seq)| {
+            seq.chars()
+                .enumerate()
+                .filter(|(_, c)| !c.is_ascii())
+                .map(|(index, character)| NonAscii {
+                    id: id.clone(),
+                    position: index + 1,
+                    character,
+                })
+        })
+        .collect();
+    if issues.is_empty() {
+        return Ok(Vec::new());
+    }
+    issues.sort_by(|a, b| (&a.id, a.position).cmp(&(&b.id, b.position)));
+
+    let Some(lenient) = lenient else {
+        let listed: Vec<String> = issues
+            .iter()
+            .take(REPORTED_ISSUES)
+            .map(ToString::to_string)
+            .collect();
+        return Err(AlignerError::InvalidInput(format!(
+            "{} non-ASCII characters in sequences: {}{} (use --lenient to repair them)",
+            issues.len(),
+            listed.join("; "),
+            if issues.len() > REPORTED_ISSUES {
+                "; ..."
+            } else {
+                ""
+            }
+        )));
+    };
+
+    let mut repaired = Vec::new();
+    for (id, seq) in input.iter_mut().filter(|(_, seq)| !seq.is_ascii()) {
+        *seq = seq
+            .chars()
+            .filter_map(|c| match lenient {
+                _ if c.is_ascii() => Some(c),
+                Lenient::Transliterate => transliterate(c),
+                Lenient::Strip => None,
+            })
+            .collect();
+        warn!(id = %id, "repaired non-ASCII characters in sequence");
+        repaired.push(id.clone());
+    }
+    repaired.sort();
+    Ok(repaired)
+}
+
+/// Checks that no sequence is longer than `max_len`.
+///
+/// Sequences longer than [`MAX_SAFE_LEN`] are always rejected, since their
+/// alignment scores could overflow.
+///
+/// # Errors
+///
+/// Returns `AlignerError::InvalidInput` listing the longest offending sequences
+/// with their lengths.
+pub fn check_lengths(
+    input: &HashMap<String, String>,
+    max_len: Option<usize>,
+) -> Result<(), AlignerError> {
+    let limit = max_len.map_or(MAX_SAFE_LEN, |max_len| max_len.min(MAX_SAFE_LEN));
+    let mut too_long: Vec<(&String, usize)> = input
+        .iter()
+        .map(|(id, seq)| (id, seq.len()))
+        .filter(|(_, len)| *len > limit)
+        .collect();
+    if too_long.is_empty() {
+        return Ok(());
+    }
+    too_long.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+    let listed: Vec<String> = too_long
+        .iter()
+        .take(REPORTED_ISSUES)
+        .map(|(id, len)| format!("{} ({})", id, len))
+        .collect();
+    Err(AlignerError::InvalidInput(format!(
+        "{} sequences exceed the maximum length of {}: {}{}",
+        too_long.len(),
+        limit,
+        listed.join(", "),
+        if too_long.len() > REPORTED_ISSUES {
+            ", ..."
+        } else {
+            ""
+        }
+    )))
+}
+
+/// Returns the ASCII equivalent of a character, if there is an unambiguous one
+fn transliterate(c: char) -> Option<char> {
+    match c {
+        // Full-width forms of the printable ASCII characters
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0),
+        // Hyphens and dashes, commonly used for gaps
+        '\u{2010}'..='\u{2015}' | '\u{2212}' => Some('-'),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_ascii() {
+        let input = HashMap::from([
+            ("a".to_string(), "AC\u{201C}GT".to_string()),
+            ("b".to_string(), "\u{FF21}C\u{2013}GT".to_string()),
+        ]);
+
+        let error = check_ascii(&mut input.clone(), None).unwrap_err();
+        assert!(error.to_string().contains("in sequence 'a' at position 3"));
+
+        let mut transliterated = input.clone();
+        assert_eq!(
+            check_ascii(&mut transliterated, Some(Lenient::Transliterate)).unwrap(),
+            ["a", "b"]
+        );
+        assert_eq!(transliterated["a"], "ACGT");
+        assert_eq!(transliterated["b"], "AC-GT");
+
+        let mut stripped = input;
+        check_ascii(&mut stripped, Some(Lenient::Strip)).unwrap();
+        assert_eq!(stripped["b"], "CGT");
+    }
+
+    #[test]
+    fn test_check_lengths() {
+        let input = HashMap::from([
+            ("short".to_string(), "ACGT".to_string()),
+            ("long".to_string(), "ACGTACGT".to_string()),
+        ]);
+        assert!(check_lengths(&input, None).is_ok());
+        assert!(check_lengths(&input, Some(8)).is_ok());
+        let error = check_lengths(&input, Some(4)).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("1 sequences exceed the maximum length of 4: long (8)")
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! Watch mode for incoming sequence files.
+//!
+//! This module polls a directory for newly appearing sequence files, aligns every
+//! sequence of each new file against a reference set that is loaded once, and
+//! appends the results to a tab-separated output file. Files are only picked up
+//! once their size and modification time have stopped changing, so files that are
+//! still being written are not read half-way.
+
+use rayon::ThreadPoolBuilder;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{info, info_span, warn};
+
+use crate::ScoringType;
+use crate::align::{MatcherFn, align_against};
+use crate::error::AlignerError;
+use crate::filter::FilterChain;
+use crate::idmap::{sanitize_ids, sanitized_map_path, write_sanitized};
+use crate::sink::{DelimitedSink, OutputFormat, ResultSink};
+use crate::utils::parse_input;
+use crate::validate::{check_ascii, check_lengths};
+
+/// Command-line arguments for the `watch` subcommand
+#[derive(clap::Args, Debug)]
+pub struct WatchArgs {
+    /// Directory to monitor for new sequence files
+    dir: PathBuf,
+
+    /// Path to the reference JSON file every incoming sequence is aligned against
+    #[arg(short, long)]
+    reference: PathBuf,
+
+    /// Path to the tab-separated output file results are appended to
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// File extension of sequence files to pick up
+    #[arg(long, default_value = "json")]
+    extension: String,
+
+    /// Fraction for pre-filtering using k-mer matches (between 0 and 1)
+    #[arg(short, long)]
+    fraction: Option<f32>,
+
+    /// Minimum number of k-mer matches required for alignment
+    #[arg(short, long, default_value = "0")]
+    min_matches: usize,
+
+    /// Scoring type to use for alignment
+    #[arg(short, long, value_enum, default_value_t = ScoringType::Identity)]
+    scoring: ScoringType,
+
+    /// Number of threads to use for parallel processing
+    #[arg(short, long, env = "ALIGNER_THREADS")]
+    threads: Option<usize>,
+
+    /// Seconds between two scans of the directory
+    #[arg(long, default_value = "5")]
+    interval: u64,
+
+    /// Seconds a file must remain unchanged before it is processed
+    #[arg(long, default_value = "2")]
+    settle: u64,
+
+    /// Also process files already present when watching starts
+    #[arg(long)]
+    existing: bool,
+
+    /// Process the files currently present and exit instead of watching
+    #[arg(long)]
+    once: bool,
+}
+
+/// Size and modification time of a file, used to detect files still being written
+type FileState = (u64, SystemTime);
+
+/// Results file the results of all files are appended to
+type Output = DelimitedSink<BufWriter<File>>;
+
+/// Runs the `watch` subcommand until interrupted (or once, with `--once`).
+///
+/// # Errors
+///
+/// Returns an error if the reference set cannot be read, the output cannot be
+/// opened, or the directory cannot be listed. Errors in individual incoming files
+/// are logged and the file is skipped.
+pub fn run(args: WatchArgs) -> Result<(), AlignerError> {
+    if let Some(fraction) = args.fraction
+        && !(0.0..=1.0).contains(&fraction)
+    {
+        return Err(AlignerError::Config(
+            "fraction must be between 0 and 1".to_string(),
+        ));
+    }
+    if let Some(n) = args.threads {
+        ThreadPoolBuilder::new()
+            .num_threads(n)
+            .build_global()
+            .expect("Failed to initialize thread pool");
+    }
+
+    let mut reference = parse_input(&args.reference)?;
+    check_ascii(&mut reference, None)?;
+    check_lengths(&reference, None)?;
+    sanitize(&mut reference, &args.output)?;
+    let matcher = args.scoring.matcher();
+    info!(sequences = reference.len(), "loaded reference set");
+
+    let mut output = open_output(&args.output)?;
+
+    // Files are processed once; pending files wait until their state stops changing
+    let mut processed: HashSet<PathBuf> = HashSet::new();
+    let mut pending: HashMap<PathBuf, (FileState, SystemTime)> = HashMap::new();
+    if !args.existing && !args.once {
+        processed.extend(scan(&args.dir, &args.extension)?.into_keys());
+    }
+
+    let settle = Duration::from_secs(if args.once { 0 } else { args.settle });
+    loop {
+        let now = SystemTime::now();
+        let mut ready = Vec::new();
+        for (path, state) in scan(&args.dir, &args.extension)? {
+            if processed.contains(&path) {
+                continue;
+            }
+            match pending.get(&path) {
+                Some((previous, since)) if *previous == state => {
+                    if now.duration_since(*since).unwrap_or_default() >= settle {
+                        ready.push(path);
+                    }
+                }
+                _ => {
+                    pending.insert(path.clone(), (state, now));
+                    if settle.is_zero() {
+                        ready.push(path);
+                    }
+                }
+            }
+        }
+        ready.sort();
+
+        for path in ready {
+            pending.remove(&path);
+            processed.insert(path.clone());
+            match process_file(&path, &reference, &matcher, &args, &mut output) {
+                Ok(count) => info!(file = %path.display(), results = count, "processed file"),
+                Err(e) => warn!(file = %path.display(), "skipping file: {}", e),
+            }
+        }
+
+        if args.once {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_secs(args.interval.max(1)));
+    }
+}
+
+/// Opens the output for appending, writing the header if the file is new or empty
+fn open_output(path: &Path) -> Result<Output, AlignerError> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let is_empty = file.metadata()?.len() == 0;
+    let mut sink = DelimitedSink::new(BufWriter::new(file), OutputFormat::Tsv);
+    if !is_empty {
+        sink = sink.without_header();
+    }
+    ResultSink::<i32>::open(&mut sink)?;
+    ResultSink::<i32>::flush(&mut sink)?;
+    Ok(sink)
+}
+
+/// Sanitizes the identifiers of `sequences`, appending renamed identifiers to the
+/// mapping file next to `output`
+fn sanitize(sequences: &mut HashMap<String, String>, output: &Path) -> Result<(), AlignerError> {
+    let sanitized = sanitize_ids(sequences);
+    if !sanitized.is_empty() {
+        let path = sanitized_map_path(output);
+        write_sanitized(&path, &sanitized, true)?;
+        warn!(count = sanitized.len(), mapping = %path.display(), "sanitized sequence identifiers");
+    }
+    Ok(())
+}
+
+/// Lists the files in `dir` with the given extension together with their state
+fn scan(dir: &Path, extension: &str) -> Result<HashMap<PathBuf, FileState>, AlignerError> {
+    let mut files = HashMap::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(extension) {
+            continue;
+        }
+        // Files may disappear between listing and inspecting them
+        if let Ok(metadata) = fs::metadata(&path)
+            && metadata.is_file()
+        {
+            files.insert(path, (metadata.len(), metadata.modified()?));
+        }
+    }
+    Ok(files)
+}
+
+/// Aligns every sequence of the file at `path` against the reference set and
+/// appends the results, returning the number of results written
+fn process_file(
+    path: &Path,
+    reference: &HashMap<String, String>,
+    matcher: &MatcherFn,
+    args: &WatchArgs,
+    sink: &mut Output,
+) -> Result<usize, AlignerError> {
+    let _span = info_span!("watch_file", file = %path.display()).entered();
+    let mut queries = parse_input(path)?;
+    check_ascii(&mut queries, None)?;
+    check_lengths(&queries, None)?;
+    sanitize(&mut queries, &args.output)?;
+
+    let filters = FilterChain::from_options(args.fraction, args.min_matches, &[]);
+    let results = align_against(&queries, reference, matcher, &filters);
+
+    sink.write_batch(&results)?;
+    ResultSink::<i32>::flush(sink)?;
+    Ok(results.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_filters_by_extension() {
+        let dir = std::env::temp_dir().join(format!("aligner-watch-{}", std::process::id()));
+        fs::create_dir_all(dir.join("nested.fasta")).unwrap();
+        for name in ["a.fasta", "b.fa", "c.fasta.part", "fasta"] {
+            fs::write(dir.join(name), ">s\nMAVKL\n").unwrap();
+        }
+
+        let files = scan(&dir, "fasta").unwrap();
+        // Directories are skipped even if their name matches
+        assert_eq!(files.keys().collect::<Vec<_>>(), [&dir.join("a.fasta")]);
+        assert_eq!(files[&dir.join("a.fasta")].0, 9);
+        assert_eq!(scan(&dir, "fa").unwrap().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_open_output_writes_header_once() {
+        let path = std::env::temp_dir().join(format!("aligner-watch-{}.tsv", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let queries = HashMap::from([("q".to_string(), "MAVKL".to_string())]);
+        let reference = HashMap::from([("r".to_string(), "MAVKI".to_string())]);
+        let results = align_against(
+            &queries,
+            &reference,
+            &ScoringType::Identity.matcher(),
+            &FilterChain::default(),
+        );
+
+        // A restarted watcher appends to the results of the previous one
+        for _ in 0..2 {
+            let mut sink = open_output(&path).unwrap();
+            sink.write_batch(&results).unwrap();
+            ResultSink::<i32>::close(&mut sink).unwrap();
+        }
+        let written = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("query_id\tsubject_id\t"));
+        assert!(lines[1].starts_with("q\tr\t"));
+        assert_eq!(lines[1], lines[2]);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,217 @@
+//! Global alignment with the wavefront algorithm (WFA).
+//!
+//! The wavefront algorithm of Marco-Sola et al. (2021) computes the alignment of
+//! lowest penalty score by score instead of cell by cell: for every penalty `s` it
+//! keeps, per diagonal, the furthest cell any alignment of penalty `s` reaches, and
+//! follows runs of matching residues along the diagonals for free. The time grows
+//! with the length of the sequences times the penalty of their alignment, so
+//! similar sequences, e.g. the members of an enzyme family, are aligned in a small
+//! fraction of the time of the full dynamic programming matrix.
+//!
+//! Penalties are charged for mismatches and gaps while matches are free, so the
+//! wavefront algorithm needs the same score for every match and for every mismatch.
+//! With these, a global alignment maximizing the score minimizes the penalties of
+//! [`Penalties::from_scores`], and its score follows from their sum.
+
+use std::time::Instant;
+
+/// Number of penalties between checks of the deadline
+const DEADLINE_INTERVAL: u32 = 64;
+
+/// Offset of a diagonal no alignment of a penalty reaches
+const NONE: i32 = i32::MIN / 2;
+
+/// Penalties of a gap-affine alignment with free matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Penalties {
+    /// Penalty of a mismatch
+    pub mismatch: u32,
+    /// Penalty of opening a gap, charged once per gap
+    pub gap_open: u32,
+    /// Penalty of every residue of a gap
+    pub gap_extend: u32,
+}
+
+impl Penalties {
+    /// Returns the penalties whose global alignments are those with the highest
+    /// score for `matches`, `mismatches` and a gap of `len` residues scoring
+    /// `gap_open + gap_extend * len`, or `None` if matches do not score higher than
+    /// mismatches and gaps.
+    ///
+    /// Every alignment of sequences of `n` and `m` residues scores
+    /// `(matches * (n + m) - penalty) / 2`; see [`score`].
+    pub fn from_scores(
+        matches: i32,
+        mismatches: i32,
+        gap_open: i32,
+        gap_extend: i32,
+    ) -> Option<Self> {
+        let penalty = |value: i32| u32::try_from(value).ok();
+        let penalties = Self {
+            mismatch: penalty(2 * (matches - mismatches))?,
+            gap_open: penalty(-2 * gap_open)?,
+            gap_extend: penalty(matches - 2 * gap_extend)?,
+        };
+        (penalties.mismatch > 0 && penalties.gap_extend > 0).then_some(penalties)
+    }
+}
+
+/// Returns the score of an alignment of sequences of `len1` and `len2` residues
+/// with `penalty`, for the scores [`Penalties::from_scores`] was given
+pub fn score(matches: i32, len1: usize, len2: usize, penalty: u32) -> i32 {
+    (matches * (len1 + len2) as i32 - penalty as i32) / 2
+}
+
+/// Furthest offsets, positions in the second sequence, reached on the diagonals
+/// `lo..=hi`, where diagonal `k` holds the cells `(j - k, j)`
+#[derive(Debug, Clone)]
+struct Wavefront {
+    lo: i32,
+    hi: i32,
+    offsets: Vec<i32>,
+}
+
+impl Wavefront {
+    fn new(lo: i32, hi: i32) -> Self {
+        Self {
+            lo,
+            hi,
+            offsets: vec![NONE; (hi - lo + 1) as usize],
+        }
+    }
+
+    fn get(&self, k: i32) -> i32 {
+        if k < self.lo || k > self.hi {
+            return NONE;
+        }
+        self.offsets[(k - self.lo) as usize]
+    }
+}
+
+/// Wavefronts of alignments ending in a match or mismatch (m), a gap in the first
+/// sequence (i) and a gap in the second sequence (d)
+#[derive(Debug, Clone, Default)]
+struct Wavefronts {
+    m: Option<Wavefront>,
+    i: Option<Wavefront>,
+    d: Option<Wavefront>,
+}
+
+/// Returns the lowest penalty of a global alignment of two sequences, or `None`
+/// if `deadline` passed before it was found
+pub fn align(
+    seq1: &[u8],
+    seq2: &[u8],
+    penalties: Penalties,
+    deadline: Option<Instant>,
+) -> Option<u32> {
+    let (n1, n2) = (seq1.len() as i32, seq2.len() as i32);
+    let end = n2 - n1;
+    // Follows the matching residues along a diagonal
+    let extend = |k: i32, mut j: i32| {
+        while j < n2 && j - k < n1 && seq1[(j - k) as usize] == seq2[j as usize] {
+            j += 1;
+        }
+        j
+    };
+    let Penalties {
+        mismatch,
+        gap_open,
+        gap_extend,
+    } = penalties;
+    // Only the wavefronts of the last penalties are read
+    let window = mismatch.max(gap_open + gap_extend) as usize + 1;
+    let mut fronts: Vec<Wavefronts> = vec![Wavefronts::default(); window];
+    // Offsets beyond the ends of the sequences reach no cell
+    let valid = |k: i32, j: i32| if j <= n2 && j - k <= n1 { j } else { NONE };
+    let get = |front: Option<&Wavefront>, k: i32| front.map_or(NONE, |front| front.get(k));
+
+    let mut start = Wavefront::new(0, 0);
+    start.offsets[0] = extend(0, 0);
+    fronts[0].m = Some(start);
+    let mut s = 0;
+    loop {
+        let current = &fronts[s as usize % window];
+        if current.m.as_ref().is_some_and(|m| m.get(end) >= n2) {
+            return Some(s);
+        }
+        s += 1;
+        if s % DEADLINE_INTERVAL == 0 && deadline.is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return None;
+        }
+
+        let back = |penalty: u32| s.checked_sub(penalty).map(|s| &fronts[s as usize % window]);
+        let substituted = back(mismatch).and_then(|front| front.m.as_ref());
+        let opened = back(gap_open + gap_extend).and_then(|front| front.m.as_ref());
+        let extended = back(gap_extend);
+        let inserted = extended.and_then(|front| front.i.as_ref());
+        let deleted = extended.and_then(|front| front.d.as_ref());
+        let sources = [substituted, opened, inserted, deleted];
+        let (Some(lo), Some(hi)) = (
+            sources.iter().flatten().map(|front| front.lo).min(),
+            sources.iter().flatten().map(|front| front.hi).max(),
+        ) else {
+            fronts[s as usize % window] = Wavefronts::default();
+            continue;
+        };
+        let (lo, hi) = (lo - 1, hi + 1);
+
+        let mut i = Wavefront::new(lo, hi);
+        let mut d = Wavefront::new(lo, hi);
+        let mut m = Wavefront::new(lo, hi);
+        for k in lo..=hi {
+            let index = (k - lo) as usize;
+            let insertion = valid(k, get(opened, k - 1).max(get(inserted, k - 1)) + 1);
+            let deletion = valid(k, get(opened, k + 1).max(get(deleted, k + 1)));
+            let substitution = valid(k, get(substituted, k) + 1);
+            i.offsets[index] = insertion;
+            d.offsets[index] = deletion;
+            let best = substitution.max(insertion).max(deletion);
+            m.offsets[index] = if best < 0 { NONE } else { extend(k, best) };
+        }
+        fronts[s as usize % window] = Wavefronts {
+            m: Some(m),
+            i: Some(i),
+            d: Some(d),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::align::{AlignMode, GapPenalties, MatcherFn, align_linear};
+
+    #[test]
+    fn test_wavefront_alignment() {
+        let identity: MatcherFn = |a, b| if a == b { 1 } else { 0 };
+        let pairs = [
+            ("MKTAYIAKQRQISFVKSHFSRQ", "MKTAYIAKQRQISFVKSHFSRQ"),
+            ("MKTAYIAKQRQISFVKSHFSRQ", "MKTAYIAKRQISFVKSHFWWSRQ"),
+            ("GATTACA", "GCATGCT"),
+            ("", "MAVMT"),
+            ("MAVMT", ""),
+            ("WWWWWWMAVMT", "MAVMTKKKKKK"),
+        ];
+        for gaps in [
+            GapPenalties::default(),
+            GapPenalties::from_costs(0, 1).unwrap(),
+            GapPenalties::from_costs(3, 2).unwrap(),
+        ] {
+            let penalties = Penalties::from_scores(1, 0, gaps.open, gaps.extend).unwrap();
+            for (seq1, seq2) in pairs {
+                let penalty = align(seq1.as_bytes(), seq2.as_bytes(), penalties, None).unwrap();
+                assert_eq!(
+                    score(1, seq1.len(), seq2.len(), penalty),
+                    align_linear(seq1, seq2, &identity, AlignMode::Global, gaps),
+                    "{} {} {:?}",
+                    seq1,
+                    seq2,
+                    gaps
+                );
+            }
+        }
+        assert_eq!(Penalties::from_scores(1, 1, -10, -1), None);
+    }
+}
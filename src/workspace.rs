@@ -0,0 +1,118 @@
+//! Alignment workspaces reused across the pairs of a thread.
+//!
+//! Creating an aligner allocates its dynamic programming rows and traceback matrix,
+//! which takes a large share of the time of aligning short sequences. Every thread
+//! keeps the aligners of the few scoring schemes it used last and aligns small pairs
+//! with them, so their buffers are allocated once per thread rather than once per
+//! pair. Pairs with more than [`MAX_POOLED_CELLS`] cells get an aligner of their
+//! own, so no thread holds on to the traceback matrix of a long pair.
+
+use bio::alignment::pairwise::{Aligner, MIN_SCORE, Scoring};
+use std::cell::RefCell;
+
+use crate::align::{GapPenalties, MatcherFn};
+
+/// Largest number of matrix cells of a pair aligned with a pooled aligner
+pub const MAX_POOLED_CELLS: usize = 1 << 20;
+
+/// Number of aligners kept by every thread
+const POOL_SIZE: usize = 4;
+
+/// Penalties of clipping the x prefix, x suffix, y prefix and y suffix
+pub type Clips = [i32; 4];
+
+/// Clips of aligners whose ends are never clipped, or are set by the alignment
+/// method, e.g. [`Aligner::global`]
+pub const NO_CLIPS: Clips = [MIN_SCORE; 4];
+
+/// Scoring scheme an aligner was created with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Key {
+    /// Address of the matcher function
+    matcher: usize,
+    gaps: GapPenalties,
+    clips: Clips,
+}
+
+thread_local! {
+    /// Aligners of the thread, the most recently used first
+    static POOL: RefCell<Vec<(Key, Aligner<MatcherFn>)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Runs `f` with an aligner for sequences of `x_len` and `y_len` residues scoring
+/// with `matcher`, `gaps` and `clips`, taken from the pool of the thread if the
+/// pair is small enough
+pub fn with_aligner<R>(
+    x_len: usize,
+    y_len: usize,
+    matcher: MatcherFn,
+    gaps: GapPenalties,
+    clips: Clips,
+    f: impl FnOnce(&mut Aligner<MatcherFn>) -> R,
+) -> R {
+    let create = || {
+        let [x_prefix, x_suffix, y_prefix, y_suffix] = clips;
+        let scoring = Scoring::new(gaps.open, gaps.extend, matcher)
+            .xclip_prefix(x_prefix)
+            .xclip_suffix(x_suffix)
+            .yclip_prefix(y_prefix)
+            .yclip_suffix(y_suffix);
+        Aligner::with_capacity_and_scoring(x_len, y_len, scoring)
+    };
+    if x_len.saturating_mul(y_len) > MAX_POOLED_CELLS {
+        return f(&mut create());
+    }
+
+    let key = Key {
+        matcher: matcher as usize,
+        gaps,
+        clips,
+    };
+    // Taken out of the pool while in use, so `f` may align other pairs as well
+    let pooled = POOL.with_borrow_mut(|pool| {
+        let index = pool.iter().position(|(pooled, _)| *pooled == key)?;
+        Some(pool.remove(index).1)
+    });
+    let mut aligner = pooled.unwrap_or_else(create);
+    let result = f(&mut aligner);
+    POOL.with_borrow_mut(|pool| {
+        pool.insert(0, (key, aligner));
+        pool.truncate(POOL_SIZE);
+    });
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ScoringType;
+
+    #[test]
+    fn test_pooled_aligner() {
+        let matcher = ScoringType::Blosum62.matcher();
+        let gaps = GapPenalties::default();
+        let pairs = [
+            ("MKTAYIAKQRQISFVKSHF", "MKTAYIAKQRQISFVKSHFSRQ"),
+            ("MAVMT", "MWWWT"),
+            ("MKTAYIAKQRQISFVKSHF", "AYIAKQ"),
+        ];
+        for (x, y) in pairs {
+            let (x, y) = (x.as_bytes(), y.as_bytes());
+            let fresh = Aligner::with_capacity(x.len(), y.len(), gaps.open, gaps.extend, matcher)
+                .global(x, y);
+            let pooled = with_aligner(x.len(), y.len(), matcher, gaps, NO_CLIPS, |aligner| {
+                aligner.global(x, y)
+            });
+            assert_eq!(pooled, fresh);
+        }
+        assert_eq!(POOL.with_borrow(Vec::len), 1);
+
+        for open in 1..=POOL_SIZE as u32 + 1 {
+            let gaps = GapPenalties::from_costs(open, 1).unwrap();
+            with_aligner(5, 5, matcher, gaps, NO_CLIPS, |aligner| {
+                aligner.local(b"MAVMT", b"MAVKT")
+            });
+        }
+        assert_eq!(POOL.with_borrow(Vec::len), POOL_SIZE);
+    }
+}